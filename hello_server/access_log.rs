@@ -0,0 +1,271 @@
+//! Access logging.
+
+use serde::Serialize;
+use std::io::{self, BufWriter, Write};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use super::http::{Request, Response};
+use super::middleware::{Middleware, Next};
+use super::spsc::{self, Full, Producer};
+use super::sync::Backoff;
+
+/// Number of lines waiting for the writer before requests wait for it to catch up.
+const CAPACITY: usize = 4096;
+
+/// Format of access-log lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Common Log Format followed by the latency in microseconds and the request ID, e.g.
+    /// `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /a HTTP/1.1" 200 2326 1042 5f3a9c01-2a`.
+    #[default]
+    Common,
+    /// One JSON object per line.
+    Json,
+}
+
+/// One line of the access log in [`LogFormat::Json`].
+#[derive(Debug, Serialize)]
+struct Entry {
+    time: String,
+    remote_addr: Option<String>,
+    method: String,
+    path: String,
+    version: String,
+    status: u16,
+    /// `None` for streamed bodies.
+    bytes: Option<u64>,
+    latency_us: u128,
+    request_id: Option<String>,
+}
+
+impl Entry {
+    fn common(&self) -> String {
+        format!(
+            "{} - - [{}] \"{} {} {}\" {} {} {} {}",
+            self.remote_addr.as_deref().unwrap_or("-"),
+            self.time,
+            self.method,
+            self.path,
+            self.version,
+            self.status,
+            self.bytes
+                .map_or_else(|| "-".to_string(), |bytes| bytes.to_string()),
+            self.latency_us,
+            self.request_id.as_deref().unwrap_or("-"),
+        )
+    }
+}
+
+/// Middleware logging one line per request with the client address, method, path, status,
+/// response body length, the time taken to handle the request, and the request ID.
+///
+/// Lines are handed over through an [SPSC ring](super::spsc) to a background thread, so that slow
+/// output doesn't hold up requests as long as the ring has room. The thread writes the remaining
+/// lines and exits when the middleware is dropped.
+#[derive(Debug)]
+pub struct AccessLog {
+    format: LogFormat,
+    /// The ring's single producer, shared by the threads handling requests.
+    producer: Option<Mutex<Producer<String>>>,
+    writer: Option<thread::JoinHandle<()>>,
+}
+
+impl AccessLog {
+    /// Creates the middleware writing lines in `format` to `writer`.
+    pub fn new<W: Write + Send + 'static>(format: LogFormat, writer: W) -> Self {
+        let (producer, mut consumer) = spsc::channel::<String>(CAPACITY);
+        let writer = thread::spawn(move || {
+            let mut writer = BufWriter::new(writer);
+            loop {
+                // Checked first, so that every line pushed before the producer was dropped is
+                // written below.
+                let abandoned = consumer.is_abandoned();
+                while let Some(line) = consumer.pop() {
+                    let _ = writeln!(writer, "{line}");
+                }
+                // Flushes once the burst of lines is written.
+                let _ = writer.flush();
+                if abandoned {
+                    return;
+                }
+                // Unparked by each push, and by the drop of the middleware.
+                thread::park();
+            }
+        });
+        Self {
+            format,
+            producer: Some(Mutex::new(producer)),
+            writer: Some(writer),
+        }
+    }
+
+    /// Creates the middleware writing lines in `format` to the standard output.
+    pub fn stdout(format: LogFormat) -> Self {
+        Self::new(format, io::stdout())
+    }
+}
+
+impl Middleware for AccessLog {
+    fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        let start = Instant::now();
+        let time = SystemTime::now();
+        let remote_addr = request.remote_addr.map(|addr| addr.ip().to_string());
+        let method = request.method.clone();
+        let path = match &request.query {
+            Some(query) => format!("{}?{query}", request.path),
+            None => request.path.clone(),
+        };
+        let version = request.version.clone();
+        let request_id = request.id.clone();
+
+        let response = next.run(request);
+
+        let mut entry = Entry {
+            time: String::new(),
+            remote_addr,
+            method,
+            path,
+            version,
+            status: response.status,
+            bytes: response.body.len(),
+            latency_us: start.elapsed().as_micros(),
+            request_id,
+        };
+        let line = match self.format {
+            LogFormat::Common => {
+                entry.time = format_time(time, false);
+                entry.common()
+            }
+            LogFormat::Json => {
+                entry.time = format_time(time, true);
+                serde_json::to_string(&entry).unwrap()
+            }
+        };
+        if let (Some(producer), Some(writer)) = (&self.producer, &self.writer) {
+            let mut producer = producer.lock().unwrap();
+            let mut line = line;
+            let mut backoff = Backoff::new();
+            // The writer is behind: waits for it to make room rather than drop the line.
+            while let Err(Full(rejected)) = producer.push(line) {
+                line = rejected;
+                writer.thread().unpark();
+                backoff.snooze();
+            }
+            drop(producer);
+            writer.thread().unpark();
+        }
+        response
+    }
+}
+
+impl Drop for AccessLog {
+    fn drop(&mut self) {
+        // Abandons the ring so that the writer exits after writing the remaining lines.
+        drop(self.producer.take());
+        if let Some(writer) = self.writer.take() {
+            writer.thread().unpark();
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Formats `time` in UTC, as `2000-10-10T13:55:36Z` (RFC 3339) if `rfc3339`, and as
+/// `10/Oct/2000:13:55:36 +0000` (Common Log Format) otherwise.
+pub(crate) fn format_time(time: SystemTime, rfc3339: bool) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let (hour, min, sec) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    // Converts days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    if rfc3339 {
+        format!("{year:04}-{month:02}-{day:02}T{hour:02}:{min:02}:{sec:02}Z")
+    } else {
+        let month = MONTHS[month as usize - 1];
+        format!("{day:02}/{month}/{year:04}:{hour:02}:{min:02}:{sec:02} +0000")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use super::super::http::{Request, Response};
+    use super::super::middleware::{Middleware, Next};
+    use super::{AccessLog, LogFormat, CAPACITY};
+
+    /// Output shared with the test, which reads it once the log is dropped.
+    #[derive(Clone)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn drop_writes_every_line() {
+        const THREADS: usize = 4;
+        // More than the ring holds, so that requests wait for the writer.
+        const REQUESTS: usize = CAPACITY;
+
+        let output = Output(Arc::default());
+        let log = AccessLog::new(LogFormat::Common, output.clone());
+        let endpoint = |_| Response::new(204);
+        thread::scope(|scope| {
+            for thread in 0..THREADS {
+                let (log, endpoint) = (&log, &endpoint);
+                let _ = scope.spawn(move || {
+                    for request in 0..REQUESTS {
+                        let head = format!("GET /{thread}/{request} HTTP/1.1\r\n\r\n");
+                        let request = Request::read_head(&mut head.as_bytes()).unwrap().unwrap();
+                        let _ = log.handle(request, Next::new(&[], endpoint));
+                    }
+                });
+            }
+        });
+        drop(log);
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), THREADS * REQUESTS);
+        for thread in 0..THREADS {
+            // Each thread's lines are in the order of its requests.
+            let paths = lines
+                .iter()
+                .filter_map(|line| line.split(' ').nth(6))
+                .filter(|path| path.starts_with(&format!("/{thread}/")))
+                .collect::<Vec<_>>();
+            let expected = (0..REQUESTS)
+                .map(|request| format!("/{thread}/{request}"))
+                .collect::<Vec<_>>();
+            assert_eq!(paths, expected);
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Actors running on a thread pool.
+//!
+//! An actor owns its state, and other threads only reach it by sending messages to its mailbox
+//! through its [`Addr`]. It doesn't take a thread of its own: a job draining its mailbox is run on
+//! a [`ThreadPool`] whenever messages arrive, so that many actors share a few threads, and a single
+//! job runs at a time, so that the actor handles its messages one by one, in the order they
+//! arrived.
+//!
+//! ```ignore
+//! struct Counter(u64);
+//!
+//! enum Message {
+//!     Add(u64),
+//!     Get(oneshot::Sender<u64>),
+//! }
+//!
+//! impl Actor for Counter {
+//!     type Message = Message;
+//!
+//!     fn handle(&mut self, message: Message, _: &Addr<Self>) {
+//!         match message {
+//!             Message::Add(n) => self.0 += n,
+//!             Message::Get(reply) => drop(reply.send(self.0)),
+//!         }
+//!     }
+//! }
+//!
+//! let counter = spawn(&pool, Supervision::Restart(3), || Counter(0));
+//! counter.tell(Message::Add(2))?;
+//! assert_eq!(counter.ask(Message::Get)?.recv()?, 2);
+//! ```
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, Weak};
+
+use super::oneshot;
+use super::thread_pool::ThreadPool;
+
+/// Number of messages a job handles before giving the thread to the other jobs of the pool.
+const BATCH: usize = 32;
+
+/// State handling the messages sent to an [`Addr`].
+pub trait Actor: Sized + Send + 'static {
+    /// The messages the actor handles. Requests expecting a reply carry a [`oneshot::Sender`], see
+    /// [`Addr::ask`].
+    type Message: Send + 'static;
+
+    /// Handles `message`. `addr` is the actor's own address, e.g. to send itself messages or stop.
+    fn handle(&mut self, message: Self::Message, addr: &Addr<Self>);
+}
+
+/// What happens to an actor whose handler panics. The message it was handling is dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Supervision {
+    /// The actor is stopped.
+    Stop,
+    /// The actor is replaced by a new one made by its factory, at most this many times over its
+    /// lifetime, and then stopped.
+    Restart(usize),
+}
+
+#[derive(Debug)]
+struct Mailbox<M> {
+    messages: VecDeque<M>,
+    /// Whether a job handling the messages is queued or running.
+    scheduled: bool,
+    stopped: bool,
+}
+
+/// The actor, accessed by the job handling its messages.
+struct Running<A> {
+    /// `None` once stopped.
+    actor: Option<A>,
+    restarts: usize,
+}
+
+struct Cell<A: Actor> {
+    mailbox: Mutex<Mailbox<A::Message>>,
+    running: Mutex<Running<A>>,
+    factory: Box<dyn Fn() -> A + Send + Sync>,
+    supervision: Supervision,
+    /// Not kept alive by the actors, so that the pool isn't dropped by one of its own jobs.
+    pool: Weak<ThreadPool>,
+}
+
+impl<A: Actor> Cell<A> {
+    /// Runs a job handling the messages on the pool. Stops the actor if the pool is gone.
+    fn schedule(self: &Arc<Self>) {
+        let Some(pool) = self.pool.upgrade() else {
+            self.close();
+            return;
+        };
+        let cell = self.clone();
+        pool.execute(move || cell.run());
+    }
+
+    /// Handles a batch of messages, and runs again if more are waiting, after the jobs queued
+    /// meanwhile.
+    fn run(self: Arc<Self>) {
+        let addr = Addr { cell: self.clone() };
+        let mut running = self.running.lock().unwrap();
+        for _ in 0..BATCH {
+            let message = {
+                let mut mailbox = self.mailbox.lock().unwrap();
+                match mailbox.messages.pop_front() {
+                    Some(message) => message,
+                    None => {
+                        mailbox.scheduled = false;
+                        if mailbox.stopped {
+                            running.actor = None;
+                        }
+                        return;
+                    }
+                }
+            };
+            let Some(actor) = running.actor.as_mut() else {
+                continue;
+            };
+            if panic::catch_unwind(AssertUnwindSafe(|| actor.handle(message, &addr))).is_err() {
+                self.supervise(&mut running);
+            }
+        }
+        drop(running);
+        self.schedule();
+    }
+
+    /// Restarts or stops the actor after its handler panicked.
+    fn supervise(&self, running: &mut Running<A>) {
+        running.actor = None;
+        if let Supervision::Restart(max) = self.supervision {
+            if running.restarts < max {
+                running.restarts += 1;
+                println!(
+                    "[actor] handler panicked, restarting ({}/{max})",
+                    running.restarts
+                );
+                if let Ok(actor) = panic::catch_unwind(AssertUnwindSafe(|| (self.factory)())) {
+                    running.actor = Some(actor);
+                    return;
+                }
+            }
+        }
+        println!("[actor] handler panicked, stopping");
+        self.close();
+    }
+
+    /// Refuses the next messages, and drops the waiting ones, so that their askers stop waiting.
+    fn close(&self) {
+        let messages = {
+            let mut mailbox = self.mailbox.lock().unwrap();
+            mailbox.stopped = true;
+            mem::take(&mut mailbox.messages)
+        };
+        drop(messages);
+    }
+}
+
+/// Spawns the actor made by `factory` on `pool`, supervised with `supervision`, and returns its
+/// address.
+///
+/// The actor is dropped once stopped, or once every address is dropped and its messages are
+/// handled. Actors don't keep the pool alive: once it is dropped, they are stopped.
+pub fn spawn<A, F>(pool: &Arc<ThreadPool>, supervision: Supervision, factory: F) -> Addr<A>
+where
+    A: Actor,
+    F: Fn() -> A + Send + Sync + 'static,
+{
+    let actor = factory();
+    Addr {
+        cell: Arc::new(Cell {
+            mailbox: Mutex::new(Mailbox {
+                messages: VecDeque::new(),
+                scheduled: false,
+                stopped: false,
+            }),
+            running: Mutex::new(Running {
+                actor: Some(actor),
+                restarts: 0,
+            }),
+            factory: Box::new(factory),
+            supervision,
+            pool: Arc::downgrade(pool),
+        }),
+    }
+}
+
+/// Sends messages to an actor. Clones send to the same actor.
+///
+/// With a bounded pool, sending a message may block while the pool's queue is full.
+pub struct Addr<A: Actor> {
+    cell: Arc<Cell<A>>,
+}
+
+impl<A: Actor> Clone for Addr<A> {
+    fn clone(&self) -> Self {
+        Self {
+            cell: self.cell.clone(),
+        }
+    }
+}
+
+impl<A: Actor> fmt::Debug for Addr<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Addr")
+            .field("stopped", &self.is_stopped())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<A: Actor> Addr<A> {
+    /// Queues `message` for the actor, unless it is stopped.
+    pub fn tell(&self, message: A::Message) -> Result<(), Stopped<A::Message>> {
+        let mut mailbox = self.cell.mailbox.lock().unwrap();
+        if mailbox.stopped {
+            return Err(Stopped(message));
+        }
+        mailbox.messages.push_back(message);
+        let idle = !mem::replace(&mut mailbox.scheduled, true);
+        drop(mailbox);
+        if idle {
+            self.cell.schedule();
+        }
+        Ok(())
+    }
+
+    /// Queues the message made by `make` with the sender of a oneshot channel, and returns the
+    /// receiver of the reply. Receiving fails if the actor drops the sender without replying, e.g.
+    /// because it stopped or panicked.
+    pub fn ask<R, F>(&self, make: F) -> Result<oneshot::Receiver<R>, Stopped<A::Message>>
+    where
+        F: FnOnce(oneshot::Sender<R>) -> A::Message,
+    {
+        let (sender, receiver) = oneshot::channel();
+        self.tell(make(sender))?;
+        Ok(receiver)
+    }
+
+    /// Stops the actor once its current message is handled, dropping the waiting ones.
+    pub fn stop(&self) {
+        self.cell.close();
+        let mut mailbox = self.cell.mailbox.lock().unwrap();
+        let idle = !mem::replace(&mut mailbox.scheduled, true);
+        drop(mailbox);
+        // Drops the actor on the pool, like its messages.
+        if idle {
+            self.cell.schedule();
+        }
+    }
+
+    /// Returns whether the actor is stopped.
+    pub fn is_stopped(&self) -> bool {
+        self.cell.mailbox.lock().unwrap().stopped
+    }
+}
+
+/// Error of [`Addr::tell`] and [`Addr::ask`] when the actor is stopped, with the message not
+/// sent.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Stopped<T>(pub T);
+
+impl<T> fmt::Debug for Stopped<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Stopped").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for Stopped<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sending to a stopped actor")
+    }
+}
+
+impl<T> Error for Stopped<T> {}
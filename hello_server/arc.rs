@@ -0,0 +1,343 @@
+//! Atomically reference-counted pointer, reimplementing `std::sync::Arc` for study.
+//!
+//! The orderings follow the same reasoning as the standard library's: increments only need to be
+//! atomic, since a thread can only make a new reference from one it already holds, while the last
+//! decrement must see every access made through the other references before freeing the data.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::process;
+use std::ptr::NonNull;
+use std::sync::atomic::Ordering;
+
+use super::sync::shim::{fence, hint, AtomicUsize};
+
+/// Number of references above which the count is considered about to overflow, e.g. because of
+/// leaked clones, and the process is aborted.
+const MAX_REFS: usize = isize::MAX as usize;
+
+/// Weak count of the allocation while [`Arc::get_mut`] checks for uniqueness, so that no weak
+/// reference is made meanwhile.
+const LOCKED: usize = usize::MAX;
+
+struct ArcInner<T> {
+    /// Number of `Arc`s.
+    strong: AtomicUsize,
+    /// Number of `Weak`s, plus 1 while there are `Arc`s.
+    weak: AtomicUsize,
+    /// Dropped once there are no `Arc`s, while `Weak`s may still point to the allocation.
+    data: UnsafeCell<ManuallyDrop<T>>,
+}
+
+/// A shared reference to a value on the heap, freed once the last `Arc` is dropped.
+///
+/// The allocation holds two counts: the strong count keeps the value alive, and the weak count
+/// keeps the allocation alive, so that a [`Weak`] can tell whether the value still exists. All the
+/// `Arc`s together hold a single weak reference, dropped along with the value.
+pub struct Arc<T> {
+    ptr: NonNull<ArcInner<T>>,
+    /// The `Arc` owns an `ArcInner<T>`, for the drop check.
+    _marker: PhantomData<ArcInner<T>>,
+}
+
+// Like `std::sync::Arc`, the value is shared between threads, and dropped by any of them.
+unsafe impl<T: Send + Sync> Send for Arc<T> {}
+unsafe impl<T: Send + Sync> Sync for Arc<T> {}
+
+impl<T> Arc<T> {
+    /// Moves `value` to the heap.
+    pub fn new(value: T) -> Self {
+        let inner = Box::new(ArcInner {
+            strong: AtomicUsize::new(1),
+            weak: AtomicUsize::new(1),
+            data: UnsafeCell::new(ManuallyDrop::new(value)),
+        });
+        Self {
+            ptr: NonNull::from(Box::leak(inner)),
+            _marker: PhantomData,
+        }
+    }
+
+    fn inner(&self) -> &ArcInner<T> {
+        // SAFETY: The allocation isn't freed while there is an `Arc`.
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// Returns a mutable reference to the value if there are no other `Arc`s nor `Weak`s to it.
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        // Locks the weak count, so that no `Weak` is made from an `Arc` of another thread while the
+        // strong count is checked. Acquire so that the drops of other `Weak`s are seen.
+        if this
+            .inner()
+            .weak
+            .compare_exchange(1, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return None;
+        }
+        let is_unique = this.inner().strong.load(Ordering::Relaxed) == 1;
+        // Release so that threads making a `Weak` from this `Arc` later see the accesses made
+        // through the mutable reference.
+        this.inner().weak.store(1, Ordering::Release);
+        if !is_unique {
+            return None;
+        }
+        // Acquire so that the accesses made through the `Arc`s dropped before are seen.
+        fence(Ordering::Acquire);
+        // SAFETY: There is no other `Arc` nor `Weak`, and `this` is borrowed mutably.
+        Some(unsafe { &mut *this.inner().data.get() })
+    }
+
+    /// Makes a `Weak` to the value of `this`.
+    pub fn downgrade(this: &Self) -> Weak<T> {
+        let mut weak = this.inner().weak.load(Ordering::Relaxed);
+        loop {
+            if weak == LOCKED {
+                // Another `Arc` is checking whether it is unique.
+                hint::spin_loop();
+                weak = this.inner().weak.load(Ordering::Relaxed);
+                continue;
+            }
+            if weak > MAX_REFS {
+                process::abort();
+            }
+            // Acquire so that `get_mut` made the accesses through its mutable reference before.
+            match this.inner().weak.compare_exchange_weak(
+                weak,
+                weak + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Weak { ptr: this.ptr },
+                Err(current) => weak = current,
+            }
+        }
+    }
+
+    /// Returns the number of `Arc`s to the value of `this`. Other threads may change it right away.
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().strong.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of `Weak`s to the value of `this`. Other threads may change it right
+    /// away.
+    pub fn weak_count(this: &Self) -> usize {
+        match this.inner().weak.load(Ordering::Relaxed) {
+            // Another `Arc` is checking whether it is unique, so there are no `Weak`s.
+            LOCKED => 0,
+            weak => weak - 1,
+        }
+    }
+
+    /// Returns whether `this` and `other` point to the same value.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Arc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: Default> Default for Arc<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> Deref for Arc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The value is only accessed mutably through `get_mut`, when there is no other
+        // reference to it.
+        unsafe { &*self.inner().data.get() }
+    }
+}
+
+impl<T> Clone for Arc<T> {
+    fn clone(&self) -> Self {
+        if self.inner().strong.fetch_add(1, Ordering::Relaxed) > MAX_REFS {
+            process::abort();
+        }
+        Self {
+            ptr: self.ptr,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for Arc<T> {
+    fn drop(&mut self) {
+        // Release so that the thread dropping the value last has seen the accesses made through
+        // this `Arc`.
+        if self.inner().strong.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        // Acquire so that the accesses made through the other `Arc`s are seen.
+        fence(Ordering::Acquire);
+        // SAFETY: This was the last `Arc`, so nothing accesses the value anymore, and `Weak`s
+        // can't upgrade anymore.
+        unsafe { ManuallyDrop::drop(&mut *self.inner().data.get()) };
+        // Drops the weak reference held by the `Arc`s.
+        drop(Weak { ptr: self.ptr });
+    }
+}
+
+/// A reference to the value of an [`Arc`] that doesn't keep it alive, e.g. to break reference
+/// cycles.
+pub struct Weak<T> {
+    ptr: NonNull<ArcInner<T>>,
+}
+
+// Like `std::sync::Weak`, upgrading shares the value between threads.
+unsafe impl<T: Send + Sync> Send for Weak<T> {}
+unsafe impl<T: Send + Sync> Sync for Weak<T> {}
+
+impl<T> Weak<T> {
+    fn inner(&self) -> &ArcInner<T> {
+        // SAFETY: The allocation isn't freed while there is a `Weak`.
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// Returns an `Arc` to the value, unless it was dropped.
+    pub fn upgrade(&self) -> Option<Arc<T>> {
+        let mut strong = self.inner().strong.load(Ordering::Relaxed);
+        loop {
+            if strong == 0 {
+                return None;
+            }
+            if strong > MAX_REFS {
+                process::abort();
+            }
+            match self.inner().strong.compare_exchange_weak(
+                strong,
+                strong + 1,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    return Some(Arc {
+                        ptr: self.ptr,
+                        _marker: PhantomData,
+                    })
+                }
+                Err(current) => strong = current,
+            }
+        }
+    }
+
+    /// Returns the number of `Arc`s to the value. Other threads may change it right away.
+    pub fn strong_count(&self) -> usize {
+        self.inner().strong.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> fmt::Debug for Weak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(Weak)")
+    }
+}
+
+impl<T> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        if self.inner().weak.fetch_add(1, Ordering::Relaxed) > MAX_REFS {
+            process::abort();
+        }
+        Self { ptr: self.ptr }
+    }
+}
+
+impl<T> Drop for Weak<T> {
+    fn drop(&mut self) {
+        // Release so that the thread freeing the allocation last has seen the accesses made
+        // through this `Weak`.
+        if self.inner().weak.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        // Acquire so that the accesses made through the other references are seen.
+        fence(Ordering::Acquire);
+        // SAFETY: This was the last reference, and the value was dropped.
+        drop(unsafe { Box::from_raw(self.ptr.as_ptr()) });
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::sync::atomic::{AtomicUsize, Ordering};
+    use loom::thread;
+
+    use super::Arc;
+
+    /// Counts its drops, in an atomic of loom so that the threads seeing them are checked too.
+    struct Counted(std::sync::Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            let _ = self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn counted() -> (Arc<Counted>, std::sync::Arc<AtomicUsize>) {
+        let drops = std::sync::Arc::new(AtomicUsize::new(0));
+        (Arc::new(Counted(drops.clone())), drops)
+    }
+
+    #[test]
+    fn clones_dropped_on_any_thread_drop_the_value_once() {
+        loom::model(|| {
+            let (arc, drops) = counted();
+            let clone = arc.clone();
+            let thread = thread::spawn(move || {
+                assert_eq!(clone.0.load(Ordering::Relaxed), 0);
+                drop(clone);
+            });
+            drop(arc);
+            thread.join().unwrap();
+            assert_eq!(drops.load(Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn upgrade_racing_the_last_drop() {
+        loom::model(|| {
+            let (arc, drops) = counted();
+            let weak = Arc::downgrade(&arc);
+            let thread = thread::spawn(move || {
+                // Either the value is alive, and stays so while upgraded, or it is dropped.
+                match weak.upgrade() {
+                    Some(arc) => assert_eq!(arc.0.load(Ordering::Relaxed), 0),
+                    None => assert!(weak.upgrade().is_none()),
+                }
+            });
+            drop(arc);
+            thread.join().unwrap();
+            assert_eq!(drops.load(Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn get_mut_racing_a_downgrade_and_a_drop() {
+        loom::model(|| {
+            let mut arc = Arc::new(0);
+            let clone = arc.clone();
+            let thread = thread::spawn(move || {
+                let weak = Arc::downgrade(&clone);
+                drop(clone);
+                weak
+            });
+            // The other thread holds an `Arc` or a `Weak` until its `Weak` is dropped.
+            assert!(Arc::get_mut(&mut arc).is_none());
+            let weak = thread.join().unwrap();
+            assert!(Arc::get_mut(&mut arc).is_none());
+            drop(weak);
+            *Arc::get_mut(&mut arc).expect("unique") += 1;
+            assert_eq!(*arc, 1);
+        });
+    }
+}
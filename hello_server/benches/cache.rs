@@ -0,0 +1,103 @@
+//! Latency of the cache: hits and misses from one thread, and hits and loads of shared keys from
+//! several threads at once.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use cs431_homework::cache::Cache;
+use std::hint::black_box;
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of keys looked up.
+const KEYS: u64 = 1_024;
+
+/// Number of threads looking up keys at once.
+const THREADS: [usize; 4] = [1, 2, 4, 8];
+
+/// A cache holding all the keys.
+fn filled() -> Cache<u64, u64> {
+    let cache = Cache::default();
+    for key in 0..KEYS {
+        let _ = cache.get_or_insert_with(key, |key| key * 2);
+    }
+    cache
+}
+
+fn single_thread(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cache");
+    let _ = group.throughput(Throughput::Elements(1));
+
+    let cache = filled();
+    let mut key = 0;
+    let _ = group.bench_function("hit", |b| {
+        b.iter(|| {
+            key = (key + 1) % KEYS;
+            cache.get_or_insert_with(black_box(key), |_| unreachable!("cached"))
+        })
+    });
+
+    // Fresh keys miss, and are loaded right away by the caller.
+    let cache = Cache::default();
+    let mut key = 0u64;
+    let _ = group.bench_function("miss", |b| {
+        b.iter(|| {
+            key += 1;
+            cache.get_or_insert_with(black_box(key), |key| key * 2)
+        })
+    });
+    group.finish();
+}
+
+/// Runs `iters` lookups with `lookup`, split across `threads` threads starting together, and
+/// returns how long they took.
+fn contended(threads: usize, iters: u64, lookup: impl Fn(usize, u64) + Send + Sync) -> Duration {
+    let barrier = Barrier::new(threads + 1);
+    let per_thread = iters.div_ceil(threads as u64);
+    thread::scope(|scope| {
+        for thread in 0..threads {
+            let (barrier, lookup) = (&barrier, &lookup);
+            let _ = scope.spawn(move || {
+                let _ = barrier.wait();
+                for i in 0..per_thread {
+                    lookup(thread, i);
+                }
+            });
+        }
+        let _ = barrier.wait();
+        let start = Instant::now();
+        // The scope joins the threads before returning.
+        start
+    })
+    .elapsed()
+}
+
+fn multi_thread(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cache/contended");
+    let _ = group.throughput(Throughput::Elements(1));
+    for threads in THREADS {
+        // All threads hit the same keys.
+        let cache = filled();
+        let _ = group.bench_function(BenchmarkId::new("hit", threads), |b| {
+            b.iter_custom(|iters| {
+                contended(threads, iters, |_, i| {
+                    let _ = black_box(cache.get_or_insert_with(i % KEYS, |_| unreachable!()));
+                })
+            })
+        });
+
+        // All threads miss the same fresh keys, so that each is loaded once while the others
+        // wait for it.
+        let _ = group.bench_function(BenchmarkId::new("miss", threads), |b| {
+            b.iter_custom(|iters| {
+                let cache = Cache::default();
+                contended(threads, iters, |_, i| {
+                    let _ = black_box(cache.get_or_insert_with(i, |key| key * 2));
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, single_thread, multi_thread);
+criterion_main!(benches);
@@ -0,0 +1,95 @@
+//! Throughput of the locks under contention: how fast threads get through short critical
+//! sections, each incrementing a shared counter, with `std::sync::Mutex` as the baseline.
+//!
+//! The fair locks hand the lock to the next waiter in line, which costs throughput, most of all
+//! with more threads than cores, when that waiter may be descheduled. So the locks also run with
+//! twice as many threads as cores.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use cs431_homework::hello_server::sync::{McsLock, SpinLock, TicketLock};
+use std::hint::black_box;
+use std::sync::{Barrier, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of threads taking the lock at once.
+const THREADS: [usize; 4] = [1, 2, 4, 8];
+
+/// A lock around a counter.
+trait Lock: Default + Sync {
+    fn increment(&self);
+}
+
+impl Lock for Mutex<u64> {
+    fn increment(&self) {
+        *self.lock().unwrap() += 1;
+    }
+}
+
+impl Lock for SpinLock<u64> {
+    fn increment(&self) {
+        *self.lock() += 1;
+    }
+}
+
+impl Lock for McsLock<u64> {
+    fn increment(&self) {
+        *self.lock() += 1;
+    }
+}
+
+impl Lock for TicketLock<u64> {
+    fn increment(&self) {
+        *self.lock() += 1;
+    }
+}
+
+/// Runs `iters` increments of a counter behind `L`, split across `threads` threads starting
+/// together, and returns how long they took.
+fn contended<L: Lock>(threads: usize, iters: u64) -> Duration {
+    let lock = L::default();
+    let barrier = Barrier::new(threads + 1);
+    let per_thread = iters.div_ceil(threads as u64);
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let (lock, barrier) = (&lock, &barrier);
+            let _ = scope.spawn(move || {
+                let _ = barrier.wait();
+                for _ in 0..per_thread {
+                    black_box(lock).increment();
+                }
+            });
+        }
+        let _ = barrier.wait();
+        let start = Instant::now();
+        // The scope joins the threads before returning.
+        start
+    })
+    .elapsed()
+}
+
+fn throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("locks");
+    let _ = group.throughput(Throughput::Elements(1));
+    // Benchmark IDs must be unique.
+    let oversubscribed = Some(2 * thread::available_parallelism().map_or(1, usize::from))
+        .filter(|threads| !THREADS.contains(threads));
+    for threads in THREADS.into_iter().chain(oversubscribed) {
+        let _ = group.bench_function(BenchmarkId::new("std_mutex", threads), |b| {
+            b.iter_custom(|iters| contended::<Mutex<u64>>(threads, iters))
+        });
+        let _ = group.bench_function(BenchmarkId::new("spin_lock", threads), |b| {
+            b.iter_custom(|iters| contended::<SpinLock<u64>>(threads, iters))
+        });
+        let _ = group.bench_function(BenchmarkId::new("mcs_lock", threads), |b| {
+            b.iter_custom(|iters| contended::<McsLock<u64>>(threads, iters))
+        });
+        let _ = group.bench_function(BenchmarkId::new("ticket_lock", threads), |b| {
+            b.iter_custom(|iters| contended::<TicketLock<u64>>(threads, iters))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -0,0 +1,55 @@
+//! Throughput of the thread pool: how fast batches of small and large jobs get through pools of
+//! different sizes, unbounded and bounded.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use cs431_homework::thread_pool::ThreadPool;
+use std::hint::black_box;
+
+/// Number of jobs per batch.
+const JOBS: u64 = 1_000;
+
+/// Number of threads of the pools.
+const THREADS: [usize; 4] = [1, 2, 4, 8];
+
+/// Capacity of the queue of the bounded pools.
+const CAPACITY: usize = 64;
+
+/// A job doing about `rounds` rounds of work, so that large jobs outweigh the cost of queuing.
+fn work(rounds: u64) {
+    let mut x = 0u64;
+    for i in 0..rounds {
+        x = black_box(x.wrapping_mul(31).wrapping_add(i));
+    }
+    let _ = black_box(x);
+}
+
+/// Runs a batch of jobs of `rounds` rounds each on `pool`, and waits for them.
+fn run_batch(pool: &ThreadPool, rounds: u64) {
+    for _ in 0..JOBS {
+        pool.execute(move || work(rounds));
+    }
+    pool.join();
+}
+
+fn throughput(c: &mut Criterion) {
+    for (size, rounds) in [("small", 0), ("large", 10_000)] {
+        let mut group = c.benchmark_group(format!("pool/{size}_jobs"));
+        let _ = group.throughput(Throughput::Elements(JOBS));
+        for threads in THREADS {
+            let pool = ThreadPool::new(threads);
+            let _ =
+                group.bench_with_input(BenchmarkId::new("unbounded", threads), &pool, |b, pool| {
+                    b.iter(|| run_batch(pool, rounds))
+                });
+            let pool = ThreadPool::bounded(threads, CAPACITY);
+            let _ =
+                group.bench_with_input(BenchmarkId::new("bounded", threads), &pool, |b, pool| {
+                    b.iter(|| run_batch(pool, rounds))
+                });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
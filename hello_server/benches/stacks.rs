@@ -0,0 +1,80 @@
+//! Throughput of the lock-free stacks under contention: how fast threads get through pairs of a
+//! push and a pop on a shared stack, with and without an elimination array.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use cs431_homework::hello_server::lockfree::{EliminationStack, Stack};
+use std::hint::black_box;
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of threads using the stack at once.
+const THREADS: [usize; 4] = [1, 2, 4, 8];
+
+/// A stack of numbers.
+trait ConcurrentStack: Default + Sync {
+    fn push(&self, value: u64);
+    fn pop(&self) -> Option<u64>;
+}
+
+impl ConcurrentStack for Stack<u64> {
+    fn push(&self, value: u64) {
+        Stack::push(self, value);
+    }
+
+    fn pop(&self) -> Option<u64> {
+        Stack::pop(self)
+    }
+}
+
+impl ConcurrentStack for EliminationStack<u64> {
+    fn push(&self, value: u64) {
+        EliminationStack::push(self, value);
+    }
+
+    fn pop(&self) -> Option<u64> {
+        EliminationStack::pop(self)
+    }
+}
+
+/// Runs `iters` pairs of a push and a pop on a stack `S`, split across `threads` threads starting
+/// together, and returns how long they took.
+fn contended<S: ConcurrentStack>(threads: usize, iters: u64) -> Duration {
+    let stack = S::default();
+    let barrier = Barrier::new(threads + 1);
+    let per_thread = iters.div_ceil(threads as u64);
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let (stack, barrier) = (&stack, &barrier);
+            let _ = scope.spawn(move || {
+                let _ = barrier.wait();
+                for i in 0..per_thread {
+                    stack.push(i);
+                    let _ = black_box(stack.pop());
+                }
+            });
+        }
+        let _ = barrier.wait();
+        let start = Instant::now();
+        // The scope joins the threads before returning.
+        start
+    })
+    .elapsed()
+}
+
+fn throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("stacks");
+    let _ = group.throughput(Throughput::Elements(2));
+    for threads in THREADS {
+        let _ = group.bench_function(BenchmarkId::new("treiber", threads), |b| {
+            b.iter_custom(|iters| contended::<Stack<u64>>(threads, iters))
+        });
+        let _ = group.bench_function(BenchmarkId::new("elimination", threads), |b| {
+            b.iter_custom(|iters| contended::<EliminationStack<u64>>(threads, iters))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -1,26 +1,661 @@
 //! Thread-safe key/value cache.
+//!
+//! A [`Cache`] computes the value of a missing key at most once, however many threads ask for it
+//! at the same time: the first one computes it and the others wait for its result. It is bounded
+//! and evicts the least recently used entries, and its entries may expire; see [`CacheBuilder`].
+//!
+//! ```ignore
+//! let cache = Cache::builder()
+//!     .capacity(1024)
+//!     .ttl(Duration::from_secs(60))
+//!     .loader(|key: String| key.to_uppercase())
+//!     .build()?;
+//! assert_eq!(cache.get("hello".to_string()), "HELLO");
+//! ```
 
-use std::collections::hash_map::{Entry, HashMap};
-use std::hash::Hash;
-use std::mem::needs_drop;
-use std::ops::Deref;
-use std::ptr::null;
-use std::sync::{Arc, Mutex, RwLock};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+use core::hash::Hash;
+use core::mem;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+#[cfg(not(feature = "std"))]
+use hashbrown::{HashMap, HashSet};
+#[cfg(feature = "std")]
+use rand::Rng;
+use serde::{Deserialize, Deserializer};
+#[cfg(feature = "std")]
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "std")]
+use std::panic::{self, AssertUnwindSafe};
+use thiserror::Error;
+
+#[cfg(feature = "std")]
+use super::sync::shim::AtomicBool;
+use super::sync::shim::{AtomicU64, Condvar, Instant, Mutex};
+#[cfg(feature = "std")]
+use super::thread_pool::PoolHandle;
+use super::trace::{event, span};
+
+/// A cached value together with the version it was written at.
+#[derive(Debug)]
+struct Slot<V> {
+    value: Arc<V>,
+    version: u64,
+
+    /// Tick of the last access. The slot with the smallest tick is the least recently used one.
+    last_used: u64,
+    expires_at: Option<Instant>,
+    /// When a hit reloads the entry on the maintenance pool, see [`CacheConfig::refresh_ahead`].
+    #[cfg(feature = "std")]
+    refresh_at: Option<Instant>,
+}
+
+impl<V> Slot<V> {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|deadline| deadline <= now)
+    }
+}
+
+/// A load in progress.
+#[derive(Debug)]
+struct InFlight {
+    /// Tells this load apart from later loads of the same key.
+    id: u64,
+    /// Number of callers waiting for the load to finish.
+    waiters: usize,
+}
+
+/// Removes the expired slots from `map` and returns them.
+fn drain_expired<K: Eq + Hash + Clone, V>(map: &mut HashMap<K, Slot<V>>) -> Vec<(K, Slot<V>)> {
+    let now = Instant::now();
+    let expired = map
+        .iter()
+        .filter(|(_, slot)| slot.is_expired(now))
+        .map(|(key, _)| key.clone())
+        .collect::<Vec<_>>();
+    expired
+        .into_iter()
+        .filter_map(|key| map.remove_entry(&key))
+        .collect()
+}
+
+/// Cache configuration.
+///
+/// All options default to "off", which gives an unbounded cache whose entries never expire. The
+/// struct can be deserialized from a config file, e.g. the TOML table
+///
+/// ```toml
+/// [cache]
+/// capacity = 1024
+/// ttl_ms = 60000
+/// ttl_jitter = 0.1
+/// sweep_interval_ms = 5000
+/// refresh_ahead = 0.8
+/// max_waiters = 64
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Maximum number of entries. When full, the least recently used entry is evicted.
+    pub capacity: Option<usize>,
+
+    /// How long an entry stays valid after it is written.
+    #[serde(rename = "ttl_ms", deserialize_with = "deserialize_millis")]
+    pub ttl: Option<Duration>,
+
+    /// Fraction of `ttl` by which each entry's deadline is randomly moved, e.g. `0.1` for ±10%.
+    ///
+    /// Entries inserted together (say, at startup) would otherwise expire together and make their
+    /// loaders stampede.
+    pub ttl_jitter: Option<f64>,
+
+    /// How often expired entries are swept by the maintenance pool. Defaults to `ttl`.
+    ///
+    /// Requires a maintenance pool (see [`CacheBuilder::maintenance_pool`]). Without one, expired
+    /// entries are dropped lazily when they are looked up or when the cache is full.
+    #[serde(rename = "sweep_interval_ms", deserialize_with = "deserialize_millis")]
+    pub sweep_interval: Option<Duration>,
+
+    /// Fraction of an entry's TTL after which a hit reloads it with the cache's loader, e.g. `0.8`
+    /// to reload it in its last 20%. The hit still returns the current value right away.
+    ///
+    /// Requires a loader and a maintenance pool to run the loads on, so that hot keys are reloaded
+    /// before they expire instead of making a caller wait for the load.
+    pub refresh_ahead: Option<f64>,
+
+    /// Maximum number of callers waiting for the same in-flight load. Further callers don't queue
+    /// up: [`Cache::try_get_or_insert_with`] fails with [`CacheError::Overloaded`] and
+    /// [`Cache::get_or_insert_with`] runs the loading function itself.
+    pub max_waiters: Option<usize>,
+}
+
+pub(crate) fn deserialize_millis<'de, D: Deserializer<'de>>(
+    d: D,
+) -> Result<Option<Duration>, D::Error> {
+    Ok(Option::<u64>::deserialize(d)?.map(Duration::from_millis))
+}
+
+impl CacheConfig {
+    /// Checks that the options make sense together.
+    pub fn validate(&self) -> Result<(), CacheConfigError> {
+        if self.capacity == Some(0) {
+            return Err(CacheConfigError::ZeroCapacity);
+        }
+        if self.ttl == Some(Duration::ZERO) {
+            return Err(CacheConfigError::ZeroTtl);
+        }
+        #[cfg(not(feature = "std"))]
+        if self.ttl.is_some() {
+            return Err(CacheConfigError::TtlWithoutClock);
+        }
+        if let Some(jitter) = self.ttl_jitter {
+            if self.ttl.is_none() {
+                return Err(CacheConfigError::JitterWithoutTtl);
+            }
+            if !(0.0..1.0).contains(&jitter) {
+                return Err(CacheConfigError::InvalidJitter);
+            }
+        }
+        if let Some(interval) = self.sweep_interval {
+            if self.ttl.is_none() {
+                return Err(CacheConfigError::SweepWithoutTtl);
+            }
+            if interval.is_zero() {
+                return Err(CacheConfigError::ZeroSweepInterval);
+            }
+        }
+        if let Some(refresh_ahead) = self.refresh_ahead {
+            if self.ttl.is_none() {
+                return Err(CacheConfigError::RefreshWithoutTtl);
+            }
+            if !(refresh_ahead > 0.0 && refresh_ahead < 1.0) {
+                return Err(CacheConfigError::InvalidRefreshAhead);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reason why a [`CacheConfig`] was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheConfigError {
+    /// `capacity` is 0, so nothing could ever be cached.
+    ZeroCapacity,
+    /// `ttl` is 0, so every entry would expire right away.
+    ZeroTtl,
+    /// `ttl` is set but there is no clock to expire entries with, without the `std` feature.
+    #[cfg(not(feature = "std"))]
+    TtlWithoutClock,
+    /// `ttl_jitter` is set but there is no `ttl` to apply it to.
+    JitterWithoutTtl,
+    /// `ttl_jitter` is not in `[0, 1)`.
+    InvalidJitter,
+    /// `sweep_interval` is set but there is no `ttl`, so nothing ever expires.
+    SweepWithoutTtl,
+    /// `sweep_interval` is 0.
+    ZeroSweepInterval,
+    /// `sweep_interval` is set but no maintenance pool was given to run the sweeps on.
+    SweepWithoutPool,
+    /// `refresh_ahead` is set but there is no `ttl`, so entries never need a refresh.
+    RefreshWithoutTtl,
+    /// `refresh_ahead` is not in `(0, 1)`.
+    InvalidRefreshAhead,
+    /// `refresh_ahead` is set but there is no loader to refresh with.
+    RefreshWithoutLoader,
+    /// `refresh_ahead` is set but no maintenance pool was given to run the refreshes on.
+    RefreshWithoutPool,
+}
+
+impl fmt::Display for CacheConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroCapacity => write!(f, "cache capacity must be positive"),
+            Self::ZeroTtl => write!(f, "cache TTL must be positive"),
+            #[cfg(not(feature = "std"))]
+            Self::TtlWithoutClock => write!(f, "cache TTL requires the `std` feature"),
+            Self::JitterWithoutTtl => write!(f, "cache TTL jitter requires a TTL"),
+            Self::InvalidJitter => write!(f, "cache TTL jitter must be in [0, 1)"),
+            Self::SweepWithoutTtl => write!(f, "cache sweep interval requires a TTL"),
+            Self::ZeroSweepInterval => write!(f, "cache sweep interval must be positive"),
+            Self::SweepWithoutPool => write!(f, "cache sweep interval requires a maintenance pool"),
+            Self::RefreshWithoutTtl => write!(f, "cache refresh-ahead requires a TTL"),
+            Self::InvalidRefreshAhead => write!(f, "cache refresh-ahead must be in (0, 1)"),
+            Self::RefreshWithoutLoader => write!(f, "cache refresh-ahead requires a loader"),
+            Self::RefreshWithoutPool => {
+                write!(f, "cache refresh-ahead requires a maintenance pool")
+            }
+        }
+    }
+}
+
+impl Error for CacheConfigError {}
+
+/// Error of looking up a value in a [`Cache`].
+#[derive(Debug, Error)]
+pub enum CacheError {
+    /// Too many callers already wait for the same key, see [`CacheConfig::max_waiters`].
+    #[error("too many callers are waiting for the cache entry")]
+    Overloaded,
+    /// The cache was built without a loader, see [`CacheBuilder::loader`].
+    #[error("cache has no loader")]
+    NoLoader,
+    /// The loading function failed, see [`Cache::get_or_try_insert_with`].
+    #[error("failed to load the cache entry")]
+    Load(#[source] Box<dyn Error + Send + Sync>),
+}
+
+/// Receives the cache's metrics, e.g. to forward them to a Prometheus registry.
+///
+/// The cache reports the following metrics:
+///
+/// - counter `cache_hits`: lookups answered from the cache.
+/// - counter `cache_misses`: lookups that had to load or wait for a load.
+/// - counter `cache_evictions`: entries evicted because the cache was full.
+/// - counter `cache_expirations`: entries dropped because their TTL elapsed.
+/// - counter `cache_overloads`: lookups that hit the `max_waiters` cap.
+/// - counter `cache_load_failures`: calls to a loading function that failed or panicked.
+/// - counter `cache_refreshes`: entries reloaded ahead of their expiry.
+/// - gauge `cache_entries`: number of entries after each insertion or removal.
+/// - histogram `cache_load_seconds`: duration of each call to a loading function, always 0 without
+///   the `std` feature, which has no clock.
+pub trait MetricsSink: Send + Sync {
+    /// Adds `delta` to the counter `name`.
+    fn counter(&self, name: &'static str, delta: u64);
+
+    /// Sets the gauge `name` to `value`.
+    fn gauge(&self, name: &'static str, value: f64);
+
+    /// Records one observation of `value` for the histogram `name`. Ignored by default.
+    fn histogram(&self, _name: &'static str, _value: f64) {}
+}
+
+/// The optional [`MetricsSink`] of a cache.
+#[derive(Clone, Default)]
+struct Metrics(Option<Arc<dyn MetricsSink>>);
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Metrics").field(&self.0.is_some()).finish()
+    }
+}
+
+impl Metrics {
+    fn counter(&self, name: &'static str, delta: u64) {
+        if let Some(sink) = &self.0 {
+            sink.counter(name, delta);
+        }
+    }
+
+    fn gauge(&self, name: &'static str, value: f64) {
+        if let Some(sink) = &self.0 {
+            sink.gauge(name, value);
+        }
+    }
+
+    fn histogram(&self, name: &'static str, value: f64) {
+        if let Some(sink) = &self.0 {
+            sink.histogram(name, value);
+        }
+    }
+}
+
+/// Loading function baked into a cache by [`Cache::with_loader`].
+struct Loader<K, V>(Option<Arc<dyn Fn(K) -> V + Send + Sync>>);
+
+impl<K, V> Clone for Loader<K, V> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<K, V> Default for Loader<K, V> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<K, V> fmt::Debug for Loader<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Loader").field(&self.0.is_some()).finish()
+    }
+}
+
+/// Why an entry left the cache, as reported to the eviction listener.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovalCause {
+    /// The entry's TTL elapsed.
+    Expired,
+    /// The entry was the least recently used one when the cache was full.
+    Evicted,
+}
+
+/// An entry that left the cache, waiting to be reported to the eviction listener.
+type Removed<K, V> = (K, Arc<V>, RemovalCause);
+
+type ListenerFn<K, V> = dyn Fn(K, V, RemovalCause) + Send + Sync;
+
+/// Callback registered by [`CacheBuilder::eviction_listener`].
+struct Listener<K, V>(Option<Arc<ListenerFn<K, V>>>);
+
+impl<K, V> Clone for Listener<K, V> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<K, V> Default for Listener<K, V> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<K, V> fmt::Debug for Listener<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Listener").field(&self.0.is_some()).finish()
+    }
+}
+
+impl<K, V: Clone> Listener<K, V> {
+    fn notify(&self, removed: Vec<Removed<K, V>>) {
+        if let Some(listener) = &self.0 {
+            for (key, value, cause) in removed {
+                listener(
+                    key,
+                    Arc::try_unwrap(value).unwrap_or_else(|arc| (*arc).clone()),
+                    cause,
+                );
+            }
+        }
+    }
+}
+
+/// Runs expiry sweeps, refresh-ahead loads and eviction listener calls on a thread pool instead
+/// of the caller threads.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+struct Maintenance<K, V> {
+    pool: PoolHandle,
+    last_sweep: Mutex<Instant>,
+    /// Whether a sweep job is queued or running.
+    sweeping: Arc<AtomicBool>,
+    /// Keys whose refresh job is queued or running.
+    refreshing: Arc<Mutex<HashSet<K>>>,
+
+    // Submitting a job to the pool needs `K, V: Send + 'static`, which most of the cache doesn't
+    // require. So the job launchers are instantiated in `CacheBuilder::maintenance_pool`, where
+    // the bounds are known to hold.
+    spawn_sweep: fn(&Cache<K, V>),
+    spawn_notify: fn(&Cache<K, V>),
+    spawn_refresh: fn(&Cache<K, V>, K, u64),
+}
+
+#[cfg(feature = "std")]
+impl<K, V> Maintenance<K, V> {
+    /// Runs `job` on the pool, or on this thread once the pool is dropped.
+    fn execute<F: FnOnce() + Send + 'static>(&self, job: F) {
+        // The pool drops the job it turns away, so it is taken back from here.
+        let job = Arc::new(Mutex::new(Some(job)));
+        let queued = Arc::clone(&job);
+        let submitted = self.pool.execute(move || {
+            let job = queued.lock().unwrap().take();
+            if let Some(job) = job {
+                job();
+            }
+        });
+        if submitted.is_err() {
+            let job = job.lock().unwrap().take();
+            if let Some(job) = job {
+                job();
+            }
+        }
+    }
+}
+
+/// Builder for a [`Cache`]. Create one with [`Cache::builder`].
+#[derive(Debug)]
+pub struct CacheBuilder<K, V> {
+    config: CacheConfig,
+    metrics: Metrics,
+    loader: Loader<K, V>,
+    listener: Listener<K, V>,
+    #[cfg(feature = "std")]
+    maintenance: Option<Maintenance<K, V>>,
+}
+
+impl<K, V> CacheBuilder<K, V> {
+    /// Starts from an existing configuration, e.g. one read from a config file.
+    pub fn from_config(config: CacheConfig) -> Self {
+        Self {
+            config,
+            metrics: Metrics::default(),
+            loader: Loader::default(),
+            listener: Listener::default(),
+            #[cfg(feature = "std")]
+            maintenance: None,
+        }
+    }
+
+    /// Bounds the number of entries.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.config.capacity = Some(capacity);
+        self
+    }
+
+    /// Expires entries `ttl` after they are written.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.config.ttl = Some(ttl);
+        self
+    }
+
+    /// Randomly moves each entry's expiry by up to `jitter * ttl` in either direction.
+    pub fn ttl_jitter(mut self, jitter: f64) -> Self {
+        self.config.ttl_jitter = Some(jitter);
+        self
+    }
+
+    /// Reports hits, misses, evictions and load durations to `sink`.
+    pub fn metrics_sink(mut self, sink: Arc<dyn MetricsSink>) -> Self {
+        self.metrics = Metrics(Some(sink));
+        self
+    }
+
+    /// Bakes in the function that computes the value of a missing key, see [`Cache::get`].
+    pub fn loader<F>(mut self, f: F) -> Self
+    where
+        F: Fn(K) -> V + Send + Sync + 'static,
+    {
+        self.loader = Loader(Some(Arc::new(f)));
+        self
+    }
+
+    /// Calls `f` with every entry that expires or is evicted. Explicit invalidations are not
+    /// reported.
+    ///
+    /// The listener runs on the maintenance pool if there is one, and on the thread whose
+    /// operation removed the entry otherwise (after the cache's locks are released).
+    pub fn eviction_listener<F>(mut self, f: F) -> Self
+    where
+        F: Fn(K, V, RemovalCause) + Send + Sync + 'static,
+    {
+        self.listener = Listener(Some(Arc::new(f)));
+        self
+    }
+
+    /// Reloads an entry on the maintenance pool when it is hit after `refresh_ahead * ttl`.
+    pub fn refresh_ahead(mut self, refresh_ahead: f64) -> Self {
+        self.config.refresh_ahead = Some(refresh_ahead);
+        self
+    }
+
+    /// Caps the number of callers waiting for the same in-flight load.
+    pub fn max_waiters(mut self, max_waiters: usize) -> Self {
+        self.config.max_waiters = Some(max_waiters);
+        self
+    }
+
+    /// Validates the configuration and creates the cache.
+    pub fn build(self) -> Result<Cache<K, V>, CacheConfigError> {
+        self.config.validate()?;
+        #[cfg(feature = "std")]
+        if self.config.sweep_interval.is_some() && self.maintenance.is_none() {
+            return Err(CacheConfigError::SweepWithoutPool);
+        }
+        if self.config.refresh_ahead.is_some() && self.loader.0.is_none() {
+            return Err(CacheConfigError::RefreshWithoutLoader);
+        }
+        #[cfg(feature = "std")]
+        if self.config.refresh_ahead.is_some() && self.maintenance.is_none() {
+            return Err(CacheConfigError::RefreshWithoutPool);
+        }
+        Ok(Cache {
+            inner: Arc::new(Mutex::new(HashMap::new())),
+            called_map: Mutex::new(HashMap::new()),
+            loaded: Condvar::new(),
+            pinned: Mutex::new(HashSet::new()),
+            next_version: Arc::new(AtomicU64::new(1)),
+            clock: AtomicU64::new(0),
+            config: self.config,
+            metrics: self.metrics,
+            loader: self.loader,
+            listener: self.listener,
+            removed: Mutex::new(Vec::new()),
+            #[cfg(feature = "std")]
+            maintenance: self.maintenance,
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V> CacheBuilder<K, V>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Runs expiry sweeps (every `sweep_interval`, or every `ttl` by default), refresh-ahead loads
+    /// (see [`CacheBuilder::refresh_ahead`]) and eviction listener calls on `pool`, so that they
+    /// don't add latency to the callers. Once the pool is dropped, they run on the callers again.
+    pub fn maintenance_pool(mut self, pool: PoolHandle) -> Self {
+        self.maintenance = Some(Maintenance {
+            pool,
+            last_sweep: Mutex::new(Instant::now()),
+            sweeping: Arc::new(AtomicBool::new(false)),
+            refreshing: Arc::new(Mutex::new(HashSet::new())),
+            spawn_sweep: Cache::spawn_sweep,
+            spawn_notify: Cache::spawn_notify,
+            spawn_refresh: Cache::spawn_refresh,
+        });
+        self
+    }
+}
+
+impl<K, V> Default for CacheBuilder<K, V> {
+    fn default() -> Self {
+        Self::from_config(CacheConfig::default())
+    }
+}
 
 /// Cache that remembers the result for each key.
 #[derive(Debug)]
 pub struct Cache<K, V> {
-    inner: Mutex<HashMap<K, Arc<V>>>,
-    called_map: Mutex<HashMap<K, bool>>,
+    inner: Arc<Mutex<HashMap<K, Slot<V>>>>,
+    called_map: Mutex<HashMap<K, InFlight>>,
+    /// Signaled with `called_map` whenever a load finishes.
+    loaded: Condvar,
+    /// Keys that capacity eviction must skip. Lock after `inner` when both are needed.
+    pinned: Mutex<HashSet<K>>,
+
+    /// Source of entry versions. Every write takes a fresh one, so a key's version only grows.
+    next_version: Arc<AtomicU64>,
+    /// Source of recency ticks for the LRU order.
+    clock: AtomicU64,
+    config: CacheConfig,
+    metrics: Metrics,
+    loader: Loader<K, V>,
+    listener: Listener<K, V>,
+    /// Entries removed by the current operations, to be reported once the locks are released.
+    removed: Mutex<Vec<Removed<K, V>>>,
+    #[cfg(feature = "std")]
+    maintenance: Option<Maintenance<K, V>>,
 }
 
 impl<K, V> Default for Cache<K, V> {
     fn default() -> Self {
-        Self {
-            inner: Mutex::new(HashMap::new()),
-            called_map: Mutex::new(HashMap::new()),
+        CacheBuilder::default()
+            .build()
+            .expect("default configuration is valid")
+    }
+}
+
+impl<K, V> Cache<K, V> {
+    /// Version reported for keys that are not in the cache.
+    pub const ABSENT: u64 = 0;
+
+    /// Returns a builder to configure a new cache.
+    pub fn builder() -> CacheBuilder<K, V> {
+        CacheBuilder::default()
+    }
+
+    /// Creates a cache that computes missing values with `f`, so that every call site of
+    /// [`Cache::get`] loads a key the same way.
+    pub fn with_loader<F>(f: F) -> Self
+    where
+        F: Fn(K) -> V + Send + Sync + 'static,
+    {
+        CacheBuilder::default()
+            .loader(f)
+            .build()
+            .expect("default configuration is valid")
+    }
+
+    /// Returns the configuration the cache was built with.
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn new_slot(&self, value: Arc<V>) -> Slot<V> {
+        let ttl = self.entry_ttl();
+        let now = Instant::now();
+        Slot {
+            value,
+            version: self.next_version.fetch_add(1, Ordering::Relaxed),
+            last_used: self.tick(),
+            expires_at: ttl.map(|ttl| now + ttl),
+            #[cfg(feature = "std")]
+            refresh_at: self.refresh_after(ttl).map(|after| now + after),
         }
     }
+
+    /// How long after it is written an entry with `ttl` is refreshed.
+    #[cfg(feature = "std")]
+    fn refresh_after(&self, ttl: Option<Duration>) -> Option<Duration> {
+        Some(ttl?.mul_f64(self.config.refresh_ahead?))
+    }
+
+    /// TTL for a new entry, with jitter applied.
+    #[cfg(feature = "std")]
+    fn entry_ttl(&self) -> Option<Duration> {
+        let ttl = self.config.ttl?;
+        Some(match self.config.ttl_jitter {
+            Some(jitter) if jitter > 0.0 => {
+                ttl.mul_f64(1.0 + rand::thread_rng().gen_range(-jitter..=jitter))
+            }
+            _ => ttl,
+        })
+    }
+
+    /// Entries don't expire without std, see [`CacheConfigError::TtlWithoutClock`].
+    #[cfg(not(feature = "std"))]
+    fn entry_ttl(&self) -> Option<Duration> {
+        None
+    }
 }
 
 impl<K: Eq + Hash + Clone, V: Clone> Cache<K, V> {
@@ -34,46 +669,714 @@ impl<K: Eq + Hash + Clone, V: Clone> Cache<K, V> {
     /// On the other hand, since `f` may consume a lot of resource (= money), it's undesirable to
     /// duplicate the work. That is, `f` should be run only once for each key. Specifically, even
     /// for concurrent invocations of `get_or_insert_with(key, f)`, `f` is called only once per key.
+    /// Once the entry expires or is evicted, the next invocation loads it again.
     ///
-    /// Hint: the [`Entry`] API may be useful in implementing this function.
+    /// If another writer stores a value with [`insert_if_version`] while `f` is running, that value
+    /// wins and is returned instead of the result of `f`.
     ///
-    /// [`Entry`]: https://doc.rust-lang.org/stable/std/collections/hash_map/struct.HashMap.html#method.entry
+    /// If [`CacheConfig::max_waiters`] callers already wait for the key, `f` is run right away
+    /// instead, without caching its result.
+    ///
+    /// [`insert_if_version`]: Cache::insert_if_version
     pub fn get_or_insert_with<F: FnOnce(K) -> V>(&self, key: K, f: F) -> V {
-        // implementation
-        let mut inner = self.inner.lock().unwrap();
+        let value = self.get_or_load(key, |key| Ok(f(key)), false);
+        self.maintain();
+        value.expect("neither rejects nor fails")
+    }
+
+    /// Like [`get_or_insert_with`], but fails with [`CacheError::Overloaded`] instead of waiting
+    /// if [`CacheConfig::max_waiters`] callers already wait for the key.
+    ///
+    /// [`get_or_insert_with`]: Cache::get_or_insert_with
+    pub fn try_get_or_insert_with<F: FnOnce(K) -> V>(&self, key: K, f: F) -> Result<V, CacheError> {
+        let value = self.get_or_load(key, |key| Ok(f(key)), true);
+        self.maintain();
+        value
+    }
+
+    /// Like [`get_or_insert_with`], but `f` may fail, e.g. when the value is fetched over the
+    /// network. A failure isn't cached: the caller that ran `f` gets it as [`CacheError::Load`],
+    /// and the callers waiting for the key load it again themselves.
+    ///
+    /// [`get_or_insert_with`]: Cache::get_or_insert_with
+    pub fn get_or_try_insert_with<F, E>(&self, key: K, f: F) -> Result<V, CacheError>
+    where
+        F: FnOnce(K) -> Result<V, E>,
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        let value = self.get_or_load(
+            key,
+            |key| f(key).map_err(|err| CacheError::Load(err.into())),
+            false,
+        );
+        self.maintain();
+        value
+    }
+
+    /// Looks up `key` and loads it with `f` on a miss. If too many callers wait for the key,
+    /// returns [`CacheError::Overloaded`] if `reject` is set and the result of `f` otherwise.
+    fn get_or_load<F>(&self, key: K, f: F, reject: bool) -> Result<V, CacheError>
+    where
+        F: FnOnce(K) -> Result<V, CacheError>,
+    {
+        let mut missed = false;
+        loop {
+            let mut inner = self.inner.lock().unwrap();
 
-        match inner.entry(key.clone()) {
             // cache hit
-            Entry::Occupied(entry) => {
-                let arc_value = entry.get().clone();
-                Arc::try_unwrap(arc_value).unwrap_or_else(|arc| (*arc).clone())
+            if let Some(slot) = self.lookup(&mut inner, &key) {
+                if !missed {
+                    self.metrics.counter("cache_hits", 1);
+                    event!(TRACE, "cache hit");
+                }
+                let value = (*slot.value).clone();
+                #[cfg(feature = "std")]
+                if slot.refresh_at.is_some_and(|at| at <= Instant::now()) {
+                    let version = slot.version;
+                    drop(inner);
+                    self.schedule_refresh(key, version);
+                }
+                return Ok(value);
             }
+
             // cache miss
-            Entry::Vacant(entry) => {
-                let mut called = self.called_map.lock().unwrap();
-                if called.contains_key(&key) {
-                    // f is already called for key
+            if !missed {
+                self.metrics.counter("cache_misses", 1);
+                event!(TRACE, "cache miss");
+                missed = true;
+            }
+            let mut called = self.called_map.lock().unwrap();
+            if let Some(in_flight) = called.get_mut(&key) {
+                // f is already called for key, so wait until its result shows up.
+                if self
+                    .config
+                    .max_waiters
+                    .is_some_and(|max| in_flight.waiters >= max)
+                {
                     drop(called);
                     drop(inner);
-                    loop {
-                        let mut inner = self.inner.lock().unwrap();
-                        if inner.contains_key(&key) {
-                            let arc_value = inner.get(&key).unwrap_or_else(|| panic!()).clone();
-                            return Arc::try_unwrap(arc_value).unwrap_or_else(|arc| (*arc).clone());
-                        }
-                    }
+                    self.metrics.counter("cache_overloads", 1);
+                    event!(
+                        DEBUG,
+                        reject,
+                        "cache overloaded, too many callers are waiting"
+                    );
+                    return if reject {
+                        Err(CacheError::Overloaded)
+                    } else {
+                        f(key)
+                    };
                 }
-                called.insert(key.clone(), true);
-                drop(called);
+                in_flight.waiters += 1;
+                let id = in_flight.id;
                 drop(inner);
+                event!(TRACE, "waiting for the load in flight");
+
+                called = self.loaded.wait(called).unwrap();
+                if let Some(in_flight) = called.get_mut(&key).filter(|in_flight| in_flight.id == id)
+                {
+                    // Woken up for another key; we'll register again.
+                    in_flight.waiters -= 1;
+                }
+                continue;
+            }
+            let _ = called.insert(
+                key.clone(),
+                InFlight {
+                    id: self.tick(),
+                    waiters: 0,
+                },
+            );
+            drop(called);
+            drop(inner);
+
+            // Withdraws the load even if `f` fails or panics, so that the waiters don't wait for
+            // a value that never comes.
+            let load = Load {
+                cache: self,
+                key: &key,
+                done: false,
+            };
+            span!(DEBUG, "cache load");
+            let started = Instant::now();
+            let value = f(key.clone());
+            let seconds = started.elapsed().as_secs_f64();
+            self.metrics.histogram("cache_load_seconds", seconds);
+            event!(DEBUG, seconds, ok = value.is_ok(), "cache loaded");
+            let value = value?;
+
+            // Don't clobber a value that an external writer stored while `f` was running.
+            inner = self.inner.lock().unwrap();
+            let arc_value = match self.lookup(&mut inner, &key) {
+                Some(slot) => slot.value.clone(),
+                None => {
+                    let slot = self.new_slot(Arc::new(value));
+                    let arc_value = slot.value.clone();
+                    self.store(&mut inner, key.clone(), slot);
+                    arc_value
+                }
+            };
+            load.finish();
+            return Ok(Arc::try_unwrap(arc_value).unwrap_or_else(|arc| (*arc).clone()));
+        }
+    }
+
+    /// Retrieve the value or insert a new one created by the cache's loader. Like
+    /// [`get_or_insert_with`], the loader runs only once for concurrent calls with the same key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cache was built without a loader.
+    ///
+    /// [`get_or_insert_with`]: Cache::get_or_insert_with
+    pub fn get(&self, key: K) -> V {
+        let loader = self
+            .loader
+            .0
+            .as_ref()
+            .expect("`Cache::get` requires a loader, see `Cache::with_loader`");
+        self.get_or_insert_with(key, |key| loader(key))
+    }
+
+    /// Like [`get`], but fails with [`CacheError::Overloaded`] instead of waiting if
+    /// [`CacheConfig::max_waiters`] callers already wait for the key, and with
+    /// [`CacheError::NoLoader`] instead of panicking if the cache was built without a loader.
+    ///
+    /// [`get`]: Cache::get
+    pub fn try_get(&self, key: K) -> Result<V, CacheError> {
+        let loader = self.loader.0.as_ref().ok_or(CacheError::NoLoader)?;
+        self.try_get_or_insert_with(key, |key| loader(key))
+    }
+
+    /// Retrieves the values of all `keys` as one consistent snapshot, loading the missing ones
+    /// with `f`.
+    ///
+    /// Missing keys are loaded as in [`get_or_insert_with`], so concurrent loads of the same key
+    /// are still coordinated. Then all values are read under a single acquisition of the cache's
+    /// lock, so no concurrent write (e.g. [`insert_if_version`]) lands in between: the result
+    /// never mixes values from before and after a write. If an entry expires or is evicted before
+    /// the snapshot is taken, it is loaded again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keys` has more distinct keys than the cache's capacity, as they could never be
+    /// cached all at once.
+    ///
+    /// [`get_or_insert_with`]: Cache::get_or_insert_with
+    /// [`insert_if_version`]: Cache::insert_if_version
+    pub fn get_all_or_insert_with<F: Fn(K) -> V>(&self, keys: &[K], f: F) -> Vec<V> {
+        if let Some(capacity) = self.config.capacity {
+            assert!(keys.iter().collect::<HashSet<_>>().len() <= capacity);
+        }
 
-                let value = f(key.clone());
-                let arc_value = Arc::new(value.clone());
+        let mut missing = keys.iter().collect::<Vec<_>>();
+        loop {
+            for key in missing {
+                let _ = self.get_or_load(key.clone(), |key| Ok(f(key)), false);
+            }
+
+            let mut inner = self.inner.lock().unwrap();
+            let mut values = Vec::with_capacity(keys.len());
+            missing = Vec::new();
+            for key in keys {
+                match self.lookup(&mut inner, key) {
+                    Some(slot) => values.push(slot.value.clone()),
+                    None => missing.push(key),
+                }
+            }
+            drop(inner);
 
-                inner = self.inner.lock().unwrap();
-                inner.insert(key, arc_value);
-                value
+            if missing.is_empty() {
+                self.maintain();
+                return values.into_iter().map(|arc| (*arc).clone()).collect();
             }
         }
     }
+
+    /// Like [`get_all_or_insert_with`], but loads the missing keys with the cache's loader.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cache was built without a loader, or if `keys` has more distinct keys than
+    /// the cache's capacity.
+    ///
+    /// [`get_all_or_insert_with`]: Cache::get_all_or_insert_with
+    pub fn get_all(&self, keys: &[K]) -> Vec<V> {
+        let loader = self
+            .loader
+            .0
+            .as_ref()
+            .expect("`Cache::get_all` requires a loader, see `Cache::with_loader`");
+        self.get_all_or_insert_with(keys, |key| loader(key))
+    }
+
+    /// Returns the cached value for `key` along with its version, or `None` if it is not cached.
+    ///
+    /// Pass the version to [`insert_if_version`] to update the entry only if nobody else has
+    /// written it in the meantime.
+    ///
+    /// [`insert_if_version`]: Cache::insert_if_version
+    pub fn get_with_version(&self, key: &K) -> Option<(V, u64)> {
+        let mut inner = self.inner.lock().unwrap();
+        let found = self
+            .lookup(&mut inner, key)
+            .map(|slot| ((*slot.value).clone(), slot.version));
+        let name = if found.is_some() {
+            "cache_hits"
+        } else {
+            "cache_misses"
+        };
+        self.metrics.counter(name, 1);
+        drop(inner);
+        self.maintain();
+        found
+    }
+
+    /// Stores `value` for `key` only if the entry is still at `expected_version`.
+    ///
+    /// Use [`Cache::ABSENT`] as `expected_version` to insert only if the key is not cached yet. On
+    /// success, returns the version of the new entry. Otherwise, returns the current version (which
+    /// may be [`Cache::ABSENT`]) and leaves the cache untouched, so a newer concurrent refresh is
+    /// never clobbered by a stale write.
+    pub fn insert_if_version(&self, key: K, expected_version: u64, value: V) -> Result<u64, u64> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let current = self
+            .lookup(&mut inner, &key)
+            .map_or(Self::ABSENT, |slot| slot.version);
+        if current != expected_version {
+            return Err(current);
+        }
+
+        let slot = self.new_slot(Arc::new(value));
+        let version = slot.version;
+        self.store(&mut inner, key, slot);
+        drop(inner);
+        self.maintain();
+        Ok(version)
+    }
+
+    /// Removes `key` from the cache, returning its value if it was cached.
+    pub fn invalidate(&self, key: &K) -> Option<V> {
+        let mut inner = self.inner.lock().unwrap();
+        let slot = inner.remove(key)?;
+        self.metrics.gauge("cache_entries", inner.len() as f64);
+        (!slot.is_expired(Instant::now())).then(|| (*slot.value).clone())
+    }
+
+    /// Protects `key` from capacity eviction, even if it is not cached yet or gets reloaded later.
+    /// The entry can still expire or be invalidated.
+    ///
+    /// If every cached entry is pinned, the cache grows beyond its capacity rather than evicting
+    /// one of them.
+    pub fn pin(&self, key: K) {
+        let _ = self.pinned.lock().unwrap().insert(key);
+    }
+
+    /// Makes `key` evictable again. Returns whether it was pinned.
+    pub fn unpin(&self, key: &K) -> bool {
+        self.pinned.lock().unwrap().remove(key)
+    }
+
+    /// Returns whether `key` is pinned.
+    pub fn is_pinned(&self, key: &K) -> bool {
+        self.pinned.lock().unwrap().contains(key)
+    }
+
+    /// Returns the least recently used unpinned entry, i.e. the next victim of capacity eviction,
+    /// without refreshing its recency.
+    pub fn peek_lru(&self) -> Option<(K, V)> {
+        let inner = self.inner.lock().unwrap();
+        let pinned = self.pinned.lock().unwrap();
+        let now = Instant::now();
+        inner
+            .iter()
+            .filter(|(key, slot)| !slot.is_expired(now) && !pinned.contains(*key))
+            .min_by_key(|(_, slot)| slot.last_used)
+            .map(|(key, slot)| (key.clone(), (*slot.value).clone()))
+    }
+
+    /// Returns a snapshot of the live unpinned entries ordered from the least to the most recently
+    /// used, without refreshing their recency.
+    ///
+    /// Together with [`invalidate`], this lets a maintenance job evict the `n` coldest entries:
+    /// `cache.iter_by_recency().take(n)`.
+    ///
+    /// [`invalidate`]: Cache::invalidate
+    pub fn iter_by_recency(&self) -> impl Iterator<Item = (K, V)> {
+        let inner = self.inner.lock().unwrap();
+        let pinned = self.pinned.lock().unwrap();
+        let now = Instant::now();
+        let mut entries = inner
+            .iter()
+            .filter(|(key, slot)| !slot.is_expired(now) && !pinned.contains(*key))
+            .map(|(key, slot)| (slot.last_used, key.clone(), (*slot.value).clone()))
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|(last_used, _, _)| *last_used);
+        entries.into_iter().map(|(_, key, value)| (key, value))
+    }
+
+    /// Returns the live slot for `key` and marks it as most recently used. An expired slot is
+    /// dropped on the way.
+    fn lookup<'a>(&self, inner: &'a mut HashMap<K, Slot<V>>, key: &K) -> Option<&'a Slot<V>> {
+        if inner
+            .get(key)
+            .is_some_and(|slot| slot.is_expired(Instant::now()))
+        {
+            let (key, slot) = inner.remove_entry(key).unwrap();
+            self.record_removal(key, slot, RemovalCause::Expired);
+            self.metrics.counter("cache_expirations", 1);
+            self.metrics.gauge("cache_entries", inner.len() as f64);
+        }
+
+        let slot = inner.get_mut(key)?;
+        slot.last_used = self.tick();
+        Some(slot)
+    }
+
+    /// Inserts `slot`, evicting the least recently used unpinned entries if the cache is full.
+    fn store(&self, inner: &mut HashMap<K, Slot<V>>, key: K, slot: Slot<V>) {
+        if let Some(capacity) = self.config.capacity {
+            if !inner.contains_key(&key) {
+                let expired = drain_expired(inner);
+                if !expired.is_empty() {
+                    self.metrics
+                        .counter("cache_expirations", expired.len() as u64);
+                }
+                for (key, slot) in expired {
+                    self.record_removal(key, slot, RemovalCause::Expired);
+                }
+                let pinned = self.pinned.lock().unwrap();
+                while inner.len() >= capacity {
+                    let Some(lru) = inner
+                        .iter()
+                        .filter(|(key, _)| !pinned.contains(*key))
+                        .min_by_key(|(_, slot)| slot.last_used)
+                        .map(|(key, _)| key.clone())
+                    else {
+                        break;
+                    };
+                    let (key, slot) = inner.remove_entry(&lru).unwrap();
+                    self.record_removal(key, slot, RemovalCause::Evicted);
+                    self.metrics.counter("cache_evictions", 1);
+                }
+            }
+        }
+        let _ = inner.insert(key, slot);
+        self.metrics.gauge("cache_entries", inner.len() as f64);
+    }
+
+    /// Queues a removed entry for the eviction listener, if there is one.
+    fn record_removal(&self, key: K, slot: Slot<V>, cause: RemovalCause) {
+        if self.listener.0.is_some() {
+            self.removed.lock().unwrap().push((key, slot.value, cause));
+        }
+    }
+
+    fn take_removed(&self) -> Vec<Removed<K, V>> {
+        mem::take(&mut *self.removed.lock().unwrap())
+    }
+
+    /// Reports the removed entries to the eviction listener and queues an expiry sweep if one is
+    /// due. Must be called without holding `inner`, since the listener may use the cache.
+    fn maintain(&self) {
+        #[cfg(feature = "std")]
+        if let Some(maintenance) = &self.maintenance {
+            (maintenance.spawn_notify)(self);
+            self.schedule_sweep(maintenance);
+            return;
+        }
+        self.listener.notify(self.take_removed());
+    }
+
+    /// Queues an expiry sweep on the maintenance pool if one is due.
+    #[cfg(feature = "std")]
+    fn schedule_sweep(&self, maintenance: &Maintenance<K, V>) {
+        let Some(interval) = self.config.sweep_interval.or(self.config.ttl) else {
+            return;
+        };
+        // Someone else is already checking.
+        let Ok(mut last_sweep) = maintenance.last_sweep.try_lock() else {
+            return;
+        };
+        if last_sweep.elapsed() < interval || maintenance.sweeping.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        *last_sweep = Instant::now();
+        drop(last_sweep);
+        (maintenance.spawn_sweep)(self);
+    }
+
+    /// Queues a reload of `key` at `version` on the maintenance pool, unless one is queued already.
+    #[cfg(feature = "std")]
+    fn schedule_refresh(&self, key: K, version: u64) {
+        let maintenance = self
+            .maintenance
+            .as_ref()
+            .expect("refresh-ahead requires a maintenance pool");
+        if maintenance.refreshing.lock().unwrap().insert(key.clone()) {
+            (maintenance.spawn_refresh)(self, key, version);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V> Cache<K, V>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Drops the expired entries on the maintenance pool.
+    fn spawn_sweep(&self) {
+        let maintenance = self.maintenance.as_ref().unwrap();
+        let inner = Arc::clone(&self.inner);
+        let metrics = self.metrics.clone();
+        let listener = self.listener.clone();
+        let sweeping = Arc::clone(&maintenance.sweeping);
+        maintenance.execute(move || {
+            let mut map = inner.lock().unwrap();
+            let expired = drain_expired(&mut map);
+            let len = map.len();
+            drop(map);
+
+            if !expired.is_empty() {
+                metrics.counter("cache_expirations", expired.len() as u64);
+                metrics.gauge("cache_entries", len as f64);
+            }
+            listener.notify(
+                expired
+                    .into_iter()
+                    .map(|(key, slot)| (key, slot.value, RemovalCause::Expired))
+                    .collect(),
+            );
+            sweeping.store(false, Ordering::Release);
+        });
+    }
+
+    /// Reports removed entries to the eviction listener on the maintenance pool.
+    fn spawn_notify(&self) {
+        let removed = self.take_removed();
+        if removed.is_empty() {
+            return;
+        }
+        let maintenance = self.maintenance.as_ref().unwrap();
+        let listener = self.listener.clone();
+        maintenance.execute(move || listener.notify(removed));
+    }
+
+    /// Reloads `key` on the maintenance pool, and replaces its entry if it is still at `version`.
+    fn spawn_refresh(&self, key: K, version: u64) {
+        let maintenance = self.maintenance.as_ref().unwrap();
+        let inner = Arc::clone(&self.inner);
+        let next_version = Arc::clone(&self.next_version);
+        let refreshing = Arc::clone(&maintenance.refreshing);
+        let loader = self.loader.clone();
+        let metrics = self.metrics.clone();
+        // The deadlines are drawn now, since the job can't reach the cache's configuration.
+        let ttl = self.entry_ttl();
+        let refresh_after = self.refresh_after(ttl);
+        maintenance.execute(move || {
+            let loader = loader.0.as_ref().expect("refresh-ahead requires a loader");
+            let started = Instant::now();
+            let loaded = panic::catch_unwind(AssertUnwindSafe(|| loader(key.clone())));
+            metrics.histogram("cache_load_seconds", started.elapsed().as_secs_f64());
+            let panicked = match loaded {
+                Ok(value) => {
+                    let mut map = inner.lock().unwrap();
+                    // Written, invalidated or expired meanwhile: the refresh is stale.
+                    if let Some(slot) = map.get_mut(&key).filter(|slot| slot.version == version) {
+                        let now = Instant::now();
+                        slot.value = Arc::new(value);
+                        slot.version = next_version.fetch_add(1, Ordering::Relaxed);
+                        slot.expires_at = ttl.map(|ttl| now + ttl);
+                        slot.refresh_at = refresh_after.map(|after| now + after);
+                        metrics.counter("cache_refreshes", 1);
+                    }
+                    None
+                }
+                Err(payload) => Some(payload),
+            };
+            // Withdrawn even if the loader panicked, so that a later hit tries again.
+            let _ = refreshing.lock().unwrap().remove(&key);
+            if let Some(payload) = panicked {
+                metrics.counter("cache_load_failures", 1);
+                panic::resume_unwind(payload);
+            }
+        });
+    }
+}
+
+/// A load of a key in flight, withdrawn from `called_map` when dropped, waking the callers waiting
+/// for it.
+struct Load<'a, K: Eq + Hash, V> {
+    cache: &'a Cache<K, V>,
+    key: &'a K,
+    /// Whether the value was stored. Otherwise the load failed or panicked.
+    done: bool,
+}
+
+impl<K: Eq + Hash, V> Load<'_, K, V> {
+    /// Withdraws the load once its value is stored.
+    fn finish(mut self) {
+        self.done = true;
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for Load<'_, K, V> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.cache.metrics.counter("cache_load_failures", 1);
+            event!(WARN, "cache load failed, waking the waiters to load again");
+        }
+        let _ = self.cache.called_map.lock().unwrap().remove(self.key);
+        self.cache.loaded.notify_all();
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::super::ThreadPool;
+    use super::{Cache, CacheConfigError};
+
+    #[test]
+    fn refresh_ahead_reloads_hot_entries_in_the_background() {
+        let pool = ThreadPool::new(1);
+        let loads = Arc::new(AtomicUsize::new(0));
+        let cache = Cache::builder()
+            .ttl(Duration::from_secs(1))
+            .refresh_ahead(0.5)
+            .loader({
+                let loads = loads.clone();
+                move |key: usize| key * 10 + loads.fetch_add(1, Ordering::Relaxed)
+            })
+            .maintenance_pool(pool.handle())
+            .build()
+            .unwrap();
+        assert_eq!(cache.get(1), 10);
+
+        // Past the refresh point, a hit still gets the current value and reloads it meanwhile.
+        thread::sleep(Duration::from_millis(600));
+        assert_eq!(cache.get(1), 10);
+        pool.join();
+        assert_eq!(loads.load(Ordering::Relaxed), 2);
+        assert_eq!(cache.get(1), 11);
+
+        // Past the first value's expiry, the refreshed one is still live.
+        thread::sleep(Duration::from_millis(600));
+        assert_eq!(cache.get(1), 11);
+    }
+
+    #[test]
+    fn maintenance_runs_on_the_callers_once_the_pool_is_dropped() {
+        let pool = ThreadPool::new(1);
+        let evicted = Arc::new(AtomicUsize::new(0));
+        let cache = Cache::builder()
+            .capacity(1)
+            .eviction_listener({
+                let evicted = evicted.clone();
+                move |_: usize, _: usize, _| {
+                    let _ = evicted.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .maintenance_pool(pool.handle())
+            .build()
+            .unwrap();
+        drop(pool);
+        let _ = cache.get_or_insert_with(1, |key| key);
+        let _ = cache.get_or_insert_with(2, |key| key);
+        assert_eq!(evicted.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn refresh_ahead_requires_a_loader_and_a_pool() {
+        let pool = ThreadPool::new(1);
+        let without_loader = Cache::<usize, usize>::builder()
+            .ttl(Duration::from_secs(1))
+            .refresh_ahead(0.5)
+            .maintenance_pool(pool.handle())
+            .build();
+        assert_eq!(
+            without_loader.unwrap_err(),
+            CacheConfigError::RefreshWithoutLoader
+        );
+        let without_pool = Cache::builder()
+            .ttl(Duration::from_secs(1))
+            .refresh_ahead(0.5)
+            .loader(|key: usize| key)
+            .build();
+        assert_eq!(
+            without_pool.unwrap_err(),
+            CacheConfigError::RefreshWithoutPool
+        );
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::sync::atomic::{AtomicUsize, Ordering};
+    use loom::thread;
+    use std::sync::Arc;
+
+    use super::Cache;
+
+    #[test]
+    fn concurrent_misses_load_once() {
+        loom::model(|| {
+            let cache = Arc::new(Cache::default());
+            let loads = Arc::new(AtomicUsize::new(0));
+            let threads = (0..2)
+                .map(|_| {
+                    let cache = cache.clone();
+                    let loads = loads.clone();
+                    thread::spawn(move || {
+                        cache.get_or_insert_with(1, |key| {
+                            let _ = loads.fetch_add(1, Ordering::Relaxed);
+                            key * 10
+                        })
+                    })
+                })
+                .collect::<Vec<_>>();
+            for thread in threads {
+                assert_eq!(thread.join().unwrap(), 10);
+            }
+            assert_eq!(loads.load(Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn failed_load_is_retried_by_a_waiter() {
+        loom::model(|| {
+            let cache = Arc::new(Cache::default());
+            let loads = Arc::new(AtomicUsize::new(0));
+            let threads = (0..2)
+                .map(|_| {
+                    let cache = cache.clone();
+                    let loads = loads.clone();
+                    thread::spawn(move || {
+                        cache.get_or_try_insert_with(1, |key| {
+                            // The first load fails, and the next one succeeds.
+                            if loads.fetch_add(1, Ordering::Relaxed) == 0 {
+                                Err("unavailable")
+                            } else {
+                                Ok(key * 10)
+                            }
+                        })
+                    })
+                })
+                .collect::<Vec<_>>();
+            let loaded = threads
+                .into_iter()
+                .filter_map(|thread| thread.join().unwrap().ok())
+                .collect::<Vec<_>>();
+            // The failing caller gets the error. The other one either waited for it and loaded
+            // again, or came after and loaded.
+            assert_eq!(loaded, [10]);
+            assert_eq!(loads.load(Ordering::Relaxed), 2);
+        });
+    }
 }
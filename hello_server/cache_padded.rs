@@ -0,0 +1,18 @@
+//! Padding values to their own cache line.
+
+use core::ops::Deref;
+
+/// Aligns a value to its own cache line, so that writes to it don't invalidate the line of its
+/// neighbors in other cores' caches (false sharing). 128 bytes since x86-64 and Apple silicon
+/// fetch lines in pairs.
+#[derive(Debug, Default)]
+#[repr(align(128))]
+pub(crate) struct CachePadded<T>(pub(crate) T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
@@ -0,0 +1,216 @@
+//! Response compression.
+
+use flate2::write::{DeflateEncoder, GzEncoder};
+use std::io::{self, Write};
+
+use super::http::{Request, Response};
+use super::middleware::{Middleware, Next};
+use super::sse::EVENT_STREAM_MEDIA_TYPE;
+
+/// Content coding applied to a response body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    /// Picks the encoding to use for a request's `Accept-Encoding` header, preferring gzip.
+    fn negotiate(accept_encoding: &str) -> Option<Self> {
+        let mut gzip = None;
+        let mut deflate = None;
+        let mut any = None;
+        for item in accept_encoding.split(',') {
+            let mut params = item.split(';');
+            let coding = params.next().unwrap_or_default().trim();
+            let accepted = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .all(|q| q.trim().parse::<f32>().is_ok_and(|q| q > 0.0));
+            match coding.to_ascii_lowercase().as_str() {
+                "gzip" | "x-gzip" => gzip = Some(accepted),
+                "deflate" => deflate = Some(accepted),
+                "*" => any = Some(accepted),
+                _ => {}
+            }
+        }
+
+        if gzip.or(any) == Some(true) {
+            Some(Self::Gzip)
+        } else if deflate.or(any) == Some(true) {
+            Some(Self::Deflate)
+        } else {
+            None
+        }
+    }
+
+    fn encode(self, bytes: &[u8], level: flate2::Compression) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), level);
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+            Self::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), level);
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Middleware compressing response bodies with gzip or deflate, as accepted by the client's
+/// `Accept-Encoding` header.
+///
+/// Only bodies of at least [`Compression::threshold`] bytes (or of unknown length) whose `Content-Type` is allowed are
+/// compressed. Responses with such a type get `Vary: Accept-Encoding` whether or not they are
+/// compressed, so that caches keep the variants apart.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    threshold: u64,
+    content_types: Vec<String>,
+    level: flate2::Compression,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            threshold: 1024,
+            content_types: [
+                "text/",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "application/wasm",
+                "image/svg+xml",
+            ]
+            .map(String::from)
+            .to_vec(),
+            level: flate2::Compression::default(),
+        }
+    }
+}
+
+impl Compression {
+    /// Creates the middleware compressing text, JSON, JavaScript, XML, WebAssembly, and SVG bodies
+    /// of at least 1 KiB.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the minimum body length in bytes to compress.
+    pub fn threshold(mut self, threshold: u64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Replaces the allowed content types. A type ending with `/`, e.g. `text/`, allows all of its
+    /// subtypes.
+    pub fn content_types<I, S>(mut self, content_types: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.content_types = content_types.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the compression level from 0 (none) to 9 (best).
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = flate2::Compression::new(level);
+        self
+    }
+
+    /// Whether bodies of `content_type`, e.g. `text/html; charset=utf-8`, may be compressed.
+    fn is_compressible(&self, content_type: &str) -> bool {
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        // Events must reach the client as they are written, not once the stream ends.
+        if mime == EVENT_STREAM_MEDIA_TYPE {
+            return false;
+        }
+        self.content_types.iter().any(|allowed| {
+            if allowed.ends_with('/') {
+                mime.starts_with(allowed.as_str())
+            } else {
+                mime == *allowed
+            }
+        })
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        let encoding = request
+            .header("Accept-Encoding")
+            .and_then(Encoding::negotiate);
+        let mut response = next.run(request);
+
+        if !response
+            .header("Content-Type")
+            .is_some_and(|content_type| self.is_compressible(content_type))
+        {
+            return response;
+        }
+        add_vary(&mut response, "Accept-Encoding");
+
+        let Some(encoding) = encoding else {
+            return response;
+        };
+        // A partial body can't be compressed on its own.
+        if response.header("Content-Encoding").is_some()
+            || response.header("Content-Range").is_some()
+            || matches!(response.status, 100..=199 | 204 | 304)
+            || response.body.len().is_some_and(|len| len < self.threshold)
+        {
+            return response;
+        }
+
+        let body = std::mem::take(&mut response.body);
+        let compressed = body
+            .into_bytes()
+            .and_then(|bytes| encoding.encode(&bytes, self.level));
+        match compressed {
+            Ok(compressed) => response
+                .with_header("Content-Encoding", encoding.name())
+                .with_body(compressed),
+            Err(err) => {
+                println!("[compression] failed to compress response: {err}");
+                Response::status_page(500)
+            }
+        }
+    }
+}
+
+/// Adds `field` to the `Vary` header of `response` unless it is already there.
+fn add_vary(response: &mut Response, field: &str) {
+    match response
+        .headers
+        .iter_mut()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Vary"))
+    {
+        Some((_, value)) => {
+            let present = value
+                .split(',')
+                .any(|v| v.trim() == "*" || v.trim().eq_ignore_ascii_case(field));
+            if !present {
+                value.push_str(", ");
+                value.push_str(field);
+            }
+        }
+        None => response
+            .headers
+            .push(("Vary".to_string(), field.to_string())),
+    }
+}
@@ -0,0 +1,7 @@
+//! Concurrent collections.
+
+mod hash_map;
+mod skip_list;
+
+pub use hash_map::HashMap;
+pub use skip_list::SkipListMap;
@@ -0,0 +1,164 @@
+//! Hash map with lock striping.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap as StdHashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread;
+
+use super::super::cache_padded::CachePadded;
+
+type Shard<K, V> = CachePadded<RwLock<StdHashMap<K, V>>>;
+
+/// A hash map split in shards, each behind its own lock, so that threads accessing keys in
+/// different shards don't wait for each other, unlike with a single `Mutex<HashMap<K, V>>`.
+pub struct HashMap<K, V> {
+    /// A power of two of them, so that the shard of a hash is found with a shift.
+    shards: Box<[Shard<K, V>]>,
+    hasher: RandomState,
+}
+
+impl<K, V> fmt::Debug for HashMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HashMap")
+            .field("shards", &self.shards.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K: Eq + Hash, V> Default for HashMap<K, V> {
+    fn default() -> Self {
+        // Four times as many shards as cores, so that threads rarely contend on one.
+        let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+        Self::with_shards(cores * 4)
+    }
+}
+
+impl<K: Eq + Hash, V> HashMap<K, V> {
+    /// Creates an empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty map split in `shards` shards, rounded up to a power of two.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is 0.
+    pub fn with_shards(shards: usize) -> Self {
+        assert_ne!(shards, 0, "shards must be positive");
+        Self {
+            shards: (0..shards.next_power_of_two())
+                .map(|_| CachePadded::default())
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn shard<Q>(&self, key: &Q) -> &RwLock<StdHashMap<K, V>>
+    where
+        Q: Hash + ?Sized,
+    {
+        if self.shards.len() == 1 {
+            return &self.shards[0];
+        }
+        let hash = self.hasher.hash_one(key);
+        let bits = self.shards.len().trailing_zeros();
+        &self.shards[(hash >> (u64::BITS - bits)) as usize]
+    }
+
+    fn read<Q>(&self, key: &Q) -> RwLockReadGuard<'_, StdHashMap<K, V>>
+    where
+        Q: Hash + ?Sized,
+    {
+        self.shard(key).read().unwrap()
+    }
+
+    fn write<Q>(&self, key: &Q) -> RwLockWriteGuard<'_, StdHashMap<K, V>>
+    where
+        Q: Hash + ?Sized,
+    {
+        self.shard(key).write().unwrap()
+    }
+
+    /// Returns a clone of the value of `key`.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+        V: Clone,
+    {
+        self.get_with(key, V::clone)
+    }
+
+    /// Returns `f` of the value of `key`, called with the key's shard locked for reading.
+    pub fn get_with<Q, R>(&self, key: &Q, f: impl FnOnce(&V) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.read(key).get(key).map(f)
+    }
+
+    /// Returns whether the map has `key`.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.read(key).contains_key(key)
+    }
+
+    /// Sets the value of `key`, returning the previous one.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.write(&key).insert(key, value)
+    }
+
+    /// Removes `key`, returning its value.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.write(key).remove(key)
+    }
+
+    /// Replaces the value of `key` with `f` of the current one, or removes the key if `f` returns
+    /// `None`, with no other thread accessing the key in between. Returns the previous value.
+    ///
+    /// `f` is called with the key's shard locked, so it must not access the map.
+    pub fn compute<F>(&self, key: K, f: F) -> Option<V>
+    where
+        F: FnOnce(Option<&V>) -> Option<V>,
+    {
+        let mut shard = self.write(&key);
+        match f(shard.get(&key)) {
+            Some(value) => shard.insert(key, value),
+            None => shard.remove(&key),
+        }
+    }
+
+    /// Returns the number of entries. Other threads may change it right away.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().unwrap().len())
+            .sum()
+    }
+
+    /// Returns whether the map is empty. Other threads may change that right away.
+    pub fn is_empty(&self) -> bool {
+        self.shards
+            .iter()
+            .all(|shard| shard.read().unwrap().is_empty())
+    }
+
+    /// Removes every entry.
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.write().unwrap().clear();
+        }
+    }
+}
@@ -0,0 +1,554 @@
+//! Lock-free skip list map.
+
+use rand::Rng;
+use std::borrow::Borrow;
+use std::cmp::Ordering as KeyOrdering;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::super::reclaim::epoch::{pin, Guard};
+
+/// Number of levels of the list. Heights are drawn with probability 1/2 of going one level up, so
+/// this is plenty for any number of entries that fits in memory.
+const MAX_HEIGHT: usize = 32;
+
+/// Low bit of a link, set once the node holding it is removed so that nothing is linked after it
+/// anymore.
+const MARK: usize = 1;
+
+/// Links to the next node at each level, as tagged pointers.
+type Tower = [AtomicUsize];
+
+struct Node<K, V> {
+    key: K,
+    /// Boxed value as a tagged pointer, marked once the node is removed. A replaced value is
+    /// handed to the garbage collector, since readers may still be cloning it.
+    value: AtomicUsize,
+    /// Number of levels the node is linked at, plus 1 until its inserting thread is done linking
+    /// it. The node is freed once it drops to 0.
+    refs: AtomicUsize,
+    tower: Box<Tower>,
+    _value: PhantomData<Box<V>>,
+}
+
+impl<K, V> Node<K, V> {
+    fn is_removed(&self) -> bool {
+        self.tower[0].load(Ordering::Relaxed) & MARK != 0
+    }
+
+    /// Returns the value of the node, unless it is removed.
+    ///
+    /// # Safety
+    ///
+    /// The value must not be freed for `'g`.
+    unsafe fn value<'g>(&self) -> Option<&'g V> {
+        // Acquire so that the value is seen as the thread that set it made it.
+        let value = self.value.load(Ordering::Acquire);
+        if value & MARK != 0 {
+            return None;
+        }
+        (value as *const V).as_ref()
+    }
+}
+
+impl<K, V> Drop for Node<K, V> {
+    fn drop(&mut self) {
+        let value = (*self.value.get_mut() & !MARK) as *mut V;
+        if !value.is_null() {
+            // SAFETY: The value belongs to the node, and no thread reads it anymore.
+            drop(unsafe { Box::from_raw(value) });
+        }
+    }
+}
+
+/// Returns the node `link` points to, if any.
+///
+/// # Safety
+///
+/// The node must not be freed for `'g`.
+unsafe fn deref<'g, K, V>(link: usize) -> Option<&'g Node<K, V>> {
+    ((link & !MARK) as *const Node<K, V>).as_ref()
+}
+
+/// Where a key is, or would be, in the list.
+struct Position<'g, K, V> {
+    /// The last link before the key at each level.
+    preds: [&'g Tower; MAX_HEIGHT],
+    /// The first node not before the key at each level, as an untagged pointer.
+    succs: [usize; MAX_HEIGHT],
+    /// The node of the key, if any.
+    found: Option<&'g Node<K, V>>,
+}
+
+/// A sorted map that threads can read and modify without locking.
+///
+/// A skip list is a linked list of the entries in key order, with more sparse lists above it as
+/// express lanes: each node is linked at a random number of levels, one more with probability 1/2,
+/// so that a search skips most of the entries from the top level down.
+///
+/// A node is removed by marking its value, and then its links from the top level down, the bottom
+/// one last, so that no node is linked after it anymore. Searches unlink the marked nodes they pass
+/// by, and the last of them hands the node to the
+/// [epoch-based garbage collector](super::super::reclaim::epoch).
+///
+/// Values are shared with the threads reading them, so they are returned as clones. Replacing the
+/// value of a key swaps it in the node at once, and hands the old one to the garbage collector.
+pub struct SkipListMap<K, V> {
+    head: Box<Tower>,
+    /// Number of levels with nodes, which searches start from.
+    height: AtomicUsize,
+    len: AtomicUsize,
+    _marker: PhantomData<Box<Node<K, V>>>,
+}
+
+// The entries are moved between threads, and shared with `&SkipListMap`.
+unsafe impl<K: Send + Sync, V: Send + Sync> Send for SkipListMap<K, V> {}
+unsafe impl<K: Send + Sync, V: Send + Sync> Sync for SkipListMap<K, V> {}
+
+impl<K, V> Default for SkipListMap<K, V> {
+    fn default() -> Self {
+        Self {
+            head: (0..MAX_HEIGHT).map(|_| AtomicUsize::new(0)).collect(),
+            height: AtomicUsize::new(1),
+            len: AtomicUsize::new(0),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K, V> fmt::Debug for SkipListMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SkipListMap")
+            .field("len", &self.len.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K, V> SkipListMap<K, V>
+where
+    K: Ord + Send + 'static,
+    V: Send + 'static,
+{
+    /// Creates an empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Finds where `key` is at each level, unlinking the removed nodes on the way.
+    fn find<'g, Q>(&'g self, key: &Q, guard: &'g Guard) -> Position<'g, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        'search: loop {
+            let mut position = Position {
+                preds: [&*self.head; MAX_HEIGHT],
+                succs: [0; MAX_HEIGHT],
+                found: None,
+            };
+            let mut pred: &Tower = &self.head;
+            for level in (0..self.height.load(Ordering::Relaxed)).rev() {
+                // Acquire so that the node is seen as its inserting thread made it.
+                let mut curr = pred[level].load(Ordering::Acquire);
+                if curr & MARK != 0 {
+                    // `pred` was removed, and nodes may be linked after it but not reachable.
+                    continue 'search;
+                }
+                // SAFETY: The nodes reached aren't freed while the guard is pinned.
+                while let Some(node) = unsafe { deref::<K, V>(curr) } {
+                    let next = node.tower[level].load(Ordering::Acquire);
+                    if next & MARK != 0 {
+                        // Release so that threads reaching the next node through `pred` see it as
+                        // its inserting thread made it.
+                        if pred[level]
+                            .compare_exchange(
+                                curr,
+                                next & !MARK,
+                                Ordering::Release,
+                                Ordering::Relaxed,
+                            )
+                            .is_err()
+                        {
+                            continue 'search;
+                        }
+                        // SAFETY: The node was linked at this level, and isn't anymore.
+                        unsafe { self.unref(node, guard) };
+                        curr = next & !MARK;
+                        continue;
+                    }
+                    match node.key.borrow().cmp(key) {
+                        KeyOrdering::Less => {
+                            pred = &node.tower;
+                            curr = next;
+                        }
+                        KeyOrdering::Equal => {
+                            if level == 0 {
+                                position.found = Some(node);
+                            }
+                            break;
+                        }
+                        KeyOrdering::Greater => break,
+                    }
+                }
+                position.preds[level] = pred;
+                position.succs[level] = curr;
+            }
+            return position;
+        }
+    }
+
+    /// Drops a reference to `node`, and frees it once there are none.
+    ///
+    /// # Safety
+    ///
+    /// The reference must be held, and dropped only once.
+    unsafe fn unref(&self, node: &Node<K, V>, guard: &Guard) {
+        // Release so that the thread freeing the node is done with it after every other thread, and
+        // acquire so that it is for this one.
+        if node.refs.fetch_sub(1, Ordering::AcqRel) == 1 {
+            guard.defer_destroy(node as *const Node<K, V> as *mut Node<K, V>);
+        }
+    }
+
+    /// Removes `node` from the map, unless another thread got there first. Returns its value then.
+    fn remove_node<'g>(&self, node: &'g Node<K, V>, guard: &'g Guard) -> Option<&'g V> {
+        // The node is removed once its value is marked, by the thread that marks it. Acquire so
+        // that the value is seen as the thread that set it made it.
+        let value = node.value.fetch_or(MARK, Ordering::Acquire);
+        // Helps the removing thread otherwise, so that searches unlink the node.
+        for link in node.tower.iter().rev() {
+            link.fetch_or(MARK, Ordering::Relaxed);
+        }
+        if value & MARK != 0 {
+            return None;
+        }
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        // Unlinks the node at every level.
+        let _ = self.find(&node.key, guard);
+        // SAFETY: The value is freed with the node, not before the guard is unpinned.
+        Some(unsafe { &*(value as *const V) })
+    }
+
+    /// Returns the first node that isn't removed from `link` on, at the bottom level, with its
+    /// value.
+    ///
+    /// # Safety
+    ///
+    /// The nodes reached must not be freed for `'g`.
+    unsafe fn first_from<'g>(mut link: usize) -> Option<(&'g Node<K, V>, &'g V)> {
+        while let Some(node) = deref::<K, V>(link) {
+            // Acquire so that the next node is seen as its inserting thread made it.
+            let next = node.tower[0].load(Ordering::Acquire);
+            if next & MARK == 0 {
+                if let Some(value) = node.value() {
+                    return Some((node, value));
+                }
+            }
+            link = next;
+        }
+        None
+    }
+
+    /// Returns a clone of the value of `key`.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        V: Clone,
+    {
+        self.get_with(key, V::clone)
+    }
+
+    /// Returns `f` of the value of `key`.
+    pub fn get_with<Q, R>(&self, key: &Q, f: impl FnOnce(&V) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let guard = pin();
+        let node = self.find(key, &guard).found?;
+        // SAFETY: The value isn't freed while the guard is pinned.
+        unsafe { node.value() }.map(f)
+    }
+
+    /// Returns whether the map has `key`.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.get_with(key, |_| ()).is_some()
+    }
+
+    /// Sets the value of `key`, returning the previous one.
+    pub fn insert(&self, key: K, value: V) -> Option<V>
+    where
+        V: Clone,
+    {
+        let height =
+            (rand::thread_rng().gen::<u32>().trailing_zeros() as usize + 1).min(MAX_HEIGHT);
+        self.height.fetch_max(height, Ordering::Relaxed);
+        let node = Box::into_raw(Box::new(Node {
+            key,
+            value: AtomicUsize::new(Box::into_raw(Box::new(value)) as usize),
+            refs: AtomicUsize::new(2),
+            tower: (0..height).map(|_| AtomicUsize::new(0)).collect(),
+            _value: PhantomData,
+        }));
+        // SAFETY: The node is freed once unlinked at every level and unreferenced by this thread.
+        let new = unsafe { &*node };
+        let guard = pin();
+
+        // Links the node at the bottom level, which inserts it, unless the key is in the map.
+        let mut position = loop {
+            let position = self.find(&new.key, &guard);
+            if let Some(found) = position.found {
+                // Acquire so that the old value is seen as the thread that set it made it.
+                let current = found.value.load(Ordering::Acquire);
+                if current & MARK != 0 {
+                    // The node is being removed: helps unlink it, and then inserts the key again.
+                    let _ = self.remove_node(found, &guard);
+                    continue;
+                }
+                let value = new.value.load(Ordering::Relaxed);
+                // Release so that threads reading the new value see it as this thread made it.
+                if found
+                    .value
+                    .compare_exchange(current, value, Ordering::Release, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    // SAFETY: The node was never shared, and its value now belongs to `found`. The
+                    // old value is unreachable for threads pinned from now on.
+                    unsafe {
+                        new.value.store(0, Ordering::Relaxed);
+                        drop(Box::from_raw(node));
+                        let previous = (*(current as *const V)).clone();
+                        guard.defer_destroy(current as *mut V);
+                        return Some(previous);
+                    }
+                }
+                continue;
+            }
+            new.tower[0].store(position.succs[0], Ordering::Relaxed);
+            // Release so that threads reaching the node see its key and value.
+            if position.preds[0][0]
+                .compare_exchange(
+                    position.succs[0],
+                    node as usize,
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                self.len.fetch_add(1, Ordering::Relaxed);
+                break position;
+            }
+        };
+
+        // Links the node at the levels above, unless it is removed meanwhile.
+        'levels: for level in 1..height {
+            loop {
+                let next = new.tower[level].load(Ordering::Relaxed);
+                let succ = position.succs[level];
+                if next & MARK != 0
+                    || new.tower[level]
+                        .compare_exchange(next, succ, Ordering::Relaxed, Ordering::Relaxed)
+                        .is_err()
+                {
+                    break 'levels;
+                }
+                new.refs.fetch_add(1, Ordering::Relaxed);
+                // Release so that threads reaching the node at this level see its link.
+                if position.preds[level][level]
+                    .compare_exchange(succ, node as usize, Ordering::Release, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    break;
+                }
+                new.refs.fetch_sub(1, Ordering::Relaxed);
+                position = self.find(&new.key, &guard);
+                if !position.found.is_some_and(|found| ptr::eq(found, new)) {
+                    break 'levels;
+                }
+            }
+        }
+        if new.is_removed() {
+            // The removing thread may have unlinked the node before it was linked at some levels.
+            let _ = self.find(&new.key, &guard);
+        }
+        // SAFETY: This thread is done linking the node.
+        unsafe { self.unref(new, &guard) };
+        None
+    }
+
+    /// Removes `key`, returning its value.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        V: Clone,
+    {
+        let guard = pin();
+        loop {
+            let node = self.find(key, &guard).found?;
+            if let Some(value) = self.remove_node(node, &guard) {
+                return Some(value.clone());
+            }
+        }
+    }
+
+    /// Returns a clone of the entry with the smallest key.
+    pub fn first(&self) -> Option<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let _guard = pin();
+        // Acquire so that the node is seen as its inserting thread made it.
+        let link = self.head[0].load(Ordering::Acquire);
+        // SAFETY: The nodes reached aren't freed while the guard is pinned.
+        let (node, value) = unsafe { Self::first_from(link) }?;
+        Some((node.key.clone(), value.clone()))
+    }
+
+    /// Removes the entry with the smallest key and returns it.
+    pub fn pop_first(&self) -> Option<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let guard = pin();
+        loop {
+            // Acquire so that the node is seen as its inserting thread made it.
+            let link = self.head[0].load(Ordering::Acquire);
+            // SAFETY: The nodes reached aren't freed while the guard is pinned.
+            let (node, _) = unsafe { Self::first_from(link) }?;
+            if let Some(value) = self.remove_node(node, &guard) {
+                return Some((node.key.clone(), value.clone()));
+            }
+        }
+    }
+
+    /// Returns clones of the entries with keys in `range`, in key order. Entries inserted or removed
+    /// meanwhile may or may not be included.
+    pub fn range<Q, R>(&self, range: R) -> Vec<(K, V)>
+    where
+        K: Borrow<Q> + Clone,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+        V: Clone,
+    {
+        let guard = pin();
+        let mut link = match range.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => self.find(start, &guard).succs[0],
+            // Acquire so that the node is seen as its inserting thread made it.
+            Bound::Unbounded => self.head[0].load(Ordering::Acquire),
+        };
+        let mut entries = Vec::new();
+        // SAFETY: The nodes reached aren't freed while the guard is pinned.
+        while let Some((node, value)) = unsafe { Self::first_from(link) } {
+            let key = node.key.borrow();
+            if let Bound::Excluded(start) = range.start_bound() {
+                if key == start {
+                    link = node.tower[0].load(Ordering::Acquire);
+                    continue;
+                }
+            }
+            let past_end = match range.end_bound() {
+                Bound::Included(end) => key > end,
+                Bound::Excluded(end) => key >= end,
+                Bound::Unbounded => false,
+            };
+            if past_end {
+                break;
+            }
+            entries.push((node.key.clone(), value.clone()));
+            // Acquire so that the next node is seen as its inserting thread made it.
+            link = node.tower[0].load(Ordering::Acquire);
+        }
+        entries
+    }
+
+    /// Returns the number of entries. Other threads may change it right away.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the map is empty. Other threads may change that right away.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K, V> Drop for SkipListMap<K, V> {
+    fn drop(&mut self) {
+        for level in (0..MAX_HEIGHT).rev() {
+            let mut link = *self.head[level].get_mut();
+            while link & !MARK != 0 {
+                let node = (link & !MARK) as *mut Node<K, V>;
+                // SAFETY: No other thread has access to the map anymore, and the nodes still
+                // linked weren't handed to the garbage collector. Each is freed after the last
+                // level it is linked at.
+                unsafe {
+                    link = *(*node).tower[level].get_mut();
+                    if *(*node).refs.get_mut() == 1 {
+                        drop(Box::from_raw(node));
+                    } else {
+                        *(*node).refs.get_mut() -= 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use super::SkipListMap;
+
+    #[test]
+    fn replacing_a_value_never_hides_the_key() {
+        const KEYS: u32 = 8;
+        let map = SkipListMap::new();
+        for key in 0..KEYS {
+            let _ = map.insert(key, 0);
+        }
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            let readers = (0..2)
+                .map(|_| {
+                    scope.spawn(|| {
+                        while !done.load(Ordering::Relaxed) {
+                            for key in 0..KEYS {
+                                assert!(map.get(&key).is_some());
+                            }
+                            assert_eq!(map.range(..).len(), KEYS as usize);
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for writer in 0..2 {
+                let map = &map;
+                let _ = scope.spawn(move || {
+                    for value in 1..10_000 {
+                        assert!(map.insert(value % KEYS, writer * 10_000 + value).is_some());
+                    }
+                });
+            }
+            thread::sleep(std::time::Duration::from_millis(100));
+            done.store(true, Ordering::Relaxed);
+            for reader in readers {
+                reader.join().unwrap();
+            }
+        });
+        assert_eq!(map.len(), KEYS as usize);
+        assert_eq!(map.remove(&0).map(|_| ()), Some(()));
+        assert_eq!(map.get(&0), None);
+    }
+}
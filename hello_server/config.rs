@@ -0,0 +1,488 @@
+//! Server configuration.
+
+use serde::{Deserialize, Deserializer};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::cache::{deserialize_millis, CacheConfig, CacheConfigError};
+
+/// Prefix of the environment variables overriding the options, e.g. `HELLO_SERVER_POOL_SIZE`.
+const ENV_PREFIX: &str = "HELLO_SERVER_";
+
+/// How connections are served.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// A thread of the pool serves each connection for as long as it is open, see
+    /// [`handle_conn`](super::handle_conn).
+    #[default]
+    Threads,
+    /// An event loop waits for the requests of all connections, and the pool only makes and writes
+    /// the responses, see [`EventLoop`](super::EventLoop). Only supported on Unix.
+    Events,
+}
+
+/// Server configuration.
+///
+/// Options are read from a TOML file, then overridden by `HELLO_SERVER_*` environment variables,
+/// then by command line options, e.g. `pool_size` by `HELLO_SERVER_POOL_SIZE` and `--pool-size`.
+/// The file is given by `--config` or `HELLO_SERVER_CONFIG`, and is optional. For example,
+///
+/// ```toml
+/// addr = ["0.0.0.0:8080", "[::]:8443"]
+/// unix_socket = "/run/hello_server.sock"
+/// unix_socket_mode = 0o660
+/// pool_size = 16
+/// backend = "events"
+/// doc_root = "public"
+/// read_timeout_ms = 10000
+/// tcp_nodelay = true
+///
+/// [cache]
+/// capacity = 1024
+///
+/// [response_cache]
+/// capacity = 256
+/// ttl_ms = 60000
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Addresses to listen to, e.g. `[::]:8080` for IPv6. `addr` in the file may be one address
+    /// or a list, and a comma-separated list in the environment or on the command line.
+    #[serde(rename = "addr", deserialize_with = "deserialize_addrs")]
+    pub addrs: Vec<String>,
+
+    /// Path of a Unix socket to listen to as well, if any. Only supported on Unix.
+    pub unix_socket: Option<PathBuf>,
+
+    /// Permissions of the Unix socket file, e.g. `0o660`. Defaults to the umask's.
+    pub unix_socket_mode: Option<u32>,
+
+    /// Number of threads serving connections.
+    pub pool_size: usize,
+
+    /// How connections are served, `threads` or `events`.
+    pub backend: Backend,
+
+    /// Maximum number of accepted connections waiting for a thread. Further connections are
+    /// answered with 503.
+    pub max_queued: usize,
+
+    /// Directory served under `/static/`, if any.
+    pub doc_root: Option<PathBuf>,
+
+    /// Whether directories under `doc_root` without `index.html` are listed.
+    pub list_directories: bool,
+
+    /// Whether `doc_root` is watched for changes, so that what request paths resolve to can be
+    /// cached until the files change, and the cached responses of the pages that change are
+    /// invalidated.
+    pub watch_doc_root: bool,
+
+    /// Whether `/healthz` and `/readyz` are served, see [`Health`](super::Health).
+    pub health: bool,
+
+    /// Whether Prometheus metrics are served at `/metrics`, see [`Metrics`](super::Metrics).
+    pub metrics: bool,
+
+    /// How long a read from a connection may block.
+    #[serde(rename = "read_timeout_ms", deserialize_with = "deserialize_millis")]
+    pub read_timeout: Option<Duration>,
+
+    /// How long a write to a connection may block.
+    #[serde(rename = "write_timeout_ms", deserialize_with = "deserialize_millis")]
+    pub write_timeout: Option<Duration>,
+
+    /// How long a kept-alive connection may wait for its next request.
+    #[serde(rename = "idle_timeout_ms", deserialize_with = "deserialize_millis")]
+    pub idle_timeout: Option<Duration>,
+
+    /// Whether `TCP_NODELAY` is set on connections, so that small responses aren't delayed by
+    /// Nagle's algorithm.
+    pub tcp_nodelay: bool,
+
+    /// Whether `SO_REUSEADDR` is set on the listeners, so that the server can be restarted while
+    /// old connections linger. Ignored on Windows.
+    pub reuse_addr: bool,
+
+    /// Maximum number of connections waiting to be accepted by each listener.
+    pub backlog: u32,
+
+    /// Size of the send buffer of connections in bytes, if not the OS's.
+    pub send_buffer_size: Option<usize>,
+
+    /// Size of the receive buffer of connections in bytes, if not the OS's.
+    pub recv_buffer_size: Option<usize>,
+
+    /// Maximum number of open connections, if any. Further connections are answered with 503.
+    pub max_connections: Option<usize>,
+
+    /// Maximum number of open connections from one IP address, if any. Further connections from
+    /// the address are answered with 429.
+    pub max_connections_per_ip: Option<usize>,
+
+    /// How long in-flight requests are waited for on shutdown.
+    #[serde(
+        rename = "shutdown_timeout_ms",
+        deserialize_with = "deserialize_millis"
+    )]
+    pub shutdown_timeout: Option<Duration>,
+
+    /// PEM certificate chain for TLS. Must be given together with `tls_key`.
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for TLS. Must be given together with `tls_cert`.
+    pub tls_key: Option<PathBuf>,
+
+    /// Configuration of the hello handler's cache. Only settable in the file.
+    pub cache: CacheConfig,
+
+    /// Configuration of the cache of whole responses, if responses are cached, see
+    /// [`ResponseCache`](super::ResponseCache). Only settable in the file.
+    pub response_cache: Option<CacheConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            addrs: vec!["localhost:7878".to_string()],
+            unix_socket: None,
+            unix_socket_mode: None,
+            pool_size: 5,
+            backend: Backend::Threads,
+            max_queued: 64,
+            doc_root: None,
+            list_directories: false,
+            watch_doc_root: false,
+            health: false,
+            metrics: false,
+            read_timeout: Some(Duration::from_secs(30)),
+            write_timeout: Some(Duration::from_secs(30)),
+            idle_timeout: Some(Duration::from_secs(5)),
+            tcp_nodelay: false,
+            reuse_addr: true,
+            backlog: 128,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            max_connections: None,
+            max_connections_per_ip: None,
+            shutdown_timeout: Some(Duration::from_secs(10)),
+            tls_cert: None,
+            tls_key: None,
+            cache: CacheConfig::default(),
+            response_cache: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Loads the configuration from the file, the environment, and the command line arguments
+    /// `args` (without the program name), and validates it.
+    pub fn load<I>(args: I) -> Result<Self, ServerConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let args = parse_args(args)?;
+        let path = args
+            .iter()
+            .rev()
+            .find(|(name, _)| name == "config")
+            .map(|(_, value)| PathBuf::from(value))
+            .or_else(|| std::env::var_os(format!("{ENV_PREFIX}CONFIG")).map(PathBuf::from));
+
+        let mut config = match path {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        for (var, value) in std::env::vars() {
+            let Some(name) = var.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let name = name.to_ascii_lowercase();
+            if name != "config" {
+                config.set(&name, &value, &var)?;
+            }
+        }
+        for (name, value) in &args {
+            if name != "config" {
+                config.set(name, value, &format!("--{}", name.replace('_', "-")))?;
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads the configuration from a TOML file, without validating it.
+    pub fn from_file(path: impl Into<PathBuf>) -> Result<Self, ServerConfigError> {
+        let path = path.into();
+        let text =
+            fs::read_to_string(&path).map_err(|err| ServerConfigError::Read(path.clone(), err))?;
+        toml::from_str(&text).map_err(|err| ServerConfigError::Parse(path, err))
+    }
+
+    /// Sets the option `name`, e.g. `pool_size`, to `value` given by `origin`.
+    fn set(&mut self, name: &str, value: &str, origin: &str) -> Result<(), ServerConfigError> {
+        let invalid = || ServerConfigError::InvalidValue {
+            origin: origin.to_string(),
+            value: value.to_string(),
+        };
+        let millis = || {
+            value
+                .parse()
+                .map(|ms| Some(Duration::from_millis(ms)))
+                .map_err(|_| invalid())
+        };
+        match name {
+            "addr" => {
+                self.addrs = value
+                    .split(',')
+                    .map(|addr| addr.trim().to_string())
+                    .collect()
+            }
+            "unix_socket" => self.unix_socket = Some(value.into()),
+            // Octal, with or without a `0o` prefix, like `chmod` takes.
+            "unix_socket_mode" => {
+                let mode = value.strip_prefix("0o").unwrap_or(value);
+                self.unix_socket_mode = Some(u32::from_str_radix(mode, 8).map_err(|_| invalid())?);
+            }
+            "pool_size" => self.pool_size = value.parse().map_err(|_| invalid())?,
+            "backend" => {
+                self.backend = match value {
+                    "threads" => Backend::Threads,
+                    "events" => Backend::Events,
+                    _ => return Err(invalid()),
+                }
+            }
+            "max_queued" => self.max_queued = value.parse().map_err(|_| invalid())?,
+            "doc_root" => self.doc_root = Some(value.into()),
+            "list_directories" => self.list_directories = value.parse().map_err(|_| invalid())?,
+            "watch_doc_root" => self.watch_doc_root = value.parse().map_err(|_| invalid())?,
+            "health" => self.health = value.parse().map_err(|_| invalid())?,
+            "metrics" => self.metrics = value.parse().map_err(|_| invalid())?,
+            "read_timeout_ms" => self.read_timeout = millis()?,
+            "write_timeout_ms" => self.write_timeout = millis()?,
+            "idle_timeout_ms" => self.idle_timeout = millis()?,
+            "tcp_nodelay" => self.tcp_nodelay = value.parse().map_err(|_| invalid())?,
+            "reuse_addr" => self.reuse_addr = value.parse().map_err(|_| invalid())?,
+            "backlog" => self.backlog = value.parse().map_err(|_| invalid())?,
+            "send_buffer_size" => {
+                self.send_buffer_size = Some(value.parse().map_err(|_| invalid())?);
+            }
+            "recv_buffer_size" => {
+                self.recv_buffer_size = Some(value.parse().map_err(|_| invalid())?);
+            }
+            "max_connections" => {
+                self.max_connections = Some(value.parse().map_err(|_| invalid())?);
+            }
+            "max_connections_per_ip" => {
+                self.max_connections_per_ip = Some(value.parse().map_err(|_| invalid())?);
+            }
+            "shutdown_timeout_ms" => self.shutdown_timeout = millis()?,
+            "tls_cert" => self.tls_cert = Some(value.into()),
+            "tls_key" => self.tls_key = Some(value.into()),
+            _ => return Err(ServerConfigError::UnknownOption(origin.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Checks that the options make sense and the files they refer to exist.
+    pub fn validate(&self) -> Result<(), ServerConfigError> {
+        if self.addrs.is_empty() {
+            return Err(ServerConfigError::NoAddr);
+        }
+        for addr in &self.addrs {
+            let _: SocketAddr = addr
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .ok_or_else(|| ServerConfigError::InvalidAddr(addr.clone()))?;
+        }
+        if self.unix_socket.is_some() && cfg!(not(unix)) {
+            return Err(ServerConfigError::UnixUnsupported);
+        }
+        if self.unix_socket_mode.is_some_and(|mode| mode > 0o777) {
+            return Err(ServerConfigError::InvalidMode);
+        }
+        if self.backend == Backend::Events && cfg!(not(unix)) {
+            return Err(ServerConfigError::EventsUnsupported);
+        }
+        if self.pool_size == 0 {
+            return Err(ServerConfigError::ZeroPoolSize);
+        }
+        if [self.max_connections, self.max_connections_per_ip].contains(&Some(0)) {
+            return Err(ServerConfigError::ZeroConnectionLimit);
+        }
+        if self.backlog == 0 || i32::try_from(self.backlog).is_err() {
+            return Err(ServerConfigError::InvalidBacklog);
+        }
+        if [self.send_buffer_size, self.recv_buffer_size].contains(&Some(0)) {
+            return Err(ServerConfigError::ZeroBufferSize);
+        }
+        if [
+            self.read_timeout,
+            self.write_timeout,
+            self.idle_timeout,
+            self.shutdown_timeout,
+        ]
+        .contains(&Some(Duration::ZERO))
+        {
+            return Err(ServerConfigError::ZeroTimeout);
+        }
+        if let Some(doc_root) = &self.doc_root {
+            if !doc_root.is_dir() {
+                return Err(ServerConfigError::NotFound(doc_root.clone()));
+            }
+        }
+        match (&self.tls_cert, &self.tls_key) {
+            (None, None) => {}
+            (Some(cert), Some(key)) => {
+                for path in [cert, key] {
+                    if !path.is_file() {
+                        return Err(ServerConfigError::NotFound(path.clone()));
+                    }
+                }
+            }
+            _ => return Err(ServerConfigError::IncompleteTls),
+        }
+        if let Some(response_cache) = &self.response_cache {
+            response_cache
+                .validate()
+                .map_err(ServerConfigError::ResponseCache)?;
+        }
+        self.cache.validate().map_err(ServerConfigError::Cache)
+    }
+}
+
+/// Deserializes one address or a list of addresses.
+fn deserialize_addrs<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Addrs {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match Addrs::deserialize(d)? {
+        Addrs::One(addr) => vec![addr],
+        Addrs::Many(addrs) => addrs,
+    })
+}
+
+/// Splits `--name value` and `--name=value` arguments into `(name, value)` pairs, with `-` in
+/// names replaced by `_`.
+fn parse_args<I>(args: I) -> Result<Vec<(String, String)>, ServerConfigError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter();
+    let mut parsed = Vec::new();
+    while let Some(arg) = args.next() {
+        let Some(option) = arg.strip_prefix("--") else {
+            return Err(ServerConfigError::UnknownOption(arg));
+        };
+        let (name, value) = match option.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| ServerConfigError::MissingValue(arg.clone()))?;
+                (option.to_string(), value)
+            }
+        };
+        parsed.push((name.replace('-', "_"), value));
+    }
+    Ok(parsed)
+}
+
+/// Error loading a [`ServerConfig`].
+#[derive(Debug)]
+pub enum ServerConfigError {
+    /// The config file can't be read.
+    Read(PathBuf, io::Error),
+    /// The config file isn't valid TOML or has unknown or mistyped options.
+    Parse(PathBuf, toml::de::Error),
+    /// An environment variable or command line option isn't an option.
+    UnknownOption(String),
+    /// A command line option has no value.
+    MissingValue(String),
+    /// An environment variable or command line option has a malformed value.
+    InvalidValue {
+        /// The variable or option, e.g. `--pool-size`.
+        origin: String,
+        /// The malformed value.
+        value: String,
+    },
+    /// `addr` is an empty list.
+    NoAddr,
+    /// An address of `addr` doesn't resolve to a socket address.
+    InvalidAddr(String),
+    /// `unix_socket` is given on a platform without Unix sockets.
+    UnixUnsupported,
+    /// `unix_socket_mode` has bits other than the permission bits.
+    InvalidMode,
+    /// `backend` is `events` on a platform other than Unix.
+    EventsUnsupported,
+    /// `pool_size` is 0.
+    ZeroPoolSize,
+    /// A timeout is 0.
+    ZeroTimeout,
+    /// A connection limit is 0.
+    ZeroConnectionLimit,
+    /// `backlog` is 0 or greater than `i32::MAX`.
+    InvalidBacklog,
+    /// A socket buffer size is 0.
+    ZeroBufferSize,
+    /// A configured file or directory doesn't exist.
+    NotFound(PathBuf),
+    /// Only one of `tls_cert` and `tls_key` is given.
+    IncompleteTls,
+    /// The cache configuration is invalid.
+    Cache(CacheConfigError),
+    /// The response cache configuration is invalid.
+    ResponseCache(CacheConfigError),
+}
+
+impl fmt::Display for ServerConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(path, err) => write!(f, "can't read {}: {err}", path.display()),
+            Self::Parse(path, err) => write!(f, "invalid config file {}: {err}", path.display()),
+            Self::UnknownOption(option) => write!(f, "unknown option {option}"),
+            Self::MissingValue(option) => write!(f, "missing value for {option}"),
+            Self::InvalidValue { origin, value } => {
+                write!(f, "invalid value {value:?} for {origin}")
+            }
+            Self::NoAddr => write!(f, "no address to listen to"),
+            Self::InvalidAddr(addr) => write!(f, "invalid address {addr:?}"),
+            Self::UnixUnsupported => write!(f, "Unix sockets are not supported"),
+            Self::InvalidMode => write!(f, "Unix socket mode must be at most 0o777"),
+            Self::EventsUnsupported => write!(f, "the events backend is only supported on Unix"),
+            Self::ZeroPoolSize => write!(f, "pool size must be positive"),
+            Self::ZeroTimeout => write!(f, "timeouts must be positive"),
+            Self::ZeroConnectionLimit => write!(f, "connection limits must be positive"),
+            Self::InvalidBacklog => write!(f, "backlog must be between 1 and {}", i32::MAX),
+            Self::ZeroBufferSize => write!(f, "socket buffer sizes must be positive"),
+            Self::NotFound(path) => write!(f, "{} doesn't exist", path.display()),
+            Self::IncompleteTls => write!(f, "TLS needs both a certificate and a key"),
+            Self::Cache(err) => write!(f, "invalid cache config: {err}"),
+            Self::ResponseCache(err) => write!(f, "invalid response cache config: {err}"),
+        }
+    }
+}
+
+impl Error for ServerConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Read(_, err) => Some(err),
+            Self::Parse(_, err) => Some(err),
+            Self::Cache(err) | Self::ResponseCache(err) => Some(err),
+            _ => None,
+        }
+    }
+}
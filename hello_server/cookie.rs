@@ -0,0 +1,193 @@
+//! HTTP cookies.
+
+use std::fmt;
+use std::time::Duration;
+
+use super::http::is_token;
+
+/// Where a cookie is sent along with requests initiated by other sites.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    /// Only with requests from the site itself.
+    Strict,
+    /// Also when following a link from another site.
+    Lax,
+    /// With every request. Browsers require the cookie to be [secure](Cookie::secure).
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Strict => "Strict",
+            Self::Lax => "Lax",
+            Self::None => "None",
+        })
+    }
+}
+
+/// A cookie set by a response, serialized as the value of a `Set-Cookie` header by its
+/// [`Display`](fmt::Display) implementation.
+///
+/// ```ignore
+/// Response::html("welcome").with_cookie(&Cookie::new("theme", "dark").max_age(WEEK))
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    http_only: bool,
+    secure: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// Creates a cookie sent back by the browser until it is closed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` isn't a token, or `value` contains whitespace, control characters, `"`,
+    /// `,`, `;`, or `\`.
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        let name = name.into();
+        let value = value.into();
+        assert!(is_token(&name), "cookie name must be a token");
+        assert!(
+            value.bytes().all(is_cookie_octet),
+            "cookie value has invalid characters"
+        );
+        Self {
+            name,
+            value,
+            path: None,
+            domain: None,
+            max_age: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        }
+    }
+
+    /// Creates a cookie that makes the browser remove the cookie `name` with the same path and
+    /// domain.
+    pub fn removal(name: impl Into<String>) -> Self {
+        Self::new(name, "").max_age(Duration::ZERO)
+    }
+
+    /// Returns the name of the cookie.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the value of the cookie.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Sets the paths the cookie is sent for, those under `path`. Defaults to the directory of
+    /// the request's path.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `path` contains control characters or `;`.
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        let path = path.into();
+        assert!(
+            is_attribute_value(&path),
+            "cookie path has invalid characters"
+        );
+        self.path = Some(path);
+        self
+    }
+
+    /// Sets the domain whose subdomains the cookie is sent to as well. Defaults to the request's
+    /// host only.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `domain` contains control characters or `;`.
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        let domain = domain.into();
+        assert!(
+            is_attribute_value(&domain),
+            "cookie domain has invalid characters"
+        );
+        self.domain = Some(domain);
+        self
+    }
+
+    /// Sets how long the browser keeps the cookie, even after it is closed. Zero removes it.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets whether the cookie is hidden from scripts. Defaults to `false`.
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Sets whether the cookie is only sent over HTTPS. Defaults to `false`.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Sets where the cookie is sent along with requests initiated by other sites. Defaults to
+    /// the browser's choice, usually [`SameSite::Lax`].
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={same_site}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the value of a `Cookie` header, e.g. `a=1; b=2`, into name-value pairs. Pairs without
+/// `=` are skipped, and quotes around values are removed.
+pub(crate) fn parse_cookies(header: &str) -> impl Iterator<Item = (&str, &str)> {
+    header.split(';').filter_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+        Some((name.trim(), value))
+    })
+}
+
+fn is_cookie_octet(b: u8) -> bool {
+    b.is_ascii_graphic() && !b"\",;\\".contains(&b)
+}
+
+fn is_attribute_value(s: &str) -> bool {
+    s.bytes().all(|b| !b.is_ascii_control() && b != b';')
+}
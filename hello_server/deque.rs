@@ -0,0 +1,299 @@
+//! Chase-Lev work-stealing deque.
+//!
+//! A [`Worker`] pushes and pops tasks at the back of its deque, and [`Stealer`]s of other threads
+//! steal them from the front, so that a thread out of tasks takes the oldest ones of a busy thread.
+//! See Lê et al., "Correct and Efficient Work-Stealing for Weak Memory Models" (PPoPP 2013) for
+//! the memory orderings.
+
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicIsize, AtomicPtr, Ordering};
+use std::sync::Arc;
+
+use super::cache_padded::CachePadded;
+use super::reclaim::epoch::pin;
+
+/// Initial number of slots of a deque.
+const MIN_CAPACITY: usize = 16;
+
+/// A circular array of slots, a power of two of them.
+struct Buffer<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+}
+
+impl<T> Buffer<T> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            slots: (0..capacity)
+                .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+                .collect(),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn slot(&self, index: isize) -> *mut MaybeUninit<T> {
+        self.slots[index as usize & (self.slots.len() - 1)].get()
+    }
+
+    /// Writes `value` in the slot of `index`.
+    ///
+    /// # Safety
+    ///
+    /// Only the worker writes, and only slots that aren't between the front and the back.
+    unsafe fn write(&self, index: isize, value: MaybeUninit<T>) {
+        ptr::write_volatile(self.slot(index), value);
+    }
+
+    /// Copies the value in the slot of `index`. The copy may be torn by the worker overwriting it,
+    /// so it is only initialized once the slot is claimed.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must not be freed meanwhile.
+    unsafe fn read(&self, index: isize) -> MaybeUninit<T> {
+        // Volatile so that it's really read, since the compiler may assume nothing writes it
+        // concurrently.
+        ptr::read_volatile(self.slot(index))
+    }
+}
+
+struct Inner<T> {
+    /// Index of the oldest task, moved forward by stealers and by the worker taking the last task.
+    front: CachePadded<AtomicIsize>,
+    /// Index after the newest task, only moved by the worker.
+    back: CachePadded<AtomicIsize>,
+    /// Replaced by the worker when full, and freed by the garbage collector since stealers may
+    /// still read the old one.
+    buffer: AtomicPtr<Buffer<T>>,
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        let front = *self.front.0.get_mut();
+        let back = *self.back.0.get_mut();
+        // SAFETY: No other thread has access to the deque anymore, and the tasks between the
+        // front and the back were neither popped nor stolen.
+        unsafe {
+            let buffer = Box::from_raw(*self.buffer.get_mut());
+            for index in front..back {
+                (*buffer.slot(index)).assume_init_drop();
+            }
+        }
+    }
+}
+
+/// The owner's end of a work-stealing deque. Tasks are popped in the reverse order they are pushed,
+/// so that the worker keeps working on the data it touched last, while stealers take the oldest.
+pub struct Worker<T> {
+    inner: Arc<Inner<T>>,
+    /// Only one thread pushes and pops at a time.
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+// The tasks are moved between threads.
+unsafe impl<T: Send> Send for Worker<T> {}
+
+impl<T> fmt::Debug for Worker<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Worker").finish_non_exhaustive()
+    }
+}
+
+impl<T> Default for Worker<T> {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                front: CachePadded(AtomicIsize::new(0)),
+                back: CachePadded(AtomicIsize::new(0)),
+                buffer: AtomicPtr::new(Box::into_raw(Box::new(Buffer::new(MIN_CAPACITY)))),
+            }),
+            _not_sync: PhantomData,
+        }
+    }
+}
+
+impl<T> Worker<T> {
+    /// Creates an empty deque.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a stealer of the tasks of this deque.
+    pub fn stealer(&self) -> Stealer<T> {
+        Stealer {
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Pushes `task` at the back.
+    pub fn push(&self, task: T) {
+        let back = self.inner.back.load(Ordering::Relaxed);
+        // Acquire so that the tasks stolen are read before their slots are written again.
+        let front = self.inner.front.load(Ordering::Acquire);
+        let mut buffer = self.inner.buffer.load(Ordering::Relaxed);
+        // SAFETY: Only the worker frees the buffer.
+        let capacity = unsafe { (*buffer).capacity() };
+        if back - front >= capacity as isize {
+            buffer = self.grow(front, back, capacity * 2);
+        }
+        // SAFETY: The slot at the back isn't between the front and the back.
+        unsafe { (*buffer).write(back, MaybeUninit::new(task)) };
+        // Release so that stealers see the task written.
+        self.inner.back.store(back + 1, Ordering::Release);
+    }
+
+    /// Moves the tasks between `front` and `back` to a buffer of `capacity` slots, and returns it.
+    fn grow(&self, front: isize, back: isize, capacity: usize) -> *mut Buffer<T> {
+        let old = self.inner.buffer.load(Ordering::Relaxed);
+        let new = Buffer::new(capacity);
+        for index in front..back {
+            // SAFETY: Only the worker writes the slots, so the copies aren't torn.
+            unsafe { new.write(index, (*old).read(index)) };
+        }
+        let new = Box::into_raw(Box::new(new));
+        let guard = pin();
+        // Release so that stealers loading the new buffer see the tasks copied.
+        self.inner.buffer.store(new, Ordering::Release);
+        // SAFETY: The old buffer is unreachable once replaced, and replaced only once. Its slots
+        // are dropped without the tasks, which moved to the new one.
+        unsafe { guard.defer_destroy(old) };
+        new
+    }
+
+    /// Pops the task at the back, the newest one.
+    pub fn pop(&self) -> Option<T> {
+        let back = self.inner.back.load(Ordering::Relaxed) - 1;
+        // Claims the task at the back, so that stealers stop short of it.
+        self.inner.back.store(back, Ordering::Relaxed);
+        // SeqCst so that either the stealers see the back moved, or the worker sees them move the
+        // front.
+        fence(Ordering::SeqCst);
+        let front = self.inner.front.load(Ordering::Relaxed);
+        if front > back {
+            self.inner.back.store(back + 1, Ordering::Relaxed);
+            return None;
+        }
+        let buffer = self.inner.buffer.load(Ordering::Relaxed);
+        // SAFETY: Only the worker frees the buffer.
+        let task = unsafe { (*buffer).read(back) };
+        if front == back {
+            // The last task, which a stealer may be claiming too. The front is moved instead.
+            let won = self
+                .inner
+                .front
+                .compare_exchange(front, front + 1, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok();
+            self.inner.back.store(back + 1, Ordering::Relaxed);
+            if !won {
+                return None;
+            }
+        }
+        // SAFETY: The task is claimed, and no other thread reads it anymore.
+        Some(unsafe { task.assume_init() })
+    }
+
+    /// Returns the number of tasks. Stealers may change it right away.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns whether the deque is empty. Stealers may change that right away.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Inner<T> {
+    fn len(&self) -> usize {
+        let back = self.back.load(Ordering::Relaxed);
+        let front = self.front.load(Ordering::Relaxed);
+        (back - front).max(0) as usize
+    }
+}
+
+/// The result of [`Stealer::steal`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Steal<T> {
+    /// The deque was empty.
+    Empty,
+    /// A task was stolen.
+    Success(T),
+    /// Another thread took the task first, and there may be others.
+    Retry,
+}
+
+impl<T> Steal<T> {
+    /// Returns the task stolen, if any.
+    pub fn success(self) -> Option<T> {
+        match self {
+            Self::Success(task) => Some(task),
+            Self::Empty | Self::Retry => None,
+        }
+    }
+}
+
+/// Another thread's end of a work-stealing deque, which steals the oldest tasks.
+pub struct Stealer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+// The tasks are moved between threads.
+unsafe impl<T: Send> Send for Stealer<T> {}
+unsafe impl<T: Send> Sync for Stealer<T> {}
+
+impl<T> fmt::Debug for Stealer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stealer").finish_non_exhaustive()
+    }
+}
+
+impl<T> Clone for Stealer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Stealer<T> {
+    /// Steals the task at the front, the oldest one.
+    pub fn steal(&self) -> Steal<T> {
+        // Acquire so that the task at the front is read after the worker took it out, if it did.
+        let front = self.inner.front.load(Ordering::Acquire);
+        // SeqCst so that either the worker sees the front moved, or this thread sees it move the
+        // back.
+        fence(Ordering::SeqCst);
+        let _guard = pin();
+        // Acquire so that the tasks before the back are seen as the worker wrote them.
+        let back = self.inner.back.load(Ordering::Acquire);
+        if front >= back {
+            return Steal::Empty;
+        }
+        // Acquire so that the tasks copied to a new buffer are seen.
+        let buffer = self.inner.buffer.load(Ordering::Acquire);
+        // SAFETY: The buffer isn't freed while the guard is pinned.
+        let task = unsafe { (*buffer).read(front) };
+        if self
+            .inner
+            .front
+            .compare_exchange(front, front + 1, Ordering::SeqCst, Ordering::Relaxed)
+            .is_err()
+        {
+            // The copy may be torn, and is forgotten.
+            return Steal::Retry;
+        }
+        // SAFETY: The task is claimed, and the worker wrote it before moving the back past it.
+        Steal::Success(unsafe { task.assume_init() })
+    }
+
+    /// Returns whether the deque is empty. Other threads may change that right away.
+    pub fn is_empty(&self) -> bool {
+        self.inner.len() == 0
+    }
+}
@@ -0,0 +1,185 @@
+//! Custom error pages.
+
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+use super::handler::Handler;
+use super::http::{reason_phrase, Request, Response};
+use super::middleware::{Middleware, Next};
+use super::static_files::escape_html;
+
+thread_local! {
+    /// Description and backtrace of the last panic on this thread, captured in debug mode.
+    static LAST_PANIC: RefCell<Option<(String, Backtrace)>> = const { RefCell::new(None) };
+}
+
+/// Page answering the responses with some status.
+enum Page {
+    /// HTML with `{status}`, `{reason}`, and `{path}` replaced.
+    Template(String),
+    /// Handler called with the request, without its body.
+    Handler(Box<dyn Handler>),
+}
+
+/// Middleware replacing the bodies of error responses, e.g. 404 or 503, with custom pages.
+///
+/// The body and content headers of a response with a status that has a page are replaced, and
+/// its other headers, e.g. `Allow` or `Retry-After`, are kept. A handler that panics is answered
+/// with 500. To use different pages for each virtual host, wrap each host's router with its own
+/// `ErrorPages`.
+///
+/// In debug mode, the 500 response to a panic shows the panic message and a backtrace instead,
+/// which must not be enabled in production.
+pub struct ErrorPages {
+    pages: HashMap<u16, Page>,
+    debug: bool,
+}
+
+impl fmt::Debug for ErrorPages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut statuses = self.pages.keys().collect::<Vec<_>>();
+        statuses.sort();
+        f.debug_struct("ErrorPages")
+            .field("statuses", &statuses)
+            .field("debug", &self.debug)
+            .finish()
+    }
+}
+
+impl Default for ErrorPages {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ErrorPages {
+    const DEBUG: &'static str = "<!DOCTYPE html>
+<html lang=\"en\">
+  <head>
+    <meta charset=\"utf-8\">
+    <title>500 Internal Server Error</title>
+  </head>
+  <body>
+    <h1>500 Internal Server Error</h1>
+    <pre>{panic}</pre>
+    <pre>{backtrace}</pre>
+  </body>
+</html>";
+
+    /// Creates the middleware without pages. Responses are only changed for panics.
+    pub fn new() -> Self {
+        Self {
+            pages: HashMap::new(),
+            debug: false,
+        }
+    }
+
+    /// Answers responses with `status` with the HTML `template`, in which `{status}`, `{reason}`,
+    /// and `{path}` are replaced with the status code, its reason phrase, and the request path.
+    pub fn template(mut self, status: u16, template: impl Into<String>) -> Self {
+        let _ = self.pages.insert(status, Page::Template(template.into()));
+        self
+    }
+
+    /// Answers responses with `status` with the response of `handler` to the request, whose body
+    /// has been consumed. The response gets `status` whatever the handler answers.
+    pub fn handler<H>(mut self, status: u16, handler: H) -> Self
+    where
+        H: Handler + 'static,
+    {
+        let _ = self.pages.insert(status, Page::Handler(Box::new(handler)));
+        self
+    }
+
+    /// Sets whether panics are answered with their message and backtrace. Defaults to `false`.
+    pub fn debug(mut self, debug: bool) -> Self {
+        if debug {
+            capture_panics();
+        }
+        self.debug = debug;
+        self
+    }
+
+    /// Returns the 500 response to a panic.
+    fn panic_page(&self, request: &Request) -> Response {
+        let last_panic = LAST_PANIC.with(|last_panic| last_panic.borrow_mut().take());
+        let Some((panic, backtrace)) = last_panic.filter(|_| self.debug) else {
+            return self.page(request, Response::status_page(500));
+        };
+        let mut response = Response::html(
+            Self::DEBUG
+                .replace("{panic}", &escape_html(&panic))
+                .replace("{backtrace}", &escape_html(&backtrace.to_string())),
+        );
+        response.status = 500;
+        response
+    }
+
+    /// Replaces the body of `response` with its page, if any.
+    fn page(&self, request: &Request, mut response: Response) -> Response {
+        let Some(page) = self.pages.get(&response.status) else {
+            return response;
+        };
+        let mut page = match page {
+            Page::Template(template) => Response::html(
+                template
+                    .replace("{status}", &response.status.to_string())
+                    .replace("{reason}", reason_phrase(response.status))
+                    .replace("{path}", &escape_html(&request.path)),
+            ),
+            Page::Handler(handler) => handler.call(request.clone()),
+        };
+        page.status = response.status;
+
+        response.headers.retain(|(name, _)| {
+            !name.to_ascii_lowercase().starts_with("content-")
+                && !page
+                    .headers
+                    .iter()
+                    .any(|(n, _)| n.eq_ignore_ascii_case(name))
+        });
+        response.headers.append(&mut page.headers);
+        response.body = page.body;
+        response
+    }
+}
+
+impl Middleware for ErrorPages {
+    fn handle(&self, mut request: Request, next: Next<'_>) -> Response {
+        // The body is only needed by the rest of the chain.
+        let body = std::mem::take(&mut request.body);
+        let head = request.clone();
+        request.body = body;
+
+        match panic::catch_unwind(AssertUnwindSafe(|| next.run(request))) {
+            Ok(response) => self.page(&head, response),
+            Err(_) => {
+                println!(
+                    "[error_pages] handler panicked on {} {}",
+                    head.method, head.path
+                );
+                self.panic_page(&head)
+            }
+        }
+    }
+}
+
+/// Installs a panic hook, once, that saves the description and a backtrace of each panic for
+/// [`ErrorPages::panic_page`] before running the previous hook.
+fn capture_panics() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let backtrace = Backtrace::force_capture();
+            LAST_PANIC.with(|last_panic| {
+                *last_panic.borrow_mut() = Some((info.to_string(), backtrace));
+            });
+            previous(info);
+        }));
+    });
+}
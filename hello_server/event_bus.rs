@@ -0,0 +1,257 @@
+//! In-process publish/subscribe.
+//!
+//! Components publish events on [`Topic`]s of an [`EventBus`] without knowing who listens, e.g. the
+//! server announces its shutdown, and whoever cares subscribes a handler. A topic is typed with
+//! its events, so publishers and subscribers agree on them at compile time.
+//!
+//! ```ignore
+//! const RELOADED: Topic<ServerConfig> = Topic::new("config.reloaded");
+//!
+//! let bus = EventBus::new().pool(pool.handle());
+//! let subscription = bus.subscribe(RELOADED, |config| println!("now serving {:?}", config.addrs));
+//! bus.publish(RELOADED, config);
+//! bus.unsubscribe(&subscription);
+//! ```
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use super::thread_pool::PoolHandle;
+
+/// A named channel of events of type `E`. Topics with the same name but different event types are
+/// different topics.
+pub struct Topic<E> {
+    name: &'static str,
+    _event: PhantomData<fn(E)>,
+}
+
+impl<E> Topic<E> {
+    /// Creates the topic `name`, e.g. in a `const`.
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            _event: PhantomData,
+        }
+    }
+
+    /// Returns the name of the topic.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<E> Clone for Topic<E> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<E> Copy for Topic<E> {}
+
+impl<E> fmt::Debug for Topic<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Topic").field(&self.name).finish()
+    }
+}
+
+/// Handles the events of a topic.
+type Handler<E> = Box<dyn Fn(&E) + Send + Sync>;
+
+/// A topic, whatever the type of its events.
+type Key = (&'static str, TypeId);
+
+struct Subscriber {
+    id: u64,
+    /// A `Handler<E>` of the topic's `E`.
+    handler: Arc<dyn Any + Send + Sync>,
+}
+
+/// A handler subscribed to a topic of an [`EventBus`], to unsubscribe it with
+/// [`EventBus::unsubscribe`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subscription {
+    key: Key,
+    id: u64,
+}
+
+/// Delivers the events published on its topics to the handlers subscribed to them.
+///
+/// Handlers are called inline, on the publishing thread, unless the bus has a [pool](Self::pool).
+/// Either way, they are called without holding the bus, so they may publish and (un)subscribe in
+/// turn, and a panicking handler doesn't keep the others from being called.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: RwLock<HashMap<Key, Vec<Subscriber>>>,
+    next_id: AtomicU64,
+    /// Doesn't own the pool, so that the pool is dropped by its owner rather than by whoever holds
+    /// the bus.
+    pool: Option<PoolHandle>,
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let subscribers = self.subscribers.read().unwrap();
+        f.debug_struct("EventBus")
+            .field(
+                "topics",
+                &subscribers.keys().map(|(name, _)| name).collect::<Vec<_>>(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl EventBus {
+    /// Creates a bus calling the handlers inline.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls each handler in a job of `pool` instead, so that publishing doesn't wait for them.
+    /// They are still called inline when the pool is saturated or dropped.
+    pub fn pool(mut self, pool: PoolHandle) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Calls `handler` with each event published on `topic` from now on, until unsubscribed.
+    pub fn subscribe<E, F>(&self, topic: Topic<E>, handler: F) -> Subscription
+    where
+        E: Send + Sync + 'static,
+        F: Fn(&E) + Send + Sync + 'static,
+    {
+        let key = (topic.name, TypeId::of::<E>());
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let handler: Handler<E> = Box::new(handler);
+        self.subscribers
+            .write()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .push(Subscriber {
+                id,
+                handler: Arc::new(handler),
+            });
+        Subscription { key, id }
+    }
+
+    /// Stops calling the handler of `subscription`. Returns whether it was subscribed. Events
+    /// published before may still be delivered to it.
+    pub fn unsubscribe(&self, subscription: &Subscription) -> bool {
+        let mut subscribers = self.subscribers.write().unwrap();
+        let Some(topic) = subscribers.get_mut(&subscription.key) else {
+            return false;
+        };
+        let len = topic.len();
+        topic.retain(|subscriber| subscriber.id != subscription.id);
+        let removed = topic.len() < len;
+        if topic.is_empty() {
+            let _ = subscribers.remove(&subscription.key);
+        }
+        removed
+    }
+
+    /// Returns the number of handlers subscribed to `topic`.
+    pub fn subscribers<E: 'static>(&self, topic: Topic<E>) -> usize {
+        self.subscribers
+            .read()
+            .unwrap()
+            .get(&(topic.name, TypeId::of::<E>()))
+            .map_or(0, Vec::len)
+    }
+
+    /// Delivers `event` to the handlers subscribed to `topic`, in the order they subscribed if
+    /// inline. Returns the number of handlers.
+    pub fn publish<E: Send + Sync + 'static>(&self, topic: Topic<E>, event: E) -> usize {
+        let handlers = match self
+            .subscribers
+            .read()
+            .unwrap()
+            .get(&(topic.name, TypeId::of::<E>()))
+        {
+            // Keyed by the type of the events, so the handlers are of it.
+            Some(topic) => topic
+                .iter()
+                .filter_map(|subscriber| subscriber.handler.clone().downcast::<Handler<E>>().ok())
+                .collect::<Vec<_>>(),
+            None => return 0,
+        };
+
+        let count = handlers.len();
+        let event = Arc::new(event);
+        for handler in handlers {
+            if let Some(pool) = &self.pool {
+                let event = event.clone();
+                let handler = handler.clone();
+                // The pool catches the panics of its jobs.
+                if pool.try_execute(move || handler(&event)).is_ok() {
+                    continue;
+                }
+            }
+            if panic::catch_unwind(AssertUnwindSafe(|| handler(&event))).is_err() {
+                println!("[event bus] handler of {} panicked", topic.name);
+            }
+        }
+        count
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+
+    use super::super::ThreadPool;
+    use super::{EventBus, Topic};
+
+    const COUNT: Topic<usize> = Topic::new("count");
+
+    #[test]
+    fn handlers_run_on_the_pool_until_it_is_dropped() {
+        let pool = ThreadPool::new(2);
+        let bus = EventBus::new().pool(pool.handle());
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let _ = bus.subscribe(COUNT, move |_| {
+            sender.lock().unwrap().send(thread::current().id()).unwrap()
+        });
+        let _ = bus.subscribe(COUNT, |_| panic!("a handler panicked"));
+
+        assert_eq!(bus.publish(COUNT, 1), 2);
+        pool.join();
+        assert_ne!(receiver.recv().unwrap(), thread::current().id());
+        assert_eq!(pool.panicked(), 1);
+
+        // The bus doesn't keep the pool, and calls the handlers inline from now on.
+        drop(pool);
+        assert_eq!(bus.publish(COUNT, 2), 2);
+        assert_eq!(receiver.recv().unwrap(), thread::current().id());
+    }
+
+    #[test]
+    fn handlers_may_publish_and_unsubscribe() {
+        let bus = Arc::new(EventBus::new());
+        let publisher = bus.clone();
+        let _ = bus.subscribe(COUNT, move |count| {
+            if *count < 3 {
+                let _ = publisher.publish(COUNT, count + 1);
+            }
+        });
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let subscription = bus.subscribe(COUNT, move |_| {
+            let _ = counter.fetch_add(1, Ordering::Relaxed);
+        });
+
+        assert_eq!(bus.publish(COUNT, 0), 2);
+        assert_eq!(calls.load(Ordering::Relaxed), 4);
+        assert!(bus.unsubscribe(&subscription));
+        assert!(!bus.unsubscribe(&subscription));
+        assert_eq!(bus.subscribers(COUNT), 1);
+    }
+}
@@ -0,0 +1,439 @@
+//! Event-driven serving of connections.
+
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token, Waker};
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::http::Request;
+use super::router::Router;
+use super::server::{
+    admit, reject, reject_overloaded, respond, Connections, MAX_BODY_LEN, MAX_REQUESTS_PER_CONN,
+};
+use super::statistics::Report;
+use super::stream::Stream;
+use super::tcp::CancellableTcpListener;
+use super::thread_pool::PoolHandle;
+use super::unix::CancellableUnixListener;
+
+/// Token of the waker, woken when a connection is handed back by the pool. The listeners' tokens
+/// are their indices, and the connections' their IDs, which follow the listeners'.
+const WAKER: Token = Token(usize::MAX);
+
+/// How often the connections are checked for timeouts.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of events handled per poll.
+const EVENTS_CAPACITY: usize = 1024;
+
+/// Size of the reads from connections.
+const READ_LEN: usize = 8 * 1024;
+
+/// Maximum number of bytes buffered for a connection. A request that doesn't fit is answered with
+/// 413.
+const MAX_BUF_LEN: usize = 2 * MAX_BODY_LEN as usize;
+
+/// A listener of the event loop.
+#[derive(Debug)]
+enum Listener {
+    Tcp(Arc<CancellableTcpListener>),
+    Unix(Arc<CancellableUnixListener>),
+}
+
+impl Listener {
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Self::Tcp(listener) => listener.accept().map(Stream::from),
+            Self::Unix(listener) => listener.inner().accept().map(|(stream, _)| stream.into()),
+        }
+    }
+
+    fn is_canceled(&self) -> bool {
+        match self {
+            Self::Tcp(listener) => listener.is_canceled(),
+            Self::Unix(listener) => listener.is_canceled(),
+        }
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Self::Tcp(listener) => listener.inner().as_raw_fd(),
+            Self::Unix(listener) => listener.inner().as_raw_fd(),
+        }
+    }
+}
+
+/// A connection waiting for its next request.
+#[derive(Debug)]
+struct Conn {
+    stream: Stream,
+    /// Bytes read but not parsed yet, the start of the next request.
+    buf: Vec<u8>,
+    /// Number of requests served.
+    served: usize,
+    /// When bytes were last read, or the connection was accepted or handed back.
+    last_read: Instant,
+}
+
+impl Conn {
+    fn new(stream: Stream) -> Self {
+        Self {
+            stream,
+            buf: Vec::new(),
+            served: 0,
+            last_read: Instant::now(),
+        }
+    }
+
+    /// Reads what has arrived without blocking. Returns whether the client has closed the
+    /// connection, or it has failed.
+    fn fill(&mut self) -> bool {
+        let mut chunk = [0; READ_LEN];
+        while self.buf.len() < MAX_BUF_LEN {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return true,
+                Ok(len) => {
+                    self.buf.extend_from_slice(&chunk[..len]);
+                    self.last_read = Instant::now();
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return false,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return true,
+            }
+        }
+        false
+    }
+}
+
+/// What the event loop hands to the jobs serving requests.
+struct Context {
+    router: Arc<Router>,
+    pool: PoolHandle,
+    connections: Arc<Connections>,
+    report: Arc<dyn Fn(Report) + Send + Sync>,
+}
+
+/// A server backend that waits for requests on all connections with a single thread, and only
+/// hands complete requests to the thread pool.
+///
+/// Unlike [`handle_conn`](super::handle_conn), which occupies a thread for as long as its
+/// connection is open, a thread is only taken while a response is made and written, so that idle
+/// and slow clients don't tie up the pool. A kept-alive connection is handed back to the loop once
+/// its response is written.
+///
+/// The loop stops once every listener is `cancel`led, then drains the connections like the other
+/// backend: requests in flight are finished and the other connections are closed.
+#[derive(Debug)]
+pub struct EventLoop {
+    poll: Poll,
+    waker: Arc<Waker>,
+    /// The listeners, `None` once cancelled.
+    listeners: Vec<Option<Listener>>,
+    conns: HashMap<usize, Conn>,
+    next_id: usize,
+    /// Connections handed back by the pool after their response, with their IDs.
+    return_sender: Sender<(usize, Conn)>,
+    returned: Receiver<(usize, Conn)>,
+}
+
+impl EventLoop {
+    /// Creates an event loop accepting connections from the TCP listeners `tcp` and the Unix
+    /// listener `unix`, if any. The listeners are put in non-blocking mode.
+    pub fn new(
+        tcp: Vec<Arc<CancellableTcpListener>>,
+        unix: Option<Arc<CancellableUnixListener>>,
+    ) -> io::Result<Self> {
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let listeners = tcp
+            .into_iter()
+            .map(Listener::Tcp)
+            .chain(unix.map(Listener::Unix))
+            .collect::<Vec<_>>();
+        for (index, listener) in listeners.iter().enumerate() {
+            match listener {
+                Listener::Tcp(listener) => listener.inner().set_nonblocking(true)?,
+                Listener::Unix(listener) => listener.inner().set_nonblocking(true)?,
+            }
+            poll.registry().register(
+                &mut SourceFd(&listener.as_raw_fd()),
+                Token(index),
+                Interest::READABLE,
+            )?;
+        }
+        let (return_sender, returned) = mpsc::channel();
+        Ok(Self {
+            poll,
+            waker,
+            next_id: listeners.len(),
+            listeners: listeners.into_iter().map(Some).collect(),
+            conns: HashMap::new(),
+            return_sender,
+            returned,
+        })
+    }
+
+    /// Serves the connections with `router` until the listeners are cancelled, making and writing
+    /// the responses on `pool` and calling `report` for each of them.
+    ///
+    /// A request that finds the pool's queue full is answered with 503.
+    pub fn run(
+        mut self,
+        router: Arc<Router>,
+        pool: PoolHandle,
+        connections: Arc<Connections>,
+        report: impl Fn(Report) + Send + Sync + 'static,
+    ) {
+        let cx = Context {
+            router,
+            pool,
+            connections,
+            report: Arc::new(report),
+        };
+        let mut events = Events::with_capacity(EVENTS_CAPACITY);
+        let mut last_sweep = Instant::now();
+        while self.listeners.iter().any(Option::is_some) || !self.conns.is_empty() {
+            match self.poll.poll(&mut events, Some(SWEEP_INTERVAL)) {
+                Ok(()) => {}
+                // Interrupted by a signal, e.g. SIGINT.
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => panic!("[event loop] failed to poll: {err}"),
+            }
+            for event in events.iter() {
+                match event.token() {
+                    WAKER => self.take_returned(&cx),
+                    Token(index) if index < self.listeners.len() => self.accept(index, &cx),
+                    Token(id) => self.read(id, &cx),
+                }
+            }
+            if last_sweep.elapsed() >= SWEEP_INTERVAL {
+                self.sweep(&cx);
+                last_sweep = Instant::now();
+            }
+        }
+    }
+
+    /// Accepts the connections waiting on the listener `index`.
+    fn accept(&mut self, index: usize, cx: &Context) {
+        let Some(listener) = &self.listeners[index] else {
+            return;
+        };
+        loop {
+            let stream = match listener.accept() {
+                Ok(stream) => stream,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    println!("[event loop] failed to accept a connection: {err}");
+                    return;
+                }
+            };
+
+            // The listener is cancelled, and the connection is the one waking it up.
+            if listener.is_canceled() {
+                let _ = self
+                    .poll
+                    .registry()
+                    .deregister(&mut SourceFd(&listener.as_raw_fd()));
+                self.listeners[index] = None;
+                if self.listeners.iter().all(Option::is_none) {
+                    // Closes the idle connections, which read EOF.
+                    cx.connections.drain();
+                }
+                return;
+            }
+
+            let id = self.next_id;
+            self.next_id += 1;
+            let Some(stream) = admit(&cx.connections, id, stream) else {
+                continue;
+            };
+            if stream.set_nonblocking(true).is_err()
+                || self
+                    .poll
+                    .registry()
+                    .register(
+                        &mut SourceFd(&stream.as_raw_fd()),
+                        Token(id),
+                        Interest::READABLE,
+                    )
+                    .is_err()
+            {
+                cx.connections.unregister(id);
+                continue;
+            }
+            let _ = self.conns.insert(id, Conn::new(stream));
+        }
+    }
+
+    /// Reads from the connection `id`, and serves its request if it has arrived.
+    fn read(&mut self, id: usize, cx: &Context) {
+        // The connection may have been handed to the pool by an earlier event.
+        let Some(conn) = self.conns.get_mut(&id) else {
+            return;
+        };
+        let closed = conn.fill();
+        self.process(id, closed, cx);
+    }
+
+    /// Serves the request buffered for the connection `id` if it is whole, or closes the
+    /// connection if it is `closed` before.
+    fn process(&mut self, id: usize, closed: bool, cx: &Context) {
+        let conn = &self.conns[&id];
+        let parsed = if conn.buf.is_empty() {
+            Ok(None)
+        } else {
+            Request::parse(&conn.buf, MAX_BODY_LEN)
+        };
+        match parsed {
+            Ok(None) if conn.buf.len() >= MAX_BUF_LEN => {
+                let conn = self.take(id);
+                self.reject(id, conn, io::ErrorKind::FileTooLarge.into(), cx);
+            }
+            Ok(None) if !closed => {}
+            // A request cut short is dropped like by the other backend.
+            Ok(None) => self.close(id, cx),
+            Ok(Some((request, len))) => {
+                let mut conn = self.take(id);
+                let _ = conn.buf.drain(..len);
+                self.serve(id, conn, request, cx);
+            }
+            Err(err) => {
+                let conn = self.take(id);
+                self.reject(id, conn, err, cx);
+            }
+        }
+    }
+
+    /// Makes and writes the response to `request` on the pool, and then hands the connection back
+    /// if it is kept alive.
+    fn serve(&self, id: usize, mut conn: Conn, mut request: Request, cx: &Context) {
+        let router = cx.router.clone();
+        let connections = cx.connections.clone();
+        let report = cx.report.clone();
+        let returned = self.return_sender.clone();
+        let waker = self.waker.clone();
+        // Kept to answer the request if the pool has no room for it.
+        let overflow = conn.stream.try_clone();
+        let job = move || {
+            conn.served += 1;
+            request.remote_addr = conn.stream.peer_addr();
+            let last = conn.served == MAX_REQUESTS_PER_CONN;
+            // The response is written blocking, with the write timeout.
+            let keep_alive = conn.stream.set_nonblocking(false).is_ok()
+                && respond(
+                    &router,
+                    &connections,
+                    id,
+                    request,
+                    last,
+                    &mut conn.stream,
+                    &mut &*report,
+                );
+            // The loop is gone if it stopped in the meantime.
+            if keep_alive && returned.send((id, conn)).is_ok() {
+                let _ = waker.wake();
+            } else {
+                connections.unregister(id);
+            }
+        };
+        if cx.pool.try_execute(job).is_err() {
+            println!("[event loop] pool saturated, rejecting request on connection {id}");
+            if let Ok(stream) = overflow {
+                let _ = stream.set_nonblocking(false);
+                reject_overloaded(stream);
+            }
+            cx.connections.unregister(id);
+        }
+    }
+
+    /// Answers a request that can't be read on the pool and closes the connection.
+    fn reject(&self, id: usize, mut conn: Conn, err: io::Error, cx: &Context) {
+        let connections = cx.connections.clone();
+        let job = move || {
+            if conn.stream.set_nonblocking(false).is_ok() {
+                reject(&mut conn.stream, id, err);
+            }
+            connections.unregister(id);
+        };
+        if cx.pool.try_execute(job).is_err() {
+            cx.connections.unregister(id);
+        }
+    }
+
+    /// Waits for the next requests on the connections handed back by the pool.
+    fn take_returned(&mut self, cx: &Context) {
+        while let Ok((id, mut conn)) = self.returned.try_recv() {
+            if cx.connections.is_draining() {
+                cx.connections.unregister(id);
+                continue;
+            }
+            conn.last_read = Instant::now();
+            if conn.stream.set_nonblocking(true).is_err()
+                || self
+                    .poll
+                    .registry()
+                    .register(
+                        &mut SourceFd(&conn.stream.as_raw_fd()),
+                        Token(id),
+                        Interest::READABLE,
+                    )
+                    .is_err()
+            {
+                cx.connections.unregister(id);
+                continue;
+            }
+            let _ = self.conns.insert(id, conn);
+            // The next request may have been sent along with the previous one.
+            self.process(id, false, cx);
+        }
+    }
+
+    /// Closes the connections that have waited too long: idle ones silently, and those in the
+    /// middle of a request with 408.
+    fn sweep(&mut self, cx: &Context) {
+        let expired = self
+            .conns
+            .iter()
+            .filter(|(_, conn)| {
+                // Like the other backend, the first request is waited for as long as a read may
+                // block.
+                let timeout = if conn.served > 0 && conn.buf.is_empty() {
+                    cx.connections.idle_timeout
+                } else {
+                    cx.connections.read_timeout
+                };
+                timeout.is_some_and(|timeout| conn.last_read.elapsed() >= timeout)
+            })
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        for id in expired {
+            if self.conns[&id].buf.is_empty() {
+                self.close(id, cx);
+            } else {
+                let conn = self.take(id);
+                self.reject(id, conn, io::ErrorKind::TimedOut.into(), cx);
+            }
+        }
+    }
+
+    /// Stops waiting on the connection `id` and returns it.
+    fn take(&mut self, id: usize) -> Conn {
+        let conn = self.conns.remove(&id).unwrap();
+        let _ = self
+            .poll
+            .registry()
+            .deregister(&mut SourceFd(&conn.stream.as_raw_fd()));
+        conn
+    }
+
+    /// Closes the connection `id`.
+    fn close(&mut self, id: usize, cx: &Context) {
+        drop(self.take(id));
+        cx.connections.unregister(id);
+    }
+}
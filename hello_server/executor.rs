@@ -0,0 +1,132 @@
+//! Minimal executor of futures.
+//!
+//! A future is polled until it returns `Poll::Pending`, after handing the waker of the context to
+//! whatever it waits for. Once that is ready, it calls the waker, and the executor polls the
+//! future again.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+use super::sync::{Parker, Unparker};
+
+/// Wakes the thread blocked in [`block_on`].
+impl Wake for Unparker {
+    fn wake(self: Arc<Self>) {
+        self.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.unpark();
+    }
+}
+
+/// Runs `future` to completion on the current thread, blocking while it waits.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let parker = Parker::new();
+    let waker = Waker::from(Arc::new(parker.unparker().clone()));
+    let mut context = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        parker.park();
+    }
+}
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A spawned future, queued again when woken.
+struct Task {
+    /// `None` once completed, or while being polled.
+    future: Mutex<Option<BoxFuture>>,
+    queue: Sender<Arc<Task>>,
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        let queue = self.queue.clone();
+        // Fails if the executor was dropped, so that the task never runs again.
+        let _ = queue.send(self);
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let _ = self.queue.send(Arc::clone(self));
+    }
+}
+
+/// Runs the futures spawned by its [`Spawner`]s on the current thread.
+///
+/// ```ignore
+/// let (executor, spawner) = Executor::new();
+/// spawner.spawn(async { println!("hello") });
+/// drop(spawner);
+/// executor.run();
+/// ```
+pub struct Executor {
+    queue: Receiver<Arc<Task>>,
+}
+
+impl fmt::Debug for Executor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Executor").finish_non_exhaustive()
+    }
+}
+
+impl Executor {
+    /// Creates an executor and a spawner of futures for it.
+    pub fn new() -> (Self, Spawner) {
+        let (sender, receiver) = mpsc::channel();
+        (Self { queue: receiver }, Spawner { queue: sender })
+    }
+
+    /// Polls the spawned futures as they are woken, until every spawner is dropped and every
+    /// future completed or dropped by what it waited for.
+    pub fn run(&self) {
+        for task in self.queue.iter() {
+            let mut slot = task.future.lock().unwrap();
+            // Tasks woken more than once are queued more than once, and may be completed already.
+            let Some(mut future) = slot.take() else {
+                continue;
+            };
+            let waker = Waker::from(Arc::clone(&task));
+            if future
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_pending()
+            {
+                *slot = Some(future);
+            }
+        }
+    }
+}
+
+/// Spawns futures on an [`Executor`]. Clones spawn on the same executor.
+#[derive(Clone)]
+pub struct Spawner {
+    queue: Sender<Arc<Task>>,
+}
+
+impl fmt::Debug for Spawner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Spawner").finish_non_exhaustive()
+    }
+}
+
+impl Spawner {
+    /// Queues `future` to be polled by the executor.
+    pub fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(Box::pin(future))),
+            queue: self.queue.clone(),
+        });
+        let _ = self.queue.send(task);
+    }
+}
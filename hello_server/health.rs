@@ -0,0 +1,71 @@
+//! Health checks.
+
+use std::fmt;
+use std::sync::Arc;
+
+use super::http::Response;
+use super::router::Router;
+
+/// Check of whether the server can take requests.
+type Check = Box<dyn Fn() -> bool + Send + Sync>;
+
+/// Health check endpoints for load balancers and orchestrators.
+///
+/// `GET /healthz` (liveness) answers 200 as long as the server answers at all. `GET /readyz`
+/// (readiness) answers 200 if every check added with [`Health::check`] passes, and 503 otherwise,
+/// e.g. while the server is draining, with the result of each check in a plain-text body.
+#[derive(Default)]
+pub struct Health {
+    checks: Vec<(String, Check)>,
+}
+
+impl fmt::Debug for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.checks.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+impl Health {
+    /// Creates health checks without readiness checks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the readiness check `name`, which passes if `check` returns `true`.
+    pub fn check<F>(mut self, name: impl Into<String>, check: F) -> Self
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        self.checks.push((name.into(), Box::new(check)));
+        self
+    }
+
+    /// Serves `/healthz` and `/readyz` with `router`.
+    pub fn mount(self, router: &mut Router) {
+        let health = Arc::new(self);
+        let _ = router
+            .get("/healthz", |_| Response::new(200).with_body("ok\n"))
+            .get("/readyz", move |_| health.ready());
+    }
+
+    /// Runs the readiness checks.
+    fn ready(&self) -> Response {
+        let mut body = String::new();
+        let mut ready = true;
+        for (name, check) in &self.checks {
+            let passed = check();
+            ready &= passed;
+            body.push_str(&format!(
+                "{name}: {}\n",
+                if passed { "ok" } else { "failed" }
+            ));
+        }
+        body.push_str(if ready { "ok\n" } else { "not ready\n" });
+        Response::new(if ready { 200 } else { 503 })
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_header("Cache-Control", "no-store")
+            .with_body(body)
+    }
+}
@@ -0,0 +1,103 @@
+//! Hello pages with a cache.
+
+use regex::Regex;
+use serde_json::json;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::cache::{Cache, CacheBuilder, CacheConfig, CacheConfigError};
+use super::handler::Handler;
+use super::http::{Request, Response};
+use super::router::Router;
+use super::sync::OnceCell;
+use super::template::Template;
+
+/// Computes the result for the given key. So expensive, much wow.
+fn very_expensive_computation_that_takes_a_few_seconds(key: String) -> String {
+    println!("[handler] doing computation for key: {key}");
+    thread::sleep(Duration::from_secs(3));
+    format!("{key}🐕")
+}
+
+/// Hello handler with a cache.
+#[derive(Debug, Clone)]
+pub struct Hello {
+    cache: Arc<Cache<String, String>>,
+}
+
+impl Default for Hello {
+    fn default() -> Self {
+        Self {
+            cache: Arc::new(Cache::with_loader(
+                very_expensive_computation_that_takes_a_few_seconds,
+            )),
+        }
+    }
+}
+
+impl Hello {
+    /// Creates the handler with a cache configured by `config`.
+    pub fn with_cache_config(config: CacheConfig) -> Result<Self, CacheConfigError> {
+        Self::with_cache_builder(CacheBuilder::from_config(config))
+    }
+
+    /// Creates the handler with a cache built by `builder`, e.g. to report its metrics. The
+    /// builder's loader is replaced.
+    pub fn with_cache_builder(
+        builder: CacheBuilder<String, String>,
+    ) -> Result<Self, CacheConfigError> {
+        let cache = builder
+            .loader(very_expensive_computation_that_takes_a_few_seconds)
+            .build()?;
+        Ok(Self {
+            cache: Arc::new(cache),
+        })
+    }
+
+    /// Answers `GET /:key` with the result for the key.
+    pub fn hello(&self, request: Request) -> Response {
+        static KEY_REGEX: OnceCell<Regex> = OnceCell::new();
+        static OK: OnceCell<Template> = OnceCell::new();
+
+        let key = request
+            .param("key")
+            .filter(|key| {
+                KEY_REGEX
+                    .get_or_init(|| Regex::new(r"^\w+$").unwrap())
+                    .is_match(key)
+            })
+            .map(String::from);
+
+        if let Some(key) = key {
+            let result = self.cache.get(key.clone());
+            let html = OK
+                .get_or_init(|| Template::parse(include_str!("templates/hello.html")).unwrap())
+                .render(&json!({ "key": key, "result": result }))
+                .unwrap();
+            Response::html(html)
+        } else {
+            Self::not_found(request)
+        }
+    }
+
+    /// Answers requests for unknown pages.
+    pub fn not_found(_request: Request) -> Response {
+        let mut response = Response::html(include_str!("templates/not_found.html"));
+        response.status = 404;
+        response
+    }
+
+    /// Returns a router that serves the hello pages with this handler.
+    pub fn into_router(self) -> Router {
+        let mut router = Router::new();
+        let _ = router.get("/:key", self).fallback(Self::not_found);
+        router
+    }
+}
+
+impl Handler for Hello {
+    fn call(&self, request: Request) -> Response {
+        self.hello(request)
+    }
+}
@@ -0,0 +1,866 @@
+//! HTTP/1.1 messages.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufWriter, Read, Write};
+use std::net::SocketAddr;
+
+use super::cookie::{parse_cookies, Cookie};
+use super::multipart::{header_param, Multipart};
+use super::session::Session;
+
+/// Maximum length of a request line and its headers in bytes.
+const MAX_HEAD_LEN: u64 = 16 * 1024;
+
+/// Media type of form bodies.
+const FORM_MEDIA_TYPE: &str = "application/x-www-form-urlencoded";
+
+/// Media type of multipart form bodies.
+const MULTIPART_MEDIA_TYPE: &str = "multipart/form-data";
+
+/// Media type of JSON bodies.
+const JSON_MEDIA_TYPE: &str = "application/json";
+
+/// Size of the chunks of a response body of unknown length.
+const CHUNK_LEN: usize = 8 * 1024;
+
+/// Function writing a streamed body, see [`Body::Stream`].
+type StreamFn = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+/// An HTTP request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    /// Request method, e.g. `GET`.
+    pub method: String,
+    /// Path of the request target, e.g. `/index.html`.
+    pub path: String,
+    /// Query string of the request target without the leading `?`, if any.
+    pub query: Option<String>,
+    /// Protocol version, e.g. `HTTP/1.1`.
+    pub version: String,
+    /// Header fields in the order they were received.
+    pub headers: Vec<(String, String)>,
+    /// Parameters extracted from the path by the [`Router`](super::Router), e.g. `id` for
+    /// `/users/:id`.
+    pub params: HashMap<String, String>,
+    /// Body, read by [`Request::read_body`].
+    pub body: Vec<u8>,
+    /// Address of the client, set by the server.
+    pub remote_addr: Option<SocketAddr>,
+    /// ID of the request for correlating logs, set by the server from the `X-Request-Id` header or
+    /// generated, and sent back in the `X-Request-Id` header of the response.
+    pub id: Option<String>,
+    /// Session of the client, set by the [`Sessions`](super::Sessions) middleware.
+    pub session: Option<Session>,
+}
+
+impl Request {
+    /// Reads a request line and its headers from `reader`. The body is left in `reader`.
+    ///
+    /// Returns `Ok(None)` if the connection is closed before a request starts, and an
+    /// `InvalidData` error if the request is malformed.
+    pub fn read_head<R: BufRead>(reader: &mut R) -> io::Result<Option<Self>> {
+        let mut reader = reader.take(MAX_HEAD_LEN);
+        let mut line = String::new();
+        let Some(request_line) = read_line(&mut reader, &mut line)? else {
+            return Ok(None);
+        };
+
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid("malformed request line"));
+        };
+        if !is_token(method) {
+            return Err(invalid("malformed method"));
+        }
+        if !target.starts_with('/') && target != "*" {
+            return Err(invalid("malformed request target"));
+        }
+        if version != "HTTP/1.1" && version != "HTTP/1.0" {
+            return Err(invalid("unsupported HTTP version"));
+        }
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (target, None),
+        };
+        let mut request = Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            query,
+            version: version.to_string(),
+            headers: Vec::new(),
+            params: HashMap::new(),
+            body: Vec::new(),
+            remote_addr: None,
+            id: None,
+            session: None,
+        };
+        request.headers = read_headers(&mut reader, &mut line)?;
+
+        // Validates the headers describing the body up front so that it can be skipped reliably.
+        let _ = request.content_length()?;
+        if let Some(transfer_encoding) = request.header("Transfer-Encoding") {
+            if !transfer_encoding.eq_ignore_ascii_case("chunked") {
+                return Err(invalid("unsupported Transfer-Encoding"));
+            }
+            if request.header("Content-Length").is_some() {
+                return Err(invalid("both Transfer-Encoding and Content-Length"));
+            }
+        }
+        Ok(Some(request))
+    }
+
+    /// Reads the body from `reader` into [`Request::body`], either `Content-Length` bytes or
+    /// chunks until the last one if the request is [chunked](Request::is_chunked).
+    ///
+    /// Returns a `FileTooLarge` error if the body is longer than `max_len` bytes, and an
+    /// `InvalidData` error if the chunks are malformed.
+    pub fn read_body<R: BufRead>(&mut self, reader: &mut R, max_len: u64) -> io::Result<()> {
+        self.body.clear();
+        if !self.is_chunked() {
+            let len = self.content_length()?;
+            if len > max_len {
+                return Err(io::ErrorKind::FileTooLarge.into());
+            }
+            return read_exact_len(reader, len, &mut self.body);
+        }
+        copy_chunked(reader, &mut self.body, max_len).map(|_| ())
+    }
+
+    /// Parses a whole request, body included, from the start of `buf`, for readers that can't
+    /// block until the rest arrives. Returns the request and its length in bytes, or `Ok(None)`
+    /// if `buf` holds only part of it.
+    ///
+    /// Fails like [`Request::read_head`] and [`Request::read_body`].
+    pub(crate) fn parse(buf: &[u8], max_body_len: u64) -> io::Result<Option<(Self, usize)>> {
+        // The head is only parsed once it's whole, so that a truncated line isn't mistaken for a
+        // malformed one. One that is too long is parsed to fail.
+        let head_ends = buf.windows(2).any(|window| window == b"\n\n")
+            || buf.windows(3).any(|window| window == b"\n\r\n");
+        if !head_ends && buf.len() as u64 <= MAX_HEAD_LEN {
+            return Ok(None);
+        }
+        let mut cursor = io::Cursor::new(buf);
+        let Some(mut request) = Self::read_head(&mut cursor)? else {
+            return Ok(None);
+        };
+        match request.read_body(&mut cursor, max_body_len) {
+            Ok(()) => Ok(Some((request, cursor.position() as usize))),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Whether the body is sent with `Transfer-Encoding: chunked`.
+    pub fn is_chunked(&self) -> bool {
+        self.header("Transfer-Encoding")
+            .is_some_and(|value| value.eq_ignore_ascii_case("chunked"))
+    }
+
+    /// Returns the value of the first header named `name` (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the cookies sent with the request as name-value pairs, from every `Cookie` header.
+    pub fn cookies(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Cookie"))
+            .flat_map(|(_, value)| parse_cookies(value))
+    }
+
+    /// Returns the value of the first cookie named `name` (case-sensitive).
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.cookies().find(|(n, _)| *n == name).map(|(_, v)| v)
+    }
+
+    /// Returns the path parameter `name`, see [`Request::params`].
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    /// Parses the query string into a map, see [`parse_urlencoded`]. Empty if there is none.
+    pub fn query_params(&self) -> io::Result<HashMap<String, String>> {
+        self.query
+            .as_deref()
+            .map_or_else(|| Ok(HashMap::new()), parse_urlencoded)
+    }
+
+    /// Parses an `application/x-www-form-urlencoded` body into a map, see [`parse_urlencoded`].
+    ///
+    /// Returns an `InvalidData` error if the request has another `Content-Type`.
+    pub fn form(&self) -> io::Result<HashMap<String, String>> {
+        if !self
+            .media_type()
+            .is_some_and(|media_type| media_type.eq_ignore_ascii_case(FORM_MEDIA_TYPE))
+        {
+            return Err(invalid("not a form"));
+        }
+        let body = std::str::from_utf8(&self.body).map_err(|_| invalid("form is not UTF-8"))?;
+        parse_urlencoded(body)
+    }
+
+    /// Returns a parser of the `multipart/form-data` body, e.g. of a form with file uploads.
+    ///
+    /// Returns an `InvalidData` error if the request has another `Content-Type` or no boundary.
+    pub fn multipart(&self) -> io::Result<Multipart<&[u8]>> {
+        if !self
+            .media_type()
+            .is_some_and(|media_type| media_type.eq_ignore_ascii_case(MULTIPART_MEDIA_TYPE))
+        {
+            return Err(invalid("not a multipart form"));
+        }
+        let boundary = self
+            .header("Content-Type")
+            .and_then(|content_type| header_param(content_type, "boundary"))
+            .filter(|boundary| (1..=70).contains(&boundary.len()))
+            .ok_or_else(|| invalid("missing multipart boundary"))?;
+        Ok(Multipart::new(&self.body, boundary))
+    }
+
+    /// Deserializes the JSON body.
+    ///
+    /// The `Content-Type` must be `application/json` or another JSON type like
+    /// `application/problem+json`. The error converts into a 415 or 400 response:
+    ///
+    /// ```ignore
+    /// let user: User = match request.json() {
+    ///     Ok(user) => user,
+    ///     Err(err) => return err.into(),
+    /// };
+    /// ```
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, JsonError> {
+        let is_json = self.media_type().is_some_and(|media_type| {
+            let media_type = media_type.to_ascii_lowercase();
+            media_type == JSON_MEDIA_TYPE
+                || media_type.starts_with("application/") && media_type.ends_with("+json")
+        });
+        if !is_json {
+            return Err(JsonError::UnsupportedMediaType);
+        }
+        serde_json::from_slice(&self.body).map_err(JsonError::Malformed)
+    }
+
+    /// Returns the media type of `Content-Type` without parameters, e.g. `text/html` for
+    /// `text/html; charset=utf-8`.
+    pub fn media_type(&self) -> Option<&str> {
+        let content_type = self.header("Content-Type")?;
+        Some(content_type.split(';').next().unwrap_or_default().trim())
+    }
+
+    /// Returns the length of the request body given by `Content-Length`, or 0.
+    ///
+    /// Repeated `Content-Length` headers must agree.
+    pub fn content_length(&self) -> io::Result<u64> {
+        Ok(content_length(&self.headers)?.unwrap_or(0))
+    }
+
+    /// Whether the client wants to keep the connection open after the response.
+    ///
+    /// HTTP/1.1 connections are persistent unless the client sends `Connection: close`, and
+    /// HTTP/1.0 ones are closed unless it sends `Connection: keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        let has_token = |token: &str| {
+            self.header("Connection").is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|t| t.trim().eq_ignore_ascii_case(token))
+            })
+        };
+        if has_token("close") {
+            return false;
+        }
+        self.version == "HTTP/1.1" || has_token("keep-alive")
+    }
+}
+
+/// Body of a [`Response`].
+pub enum Body {
+    /// Bytes in memory.
+    Bytes(Vec<u8>),
+    /// The given number of bytes read from a reader, e.g. a file, and sent in chunks.
+    Reader(Box<dyn Read + Send>, u64),
+    /// Body of unknown length written by a function, e.g. as it is being generated. Sent with
+    /// `Transfer-Encoding: chunked`.
+    Stream(StreamFn),
+}
+
+impl Body {
+    /// Creates a body streamed by `f`, see [`Body::Stream`].
+    pub fn stream<F>(f: F) -> Self
+    where
+        F: FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+    {
+        Self::Stream(Box::new(f))
+    }
+
+    /// Returns the length of the body in bytes, or `None` if it is streamed.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Self::Bytes(bytes) => Some(bytes.len() as u64),
+            Self::Reader(_, len) => Some(*len),
+            Self::Stream(_) => None,
+        }
+    }
+
+    /// Returns whether the body is known to be empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// Returns the bytes of an in-memory body.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Bytes(bytes) => Some(bytes),
+            Self::Reader(..) | Self::Stream(_) => None,
+        }
+    }
+
+    /// Returns the bytes of the body, reading it into memory if needed.
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Bytes(bytes) => Ok(bytes),
+            Self::Reader(mut reader, len) => {
+                let mut bytes = Vec::with_capacity(len as usize);
+                read_exact_len(&mut reader, len, &mut bytes)?;
+                Ok(bytes)
+            }
+            Self::Stream(f) => {
+                let mut bytes = Vec::new();
+                f(&mut bytes)?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    fn write_to<W: Write>(self, writer: &mut W) -> io::Result<()> {
+        match self {
+            Self::Bytes(bytes) => writer.write_all(&bytes),
+            Self::Reader(reader, len) => {
+                let copied = io::copy(&mut reader.take(len), writer)?;
+                if copied < len {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                Ok(())
+            }
+            Self::Stream(f) => f(writer),
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Self::Reader(_, len) => f.debug_tuple("Reader").field(len).finish(),
+            Self::Stream(_) => f.debug_tuple("Stream").finish_non_exhaustive(),
+        }
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Self::Bytes(Vec::new())
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Self {
+        Self::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<String> for Body {
+    fn from(string: String) -> Self {
+        Self::Bytes(string.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(string: &str) -> Self {
+        Self::Bytes(string.as_bytes().to_vec())
+    }
+}
+
+/// An HTTP response.
+#[derive(Debug)]
+pub struct Response {
+    /// Status code, e.g. 200.
+    pub status: u16,
+    /// Header fields, except `Content-Length` and `Connection` which are set when writing.
+    pub headers: Vec<(String, String)>,
+    /// Body, written after the headers.
+    pub body: Body,
+}
+
+impl Response {
+    /// Creates an empty response with the given status code.
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Body::default(),
+        }
+    }
+
+    /// Creates a 200 response with an HTML body.
+    pub fn html(body: impl Into<String>) -> Self {
+        Self::new(200)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body.into())
+    }
+
+    /// Creates a 200 response with `value` serialized as the JSON body.
+    ///
+    /// Responds with 500 if `value` can't be serialized, e.g. a map with non-string keys.
+    pub fn json<T: Serialize + ?Sized>(value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Self::new(200)
+                .with_header("Content-Type", JSON_MEDIA_TYPE)
+                .with_body(body),
+            Err(err) => {
+                println!("[http] failed to serialize JSON response: {err}");
+                Self::status_page(500)
+            }
+        }
+    }
+
+    /// Creates a plain-text response with the status code and its reason phrase as the body.
+    pub fn status_page(status: u16) -> Self {
+        Self::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(format!("{status} {}\n", reason_phrase(status)))
+    }
+
+    /// Adds a header field.
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+
+    /// Adds a `Set-Cookie` header field setting `cookie`.
+    pub fn with_cookie(self, cookie: &Cookie) -> Self {
+        self.with_header("Set-Cookie", cookie.to_string())
+    }
+
+    /// Replaces the body.
+    pub fn with_body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Returns the value of the first header named `name` (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Reads a status line and its headers from `reader`, e.g. a response from another server.
+    /// The body is left in `reader` and [`Response::body`] is empty.
+    ///
+    /// Returns an `InvalidData` error if the response is malformed.
+    pub fn read_head<R: BufRead>(reader: &mut R) -> io::Result<Self> {
+        let mut reader = reader.take(MAX_HEAD_LEN);
+        let mut line = String::new();
+        let status_line = read_line(&mut reader, &mut line)?
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+
+        // The reason phrase is ignored.
+        let mut parts = status_line.splitn(3, ' ');
+        let (Some(version), Some(status)) = (parts.next(), parts.next()) else {
+            return Err(invalid("malformed status line"));
+        };
+        if version != "HTTP/1.1" && version != "HTTP/1.0" {
+            return Err(invalid("unsupported HTTP version"));
+        }
+        let status = status
+            .parse()
+            .ok()
+            .filter(|status| (100..1000).contains(status))
+            .ok_or_else(|| invalid("malformed status code"))?;
+
+        let mut response = Self::new(status);
+        response.headers = read_headers(&mut reader, &mut line)?;
+        let _ = content_length(&response.headers)?;
+        Ok(response)
+    }
+
+    /// Writes the response to `writer`, telling the client whether the connection stays open.
+    ///
+    /// Bodies of unknown length are sent with `Transfer-Encoding: chunked`. The body of a 1xx,
+    /// 204, or 304 response is not sent.
+    pub fn write_to<W: Write>(self, writer: &mut W, keep_alive: bool) -> io::Result<()> {
+        self.write(writer, keep_alive, true)
+    }
+
+    /// Writes the response to a `HEAD` request to `writer`: the status line and headers that
+    /// [`Response::write_to`] would write, including the length of the body, but not the body.
+    pub fn write_head_to<W: Write>(self, writer: &mut W, keep_alive: bool) -> io::Result<()> {
+        self.write(writer, keep_alive, false)
+    }
+
+    fn write<W: Write>(self, writer: &mut W, keep_alive: bool, with_body: bool) -> io::Result<()> {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\n",
+            self.status,
+            reason_phrase(self.status)
+        );
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        // These responses never have a body, so they don't describe one either.
+        let bodiless = matches!(self.status, 100..=199 | 204 | 304);
+        let len = self.body.len();
+        match len {
+            _ if bodiless => {}
+            Some(len) => head.push_str(&format!("Content-Length: {len}\r\n")),
+            None => head.push_str("Transfer-Encoding: chunked\r\n"),
+        }
+        head.push_str(&format!(
+            "Connection: {}\r\n\r\n",
+            if keep_alive { "keep-alive" } else { "close" }
+        ));
+        writer.write_all(head.as_bytes())?;
+
+        if bodiless || !with_body {
+            // The body is dropped.
+        } else if len.is_some() {
+            self.body.write_to(writer)?;
+        } else {
+            let mut chunked = BufWriter::with_capacity(CHUNK_LEN, ChunkedWriter(&mut *writer));
+            self.body.write_to(&mut chunked)?;
+            chunked
+                .into_inner()
+                .map_err(io::IntoInnerError::into_error)?
+                .finish()?;
+        }
+        writer.flush()
+    }
+}
+
+/// Error of [`Request::json`].
+#[derive(Debug)]
+pub enum JsonError {
+    /// The request's `Content-Type` isn't JSON.
+    UnsupportedMediaType,
+    /// The body isn't valid JSON for the requested type.
+    Malformed(serde_json::Error),
+}
+
+impl JsonError {
+    /// Returns the status code of the response to the request.
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::UnsupportedMediaType => 415,
+            Self::Malformed(_) => 400,
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedMediaType => write!(f, "Content-Type must be {JSON_MEDIA_TYPE}"),
+            Self::Malformed(err) => write!(f, "malformed JSON body: {err}"),
+        }
+    }
+}
+
+impl Error for JsonError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::UnsupportedMediaType => None,
+            Self::Malformed(err) => Some(err),
+        }
+    }
+}
+
+impl From<JsonError> for Response {
+    /// Responds with the error as `{"error": "..."}`.
+    fn from(err: JsonError) -> Self {
+        let mut response = Self::json(&HashMap::from([("error", err.to_string())]));
+        response.status = err.status();
+        response
+    }
+}
+
+/// Writes each write as a chunk of `Transfer-Encoding: chunked`.
+#[derive(Debug)]
+struct ChunkedWriter<W: Write>(W);
+
+impl<W: Write> ChunkedWriter<W> {
+    /// Writes the last chunk.
+    fn finish(mut self) -> io::Result<()> {
+        self.0.write_all(b"0\r\n\r\n")
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // An empty chunk would end the body.
+        if !buf.is_empty() {
+            write!(self.0, "{:x}\r\n", buf.len())?;
+            self.0.write_all(buf)?;
+            self.0.write_all(b"\r\n")?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Returns the reason phrase of a status code.
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Unknown",
+    }
+}
+
+/// Parses `name=value` pairs separated by `&`, as in query strings and form bodies, decoding `+`
+/// to a space and `%XX` escapes. A later value of a name replaces earlier ones, and a pair without
+/// `=` has an empty value.
+///
+/// Returns an `InvalidData` error if an escape is malformed.
+pub fn parse_urlencoded(s: &str) -> io::Result<HashMap<String, String>> {
+    let decode = |s: &str| {
+        percent_decode(&s.replace('+', " ")).ok_or_else(|| invalid("malformed percent-encoding"))
+    };
+    s.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((decode(name)?, decode(value)?))
+        })
+        .collect()
+}
+
+/// Encodes the bytes of `s` other than ASCII letters, digits, and `-._~` as `%XX` escapes, e.g. to
+/// put a file name in a URL path.
+pub fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(char::from(b));
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    encoded
+}
+
+/// Decodes `%XX` escapes in `s`. Returns `None` if an escape is malformed or the result is not
+/// UTF-8.
+pub fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            // Checked first, since `from_str_radix` also takes a sign, e.g. in `%+F`.
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Reads header lines up to the empty line that ends them.
+fn read_headers<R: BufRead>(
+    reader: &mut io::Take<R>,
+    line: &mut String,
+) -> io::Result<Vec<(String, String)>> {
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader, line)?
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if line.starts_with([' ', '\t']) {
+            return Err(invalid("obsolete header line folding"));
+        }
+        let (name, value) = line
+            .split_once(':')
+            .filter(|(name, _)| is_token(name))
+            .ok_or_else(|| invalid("malformed header"))?;
+        headers.push((name.to_string(), value.trim().to_string()));
+    }
+}
+
+/// Returns the length given by the `Content-Length` headers, which must agree if repeated.
+fn content_length(headers: &[(String, String)]) -> io::Result<Option<u64>> {
+    let mut content_length = None;
+    for (_, value) in headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+    {
+        let len = value
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| value.parse::<u64>().ok())
+            .flatten()
+            .ok_or_else(|| invalid("bad Content-Length"))?;
+        if content_length.is_some_and(|prev| prev != len) {
+            return Err(invalid("conflicting Content-Length"));
+        }
+        content_length = Some(len);
+    }
+    Ok(content_length)
+}
+
+/// Decodes a `Transfer-Encoding: chunked` body from `reader` into `writer`, up to and including
+/// the trailer fields, which are ignored. Returns the length of the decoded body.
+///
+/// Returns a `FileTooLarge` error if the body is longer than `max_len` bytes, and an
+/// `InvalidData` error if the chunks are malformed.
+pub(crate) fn copy_chunked<R, W>(reader: &mut R, writer: &mut W, max_len: u64) -> io::Result<u64>
+where
+    R: BufRead,
+    W: Write + ?Sized,
+{
+    let mut line = String::new();
+    let mut len = 0u64;
+    loop {
+        let size_line = read_line(&mut reader.take(MAX_HEAD_LEN), &mut line)?
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        // Chunk extensions after `;` are ignored.
+        let size = size_line.split(';').next().unwrap_or_default().trim();
+        let size = size
+            .bytes()
+            .all(|b| b.is_ascii_hexdigit())
+            .then(|| u64::from_str_radix(size, 16).ok())
+            .flatten()
+            .ok_or_else(|| invalid("bad chunk size"))?;
+        if size == 0 {
+            break;
+        }
+        len = len
+            .checked_add(size)
+            .filter(|&len| len <= max_len)
+            .ok_or(io::ErrorKind::FileTooLarge)?;
+        if io::copy(&mut reader.take(size), writer)? < size {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if read_line(&mut reader.take(2), &mut line)? != Some("") {
+            return Err(invalid("missing CRLF after chunk"));
+        }
+    }
+
+    let mut reader = reader.take(MAX_HEAD_LEN);
+    loop {
+        let trailer = read_line(&mut reader, &mut line)?
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        if trailer.is_empty() {
+            return Ok(len);
+        }
+    }
+}
+
+/// Reads exactly `len` bytes from `reader` and appends them to `buf`.
+fn read_exact_len<R: Read>(reader: &mut R, len: u64, buf: &mut Vec<u8>) -> io::Result<()> {
+    let read = reader.take(len).read_to_end(buf)?;
+    if (read as u64) < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// Reads a line from `reader` into `buf` and returns it without the line terminator, or `None` on
+/// EOF.
+fn read_line<'a, R: BufRead>(
+    reader: &mut io::Take<R>,
+    buf: &'a mut String,
+) -> io::Result<Option<&'a str>> {
+    buf.clear();
+    if reader.read_line(buf)? == 0 {
+        return Ok(None);
+    }
+    match buf.strip_suffix('\n') {
+        Some(line) => Ok(Some(line.strip_suffix('\r').unwrap_or(line))),
+        None if reader.limit() == 0 => Err(invalid("request head too long")),
+        None => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+/// Whether `s` is a non-empty token, i.e. a valid method or header name.
+pub(crate) fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{copy_chunked, percent_decode};
+
+    #[test]
+    fn percent_decode_requires_two_hex_digits() {
+        assert_eq!(percent_decode("a%20b%2fc").as_deref(), Some("a b/c"));
+        assert_eq!(percent_decode("%C3%A9").as_deref(), Some("\u{e9}"));
+        for malformed in ["%+F", "%-1", "%2", "%", "%G0", "%C3"] {
+            assert_eq!(percent_decode(malformed), None, "{malformed}");
+        }
+    }
+
+    #[test]
+    fn copy_chunked_rejects_overflowing_chunk_sizes() {
+        let mut body = &b"1\r\na\r\nffffffffffffffff\r\n"[..];
+        let err = copy_chunked(&mut body, &mut io::sink(), u64::MAX).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::FileTooLarge);
+
+        let mut body = &b"2\r\nab\r\n0\r\n\r\n"[..];
+        let mut out = Vec::new();
+        assert_eq!(copy_chunked(&mut body, &mut out, 2).unwrap(), 2);
+        assert_eq!(out, b"ab");
+    }
+}
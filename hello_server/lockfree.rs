@@ -0,0 +1,13 @@
+//! Lock-free data structures.
+
+#[cfg(feature = "std")]
+mod elimination_stack;
+#[cfg(feature = "std")]
+pub mod hazard;
+mod queue;
+mod stack;
+
+#[cfg(feature = "std")]
+pub use elimination_stack::EliminationStack;
+pub use queue::Queue;
+pub use stack::Stack;
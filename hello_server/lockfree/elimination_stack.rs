@@ -0,0 +1,134 @@
+//! Elimination backoff stack.
+
+use rand::Rng;
+use std::fmt;
+use std::hint;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::thread;
+
+use super::super::cache_padded::CachePadded;
+use super::super::reclaim::epoch::{pin, Guard};
+use super::stack::{Node, Stack};
+
+/// Number of times a pushing thread checks whether its node was taken before taking it back.
+const PATIENCE: usize = 64;
+
+/// A Treiber stack with an elimination array, so that pushes and pops contending on the top of the
+/// stack can exchange values directly instead of retrying.
+///
+/// A push or pop whose compare-and-swap on the top fails backs off to a random slot of the array
+/// before retrying: a pushing thread offers its node there for a while, and a popping thread takes
+/// the node it finds there. The pair cancels out, as if the push and then the pop happened on the
+/// stack, so the more threads contend, the more of them are served without touching the top.
+/// Without contention, it behaves like a [`Stack`].
+pub struct EliminationStack<T> {
+    stack: Stack<T>,
+    /// Nodes offered by pushing threads, or null.
+    slots: Box<[CachePadded<AtomicPtr<Node<T>>>]>,
+}
+
+impl<T> fmt::Debug for EliminationStack<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EliminationStack")
+            .field("slots", &self.slots.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Default for EliminationStack<T> {
+    fn default() -> Self {
+        // A slot per core, since at most that many threads back off at a time.
+        let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+        Self::with_slots(cores)
+    }
+}
+
+impl<T> EliminationStack<T> {
+    /// Creates an empty stack.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty stack with an elimination array of `slots` slots.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `slots` is 0.
+    pub fn with_slots(slots: usize) -> Self {
+        assert_ne!(slots, 0, "slots must be positive");
+        Self {
+            stack: Stack::new(),
+            slots: (0..slots)
+                .map(|_| CachePadded(AtomicPtr::new(ptr::null_mut())))
+                .collect(),
+        }
+    }
+
+    fn random_slot(&self) -> &AtomicPtr<Node<T>> {
+        &self.slots[rand::thread_rng().gen_range(0..self.slots.len())]
+    }
+
+    /// Pushes `value` on the top of the stack.
+    pub fn push(&self, value: T) {
+        let node = Node::boxed(value);
+        // Pinned so that the node isn't freed, and its address reused, while it may be offered.
+        let _guard = pin();
+        while !self.stack.try_push(node) && !self.offer(node) {}
+    }
+
+    /// Offers `node` in a random slot for a while, and returns whether a popping thread took it.
+    fn offer(&self, node: *mut Node<T>) -> bool {
+        let slot = self.random_slot();
+        // Release so that the popping thread taking the node sees its data.
+        if slot
+            .compare_exchange(ptr::null_mut(), node, Ordering::Release, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+        for _ in 0..PATIENCE {
+            if slot.load(Ordering::Relaxed) != node {
+                return true;
+            }
+            hint::spin_loop();
+        }
+        // Takes the node back, unless a popping thread got there first.
+        slot.compare_exchange(node, ptr::null_mut(), Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+    }
+
+    /// Pops the value on the top of the stack, if any.
+    pub fn pop(&self) -> Option<T> {
+        let guard = pin();
+        loop {
+            if let Ok(value) = self.stack.try_pop(&guard) {
+                return value;
+            }
+            if let Some(value) = self.take(&guard) {
+                return Some(value);
+            }
+        }
+    }
+
+    /// Takes the node offered in a random slot, if any, and returns its value.
+    fn take(&self, guard: &Guard) -> Option<T> {
+        let slot = self.random_slot();
+        // Acquire so that the node's data is seen as the pushing thread wrote it.
+        let node = slot.load(Ordering::Acquire);
+        if node.is_null()
+            || slot
+                .compare_exchange(node, ptr::null_mut(), Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            return None;
+        }
+        // SAFETY: The node is taken out of the slot by this thread.
+        Some(unsafe { Node::take(node, guard) })
+    }
+
+    /// Returns whether the stack is empty. Other threads may change that right away.
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+}
@@ -0,0 +1,340 @@
+//! The lock-free stack and queue, with [hazard pointers](super::super::reclaim::hazard) instead of
+//! epochs.
+//!
+//! A thread pinned in an epoch holds back the nodes unlinked by all threads until it unpins, while
+//! a hazard pointer only holds back the node it protects, so a stalled thread can't keep the
+//! garbage growing. In exchange, each node loaded is announced with a fence and checked again.
+
+use std::fmt;
+use std::mem::{ManuallyDrop, MaybeUninit};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use super::super::reclaim::hazard::{retire, Shield};
+
+struct StackNode<T> {
+    /// Moved out by the thread that pops the node, while others may still read `next`.
+    data: ManuallyDrop<T>,
+    next: *mut StackNode<T>,
+}
+
+/// Treiber's lock-free stack, see [`super::Stack`].
+pub struct Stack<T> {
+    head: AtomicPtr<StackNode<T>>,
+}
+
+// The data is moved between threads, and `&Stack` only gives access to it by moving it out.
+unsafe impl<T: Send> Send for Stack<T> {}
+unsafe impl<T: Send> Sync for Stack<T> {}
+
+impl<T> Default for Stack<T> {
+    fn default() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Stack<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stack").finish_non_exhaustive()
+    }
+}
+
+impl<T> Stack<T> {
+    /// Creates an empty stack.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pushes `value` on the top of the stack.
+    pub fn push(&self, value: T) {
+        let node = Box::into_raw(Box::new(StackNode {
+            data: ManuallyDrop::new(value),
+            next: ptr::null_mut(),
+        }));
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            // SAFETY: The node isn't shared until the CAS succeeds.
+            unsafe { (*node).next = head };
+            // Release so that a thread popping the node sees its data.
+            match self
+                .head
+                .compare_exchange(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Pops the value on the top of the stack, if any.
+    pub fn pop(&self) -> Option<T> {
+        let shield = Shield::new();
+        loop {
+            let head = shield.protect(&self.head);
+            // SAFETY: The node isn't freed while protected.
+            let node = unsafe { head.as_ref() }?;
+            if self
+                .head
+                .compare_exchange(head, node.next, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                // SAFETY: The node is unlinked by this thread, which alone moves the data out.
+                unsafe {
+                    let data = ManuallyDrop::into_inner(ptr::read(&node.data));
+                    retire(head);
+                    return Some(data);
+                }
+            }
+        }
+    }
+
+    /// Returns whether the stack is empty. Other threads may change that right away.
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed).is_null()
+    }
+}
+
+impl<T> Drop for Stack<T> {
+    fn drop(&mut self) {
+        let mut node = *self.head.get_mut();
+        while !node.is_null() {
+            // SAFETY: No other thread has access to the stack anymore, and the nodes left weren't
+            // popped.
+            unsafe {
+                let mut owned = Box::from_raw(node);
+                node = owned.next;
+                ManuallyDrop::drop(&mut owned.data);
+            }
+        }
+    }
+}
+
+struct QueueNode<T> {
+    /// Uninitialized in the sentinel node, and moved out when the node becomes the sentinel.
+    data: MaybeUninit<T>,
+    next: AtomicPtr<QueueNode<T>>,
+}
+
+impl<T> QueueNode<T> {
+    fn boxed(data: MaybeUninit<T>) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            data,
+            next: AtomicPtr::new(ptr::null_mut()),
+        }))
+    }
+}
+
+/// Michael and Scott's lock-free queue, see [`super::Queue`].
+///
+/// Dequeuing reads the node after the sentinel, so it protects both.
+pub struct Queue<T> {
+    head: AtomicPtr<QueueNode<T>>,
+    tail: AtomicPtr<QueueNode<T>>,
+}
+
+// The data is moved between threads, and `&Queue` only gives access to it by moving it out.
+unsafe impl<T: Send> Send for Queue<T> {}
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Self {
+        let sentinel = QueueNode::boxed(MaybeUninit::uninit());
+        Self {
+            head: AtomicPtr::new(sentinel),
+            tail: AtomicPtr::new(sentinel),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Queue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Queue").finish_non_exhaustive()
+    }
+}
+
+impl<T> Queue<T> {
+    /// Creates an empty queue.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `value` at the back of the queue.
+    pub fn enqueue(&self, value: T) {
+        let shield = Shield::new();
+        let node = QueueNode::boxed(MaybeUninit::new(value));
+        loop {
+            let tail = shield.protect(&self.tail);
+            // SAFETY: `tail` is never null, and isn't freed while protected.
+            let tail_next = unsafe { &(*tail).next };
+            let next = tail_next.load(Ordering::Acquire);
+            if !next.is_null() {
+                // `tail` is lagging behind: helps the enqueuing thread swing it forward.
+                let _ =
+                    self.tail
+                        .compare_exchange(tail, next, Ordering::Release, Ordering::Relaxed);
+                continue;
+            }
+            // Release so that a thread dequeuing the node sees its data.
+            if tail_next
+                .compare_exchange(ptr::null_mut(), node, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                // May fail if another thread swung it forward already.
+                let _ =
+                    self.tail
+                        .compare_exchange(tail, node, Ordering::Release, Ordering::Relaxed);
+                return;
+            }
+        }
+    }
+
+    /// Removes the value at the front of the queue, if any.
+    pub fn dequeue(&self) -> Option<T> {
+        let head_shield = Shield::new();
+        let next_shield = Shield::new();
+        loop {
+            let head = head_shield.protect(&self.head);
+            // SAFETY: `head` is never null, and isn't freed while protected.
+            let next = unsafe { &(*head).next }.load(Ordering::Acquire);
+            if next.is_null() {
+                return None;
+            }
+            next_shield.set(next);
+            // `next` was reachable when protected only if `head` still is the sentinel: it is
+            // retired after `head` is.
+            if self.head.load(Ordering::Acquire) != head {
+                continue;
+            }
+
+            // Swings `tail` forward before `head` passes it, so that it never points to a retired
+            // node.
+            let tail = self.tail.load(Ordering::Acquire);
+            if tail == head {
+                let _ =
+                    self.tail
+                        .compare_exchange(tail, next, Ordering::Release, Ordering::Relaxed);
+                continue;
+            }
+
+            if self
+                .head
+                .compare_exchange(head, next, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                // SAFETY: Only the thread that made `next` the sentinel moves its data out, and
+                // `next` is protected. The old sentinel is unlinked by this thread.
+                unsafe {
+                    let data = (*next).data.assume_init_read();
+                    retire(head);
+                    return Some(data);
+                }
+            }
+        }
+    }
+
+    /// Returns whether the queue is empty. Other threads may change that right away.
+    pub fn is_empty(&self) -> bool {
+        let shield = Shield::new();
+        let head = shield.protect(&self.head);
+        // SAFETY: `head` is never null, and isn't freed while protected.
+        unsafe { &(*head).next }.load(Ordering::Acquire).is_null()
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        while self.dequeue().is_some() {}
+        // SAFETY: No other thread has access to the queue anymore, and the sentinel's data is
+        // uninitialized or already moved out.
+        unsafe { drop(Box::from_raw(*self.head.get_mut())) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use super::{Queue, Stack};
+
+    const THREADS: usize = if cfg!(miri) { 2 } else { 4 };
+    const VALUES: usize = if cfg!(miri) { 50 } else { 10_000 };
+
+    #[test]
+    fn stack_pops_every_value_once() {
+        let stack = Stack::new();
+        let popped = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for producer in 0..THREADS {
+                let (stack, popped) = (&stack, &popped);
+                let _ = scope.spawn(move || {
+                    for value in 0..VALUES {
+                        stack.push(producer * VALUES + value);
+                    }
+                });
+                let _ = scope.spawn(move || {
+                    let mut mine = Vec::new();
+                    while mine.len() < VALUES {
+                        mine.extend(stack.pop());
+                    }
+                    popped.lock().unwrap().extend(mine);
+                });
+            }
+        });
+        let popped = popped.into_inner().unwrap();
+        assert_eq!(popped.len(), THREADS * VALUES);
+        assert_eq!(
+            popped.iter().collect::<HashSet<_>>().len(),
+            THREADS * VALUES
+        );
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn queue_keeps_the_order_of_each_producer() {
+        let queue = Queue::new();
+        thread::scope(|scope| {
+            for producer in 0..THREADS {
+                let queue = &queue;
+                let _ = scope.spawn(move || {
+                    for value in 0..VALUES {
+                        queue.enqueue((producer, value));
+                    }
+                });
+            }
+            for _ in 0..THREADS {
+                let queue = &queue;
+                let _ = scope.spawn(move || {
+                    let mut last = [None; THREADS];
+                    let mut dequeued = 0;
+                    while dequeued < VALUES {
+                        if let Some((producer, value)) = queue.dequeue() {
+                            assert!(last[producer] < Some(value));
+                            last[producer] = Some(value);
+                            dequeued += 1;
+                        }
+                    }
+                });
+            }
+        });
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn drop_frees_the_values_left() {
+        let value = Arc::new(());
+        let stack = Stack::new();
+        let queue = Queue::new();
+        for _ in 0..3 {
+            stack.push(value.clone());
+            queue.enqueue(value.clone());
+        }
+        drop(stack.pop());
+        drop(queue.dequeue());
+        drop((stack, queue));
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,148 @@
+//! Michael and Scott's lock-free queue.
+
+use alloc::boxed::Box;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use super::super::reclaim::epoch::pin;
+
+struct Node<T> {
+    /// Uninitialized in the sentinel node, and moved out when the node becomes the sentinel.
+    data: MaybeUninit<T>,
+    next: AtomicPtr<Node<T>>,
+}
+
+impl<T> Node<T> {
+    /// Allocates a node holding `data`.
+    fn boxed(data: MaybeUninit<T>) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            data,
+            next: AtomicPtr::new(ptr::null_mut()),
+        }))
+    }
+}
+
+/// Michael and Scott's lock-free queue.
+///
+/// The queue is a linked list from `head` to `tail` whose first node is a sentinel: the values
+/// are in the nodes after it. `enqueue` links a node after the last one and then swings `tail` to
+/// it, and `dequeue` swings `head` to the node after the sentinel, which becomes the new sentinel.
+/// A thread that finds `tail` lagging behind the last node swings it forward itself rather than
+/// waiting for the enqueuing thread, so no thread can block the others.
+///
+/// Like [`Stack`](super::Stack), dequeued nodes are freed by the [epoch-based garbage
+/// collector](super::super::reclaim::epoch) once no thread may read them anymore.
+pub struct Queue<T> {
+    head: AtomicPtr<Node<T>>,
+    tail: AtomicPtr<Node<T>>,
+}
+
+// The data is moved between threads, and `&Queue` only gives access to it by moving it out.
+unsafe impl<T: Send> Send for Queue<T> {}
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Self {
+        let sentinel = Node::boxed(MaybeUninit::uninit());
+        Self {
+            head: AtomicPtr::new(sentinel),
+            tail: AtomicPtr::new(sentinel),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Queue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Queue").finish_non_exhaustive()
+    }
+}
+
+impl<T> Queue<T> {
+    /// Creates an empty queue.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `value` at the back of the queue.
+    pub fn enqueue(&self, value: T) {
+        let _guard = pin();
+        let node = Node::boxed(MaybeUninit::new(value));
+        loop {
+            let tail = self.tail.load(Ordering::Acquire);
+            // SAFETY: `tail` is never null, and isn't freed while the guard is pinned.
+            let tail_next = unsafe { &(*tail).next };
+            let next = tail_next.load(Ordering::Acquire);
+            if !next.is_null() {
+                // `tail` is lagging behind: helps the enqueuing thread swing it forward.
+                let _ =
+                    self.tail
+                        .compare_exchange(tail, next, Ordering::Release, Ordering::Relaxed);
+                continue;
+            }
+            // Release so that a thread dequeuing the node sees its data.
+            if tail_next
+                .compare_exchange(ptr::null_mut(), node, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                // May fail if another thread swung it forward already.
+                let _ =
+                    self.tail
+                        .compare_exchange(tail, node, Ordering::Release, Ordering::Relaxed);
+                return;
+            }
+        }
+    }
+
+    /// Removes the value at the front of the queue, if any.
+    pub fn dequeue(&self) -> Option<T> {
+        let guard = pin();
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            // SAFETY: `head` is never null, and isn't freed while the guard is pinned.
+            let next = unsafe { (*head).next.load(Ordering::Acquire) };
+            // SAFETY: Same as above.
+            let next_ref = unsafe { next.as_ref() }?;
+
+            // Swings `tail` forward before `head` passes it, so that it never points to a freed
+            // node.
+            let tail = self.tail.load(Ordering::Relaxed);
+            if tail == head {
+                let _ =
+                    self.tail
+                        .compare_exchange(tail, next, Ordering::Release, Ordering::Relaxed);
+            }
+
+            if self
+                .head
+                .compare_exchange(head, next, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                // SAFETY: Only the thread that made `next` the sentinel moves its data out, and
+                // the old sentinel is freed once no pinned thread may read it anymore.
+                unsafe {
+                    guard.defer_destroy(head);
+                    return Some(next_ref.data.assume_init_read());
+                }
+            }
+        }
+    }
+
+    /// Returns whether the queue is empty. Other threads may change that right away.
+    pub fn is_empty(&self) -> bool {
+        let _guard = pin();
+        let head = self.head.load(Ordering::Acquire);
+        // SAFETY: `head` is never null, and isn't freed while the guard is pinned.
+        unsafe { (*head).next.load(Ordering::Acquire) }.is_null()
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        while self.dequeue().is_some() {}
+        // SAFETY: No other thread has access to the queue anymore, and the sentinel's data is
+        // uninitialized or already moved out.
+        unsafe { drop(Box::from_raw(*self.head.get_mut())) };
+    }
+}
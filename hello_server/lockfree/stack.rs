@@ -0,0 +1,210 @@
+//! Treiber's lock-free stack.
+
+use alloc::boxed::Box;
+use core::fmt;
+use core::mem::ManuallyDrop;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use super::super::reclaim::epoch::{pin, Guard};
+
+#[derive(Debug)]
+pub(super) struct Node<T> {
+    /// Moved out by the thread that pops the node, while others may still read `next`.
+    data: ManuallyDrop<T>,
+    next: *mut Node<T>,
+}
+
+impl<T> Node<T> {
+    /// Allocates a node holding `value`.
+    pub(super) fn boxed(value: T) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            data: ManuallyDrop::new(value),
+            next: ptr::null_mut(),
+        }))
+    }
+
+    /// Moves the value out of `node`, which is freed once no pinned thread may read it anymore.
+    ///
+    /// # Safety
+    ///
+    /// Only the thread that made the node unreachable for threads pinned from now on may call it,
+    /// and only once.
+    pub(super) unsafe fn take(node: *mut Self, guard: &Guard) -> T {
+        let data = ManuallyDrop::into_inner(ptr::read(&(*node).data));
+        guard.defer_destroy(node);
+        data
+    }
+}
+
+/// Treiber's lock-free stack.
+///
+/// The top of the stack is an atomic pointer to a linked list of nodes, which `push` and `pop`
+/// swing with compare-and-swap, retrying if another thread got there first.
+///
+/// A popped node can't be freed right away, since threads that loaded it before the pop may still
+/// read its `next`. It is handed to the [epoch-based garbage
+/// collector](super::super::reclaim::epoch) instead, which frees it once every thread that was
+/// pinned at the time has unpinned, so nodes are never used after they are freed, nor reused while
+/// a stale pointer to them may be compared (the ABA problem).
+pub struct Stack<T> {
+    head: AtomicPtr<Node<T>>,
+}
+
+// The data is moved between threads, and `&Stack` only gives access to it by moving it out.
+unsafe impl<T: Send> Send for Stack<T> {}
+unsafe impl<T: Send> Sync for Stack<T> {}
+
+impl<T> Default for Stack<T> {
+    fn default() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Stack<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stack").finish_non_exhaustive()
+    }
+}
+
+impl<T> Stack<T> {
+    /// Creates an empty stack.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pushes `value` on the top of the stack.
+    pub fn push(&self, value: T) {
+        let node = Node::boxed(value);
+        while !self.try_push(node) {}
+    }
+
+    /// Pushes `node` on the top of the stack, unless another thread changed the top meanwhile.
+    pub(super) fn try_push(&self, node: *mut Node<T>) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        // SAFETY: The node isn't shared until the CAS succeeds.
+        unsafe { (*node).next = head };
+        // Release so that a thread popping the node sees its data.
+        self.head
+            .compare_exchange(head, node, Ordering::Release, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Pops the value on the top of the stack, if any.
+    pub fn pop(&self) -> Option<T> {
+        let guard = pin();
+        loop {
+            if let Ok(value) = self.try_pop(&guard) {
+                return value;
+            }
+        }
+    }
+
+    /// Pops the value on the top of the stack, if any, unless another thread changed the top
+    /// meanwhile.
+    pub(super) fn try_pop(&self, guard: &Guard) -> Result<Option<T>, ()> {
+        // Acquire so that the node's data and `next` are seen as the pushing thread wrote them.
+        let head = self.head.load(Ordering::Acquire);
+        // SAFETY: The node isn't freed while the guard is pinned.
+        let Some(node) = (unsafe { head.as_ref() }) else {
+            return Ok(None);
+        };
+        self.head
+            .compare_exchange(head, node.next, Ordering::Relaxed, Ordering::Relaxed)
+            .map_err(|_| ())?;
+        // SAFETY: The node is unlinked by this thread.
+        Ok(Some(unsafe { Node::take(head, guard) }))
+    }
+
+    /// Returns whether the stack is empty. Other threads may change that right away.
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed).is_null()
+    }
+}
+
+impl<T> Drop for Stack<T> {
+    fn drop(&mut self) {
+        let mut node = *self.head.get_mut();
+        while !node.is_null() {
+            // SAFETY: No other thread has access to the stack anymore, and the nodes left weren't
+            // popped.
+            unsafe {
+                let mut owned = Box::from_raw(node);
+                node = owned.next;
+                ManuallyDrop::drop(&mut owned.data);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+
+    use super::Stack;
+
+    const THREADS: usize = 8;
+    const VALUES: usize = 10_000;
+
+    #[test]
+    fn pops_every_value_once() {
+        let stack = Stack::new();
+        let popped = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for producer in 0..THREADS {
+                let stack = &stack;
+                let _ = scope.spawn(move || {
+                    for value in 0..VALUES {
+                        stack.push(producer * VALUES + value);
+                    }
+                });
+            }
+            for _ in 0..THREADS {
+                let (stack, popped) = (&stack, &popped);
+                let _ = scope.spawn(move || {
+                    let mut mine = Vec::new();
+                    while mine.len() < VALUES {
+                        mine.extend(stack.pop());
+                    }
+                    popped.lock().unwrap().extend(mine);
+                });
+            }
+        });
+        let mut popped = popped.into_inner().unwrap();
+        popped.sort_unstable();
+        assert!(popped.into_iter().eq(0..THREADS * VALUES));
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn pushing_and_popping_threads_drop_every_value_once() {
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+        struct Counted;
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                let _ = DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let stack = Stack::new();
+        thread::scope(|scope| {
+            for _ in 0..THREADS {
+                let stack = &stack;
+                let _ = scope.spawn(move || {
+                    for _ in 0..VALUES {
+                        stack.push(Counted);
+                        drop(stack.pop());
+                    }
+                });
+            }
+        });
+        assert!(stack.pop().is_none());
+        assert_eq!(DROPPED.load(Ordering::Relaxed), THREADS * VALUES);
+    }
+}
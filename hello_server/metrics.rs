@@ -0,0 +1,206 @@
+//! Prometheus metrics.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use super::cache::MetricsSink;
+use super::handler::Handler;
+use super::http::{Request, Response};
+use super::middleware::{Middleware, Next};
+use super::router::Router;
+use super::thread_pool::PoolHandle;
+
+/// Media type of the Prometheus text format.
+const PROMETHEUS_MEDIA_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Upper bounds in seconds of the buckets of the histograms.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Methods counted under their own name. Others are counted as `other`.
+const METHODS: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS", "CONNECT", "TRACE",
+];
+
+/// Observations of a histogram.
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Number of observations in each bucket of [`BUCKETS`], not cumulative.
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(bucket) = BUCKETS.iter().position(|bound| value <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(self.buckets) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum {}", self.sum);
+        let _ = writeln!(out, "{name}_count {}", self.count);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    /// Number of requests by method and status.
+    requests: BTreeMap<(&'static str, u16), u64>,
+    latency: Histogram,
+    /// Metrics reported through [`MetricsSink`].
+    counters: BTreeMap<&'static str, u64>,
+    gauges: BTreeMap<&'static str, f64>,
+    histograms: BTreeMap<&'static str, Histogram>,
+}
+
+/// Request, thread pool, and cache metrics, served in the Prometheus text format.
+///
+/// As a middleware, it counts the requests by method and status and records how long they take to
+/// handle. As a [`MetricsSink`] given to [`CacheBuilder::metrics_sink`](super::CacheBuilder), it
+/// collects the cache's metrics, from which the hit ratio is computed. The thread pool set with
+/// [`Metrics::pool`] is sampled when the metrics are served.
+///
+/// Clones share the same metrics.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+    pool: Option<PoolHandle>,
+}
+
+impl Metrics {
+    /// Creates empty metrics.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reports the number of threads, of queued and running jobs, and of completed jobs of `pool`.
+    pub fn pool(mut self, pool: PoolHandle) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Serves the metrics at `path` and records the requests to `router`.
+    pub fn mount(self, router: &mut Router, path: &str) {
+        let _ = router.get(path, self.clone()).wrap(self);
+    }
+
+    /// Returns the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# TYPE http_requests_total counter");
+        for ((method, status), count) in &registry.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{method}\",status=\"{status}\"}} {count}"
+            );
+        }
+        registry
+            .latency
+            .render(&mut out, "http_request_duration_seconds");
+
+        if let Some(pool) = &self.pool {
+            let jobs = pool.jobs();
+            let queued = pool.queued();
+            for (name, value) in [
+                ("thread_pool_threads", pool.size()),
+                ("thread_pool_queued_jobs", queued),
+                ("thread_pool_running_jobs", jobs.saturating_sub(queued)),
+            ] {
+                let _ = writeln!(out, "# TYPE {name} gauge\n{name} {value}");
+            }
+            let _ = writeln!(
+                out,
+                "# TYPE thread_pool_completed_jobs_total counter\nthread_pool_completed_jobs_total {}",
+                pool.completed()
+            );
+        }
+
+        for (name, value) in &registry.counters {
+            let _ = writeln!(out, "# TYPE {name}_total counter\n{name}_total {value}");
+        }
+        for (name, value) in &registry.gauges {
+            let _ = writeln!(out, "# TYPE {name} gauge\n{name} {value}");
+        }
+        for (name, histogram) in &registry.histograms {
+            histogram.render(&mut out, name);
+        }
+        let hits = registry.counters.get("cache_hits").copied().unwrap_or(0);
+        let misses = registry.counters.get("cache_misses").copied().unwrap_or(0);
+        if hits + misses > 0 {
+            let ratio = hits as f64 / (hits + misses) as f64;
+            let _ = writeln!(out, "# TYPE cache_hit_ratio gauge\ncache_hit_ratio {ratio}");
+        }
+        out
+    }
+}
+
+impl Middleware for Metrics {
+    fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        let method = METHODS
+            .into_iter()
+            .find(|method| *method == request.method)
+            .unwrap_or("other");
+        let start = Instant::now();
+        let response = next.run(request);
+        let elapsed = start.elapsed().as_secs_f64();
+
+        let mut registry = self.registry.lock().unwrap();
+        *registry
+            .requests
+            .entry((method, response.status))
+            .or_default() += 1;
+        registry.latency.observe(elapsed);
+        response
+    }
+}
+
+impl MetricsSink for Metrics {
+    fn counter(&self, name: &'static str, delta: u64) {
+        *self
+            .registry
+            .lock()
+            .unwrap()
+            .counters
+            .entry(name)
+            .or_default() += delta;
+    }
+
+    fn gauge(&self, name: &'static str, value: f64) {
+        let _ = self.registry.lock().unwrap().gauges.insert(name, value);
+    }
+
+    fn histogram(&self, name: &'static str, value: f64) {
+        self.registry
+            .lock()
+            .unwrap()
+            .histograms
+            .entry(name)
+            .or_default()
+            .observe(value);
+    }
+}
+
+impl Handler for Metrics {
+    fn call(&self, _request: Request) -> Response {
+        Response::new(200)
+            .with_header("Content-Type", PROMETHEUS_MEDIA_TYPE)
+            .with_header("Cache-Control", "no-store")
+            .with_body(self.render())
+    }
+}
@@ -0,0 +1,56 @@
+//! Middlewares wrapping the handlers of a router.
+
+use std::fmt;
+
+use super::handler::Handler;
+use super::http::{Request, Response};
+
+/// Code run around the handling of every request, e.g. logging or authentication.
+///
+/// A middleware may inspect or modify the request, pass it on with [`Next::run`] and then
+/// inspect or modify the response, or answer the request itself without calling `next`.
+/// Closures `Fn(Request, Next<'_>) -> Response` are middlewares too.
+pub trait Middleware: Send + Sync {
+    /// Handles `request`, calling `next` to pass it to the rest of the chain.
+    fn handle(&self, request: Request, next: Next<'_>) -> Response;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(Request, Next<'_>) -> Response + Send + Sync,
+{
+    fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        self(request, next)
+    }
+}
+
+/// The rest of a middleware chain, ending with the router's handler.
+pub struct Next<'a> {
+    middlewares: &'a [Box<dyn Middleware>],
+    endpoint: &'a dyn Handler,
+}
+
+impl fmt::Debug for Next<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Next")
+            .field("middlewares", &self.middlewares.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(middlewares: &'a [Box<dyn Middleware>], endpoint: &'a dyn Handler) -> Self {
+        Self {
+            middlewares,
+            endpoint,
+        }
+    }
+
+    /// Passes `request` to the next middleware, or to the handler if there are no more.
+    pub fn run(self, request: Request) -> Response {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => middleware.handle(request, Next::new(rest, self.endpoint)),
+            None => self.endpoint.call(request),
+        }
+    }
+}
@@ -1,13 +1,160 @@
 //! Hello server with a cache.
 
-mod cache;
+#[cfg(feature = "std")]
+mod access_log;
+#[cfg(feature = "std")]
+pub mod actor;
+#[cfg(feature = "std")]
+pub mod arc;
+pub mod cache;
+mod cache_padded;
+#[cfg(feature = "std")]
+mod compression;
+#[cfg(feature = "std")]
+pub mod concurrent;
+#[cfg(feature = "std")]
+mod config;
+#[cfg(feature = "std")]
+mod cookie;
+#[cfg(feature = "std")]
+pub mod deque;
+#[cfg(feature = "std")]
+mod error_pages;
+#[cfg(feature = "std")]
+pub mod event_bus;
+#[cfg(all(unix, feature = "std"))]
+mod event_loop;
+#[cfg(feature = "std")]
+pub mod executor;
+#[cfg(feature = "std")]
 mod handler;
+#[cfg(feature = "std")]
+mod health;
+#[cfg(feature = "std")]
+mod hello;
+#[cfg(feature = "std")]
+mod http;
+pub mod lockfree;
+#[cfg(feature = "std")]
+mod metrics;
+#[cfg(feature = "std")]
+mod middleware;
+#[cfg(feature = "std")]
+pub mod mpmc;
+#[cfg(feature = "std")]
+mod multipart;
+#[cfg(feature = "std")]
+pub mod oneshot;
+#[cfg(feature = "std")]
+pub mod pool;
+#[cfg(feature = "std")]
+mod proxy;
+#[cfg(feature = "std")]
+pub mod ratelimit;
+#[cfg(feature = "std")]
+pub mod reactor;
+pub mod reclaim;
+#[cfg(feature = "std")]
+mod response_cache;
+#[cfg(feature = "std")]
+mod router;
+#[cfg(feature = "std")]
+pub mod server;
+#[cfg(feature = "std")]
+mod session;
+pub mod spsc;
+#[cfg(feature = "std")]
+mod sse;
+#[cfg(feature = "std")]
+mod static_files;
+#[cfg(feature = "std")]
 mod statistics;
+#[cfg(feature = "std")]
+pub mod stm;
+#[cfg(feature = "std")]
+mod stream;
+pub mod sync;
+#[cfg(feature = "std")]
 mod tcp;
-mod thread_pool;
+#[cfg(feature = "std")]
+mod template;
+#[cfg(feature = "std")]
+pub mod testing;
+#[cfg(feature = "std")]
+pub mod thread_pool;
+mod trace;
+#[cfg(all(unix, feature = "std"))]
+mod unix;
+#[cfg(feature = "std")]
+mod vhost;
+#[cfg(feature = "std")]
+mod watch;
 
-pub use cache::Cache;
+#[cfg(feature = "std")]
+pub use access_log::{AccessLog, LogFormat};
+pub use cache::{
+    Cache, CacheBuilder, CacheConfig, CacheConfigError, CacheError, MetricsSink, RemovalCause,
+};
+#[cfg(feature = "std")]
+pub use compression::Compression;
+#[cfg(feature = "std")]
+pub use config::{Backend, ServerConfig, ServerConfigError};
+#[cfg(feature = "std")]
+pub use cookie::{Cookie, SameSite};
+#[cfg(feature = "std")]
+pub use error_pages::ErrorPages;
+#[cfg(feature = "std")]
+pub use event_bus::{EventBus, Subscription, Topic};
+#[cfg(all(unix, feature = "std"))]
+pub use event_loop::EventLoop;
+#[cfg(feature = "std")]
 pub use handler::Handler;
-pub use statistics::{Report, Statistics};
-pub use tcp::CancellableTcpListener;
-pub use thread_pool::ThreadPool;
+#[cfg(feature = "std")]
+pub use health::Health;
+#[cfg(feature = "std")]
+pub use hello::Hello;
+#[cfg(feature = "std")]
+pub use http::{
+    parse_urlencoded, percent_decode, percent_encode, Body, JsonError, Request, Response,
+};
+#[cfg(feature = "std")]
+pub use metrics::Metrics;
+#[cfg(feature = "std")]
+pub use middleware::{Middleware, Next};
+#[cfg(feature = "std")]
+pub use multipart::{Multipart, Part};
+#[cfg(feature = "std")]
+pub use proxy::Proxy;
+#[cfg(feature = "std")]
+pub use ratelimit::RateLimit;
+#[cfg(feature = "std")]
+pub use response_cache::ResponseCache;
+#[cfg(feature = "std")]
+pub use router::Router;
+#[cfg(feature = "std")]
+pub use server::{
+    handle_conn, reject_overloaded, Connections, Server, ServerError, ShutdownHandle, PAGE_CHANGED,
+    SHUTDOWN,
+};
+#[cfg(feature = "std")]
+pub use session::{Session, Sessions};
+#[cfg(feature = "std")]
+pub use sse::{Event, Sse};
+#[cfg(feature = "std")]
+pub use static_files::StaticFiles;
+#[cfg(feature = "std")]
+pub use statistics::{Report, Statistics, Summary};
+#[cfg(feature = "std")]
+pub use stream::Stream;
+#[cfg(feature = "std")]
+pub use tcp::{CancellableTcpListener, SocketOptions};
+#[cfg(feature = "std")]
+pub use template::{Template, TemplateError, Templates};
+#[cfg(feature = "std")]
+pub use thread_pool::{PoolError, PoolHandle, ThreadPool};
+#[cfg(all(unix, feature = "std"))]
+pub use unix::CancellableUnixListener;
+#[cfg(feature = "std")]
+pub use vhost::VirtualHosts;
+#[cfg(feature = "std")]
+pub use watch::FileWatcher;
@@ -0,0 +1,453 @@
+//! Bounded multi-producer multi-consumer channel.
+
+use std::cell::UnsafeCell;
+use std::error::Error;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use super::cache_padded::CachePadded;
+use super::sync::shim::{fence, AtomicUsize, Condvar, Mutex};
+
+/// A slot of the buffer. Its stamp tells which position it is ready for: twice the position of
+/// the next value to be pushed in it, or twice the position plus one of the value it holds.
+struct Slot<T> {
+    stamp: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// Dmitry Vyukov's bounded queue, with positions counting pushes and pops from the start, and
+/// blocking on top.
+struct Channel<T> {
+    slots: Box<[Slot<T>]>,
+    /// Position of the next value to pop.
+    head: CachePadded<AtomicUsize>,
+    /// Position of the next value to push.
+    tail: CachePadded<AtomicUsize>,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    /// Taken by blocked threads to wait on the condition variables, and by threads waking them.
+    lock: Mutex<()>,
+    blocked_senders: AtomicUsize,
+    blocked_receivers: AtomicUsize,
+    not_full: Condvar,
+    not_empty: Condvar,
+}
+
+// Each slot is accessed by one thread at a time, handed over by its stamp.
+unsafe impl<T: Send> Send for Channel<T> {}
+unsafe impl<T: Send> Sync for Channel<T> {}
+
+impl<T> Channel<T> {
+    fn push(&self, value: T) -> Result<(), T> {
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[tail % self.slots.len()];
+            // Acquire so that the value last in the slot was read before it is overwritten.
+            let stamp = slot.stamp.load(Ordering::Acquire);
+            if stamp == tail << 1 {
+                match self.tail.compare_exchange_weak(
+                    tail,
+                    tail.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // SAFETY: The slot is claimed by advancing the tail past it.
+                        unsafe { (*slot.value.get()).write(value) };
+                        // Release so that the receiver popping it sees the value.
+                        slot.stamp.store(tail << 1 | 1, Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => tail = current,
+                }
+            } else {
+                let current = self.tail.load(Ordering::Relaxed);
+                if current == tail {
+                    // The slot still holds the value pushed a lap before, or is being popped.
+                    return Err(value);
+                }
+                tail = current;
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<T> {
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[head % self.slots.len()];
+            // Acquire so that the value is seen as the sender wrote it.
+            let stamp = slot.stamp.load(Ordering::Acquire);
+            if stamp == head << 1 | 1 {
+                match self.head.compare_exchange_weak(
+                    head,
+                    head.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // SAFETY: The slot holds a value, claimed by advancing the head past it.
+                        let value = unsafe { (*slot.value.get()).assume_init_read() };
+                        // Release so that the sender pushing in the slot a lap later overwrites
+                        // the value only once it was read.
+                        slot.stamp
+                            .store(head.wrapping_add(self.slots.len()) << 1, Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(current) => head = current,
+                }
+            } else {
+                let current = self.head.load(Ordering::Relaxed);
+                if current == head {
+                    // The slot is waiting for the value, or it is being pushed.
+                    return None;
+                }
+                head = current;
+            }
+        }
+    }
+
+    fn try_push(&self, value: T) -> Result<(), TrySendError<T>> {
+        if self.receivers.load(Ordering::Relaxed) == 0 {
+            return Err(TrySendError::Disconnected(value));
+        }
+        self.push(value).map_err(TrySendError::Full)
+    }
+
+    fn try_pop(&self) -> Result<T, TryRecvError> {
+        match self.pop() {
+            Some(value) => Ok(value),
+            None if self.senders.load(Ordering::Acquire) > 0 => Err(TryRecvError::Empty),
+            // Tried again, since values may have been sent before the last sender was dropped.
+            None => self.pop().ok_or(TryRecvError::Disconnected),
+        }
+    }
+
+    fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Relaxed);
+        tail.wrapping_sub(head).min(self.slots.len())
+    }
+
+    /// Calls `attempt` until it returns `Some`, waiting on `condvar` in between. `attempt` may be
+    /// called with the lock held, so it must not wake other threads.
+    fn block<R>(
+        &self,
+        blocked: &AtomicUsize,
+        condvar: &Condvar,
+        mut attempt: impl FnMut() -> Option<R>,
+    ) -> R {
+        if let Some(result) = attempt() {
+            return result;
+        }
+        let mut guard = self.lock.lock().unwrap();
+        blocked.fetch_add(1, Ordering::Relaxed);
+        // See `wake`. The lock is held from here to waiting, so that a waking thread that sees
+        // this one blocked wakes it.
+        fence(Ordering::SeqCst);
+        let result = loop {
+            if let Some(result) = attempt() {
+                break result;
+            }
+            guard = condvar.wait(guard).unwrap();
+        };
+        blocked.fetch_sub(1, Ordering::Relaxed);
+        result
+    }
+
+    /// Wakes a thread blocked on `condvar` after a push or a pop.
+    fn wake(&self, blocked: &AtomicUsize, condvar: &Condvar) {
+        // Orders the push or pop before reading `blocked`, as `block` orders counting the thread
+        // before its next attempt. Either this sees the thread blocked, or its attempt sees the
+        // change.
+        fence(Ordering::SeqCst);
+        if blocked.load(Ordering::Relaxed) > 0 {
+            let _guard = self.lock.lock().unwrap();
+            condvar.notify_one();
+        }
+    }
+
+    /// Wakes every blocked thread after a disconnection.
+    fn wake_all(&self) {
+        let _guard = self.lock.lock().unwrap();
+        self.not_full.notify_all();
+        self.not_empty.notify_all();
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        // Loom's atomics have no `get_mut`, and there is no other thread left to race with.
+        let tail = self.tail.load(Ordering::Relaxed);
+        let mut head = self.head.load(Ordering::Relaxed);
+        while head != tail {
+            let slot = &mut self.slots[head % self.slots.len()];
+            // SAFETY: Slots from the head to the tail hold values pushed and not popped.
+            unsafe { slot.value.get_mut().assume_init_drop() };
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+/// Creates a channel holding at most `capacity` values, and returns its two ends. Both can be
+/// cloned to send or receive from several threads.
+///
+/// # Panics
+///
+/// Panics if `capacity` is 0.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert_ne!(capacity, 0, "capacity must be positive");
+    let channel = Arc::new(Channel {
+        slots: (0..capacity)
+            .map(|position| Slot {
+                stamp: AtomicUsize::new(position << 1),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect(),
+        head: CachePadded::default(),
+        tail: CachePadded::default(),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        lock: Mutex::new(()),
+        blocked_senders: AtomicUsize::new(0),
+        blocked_receivers: AtomicUsize::new(0),
+        not_full: Condvar::new(),
+        not_empty: Condvar::new(),
+    });
+    (
+        Sender {
+            channel: Arc::clone(&channel),
+        },
+        Receiver { channel },
+    )
+}
+
+/// The sending end of a [`channel`].
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            channel: Arc::clone(&self.channel),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.channel.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.channel.wake_all();
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Sends `value` unless the channel is full or every receiver was dropped.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let channel = &*self.channel;
+        channel.try_push(value)?;
+        channel.wake(&channel.blocked_receivers, &channel.not_empty);
+        Ok(())
+    }
+
+    /// Sends `value`, blocking while the channel is full. Fails if every receiver was dropped.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let channel = &*self.channel;
+        let mut value = Some(value);
+        channel.block(
+            &channel.blocked_senders,
+            &channel.not_full,
+            || match channel.try_push(value.take().unwrap()) {
+                Ok(()) => Some(Ok(())),
+                Err(TrySendError::Disconnected(v)) => Some(Err(SendError(v))),
+                Err(TrySendError::Full(v)) => {
+                    value = Some(v);
+                    None
+                }
+            },
+        )?;
+        channel.wake(&channel.blocked_receivers, &channel.not_empty);
+        Ok(())
+    }
+
+    /// Returns the number of values in the channel.
+    pub fn len(&self) -> usize {
+        self.channel.len()
+    }
+
+    /// Returns whether the channel is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of values in the channel.
+    pub fn capacity(&self) -> usize {
+        self.channel.slots.len()
+    }
+}
+
+/// The receiving end of a [`channel`].
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.channel.receivers.fetch_add(1, Ordering::Relaxed);
+        Self {
+            channel: Arc::clone(&self.channel),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self.channel.receivers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.channel.wake_all();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Receives a value unless the channel is empty. Fails with [`TryRecvError::Disconnected`]
+    /// once it is empty and every sender was dropped.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let channel = &*self.channel;
+        let value = channel.try_pop()?;
+        channel.wake(&channel.blocked_senders, &channel.not_full);
+        Ok(value)
+    }
+
+    /// Receives a value, blocking while the channel is empty. Fails once it is empty and every
+    /// sender was dropped.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let channel = &*self.channel;
+        let value = channel.block(
+            &channel.blocked_receivers,
+            &channel.not_empty,
+            || match channel.try_pop() {
+                Ok(value) => Some(Ok(value)),
+                Err(TryRecvError::Disconnected) => Some(Err(RecvError)),
+                Err(TryRecvError::Empty) => None,
+            },
+        )?;
+        channel.wake(&channel.blocked_senders, &channel.not_full);
+        Ok(value)
+    }
+
+    /// Returns an iterator receiving values until the channel is empty and every sender was
+    /// dropped.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(|| self.recv().ok())
+    }
+
+    /// Returns the number of values in the channel.
+    pub fn len(&self) -> usize {
+        self.channel.len()
+    }
+
+    /// Returns whether the channel is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of values in the channel.
+    pub fn capacity(&self) -> usize {
+        self.channel.slots.len()
+    }
+}
+
+/// Error of [`Sender::send`] when every receiver was dropped, with the value not sent.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sending on a disconnected channel")
+    }
+}
+
+impl<T> Error for SendError<T> {}
+
+/// Error of [`Sender::try_send`], with the value not sent.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel is full.
+    Full(T),
+    /// Every receiver was dropped.
+    Disconnected(T),
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(_) => f.debug_tuple("Full").finish_non_exhaustive(),
+            Self::Disconnected(_) => f.debug_tuple("Disconnected").finish_non_exhaustive(),
+        }
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(_) => write!(f, "sending on a full channel"),
+            Self::Disconnected(_) => write!(f, "sending on a disconnected channel"),
+        }
+    }
+}
+
+impl<T> Error for TrySendError<T> {}
+
+/// Error of [`Receiver::recv`] when the channel is empty and every sender was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "receiving on an empty and disconnected channel")
+    }
+}
+
+impl Error for RecvError {}
+
+/// Error of [`Receiver::try_recv`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// The channel is empty.
+    Empty,
+    /// The channel is empty and every sender was dropped.
+    Disconnected,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "receiving on an empty channel"),
+            Self::Disconnected => write!(f, "receiving on an empty and disconnected channel"),
+        }
+    }
+}
+
+impl Error for TryRecvError {}
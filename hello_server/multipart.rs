@@ -0,0 +1,265 @@
+//! `multipart/form-data` bodies.
+
+use std::io::{self, Read};
+
+/// Size of the reads from the underlying reader.
+const READ_LEN: usize = 8 * 1024;
+
+/// Maximum length of the headers of a part in bytes.
+const MAX_PART_HEAD_LEN: usize = 16 * 1024;
+
+/// Default maximum length of the body of a part in bytes.
+const DEFAULT_MAX_PART_LEN: u64 = 1024 * 1024;
+
+/// Where the parser is in the body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Before the first boundary.
+    Preamble,
+    /// In the body of a part.
+    Part,
+    /// Right after a boundary.
+    Boundary,
+    /// After the closing boundary.
+    Done,
+}
+
+/// Streaming parser of a `multipart/form-data` body, e.g. a form with file uploads.
+///
+/// Parts are read one at a time with [`Multipart::next_part`], and the body of each part is read
+/// from the returned [`Part`] as it arrives, so that only a few kilobytes are held in memory
+/// whatever the size of the uploads. A part whose body is longer than the limit set with
+/// [`Multipart::max_part_len`] fails with a `FileTooLarge` error.
+///
+/// ```ignore
+/// let mut multipart = request.multipart()?;
+/// while let Some(mut part) = multipart.next_part()? {
+///     if part.file_name().is_some() {
+///         io::copy(&mut part, &mut file)?;
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Multipart<R> {
+    reader: R,
+    /// `\r\n--` followed by the boundary. The body is read as if it started with `\r\n`, so that
+    /// the first boundary is found like the others.
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    pos: usize,
+    state: State,
+    part_len: u64,
+    max_part_len: u64,
+}
+
+impl<R: Read> Multipart<R> {
+    /// Creates a parser of the body read from `reader` with parts separated by `boundary`, the
+    /// parameter of the body's `Content-Type`.
+    pub fn new(reader: R, boundary: &str) -> Self {
+        Self {
+            reader,
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            buf: b"\r\n".to_vec(),
+            pos: 0,
+            state: State::Preamble,
+            part_len: 0,
+            max_part_len: DEFAULT_MAX_PART_LEN,
+        }
+    }
+
+    /// Sets the maximum length of the body of each part in bytes. Defaults to 1 MiB.
+    pub fn max_part_len(mut self, len: u64) -> Self {
+        self.max_part_len = len;
+        self
+    }
+
+    /// Returns the next part, or `None` after the last one. The rest of the previous part is
+    /// skipped.
+    ///
+    /// Returns an `InvalidData` error if the body is malformed.
+    pub fn next_part(&mut self) -> io::Result<Option<Part<'_, R>>> {
+        // Skips the preamble or the rest of the previous part.
+        let mut skipped = [0; READ_LEN];
+        while matches!(self.state, State::Preamble | State::Part) {
+            let _ = self.read_part(&mut skipped, false)?;
+        }
+        if self.state == State::Done {
+            return Ok(None);
+        }
+
+        // The closing boundary is followed by `--`, and any other by optional whitespace. The
+        // epilogue after the closing boundary is ignored.
+        while self.buf.len() - self.pos < 2 {
+            if !self.fill()? {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        if self.buf[self.pos..].starts_with(b"--") {
+            self.state = State::Done;
+            return Ok(None);
+        }
+        let line = self.read_line(MAX_PART_HEAD_LEN)?;
+        if !line.trim_matches([' ', '\t']).is_empty() {
+            return Err(invalid("malformed boundary"));
+        }
+
+        let mut headers = Vec::new();
+        let mut head_len = 0;
+        loop {
+            let line = self.read_line(MAX_PART_HEAD_LEN.saturating_sub(head_len))?;
+            if line.is_empty() {
+                break;
+            }
+            head_len += line.len() + 2;
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid("malformed part header"))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        self.state = State::Part;
+        self.part_len = 0;
+        Ok(Some(Part {
+            multipart: self,
+            headers,
+        }))
+    }
+
+    /// Reads from the body of the current part into `out`. Returns 0 at the end of the part.
+    fn read_part(&mut self, out: &mut [u8], limited: bool) -> io::Result<usize> {
+        if !matches!(self.state, State::Preamble | State::Part) || out.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let data = &self.buf[self.pos..];
+            let (len, end) = match find(data, &self.delimiter) {
+                Some(index) => (index, true),
+                // The end of the buffer may be the start of the delimiter.
+                None => (data.len().saturating_sub(self.delimiter.len() - 1), false),
+            };
+            if len == 0 && end {
+                self.pos += self.delimiter.len();
+                self.state = State::Boundary;
+                return Ok(0);
+            }
+            if len > 0 {
+                let len = len.min(out.len());
+                out[..len].copy_from_slice(&data[..len]);
+                self.pos += len;
+                if limited {
+                    self.part_len += len as u64;
+                    if self.part_len > self.max_part_len {
+                        return Err(io::ErrorKind::FileTooLarge.into());
+                    }
+                }
+                return Ok(len);
+            }
+            if !self.fill()? {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+
+    /// Reads a line ending with CRLF of at most `max_len` bytes, without the CRLF.
+    fn read_line(&mut self, max_len: usize) -> io::Result<String> {
+        loop {
+            let data = &self.buf[self.pos..];
+            if let Some(index) = find(data, b"\r\n") {
+                let line = std::str::from_utf8(&data[..index])
+                    .map_err(|_| invalid("part header is not UTF-8"))?
+                    .to_string();
+                self.pos += index + 2;
+                return Ok(line);
+            }
+            if data.len() > max_len {
+                return Err(invalid("part headers too long"));
+            }
+            if !self.fill()? {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+
+    /// Reads more of the body into the buffer. Returns `false` at the end of the body.
+    fn fill(&mut self) -> io::Result<bool> {
+        let _ = self.buf.drain(..self.pos);
+        self.pos = 0;
+        let len = self.buf.len();
+        self.buf.resize(len + READ_LEN, 0);
+        match self.reader.read(&mut self.buf[len..]) {
+            Ok(read) => {
+                self.buf.truncate(len + read);
+                Ok(read > 0)
+            }
+            Err(err) => {
+                self.buf.truncate(len);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A part of a `multipart/form-data` body, whose body is read with [`Read`].
+#[derive(Debug)]
+pub struct Part<'a, R> {
+    multipart: &'a mut Multipart<R>,
+    /// Header fields of the part, e.g. `Content-Disposition`.
+    pub headers: Vec<(String, String)>,
+}
+
+impl<R> Part<'_, R> {
+    /// Returns the value of the first header named `name` (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the name of the form field, the `name` parameter of `Content-Disposition`.
+    pub fn name(&self) -> Option<&str> {
+        header_param(self.header("Content-Disposition")?, "name")
+    }
+
+    /// Returns the name of the uploaded file, the `filename` parameter of
+    /// `Content-Disposition`, if the part is a file.
+    pub fn file_name(&self) -> Option<&str> {
+        header_param(self.header("Content-Disposition")?, "filename")
+    }
+
+    /// Returns the `Content-Type` of the part. Defaults to `text/plain` if there is none.
+    pub fn content_type(&self) -> &str {
+        self.header("Content-Type").unwrap_or("text/plain")
+    }
+}
+
+impl<R: Read> Read for Part<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.multipart.read_part(buf, true)
+    }
+}
+
+/// Returns the parameter `name` of a header value like `form-data; name="a"`, without quotes.
+pub(crate) fn header_param<'a>(value: &'a str, name: &str) -> Option<&'a str> {
+    value.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| {
+            let value = value.trim();
+            value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value)
+        })
+    })
+}
+
+/// Returns the index of the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
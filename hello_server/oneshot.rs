@@ -0,0 +1,212 @@
+//! Channel sending a single value.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+#[derive(Debug)]
+struct State<T> {
+    value: Option<T>,
+    sender_dropped: bool,
+    receiver_dropped: bool,
+}
+
+#[derive(Debug)]
+struct Inner<T> {
+    state: Mutex<State<T>>,
+    changed: Condvar,
+}
+
+impl<T> Inner<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        // Never poisoned, since no code of the user runs while it is held.
+        self.state.lock().unwrap()
+    }
+}
+
+/// Creates a channel sending a single value, e.g. the result of a job to the thread waiting for
+/// it, and returns its two ends.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        state: Mutex::new(State {
+            value: None,
+            sender_dropped: false,
+            receiver_dropped: false,
+        }),
+        changed: Condvar::new(),
+    });
+    (
+        Sender {
+            inner: Arc::clone(&inner),
+        },
+        Receiver { inner },
+    )
+}
+
+/// The sending end of a oneshot [`channel`], used up by sending.
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, unless the receiver was dropped.
+    pub fn send(self, value: T) -> Result<(), SendError<T>> {
+        let mut state = self.inner.lock();
+        if state.receiver_dropped {
+            return Err(SendError(value));
+        }
+        state.value = Some(value);
+        self.inner.changed.notify_one();
+        Ok(())
+    }
+
+    /// Returns whether the receiver was dropped, so that sending fails.
+    pub fn is_closed(&self) -> bool {
+        self.inner.lock().receiver_dropped
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.inner.lock().sender_dropped = true;
+        self.inner.changed.notify_one();
+    }
+}
+
+/// The receiving end of a oneshot [`channel`].
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Receives the value, blocking until it is sent. Fails if the sender was dropped without
+    /// sending, or the value was received already.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let state = self.inner.lock();
+        let mut state = self
+            .inner
+            .changed
+            .wait_while(state, |state| {
+                state.value.is_none() && !state.sender_dropped
+            })
+            .unwrap();
+        state.value.take().ok_or(RecvError)
+    }
+
+    /// Like [`Receiver::recv`], but gives up after `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let state = self.inner.lock();
+        let (mut state, _) = self
+            .inner
+            .changed
+            .wait_timeout_while(state, timeout, |state| {
+                state.value.is_none() && !state.sender_dropped
+            })
+            .unwrap();
+        match state.value.take() {
+            Some(value) => Ok(value),
+            None if state.sender_dropped => Err(RecvTimeoutError::Disconnected),
+            None => Err(RecvTimeoutError::Timeout),
+        }
+    }
+
+    /// Receives the value if it was sent.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.inner.lock();
+        match state.value.take() {
+            Some(value) => Ok(value),
+            None if state.sender_dropped => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.lock().receiver_dropped = true;
+    }
+}
+
+/// Error of [`Sender::send`] when the receiver was dropped, with the value not sent.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sending on a closed channel")
+    }
+}
+
+impl<T> Error for SendError<T> {}
+
+/// Error of [`Receiver::recv`] when the sender was dropped without sending, or the value was
+/// received already.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "receiving on a disconnected channel")
+    }
+}
+
+impl Error for RecvError {}
+
+/// Error of [`Receiver::recv_timeout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    /// The value wasn't sent in time.
+    Timeout,
+    /// The sender was dropped without sending, or the value was received already.
+    Disconnected,
+}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "timed out waiting on a channel"),
+            Self::Disconnected => write!(f, "receiving on a disconnected channel"),
+        }
+    }
+}
+
+impl Error for RecvTimeoutError {}
+
+/// Error of [`Receiver::try_recv`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// The value wasn't sent yet.
+    Empty,
+    /// The sender was dropped without sending, or the value was received already.
+    Disconnected,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "receiving on an empty channel"),
+            Self::Disconnected => write!(f, "receiving on a disconnected channel"),
+        }
+    }
+}
+
+impl Error for TryRecvError {}
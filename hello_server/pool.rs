@@ -0,0 +1,271 @@
+//! Pool of reusable objects.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+type Create<T> = Box<dyn Fn() -> io::Result<T> + Send + Sync>;
+type HealthCheck<T> = Box<dyn Fn(&mut T) -> bool + Send + Sync>;
+
+/// An object waiting in the pool.
+#[derive(Debug)]
+struct Idle<T> {
+    object: T,
+    /// When the object was returned.
+    since: Instant,
+}
+
+#[derive(Debug)]
+struct State<T> {
+    /// The objects waiting to be checked out, least recently returned first.
+    idle: VecDeque<Idle<T>>,
+    /// Number of objects idle, checked out, or being created.
+    size: usize,
+}
+
+/// Lends objects that are expensive to create, e.g. connections to an upstream server, and takes
+/// them back for reuse.
+///
+/// At most `max_size` objects exist at a time, and checking one out blocks while they are all
+/// checked out. The most recently returned object is checked out first, so that the others stay
+/// idle long enough to expire if the pool is oversized, and it is health-checked first if the
+/// pool has a check, e.g. that a connection wasn't closed by the peer meanwhile.
+///
+/// ```ignore
+/// let pool = Arc::new(ObjectPool::new(8, || TcpStream::connect(addr)).idle_timeout(timeout));
+/// let mut conn = pool.get()?;
+/// conn.write_all(b"ping")?;
+/// // Returned to the pool when dropped.
+/// ```
+pub struct ObjectPool<T> {
+    create: Create<T>,
+    health_check: Option<HealthCheck<T>>,
+    max_size: usize,
+    idle_timeout: Option<Duration>,
+    state: Mutex<State<T>>,
+    /// Notified when an object is returned or discarded.
+    available: Condvar,
+}
+
+impl<T> fmt::Debug for ObjectPool<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ObjectPool")
+            .field("size", &self.size())
+            .field("idle", &self.idle())
+            .field("max_size", &self.max_size)
+            .field("idle_timeout", &self.idle_timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> ObjectPool<T> {
+    /// Creates an empty pool of at most `max_size` objects, created with `create` as needed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_size` is 0.
+    pub fn new(
+        max_size: usize,
+        create: impl Fn() -> io::Result<T> + Send + Sync + 'static,
+    ) -> Self {
+        assert_ne!(max_size, 0, "max_size must be positive");
+        Self {
+            create: Box::new(create),
+            health_check: None,
+            max_size,
+            idle_timeout: None,
+            state: Mutex::new(State {
+                idle: VecDeque::new(),
+                size: 0,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Drops the objects that stayed idle for `timeout`. Defaults to never.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timeout is zero.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        assert_ne!(timeout, Duration::ZERO, "timeout must be positive");
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Checks idle objects with `check` before checking them out, and drops those failing it.
+    pub fn health_check(mut self, check: impl Fn(&mut T) -> bool + Send + Sync + 'static) -> Self {
+        self.health_check = Some(Box::new(check));
+        self
+    }
+
+    /// Checks out an object, blocking while `max_size` are checked out.
+    pub fn get(self: &Arc<Self>) -> io::Result<Pooled<T>> {
+        self.checkout(None)
+    }
+
+    /// Like [`ObjectPool::get`], but fails with `TimedOut` after `timeout`.
+    pub fn get_timeout(self: &Arc<Self>, timeout: Duration) -> io::Result<Pooled<T>> {
+        self.checkout(Some(Instant::now() + timeout))
+    }
+
+    fn checkout(self: &Arc<Self>, deadline: Option<Instant>) -> io::Result<Pooled<T>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let expired = self.expire(&mut state);
+            if !expired.is_empty() {
+                drop(state);
+                drop(expired);
+                state = self.state.lock().unwrap();
+                continue;
+            }
+            if let Some(Idle { mut object, .. }) = state.idle.pop_back() {
+                drop(state);
+                if self
+                    .health_check
+                    .as_ref()
+                    .is_none_or(|check| check(&mut object))
+                {
+                    return Ok(self.lend(object));
+                }
+                drop(object);
+                state = self.state.lock().unwrap();
+                state.size -= 1;
+                continue;
+            }
+            if state.size < self.max_size {
+                state.size += 1;
+                drop(state);
+                return match (self.create)() {
+                    Ok(object) => Ok(self.lend(object)),
+                    Err(err) => {
+                        self.release();
+                        Err(err)
+                    }
+                };
+            }
+            state = match deadline {
+                None => self.available.wait(state).unwrap(),
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    if timeout.is_zero() {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
+                    self.available.wait_timeout(state, timeout).unwrap().0
+                }
+            };
+        }
+    }
+
+    /// Removes the objects idle for longer than the idle timeout, to be dropped without the lock.
+    fn expire(&self, state: &mut State<T>) -> Vec<T> {
+        let Some(timeout) = self.idle_timeout else {
+            return Vec::new();
+        };
+        let mut expired = Vec::new();
+        while state
+            .idle
+            .front()
+            .is_some_and(|idle| idle.since.elapsed() >= timeout)
+        {
+            expired.push(state.idle.pop_front().unwrap().object);
+        }
+        state.size -= expired.len();
+        expired
+    }
+
+    fn lend(self: &Arc<Self>, object: T) -> Pooled<T> {
+        Pooled {
+            pool: self.clone(),
+            object: Some(object),
+        }
+    }
+
+    /// Takes `object` back for reuse.
+    fn checkin(&self, object: T) {
+        self.state.lock().unwrap().idle.push_back(Idle {
+            object,
+            since: Instant::now(),
+        });
+        self.available.notify_one();
+    }
+
+    /// Frees the place of an object that was dropped or detached.
+    fn release(&self) {
+        self.state.lock().unwrap().size -= 1;
+        self.available.notify_one();
+    }
+
+    /// Returns the number of objects idle or checked out. Other threads may change it right away.
+    pub fn size(&self) -> usize {
+        self.state.lock().unwrap().size
+    }
+
+    /// Returns the number of idle objects. Other threads may change it right away.
+    pub fn idle(&self) -> usize {
+        self.state.lock().unwrap().idle.len()
+    }
+}
+
+/// An object checked out of an [`ObjectPool`], returned to it when dropped.
+///
+/// An object dropped while its thread panics is dropped instead, since it may have been left
+/// broken, e.g. a connection in the middle of a response.
+pub struct Pooled<T> {
+    pool: Arc<ObjectPool<T>>,
+    /// Taken when the object is discarded or detached.
+    object: Option<T>,
+}
+
+impl<T: fmt::Debug> fmt::Debug for Pooled<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T> Pooled<T> {
+    /// Drops the object instead of returning it to the pool, e.g. a connection that failed.
+    pub fn discard(mut this: Self) {
+        drop(this.object.take());
+        this.pool.release();
+    }
+
+    /// Takes the object out of the pool for good, making room for another.
+    pub fn detach(mut this: Self) -> T {
+        let object = this.object.take().expect("taken once");
+        this.pool.release();
+        object
+    }
+}
+
+impl<T> Deref for Pooled<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.object.as_ref().expect("taken when consumed")
+    }
+}
+
+impl<T> DerefMut for Pooled<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.object.as_mut().expect("taken when consumed")
+    }
+}
+
+impl<T> Drop for Pooled<T> {
+    fn drop(&mut self) {
+        let Some(object) = self.object.take() else {
+            return;
+        };
+        if thread::panicking() {
+            drop(object);
+            self.pool.release();
+        } else {
+            self.pool.checkin(object);
+        }
+    }
+}
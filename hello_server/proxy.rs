@@ -0,0 +1,382 @@
+//! Reverse proxy.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::handler::Handler;
+use super::http::{copy_chunked, Body, Request, Response};
+use super::pool::{ObjectPool, Pooled};
+use super::router::Router;
+
+/// Timeout of connecting to the upstream server.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default timeout of reads from and writes to the upstream server.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Headers that only describe a single connection, which are not forwarded.
+const HOP_BY_HOP: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// Forwards requests to an upstream server.
+///
+/// The request is sent with the upstream address as `Host`, the client is recorded in
+/// `X-Forwarded-For`, `X-Forwarded-Host`, and `X-Forwarded-Proto`, and the request ID is passed on
+/// in `X-Request-Id` so that the logs of both servers can be correlated. Hop-by-hop headers are
+/// dropped in both directions, and the upstream response body is streamed back as it arrives.
+///
+/// A request that can't be forwarded, because the upstream server is unreachable or its response
+/// is malformed, is answered with 502, and one whose upstream response times out with 504.
+///
+/// Each request is forwarded on a new connection, unless connections are pooled with
+/// [`Proxy::pool`].
+#[derive(Debug, Clone)]
+pub struct Proxy {
+    upstream: String,
+    timeout: Duration,
+    pool: Option<Arc<ObjectPool<BufReader<TcpStream>>>>,
+}
+
+impl Proxy {
+    /// Creates a proxy forwarding to `upstream`, a `host:port` address.
+    pub fn new(upstream: impl Into<String>) -> Self {
+        Self {
+            upstream: upstream.into(),
+            timeout: DEFAULT_TIMEOUT,
+            pool: None,
+        }
+    }
+
+    /// Sets how long a read from or a write to the upstream server may block. Defaults to 30
+    /// seconds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timeout is zero.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        assert_ne!(timeout, Duration::ZERO, "timeout must be positive");
+        self.timeout = timeout;
+        self
+    }
+
+    /// Keeps the connections to the upstream server open for the next requests, at most
+    /// `max_size` of them, and closes those unused for `idle_timeout`.
+    ///
+    /// A connection is reused once the upstream response is read whole, unless the upstream server
+    /// closes it. A request waits for a connection while `max_size` are in use, and is answered
+    /// with 504 if none is freed within the timeout (see [`Proxy::timeout`]).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_size` or `idle_timeout` is zero.
+    pub fn pool(mut self, max_size: usize, idle_timeout: Duration) -> Self {
+        let upstream = self.upstream.clone();
+        let pool = ObjectPool::new(max_size, move || connect(&upstream).map(BufReader::new))
+            .idle_timeout(idle_timeout)
+            .health_check(is_idle);
+        self.pool = Some(Arc::new(pool));
+        self
+    }
+
+    /// Forwards requests with any method whose path starts with `prefix` to the upstream server.
+    /// The path is forwarded unchanged.
+    pub fn mount(self, router: &mut Router, prefix: &str) {
+        let pattern = format!("{}/*path", prefix.trim_end_matches('/'));
+        let _ = router.any(&pattern, self);
+    }
+
+    /// Forwards `request` to the upstream server and answers with its response.
+    pub fn handle(&self, request: Request) -> Response {
+        self.forward(request).unwrap_or_else(|err| {
+            println!("[proxy] failed to forward to {}: {err}", self.upstream);
+            let status = match err.kind() {
+                // The read timeout elapsed, reported as `WouldBlock` on some platforms.
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => 504,
+                _ => 502,
+            };
+            Response::status_page(status)
+        })
+    }
+
+    fn forward(&self, request: Request) -> io::Result<Response> {
+        let mut upstream = match &self.pool {
+            Some(pool) => Upstream(Some(Conn::Pooled(pool.get_timeout(self.timeout)?))),
+            None => Upstream(Some(Conn::Owned(BufReader::new(connect(&self.upstream)?)))),
+        };
+        let mut stream = upstream.stream();
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.write_all(&self.request_head(&request))?;
+        stream.write_all(&request.body)?;
+        stream.flush()?;
+
+        let mut response = loop {
+            let response = Response::read_head(&mut upstream)?;
+            // Interim responses such as 100 Continue are for the proxy only.
+            if !(100..200).contains(&response.status) {
+                break response;
+            }
+        };
+
+        // Any transfer coding but chunked is delimited by closing the connection.
+        let chunked = response.header("Transfer-Encoding").map(|value| {
+            let coding = value.rsplit(',').next().unwrap_or_default().trim();
+            coding.eq_ignore_ascii_case("chunked")
+        });
+        // Validated by `Response::read_head`.
+        let len = response
+            .header("Content-Length")
+            .map(|value| value.parse::<u64>().unwrap());
+        let reusable = self.pool.is_some()
+            && !response.header("Connection").is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|option| option.trim().eq_ignore_ascii_case("close"))
+            });
+        strip_hop_by_hop(&mut response.headers);
+        response
+            .headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case("Content-Length"));
+
+        // The body of a response to `HEAD` describes the upstream one but is never read, as the
+        // server doesn't send it.
+        let bodiless = matches!(response.status, 204 | 304);
+        if reusable && (bodiless || request.method == "HEAD") {
+            upstream.release();
+        }
+        response.body = if bodiless {
+            Body::default()
+        } else {
+            match (chunked, len) {
+                (Some(true), _) => Body::stream(move |writer| {
+                    copy_chunked(&mut upstream, writer, u64::MAX)?;
+                    if reusable {
+                        upstream.release();
+                    }
+                    Ok(())
+                }),
+                (None, Some(len)) => Body::Reader(
+                    Box::new(Delimited {
+                        upstream,
+                        remaining: len,
+                        reusable,
+                    }),
+                    len,
+                ),
+                _ => Body::stream(move |writer| io::copy(&mut upstream, writer).map(|_| ())),
+            }
+        };
+        Ok(response)
+    }
+
+    /// Returns the request line and headers forwarded for `request`.
+    fn request_head(&self, request: &Request) -> Vec<u8> {
+        let mut headers = request.headers.clone();
+        strip_hop_by_hop(&mut headers);
+        let forwarded_for = headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("X-Forwarded-For"))
+            .map(|(_, value)| value.as_str())
+            .chain(
+                request
+                    .remote_addr
+                    .map(|addr| addr.ip().to_string())
+                    .as_deref(),
+            )
+            .collect::<Vec<_>>()
+            .join(", ");
+        headers.retain(|(name, _)| {
+            ![
+                "Host",
+                "Content-Length",
+                "Expect",
+                "X-Forwarded-For",
+                "X-Request-Id",
+            ]
+            .iter()
+            .any(|header| name.eq_ignore_ascii_case(header))
+        });
+
+        let target = match &request.query {
+            Some(query) => format!("{}?{query}", request.path),
+            None => request.path.clone(),
+        };
+        let mut head = format!(
+            "{} {target} HTTP/1.1\r\nHost: {}\r\n",
+            request.method, self.upstream
+        );
+        for (name, value) in &headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if !forwarded_for.is_empty() {
+            head.push_str(&format!("X-Forwarded-For: {forwarded_for}\r\n"));
+        }
+        if let Some(host) = request.header("Host") {
+            head.push_str(&format!("X-Forwarded-Host: {host}\r\n"));
+        }
+        head.push_str("X-Forwarded-Proto: http\r\n");
+        if let Some(id) = &request.id {
+            head.push_str(&format!("X-Request-Id: {id}\r\n"));
+        }
+        // The body has been read whole, so it's forwarded with its length.
+        if !request.body.is_empty()
+            || request.header("Content-Length").is_some()
+            || request.is_chunked()
+        {
+            head.push_str(&format!("Content-Length: {}\r\n", request.body.len()));
+        }
+        if self.pool.is_none() {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+        head.into_bytes()
+    }
+}
+
+impl Handler for Proxy {
+    fn call(&self, request: Request) -> Response {
+        self.handle(request)
+    }
+}
+
+/// Connects to the first address of `upstream` that accepts the connection.
+fn connect(upstream: &str) -> io::Result<TcpStream> {
+    let mut last_err = None;
+    for addr in upstream.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| io::ErrorKind::NotFound.into()))
+}
+
+/// Returns whether a pooled connection can carry another request: the upstream server neither
+/// closed it nor sent anything unprompted.
+fn is_idle(conn: &mut BufReader<TcpStream>) -> bool {
+    if !conn.buffer().is_empty() {
+        return false;
+    }
+    let stream = conn.get_ref();
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let idle = matches!(
+        stream.peek(&mut [0]),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock
+    );
+    stream.set_nonblocking(false).is_ok() && idle
+}
+
+/// A connection to the upstream server, pooled or not.
+enum Conn {
+    Pooled(Pooled<BufReader<TcpStream>>),
+    Owned(BufReader<TcpStream>),
+}
+
+/// The connection of a request, closed when dropped unless released to the pool once the response
+/// is read whole, so that a connection is never reused in the middle of a response.
+struct Upstream(Option<Conn>);
+
+impl Upstream {
+    fn reader(&mut self) -> Option<&mut BufReader<TcpStream>> {
+        match self.0.as_mut()? {
+            Conn::Pooled(conn) => Some(conn),
+            Conn::Owned(conn) => Some(conn),
+        }
+    }
+
+    /// Returns the stream, to write the request.
+    fn stream(&mut self) -> &TcpStream {
+        self.reader().expect("not released yet").get_ref()
+    }
+
+    /// Returns the connection to the pool, if pooled. It reads as empty afterwards.
+    fn release(&mut self) {
+        drop(self.0.take());
+    }
+}
+
+impl Drop for Upstream {
+    fn drop(&mut self) {
+        if let Some(Conn::Pooled(conn)) = self.0.take() {
+            Pooled::discard(conn);
+        }
+    }
+}
+
+impl Read for Upstream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.reader() {
+            Some(reader) => reader.read(buf),
+            None => Ok(0),
+        }
+    }
+}
+
+impl BufRead for Upstream {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self.reader() {
+            Some(reader) => reader.fill_buf(),
+            None => Ok(&[]),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Some(reader) = self.reader() {
+            reader.consume(amt);
+        }
+    }
+}
+
+/// A response body of known length, releasing its connection once read whole.
+struct Delimited {
+    upstream: Upstream,
+    remaining: u64,
+    reusable: bool,
+}
+
+impl Read for Delimited {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        if max == 0 {
+            return Ok(0);
+        }
+        let len = self.upstream.read(&mut buf[..max])?;
+        self.remaining -= len as u64;
+        if self.remaining == 0 && self.reusable {
+            self.upstream.release();
+        }
+        Ok(len)
+    }
+}
+
+/// Removes the hop-by-hop headers, including those listed in `Connection`.
+fn strip_hop_by_hop(headers: &mut Vec<(String, String)>) {
+    let listed = headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Connection"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(|name| name.trim().to_string())
+        .collect::<Vec<_>>();
+    headers.retain(|(name, _)| {
+        !HOP_BY_HOP
+            .iter()
+            .copied()
+            .chain(listed.iter().map(String::as_str))
+            .any(|header| name.eq_ignore_ascii_case(header))
+    });
+}
@@ -0,0 +1,238 @@
+//! Rate limiters: token buckets and leaky buckets.
+//!
+//! Both let callers through at `rate` per second on average, and differ in how they treat bursts:
+//! a [`TokenBucket`] lets `burst` callers through at once after a quiet period, while a
+//! [`LeakyBucket`] always spaces them by `1 / rate`, holding up to `burst` of them meanwhile.
+//!
+//! ```ignore
+//! let limiter = TokenBucket::new(10.0, 20);
+//! match limiter.try_acquire() {
+//!     Ok(()) => serve(),
+//!     Err(limited) => reject(limited.retry_after),
+//! }
+//! ```
+//!
+//! The [`RateLimit`] middleware limits each client of the server with a token bucket.
+
+mod middleware;
+
+pub use middleware::RateLimit;
+
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// A caller was turned away by a rate limiter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("rate limited, retry after {retry_after:?}")]
+pub struct RateLimited {
+    /// How long it takes until the caller would be let through.
+    pub retry_after: Duration,
+}
+
+/// Checks the arguments of the limiters' constructors.
+fn check(rate: f64, burst: u32) {
+    assert!(rate > 0.0, "rate must be positive");
+    assert!(burst > 0, "burst must be positive");
+}
+
+#[derive(Debug)]
+struct Tokens {
+    /// Negative while callers of `acquire` wait for the tokens they took in advance.
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// A bucket of `burst` tokens refilled at `rate` tokens per second, each caller taking one.
+///
+/// A caller is let through right away as long as tokens are left, so `burst` callers may go at
+/// once after the bucket had time to fill up, and then `rate` per second.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: Mutex<Tokens>,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket letting `rate` callers through per second with bursts of `burst`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not positive or `burst` is 0.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        check(rate, burst);
+        let burst = f64::from(burst);
+        Self {
+            rate,
+            burst,
+            tokens: Mutex::new(Tokens {
+                tokens: burst,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Adds the tokens refilled since the last time, and returns the bucket.
+    fn refill(&self) -> MutexGuard<'_, Tokens> {
+        let mut tokens = self.tokens.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(tokens.refilled_at).as_secs_f64();
+        tokens.tokens = (tokens.tokens + elapsed * self.rate).min(self.burst);
+        tokens.refilled_at = now;
+        tokens
+    }
+
+    /// Takes a token if there is one. Otherwise, returns how long it takes until there is one.
+    pub fn try_acquire(&self) -> Result<(), RateLimited> {
+        let mut tokens = self.refill();
+        if tokens.tokens >= 1.0 {
+            tokens.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(RateLimited {
+                retry_after: Duration::from_secs_f64((1.0 - tokens.tokens) / self.rate),
+            })
+        }
+    }
+
+    /// Takes a token, waiting for it if there is none. Callers waiting together are let through
+    /// in the order they called.
+    pub fn acquire(&self) {
+        let wait = {
+            let mut tokens = self.refill();
+            // Taken in advance: the refill pays it back, after the callers waiting already.
+            tokens.tokens -= 1.0;
+            (-tokens.tokens).max(0.0) / self.rate
+        };
+        thread::sleep(Duration::from_secs_f64(wait));
+    }
+
+    /// Returns the number of tokens left, which may be fractional.
+    pub fn available(&self) -> f64 {
+        let tokens = self.tokens.lock().unwrap();
+        let elapsed = tokens.refilled_at.elapsed().as_secs_f64();
+        (tokens.tokens + elapsed * self.rate).clamp(0.0, self.burst)
+    }
+}
+
+/// A bucket leaking callers at `rate` per second, evenly spaced, that holds up to `burst` callers
+/// waiting for their turn.
+///
+/// Unlike a [`TokenBucket`], no two callers are let through less than `1 / rate` apart, however
+/// long the bucket was idle: bursts are smoothed out by making callers wait, up to the capacity of
+/// the bucket, rather than let through.
+#[derive(Debug)]
+pub struct LeakyBucket {
+    interval: Duration,
+    /// How long the callers waiting in a full bucket wait, each taking an interval.
+    room: Duration,
+    /// When the next caller may leave the bucket.
+    next: Mutex<Instant>,
+}
+
+impl LeakyBucket {
+    /// Creates an empty bucket letting `rate` callers through per second, holding up to `burst`
+    /// of them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not positive or `burst` is 0, or if `rate` is so high that the
+    /// interval rounds to 0, or so low that the callers of a full bucket would wait past what an
+    /// [`Instant`] can represent.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        check(rate, burst);
+        let now = Instant::now();
+        let (interval, room) = Duration::try_from_secs_f64(1.0 / rate)
+            .ok()
+            .filter(|interval| !interval.is_zero())
+            .and_then(|interval| Some((interval, interval.checked_mul(burst)?)))
+            // The last caller waiting sets the next turn an interval after its own.
+            .filter(|&(interval, room)| {
+                now.checked_add(room)
+                    .and_then(|turn| turn.checked_add(interval))
+                    .is_some()
+            })
+            .expect("rate out of range for a leaky bucket of this burst");
+        Self {
+            interval,
+            room,
+            next: Mutex::new(now),
+        }
+    }
+
+    /// Lets the caller through if it doesn't have to wait for its turn. Otherwise, returns how
+    /// long until its turn.
+    pub fn try_acquire(&self) -> Result<(), RateLimited> {
+        let mut next = self.next.lock().unwrap();
+        let now = Instant::now();
+        if *next > now {
+            return Err(RateLimited {
+                retry_after: *next - now,
+            });
+        }
+        // Only out of range long after `new` checked it.
+        *next = now.checked_add(self.interval).ok_or(RateLimited {
+            retry_after: self.interval,
+        })?;
+        Ok(())
+    }
+
+    /// Waits for the caller's turn, unless `burst` callers are waiting already. Then, returns how
+    /// long until one of them leaves the bucket.
+    pub fn acquire(&self) -> Result<(), RateLimited> {
+        let wait = {
+            let mut next = self.next.lock().unwrap();
+            let now = Instant::now();
+            let turn = (*next).max(now);
+            let wait = turn - now;
+            if wait > self.room {
+                return Err(RateLimited {
+                    retry_after: wait - self.room,
+                });
+            }
+            // Only out of range long after `new` checked it.
+            *next = turn.checked_add(self.interval).ok_or(RateLimited {
+                retry_after: self.interval,
+            })?;
+            wait
+        };
+        thread::sleep(wait);
+        Ok(())
+    }
+
+    /// Returns the number of callers waiting in the bucket, which may be fractional while the
+    /// first one's turn comes.
+    pub fn waiting(&self) -> f64 {
+        let next = self.next.lock().unwrap();
+        let wait = next.saturating_duration_since(Instant::now());
+        wait.as_secs_f64() / self.interval.as_secs_f64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LeakyBucket;
+
+    #[test]
+    fn leaky_bucket_spaces_callers_by_long_intervals() {
+        // An interval of about 31 years, and a full bucket waiting for some 4 billion of them.
+        let bucket = LeakyBucket::new(1e-9, u32::MAX);
+        assert_eq!(bucket.try_acquire(), Ok(()));
+        let limited = bucket.try_acquire().unwrap_err();
+        assert!(limited.retry_after.as_secs() > 999_999_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "rate out of range")]
+    fn leaky_bucket_rejects_waits_out_of_range() {
+        let _ = LeakyBucket::new(1e-12, u32::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "rate out of range")]
+    fn leaky_bucket_rejects_intervals_rounding_to_zero() {
+        let _ = LeakyBucket::new(1e12, 1);
+    }
+}
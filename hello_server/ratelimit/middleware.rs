@@ -0,0 +1,145 @@
+//! Per-client rate limiting of the server's requests.
+
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::super::cache::Cache;
+use super::super::http::{Request, Response};
+use super::super::middleware::{Middleware, Next};
+use super::TokenBucket;
+
+/// Default maximum number of clients tracked at once.
+const DEFAULT_MAX_CLIENTS: usize = 10_000;
+
+/// Token buckets of the clients by IP.
+type Buckets = Cache<IpAddr, Arc<TokenBucket>>;
+
+/// Middleware limiting the request rate of each client IP with a [`TokenBucket`], answering
+/// requests over the limit with 429 and a `Retry-After` header.
+///
+/// A client may send `burst` requests at once, and then `rate` requests per second. Buckets are
+/// kept in a [`Cache`] shared by all workers, and the bucket of a client that has been idle long
+/// enough to refill it is dropped. Requests without a remote address are not limited.
+#[derive(Debug)]
+pub struct RateLimit {
+    rate: f64,
+    burst: u32,
+    buckets: Buckets,
+}
+
+impl RateLimit {
+    /// Creates the middleware allowing `rate` requests per second with bursts of `burst`
+    /// requests per client.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not positive or `burst` is 0, as [`TokenBucket::new`] does.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        // Checks the arguments as each client's bucket will, but before the first request.
+        let _ = TokenBucket::new(rate, burst);
+        Self {
+            rate,
+            burst,
+            buckets: Self::buckets(rate, burst, DEFAULT_MAX_CLIENTS),
+        }
+    }
+
+    /// Sets the maximum number of clients tracked at once. When more clients show up, the least
+    /// recently seen one is forgotten, which lets it start over with a full bucket. Defaults to
+    /// 10000.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_clients` is 0.
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        self.buckets = Self::buckets(self.rate, self.burst, max_clients);
+        self
+    }
+
+    fn buckets(rate: f64, burst: u32, max_clients: usize) -> Buckets {
+        // An idle bucket is full again after this long, so forgetting it then changes nothing.
+        let ttl = Duration::from_secs_f64(f64::from(burst) / rate).max(Duration::from_secs(1));
+        Buckets::builder()
+            .capacity(max_clients)
+            .ttl(ttl)
+            .build()
+            .expect("max_clients must be positive")
+    }
+}
+
+impl Middleware for RateLimit {
+    fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        let Some(ip) = request.remote_addr.map(|addr| addr.ip()) else {
+            return next.run(request);
+        };
+
+        // Concurrent first requests of a client wait for the one creating its bucket, and share it.
+        let bucket = self
+            .buckets
+            .get_or_insert_with(ip, |_| Arc::new(TokenBucket::new(self.rate, self.burst)));
+        let taken = bucket.try_acquire();
+        // Stores the bucket again so that it expires only once the client has been idle for the
+        // TTL, unless it expired meanwhile and another request of the client made a new one.
+        if let Some((current, version)) = self.buckets.get_with_version(&ip) {
+            if Arc::ptr_eq(&current, &bucket) {
+                let _ = self.buckets.insert_if_version(ip, version, bucket);
+            }
+        }
+
+        match taken {
+            Ok(()) => next.run(request),
+            Err(limited) => Response::status_page(429).with_header(
+                "Retry-After",
+                limited.retry_after.as_secs_f64().ceil().to_string(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Barrier;
+    use std::thread;
+
+    use super::super::super::http::{Request, Response};
+    use super::super::super::middleware::{Middleware, Next};
+    use super::RateLimit;
+
+    #[test]
+    fn concurrent_first_requests_share_one_bucket() {
+        const THREADS: usize = 8;
+        const CLIENTS: u8 = 100;
+        const BURST: u32 = 2;
+
+        // Slow enough that no token comes back during the test. Each client starts with a burst of
+        // concurrent requests, only `BURST` of which are served.
+        let limit = RateLimit::new(0.001, BURST);
+        let endpoint = |_| Response::new(204);
+        for client in 0..CLIENTS {
+            let start = Barrier::new(THREADS);
+            let served = thread::scope(|scope| {
+                let threads = (0..THREADS)
+                    .map(|_| {
+                        let (limit, start, endpoint) = (&limit, &start, &endpoint);
+                        scope.spawn(move || {
+                            let head = "GET / HTTP/1.1\r\n\r\n";
+                            let mut request =
+                                Request::read_head(&mut head.as_bytes()).unwrap().unwrap();
+                            request.remote_addr = Some(SocketAddr::from(([192, 0, 2, client], 80)));
+                            let _ = start.wait();
+                            limit.handle(request, Next::new(&[], endpoint)).status == 204
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                threads
+                    .into_iter()
+                    .map(|thread| thread.join().unwrap())
+                    .filter(|&served| served)
+                    .count()
+            });
+            assert_eq!(served, BURST as usize, "client {client}");
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! Reactor dispatching IO readiness to wakers.
+//!
+//! The [`Reactor`] waits for readiness events on the sources registered through its [`Handle`],
+//! and wakes whoever waits for them: a future polled by an [`Executor`](super::executor::Executor)
+//! or [`block_on`](super::executor::block_on), or a job run on a [`ThreadPool`] with
+//! [`pool_waker`]. The IO itself is non-blocking, and retried once woken.
+//!
+//! ```ignore
+//! let mut reactor = Reactor::new()?;
+//! let handle = reactor.handle();
+//! thread::spawn(move || loop {
+//!     reactor.turn(None).unwrap();
+//! });
+//!
+//! let mut stream = mio::net::TcpStream::connect(addr)?;
+//! let registration = handle.register(&mut stream, Interest::READABLE)?;
+//! let len = block_on(async {
+//!     loop {
+//!         match stream.read(&mut buf) {
+//!             Err(err) if err.kind() == io::ErrorKind::WouldBlock => registration.readable().await,
+//!             result => break result,
+//!         }
+//!     }
+//! })?;
+//! ```
+
+use mio::event::Source;
+use mio::{Events, Interest, Poll as MioPoll, Registry, Token};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+
+use super::thread_pool::ThreadPool;
+
+/// Token of the waker interrupting [`Reactor::turn`].
+const WAKER: Token = Token(usize::MAX);
+
+/// Maximum number of events handled per turn.
+const EVENTS_CAPACITY: usize = 1024;
+
+/// Direction of IO, the index of its state in an [`Entry`].
+const READ: usize = 0;
+const WRITE: usize = 1;
+
+/// Readiness of a registered source in one direction.
+#[derive(Debug, Default)]
+struct Direction {
+    /// Whether an event arrived since the readiness was last consumed.
+    ready: bool,
+    /// Woken by the next event.
+    waker: Option<Waker>,
+}
+
+type Entry = [Direction; 2];
+
+struct Shared {
+    registry: Registry,
+    waker: mio::Waker,
+    entries: Mutex<HashMap<Token, Entry>>,
+    next_token: AtomicUsize,
+}
+
+/// Waits for readiness events and wakes the tasks waiting for them.
+///
+/// Sources are registered edge-triggered: an event only arrives once the source becomes ready
+/// again after an operation returned `WouldBlock`. It is recorded, so that a task polling for it
+/// after it arrived doesn't miss it.
+pub struct Reactor {
+    poll: MioPoll,
+    events: Events,
+    handle: Handle,
+}
+
+impl fmt::Debug for Reactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reactor")
+            .field("handle", &self.handle)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Reactor {
+    /// Creates a reactor without sources.
+    pub fn new() -> io::Result<Self> {
+        let poll = MioPoll::new()?;
+        let registry = poll.registry().try_clone()?;
+        let waker = mio::Waker::new(poll.registry(), WAKER)?;
+        Ok(Self {
+            poll,
+            events: Events::with_capacity(EVENTS_CAPACITY),
+            handle: Handle {
+                shared: Arc::new(Shared {
+                    registry,
+                    waker,
+                    entries: Mutex::new(HashMap::new()),
+                    next_token: AtomicUsize::new(0),
+                }),
+            },
+        })
+    }
+
+    /// Returns a handle registering sources on the reactor, e.g. from other threads.
+    pub fn handle(&self) -> Handle {
+        self.handle.clone()
+    }
+
+    /// Waits for events, for at most `timeout` if any, and wakes the tasks waiting for them.
+    /// Returns the number of tasks woken.
+    ///
+    /// Returns early, possibly without waking any task, if [`Handle::wake`] is called or a signal
+    /// interrupts the wait.
+    pub fn turn(&mut self, timeout: Option<Duration>) -> io::Result<usize> {
+        match self.poll.poll(&mut self.events, timeout) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => return Ok(0),
+            Err(err) => return Err(err),
+        }
+        let mut wakers = Vec::new();
+        let mut entries = self.handle.shared.entries.lock().unwrap();
+        for event in self.events.iter() {
+            // A source may have been deregistered after the event.
+            let Some(entry) = entries.get_mut(&event.token()) else {
+                continue;
+            };
+            if event.is_readable() || event.is_read_closed() || event.is_error() {
+                entry[READ].ready = true;
+                wakers.extend(entry[READ].waker.take());
+            }
+            if event.is_writable() || event.is_write_closed() || event.is_error() {
+                entry[WRITE].ready = true;
+                wakers.extend(entry[WRITE].waker.take());
+            }
+        }
+        drop(entries);
+        let woken = wakers.len();
+        for waker in wakers {
+            waker.wake();
+        }
+        Ok(woken)
+    }
+}
+
+/// Registers sources on a [`Reactor`]. Clones register on the same reactor.
+#[derive(Clone)]
+pub struct Handle {
+    shared: Arc<Shared>,
+}
+
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handle")
+            .field("sources", &self.shared.entries.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Handle {
+    /// Registers `source` for the events of `interest`. The source must be in non-blocking mode.
+    pub fn register<S>(&self, source: &mut S, interest: Interest) -> io::Result<Registration>
+    where
+        S: Source + ?Sized,
+    {
+        let token = Token(self.shared.next_token.fetch_add(1, Ordering::Relaxed));
+        // Inserted first, so that the events arriving right after registering are recorded.
+        let _ = self
+            .shared
+            .entries
+            .lock()
+            .unwrap()
+            .insert(token, Entry::default());
+        let registration = Registration {
+            handle: self.clone(),
+            token,
+        };
+        self.shared.registry.register(source, token, interest)?;
+        Ok(registration)
+    }
+
+    /// Interrupts the current or next [`Reactor::turn`], e.g. to stop the thread running it.
+    pub fn wake(&self) -> io::Result<()> {
+        self.shared.waker.wake()
+    }
+}
+
+/// A source registered on a [`Reactor`], polled for its readiness.
+///
+/// Dropping it stops recording the events of the source, which should be deregistered with
+/// [`Registration::deregister`] or dropped along with it.
+pub struct Registration {
+    handle: Handle,
+    token: Token,
+}
+
+impl fmt::Debug for Registration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registration")
+            .field("token", &self.token)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Registration {
+    /// Consumes the readiness in `direction` if an event arrived, or registers the waker of `cx`
+    /// to be woken by the next one.
+    fn poll_ready(&self, cx: &mut Context<'_>, direction: usize) -> Poll<()> {
+        let mut entries = self.handle.shared.entries.lock().unwrap();
+        let state = &mut entries.get_mut(&self.token).expect("registered")[direction];
+        if state.ready {
+            state.ready = false;
+            return Poll::Ready(());
+        }
+        match &mut state.waker {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            waker => *waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+
+    /// Polls whether the source may be read without blocking. Ready means that an event arrived
+    /// since the last time, and reading may still return `WouldBlock`, after which this is polled
+    /// again.
+    pub fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.poll_ready(cx, READ)
+    }
+
+    /// Like [`Registration::poll_readable`], but for writing.
+    pub fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.poll_ready(cx, WRITE)
+    }
+
+    /// Waits until the source may be read without blocking.
+    pub fn readable(&self) -> Readiness<'_> {
+        Readiness {
+            registration: self,
+            direction: READ,
+        }
+    }
+
+    /// Waits until the source may be written without blocking.
+    pub fn writable(&self) -> Readiness<'_> {
+        Readiness {
+            registration: self,
+            direction: WRITE,
+        }
+    }
+
+    /// Deregisters `source`, which must be the one registered.
+    pub fn deregister<S>(self, source: &mut S) -> io::Result<()>
+    where
+        S: Source + ?Sized,
+    {
+        self.handle.shared.registry.deregister(source)
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let _ = self
+            .handle
+            .shared
+            .entries
+            .lock()
+            .unwrap()
+            .remove(&self.token);
+    }
+}
+
+/// Future of [`Registration::readable`] and [`Registration::writable`].
+#[derive(Debug)]
+pub struct Readiness<'a> {
+    registration: &'a Registration,
+    direction: usize,
+}
+
+impl Future for Readiness<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.registration.poll_ready(cx, self.direction)
+    }
+}
+
+/// Runs a job on a thread pool when woken.
+struct PoolWaker {
+    pool: Arc<ThreadPool>,
+    job: Arc<dyn Fn() + Send + Sync>,
+}
+
+impl Wake for PoolWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let job = self.job.clone();
+        self.pool.execute(move || job());
+    }
+}
+
+/// Returns a waker running `job` on `pool` each time it is woken, e.g. to poll a registration
+/// and serve its source once it is ready.
+///
+/// The waking thread, e.g. the reactor's, blocks while the queue of a bounded pool is full.
+pub fn pool_waker(pool: Arc<ThreadPool>, job: impl Fn() + Send + Sync + 'static) -> Waker {
+    Waker::from(Arc::new(PoolWaker {
+        pool,
+        job: Arc::new(job),
+    }))
+}
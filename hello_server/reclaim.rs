@@ -0,0 +1,8 @@
+//! Memory reclamation for lock-free data structures.
+//!
+//! A node unlinked from a lock-free structure can't be freed right away, since other threads may
+//! have loaded a pointer to it before it was unlinked. These schemes tell when it is safe.
+
+pub mod epoch;
+#[cfg(feature = "std")]
+pub mod hazard;
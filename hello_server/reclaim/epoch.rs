@@ -0,0 +1,472 @@
+//! Epoch-based reclamation, a small companion to crossbeam-epoch.
+//!
+//! A thread [`pin`]s itself while it accesses a lock-free structure, announcing the global epoch
+//! it saw, and a thread that unlinked a node [`defer_destroy`](Guard::defer_destroy)s it instead of
+//! freeing it. The global epoch advances only once every pinned thread has seen it, so a thread
+//! pinned when a node was unlinked can't still be pinned a few epochs later, and the node is freed
+//! then.
+//!
+//! Without std, there are no thread-locals to keep each thread's state in: each guard takes an
+//! announcement of its own, and the nodes deferred by all threads wait in a single list. The same
+//! goes for a thread pinning once its state was dropped as it exits, e.g. from the destructor of
+//! another thread-local, whose nodes are left to the other threads.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::cell::{Cell, RefCell};
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+
+#[cfg(not(feature = "std"))]
+use super::super::sync::SpinLock;
+
+/// Epochs are even, so that the low bit of a thread's announced epoch tells whether it is pinned.
+const STEP: usize = 2;
+
+/// Number of epochs after the one a node was unlinked in before it is freed. Threads pinned then
+/// announced an epoch at most one behind it, and the global epoch can't get two ahead of theirs
+/// until they unpin.
+const EXPIRY: usize = 3 * STEP;
+
+/// Number of pins after which a thread tries to advance the epoch and free its garbage.
+const COLLECT_PERIOD: usize = 128;
+
+/// Number of deferred nodes of a thread above which it tries to free them.
+const COLLECT_THRESHOLD: usize = 64;
+
+/// A thread's announcement of the epoch it is pinned in.
+#[derive(Debug)]
+struct Local {
+    active: AtomicBool,
+    /// The epoch plus 1 while pinned, 0 otherwise.
+    epoch: AtomicUsize,
+    next: *const Local,
+}
+
+// Only `active` and `epoch` are modified once the announcement is shared.
+unsafe impl Sync for Local {}
+
+/// A node waiting to be freed.
+struct Deferred {
+    ptr: *mut (),
+    free: unsafe fn(*mut ()),
+    /// The epoch the node was unlinked in.
+    epoch: usize,
+}
+
+// The node is only freed once, by any thread.
+unsafe impl Send for Deferred {}
+
+impl Deferred {
+    /// Defers freeing `ptr`, unlinked just before, to the current global epoch.
+    fn new<T>(ptr: *mut T) -> Self {
+        unsafe fn free<T>(ptr: *mut ()) {
+            drop(Box::from_raw(ptr.cast::<T>()));
+        }
+        // Not the epoch the thread announced, which may lag two behind the global one if the
+        // epoch advanced while the thread was pinning. Ordered after the unlinking like the
+        // announcements, so that the epoch read is at least that of any thread that may still
+        // see the node.
+        fence(Ordering::SeqCst);
+        Self {
+            ptr: ptr.cast(),
+            free: free::<T>,
+            epoch: GLOBAL.epoch.load(Ordering::Relaxed),
+        }
+    }
+
+    fn is_expired(&self, epoch: usize) -> bool {
+        // Signed, since the epoch of a collecting thread may be behind that of the node, e.g. of
+        // one left behind by an exited thread.
+        epoch.wrapping_sub(self.epoch) as isize >= EXPIRY as isize
+    }
+
+    /// Frees the node.
+    ///
+    /// # Safety
+    ///
+    /// No thread may access the node anymore.
+    unsafe fn call(self) {
+        (self.free)(self.ptr)
+    }
+}
+
+/// Removes the nodes of `garbage` expired in `epoch`, and returns them.
+fn take_expired(garbage: &mut Vec<Deferred>, epoch: usize) -> Vec<Deferred> {
+    let (expired, kept) = mem::take(garbage)
+        .into_iter()
+        .partition(|deferred: &Deferred| deferred.is_expired(epoch));
+    *garbage = kept;
+    expired
+}
+
+struct Global {
+    epoch: AtomicUsize,
+    /// Lock-free list of the announcements of all threads. They are never freed, only reused.
+    locals: AtomicPtr<Local>,
+    /// Nodes left behind by exited threads.
+    #[cfg(feature = "std")]
+    orphans: Mutex<Vec<Deferred>>,
+    /// Nodes deferred by all threads.
+    #[cfg(not(feature = "std"))]
+    garbage: SpinLock<Vec<Deferred>>,
+}
+
+static GLOBAL: Global = Global {
+    epoch: AtomicUsize::new(0),
+    locals: AtomicPtr::new(ptr::null_mut()),
+    #[cfg(feature = "std")]
+    orphans: Mutex::new(Vec::new()),
+    #[cfg(not(feature = "std"))]
+    garbage: SpinLock::new(Vec::new()),
+};
+
+impl Global {
+    fn locals(&self) -> impl Iterator<Item = &'static Local> {
+        let mut local = self.locals.load(Ordering::Acquire).cast_const();
+        core::iter::from_fn(move || {
+            // SAFETY: Announcements are never freed.
+            let current = unsafe { local.as_ref() }?;
+            local = current.next;
+            Some(current)
+        })
+    }
+
+    /// Takes an inactive announcement, or adds a new one.
+    fn register(&self) -> &'static Local {
+        for local in self.locals() {
+            if local
+                .active
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return local;
+            }
+        }
+
+        let local = Box::leak(Box::new(Local {
+            active: AtomicBool::new(true),
+            epoch: AtomicUsize::new(0),
+            next: ptr::null(),
+        }));
+        let mut head = self.locals.load(Ordering::Relaxed);
+        loop {
+            local.next = head;
+            // Release so that threads iterating the list see `next`.
+            match self
+                .locals
+                .compare_exchange(head, local, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return local,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Advances the epoch if every pinned thread has seen it, and returns the current epoch.
+    fn try_advance(&self) -> usize {
+        let epoch = self.epoch.load(Ordering::Relaxed);
+        // Orders reading the announcements after those of threads pinning concurrently, see
+        // `Handle::pin`.
+        fence(Ordering::SeqCst);
+        for local in self.locals() {
+            let announced = local.epoch.load(Ordering::Relaxed);
+            if announced != 0 && announced != epoch + 1 {
+                return epoch;
+            }
+        }
+        // Acquire so that the accesses of threads that unpinned happen before freeing their nodes.
+        fence(Ordering::Acquire);
+        match self.epoch.compare_exchange(
+            epoch,
+            epoch.wrapping_add(STEP),
+            Ordering::Release,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => epoch.wrapping_add(STEP),
+            Err(current) => current,
+        }
+    }
+}
+
+/// A thread's state.
+#[cfg(feature = "std")]
+struct Handle {
+    local: &'static Local,
+    /// Number of live guards.
+    guards: Cell<usize>,
+    pins: Cell<usize>,
+    garbage: RefCell<Vec<Deferred>>,
+}
+
+#[cfg(feature = "std")]
+impl Handle {
+    fn new() -> Self {
+        Self {
+            local: GLOBAL.register(),
+            guards: Cell::new(0),
+            pins: Cell::new(0),
+            garbage: RefCell::default(),
+        }
+    }
+
+    fn pin(&self) {
+        let guards = self.guards.get();
+        self.guards.set(guards + 1);
+        if guards > 0 {
+            return;
+        }
+
+        let epoch = GLOBAL.epoch.load(Ordering::Relaxed);
+        self.local.epoch.store(epoch + 1, Ordering::Relaxed);
+        // Orders the announcement before the accesses made while pinned, and before reading the
+        // announcements in `try_advance`. Either the thread advancing the epoch sees this one
+        // pinned, or this one sees the nodes unlinked before.
+        fence(Ordering::SeqCst);
+
+        let pins = self.pins.get().wrapping_add(1);
+        self.pins.set(pins);
+        if pins.is_multiple_of(COLLECT_PERIOD) {
+            self.collect();
+        }
+    }
+
+    fn unpin(&self) {
+        let guards = self.guards.get() - 1;
+        self.guards.set(guards);
+        if guards == 0 {
+            // Release so that the accesses made while pinned happen before the nodes are freed.
+            self.local.epoch.store(0, Ordering::Release);
+        }
+    }
+
+    fn defer<T>(&self, ptr: *mut T) {
+        let deferred = Deferred::new(ptr);
+        let len = {
+            let mut garbage = self.garbage.borrow_mut();
+            garbage.push(deferred);
+            garbage.len()
+        };
+        if len >= COLLECT_THRESHOLD {
+            self.collect();
+        }
+    }
+
+    /// Frees the expired nodes of this thread and of exited threads.
+    fn collect(&self) {
+        let epoch = GLOBAL.try_advance();
+        let expired = take_expired(&mut self.garbage.borrow_mut(), epoch);
+        let orphans = match GLOBAL.orphans.try_lock() {
+            Ok(mut orphans) if !orphans.is_empty() => take_expired(&mut orphans, epoch),
+            _ => Vec::new(),
+        };
+        // Freed once the garbage isn't borrowed, since dropping the nodes may pin again.
+        for deferred in expired.into_iter().chain(orphans) {
+            // SAFETY: The epoch advanced enough for no thread to access the node anymore.
+            unsafe { deferred.call() };
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.pin();
+        self.collect();
+        self.unpin();
+        let garbage = mem::take(self.garbage.get_mut());
+        if !garbage.is_empty() {
+            GLOBAL.orphans.lock().unwrap().extend(garbage);
+        }
+        self.local.active.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(feature = "std")]
+thread_local! {
+    static HANDLE: Handle = Handle::new();
+}
+
+/// Takes an announcement of its own for a guard, and pins it.
+fn pin_local() -> &'static Local {
+    let local = GLOBAL.register();
+    let epoch = GLOBAL.epoch.load(Ordering::Relaxed);
+    local.epoch.store(epoch + 1, Ordering::Relaxed);
+    // Same as in `Handle::pin`.
+    fence(Ordering::SeqCst);
+    local
+}
+
+/// Unpins the announcement taken by [`pin_local`], and gives it back.
+fn unpin_local(local: &Local) {
+    // Release so that the accesses made while pinned happen before the nodes are freed.
+    local.epoch.store(0, Ordering::Release);
+    local.active.store(false, Ordering::Release);
+}
+
+/// Number of pins of all threads, without std.
+#[cfg(not(feature = "std"))]
+static PINS: AtomicUsize = AtomicUsize::new(0);
+
+/// Frees the expired nodes of all threads, without std.
+#[cfg(not(feature = "std"))]
+fn collect() {
+    let epoch = GLOBAL.try_advance();
+    let expired = take_expired(&mut GLOBAL.garbage.lock(), epoch);
+    // Freed once the garbage is unlocked, since dropping the nodes may pin again.
+    for deferred in expired {
+        // SAFETY: The epoch advanced enough for no thread to access the node anymore.
+        unsafe { deferred.call() };
+    }
+}
+
+/// Pins the current thread until the guard is dropped, so that the nodes it loads from lock-free
+/// structures aren't freed in the meantime. Pinning again while pinned is cheap.
+#[cfg(feature = "std")]
+pub fn pin() -> Guard {
+    let local = match HANDLE.try_with(Handle::pin) {
+        Ok(()) => None,
+        Err(_) => Some(pin_local()),
+    };
+    Guard {
+        local,
+        _not_send: PhantomData,
+    }
+}
+
+/// Pins the current thread until the guard is dropped, so that the nodes it loads from lock-free
+/// structures aren't freed in the meantime.
+#[cfg(not(feature = "std"))]
+pub fn pin() -> Guard {
+    let local = pin_local();
+    let pins = PINS.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+    if pins.is_multiple_of(COLLECT_PERIOD) {
+        collect();
+    }
+    Guard {
+        local,
+        _not_send: PhantomData,
+    }
+}
+
+/// Keeps the current thread pinned, see [`pin`].
+#[derive(Debug)]
+pub struct Guard {
+    /// The announcement taken by the guard, if the thread's state was already dropped.
+    #[cfg(feature = "std")]
+    local: Option<&'static Local>,
+    /// The announcement taken by the guard, without std.
+    #[cfg(not(feature = "std"))]
+    local: &'static Local,
+    /// Pinning is per thread.
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl Guard {
+    /// Frees `ptr` once no thread pinned at the time may access it anymore.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Box::into_raw`, be unreachable for threads pinned from now on, and be
+    /// destroyed only once. The pointee must be safe to drop on any thread at any later time.
+    pub unsafe fn defer_destroy<T>(&self, ptr: *mut T) {
+        #[cfg(feature = "std")]
+        if HANDLE.try_with(|handle| handle.defer(ptr)).is_err() {
+            GLOBAL.orphans.lock().unwrap().push(Deferred::new(ptr));
+        }
+        #[cfg(not(feature = "std"))]
+        {
+            let deferred = Deferred::new(ptr);
+            let len = {
+                let mut garbage = GLOBAL.garbage.lock();
+                garbage.push(deferred);
+                garbage.len()
+            };
+            if len >= COLLECT_THRESHOLD {
+                collect();
+            }
+        }
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        #[cfg(feature = "std")]
+        match self.local {
+            Some(local) => unpin_local(local),
+            // Nothing left to unpin if the thread's state was dropped first, e.g. with the guard
+            // kept in a thread-local.
+            None => {
+                let _ = HANDLE.try_with(Handle::unpin);
+            }
+        }
+        #[cfg(not(feature = "std"))]
+        unpin_local(self.local);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::cell::RefCell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use super::pin;
+
+    /// Counts its drops, and pins and defers the next node when dropped, if any.
+    struct Node {
+        drops: &'static AtomicUsize,
+        next: Option<Box<Node>>,
+    }
+
+    impl Drop for Node {
+        fn drop(&mut self) {
+            let _ = self.drops.fetch_add(1, Ordering::Relaxed);
+            if let Some(next) = self.next.take() {
+                let guard = pin();
+                // SAFETY: Owned by this node, and never shared.
+                unsafe { guard.defer_destroy(Box::into_raw(next)) };
+            }
+        }
+    }
+
+    #[test]
+    fn thread_local_dropped_after_the_threads_state_may_pin_and_defer() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        thread_local! {
+            static NODE: RefCell<Option<Node>> = const { RefCell::new(None) };
+        }
+        thread::spawn(|| {
+            let next = Some(Box::new(Node {
+                drops: &DROPS,
+                next: None,
+            }));
+            NODE.with(|node| {
+                *node.borrow_mut() = Some(Node {
+                    drops: &DROPS,
+                    next,
+                })
+            });
+            // The thread's state is created after, so that it's dropped before the node.
+            drop(pin());
+        })
+        .join()
+        .unwrap();
+        assert_eq!(DROPS.load(Ordering::Relaxed), 1);
+
+        // The node deferred while exiting is left to the other threads.
+        for _ in 0..10_000 {
+            drop(pin());
+            if DROPS.load(Ordering::Relaxed) == 2 {
+                break;
+            }
+            thread::yield_now();
+        }
+        assert_eq!(DROPS.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,376 @@
+//! Hazard pointers.
+//!
+//! A thread announces the pointer it is about to dereference in a hazard slot with a [`Shield`],
+//! and a thread that unlinked a node [`retire`]s it instead of freeing it. Retired nodes are freed
+//! by [`scan`], which skips those announced in any slot.
+//!
+//! [`lockfree::hazard`](super::super::lockfree::hazard) has the lock-free stack and queue on top
+//! of them. For example, the `pop` of a Treiber stack whose `head` is an `AtomicPtr<Node<T>>`:
+//!
+//! ```ignore
+//! let shield = Shield::new();
+//! loop {
+//!     let head = shield.protect(&self.head);
+//!     // The node isn't freed while protected.
+//!     let node = unsafe { head.as_ref() }?;
+//!     if self.head.compare_exchange(head, node.next, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+//!         let data = unsafe { ptr::read(&node.data) };
+//!         unsafe { retire(head) };
+//!         return Some(data);
+//!     }
+//! }
+//! ```
+//!
+//! The pointers a thread still has retired when it exits are left to the scans of the others.
+//!
+//! The tests are meant to run under Miri as well, e.g. `cargo +nightly miri test hazard`, which
+//! catches a use after free. Loom can't model them, since the slots live in a static.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Number of retired pointers of a thread above which they are scanned.
+const SCAN_THRESHOLD: usize = 64;
+
+/// A slot announcing a hazard pointer, owned by one [`Shield`] at a time.
+#[derive(Debug)]
+struct Slot {
+    active: AtomicBool,
+    /// Address of the protected pointer, 0 if none.
+    hazard: AtomicUsize,
+    next: *const Slot,
+}
+
+// Only `active` and `hazard` are modified once the slot is shared.
+unsafe impl Sync for Slot {}
+
+/// Lock-free list of the slots of all threads. Slots are never freed, only reused.
+#[derive(Debug)]
+struct Slots {
+    head: AtomicPtr<Slot>,
+}
+
+static SLOTS: Slots = Slots {
+    head: AtomicPtr::new(ptr::null_mut()),
+};
+
+impl Slots {
+    fn iter(&self) -> impl Iterator<Item = &'static Slot> {
+        let mut slot = self.head.load(Ordering::Acquire).cast_const();
+        std::iter::from_fn(move || {
+            // SAFETY: Slots are never freed.
+            let current = unsafe { slot.as_ref() }?;
+            slot = current.next;
+            Some(current)
+        })
+    }
+
+    /// Takes an inactive slot, or adds a new one.
+    fn acquire(&self) -> &'static Slot {
+        for slot in self.iter() {
+            if slot
+                .active
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return slot;
+            }
+        }
+
+        let slot = Box::leak(Box::new(Slot {
+            active: AtomicBool::new(true),
+            hazard: AtomicUsize::new(0),
+            next: ptr::null(),
+        }));
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            slot.next = head;
+            // Release so that threads iterating the list see `next`.
+            match self
+                .head
+                .compare_exchange(head, slot, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return slot,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Returns the addresses currently protected.
+    fn hazards(&self) -> HashSet<usize> {
+        self.iter()
+            .filter(|slot| slot.active.load(Ordering::Acquire))
+            .map(|slot| slot.hazard.load(Ordering::Acquire))
+            .filter(|&hazard| hazard != 0)
+            .collect()
+    }
+}
+
+/// Protects one pointer at a time from being freed by [`scan`], as long as the pointer was still
+/// reachable when it was protected.
+pub struct Shield {
+    slot: &'static Slot,
+}
+
+impl fmt::Debug for Shield {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shield")
+            .field("hazard", &self.slot.hazard.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+impl Default for Shield {
+    fn default() -> Self {
+        Self {
+            slot: SLOTS.acquire(),
+        }
+    }
+}
+
+impl Shield {
+    /// Creates a shield protecting nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the pointer in `src` and protects it, retrying until `src` still holds it once
+    /// protected, so that it can't have been retired and scanned in between.
+    pub fn protect<T>(&self, src: &AtomicPtr<T>) -> *mut T {
+        let mut ptr = src.load(Ordering::Relaxed);
+        loop {
+            self.set(ptr);
+            // Acquire so that the pointee is seen as the thread storing it wrote it.
+            let current = src.load(Ordering::Acquire);
+            if current == ptr {
+                return ptr;
+            }
+            ptr = current;
+        }
+    }
+
+    /// Protects `ptr`, replacing the pointer protected so far. `ptr` is only safe to dereference
+    /// if it is checked to be still reachable afterwards, as [`Shield::protect`] does.
+    pub fn set<T>(&self, ptr: *mut T) {
+        self.slot.hazard.store(ptr as usize, Ordering::Relaxed);
+        // Orders the announcement before the check that the pointer is still reachable, as the
+        // fence in `scan` orders unlinking before reading the announcements. Either the check
+        // fails, or `scan` sees the announcement.
+        fence(Ordering::SeqCst);
+    }
+
+    /// Stops protecting the pointer.
+    pub fn clear(&self) {
+        self.slot.hazard.store(0, Ordering::Release);
+    }
+}
+
+impl Drop for Shield {
+    fn drop(&mut self) {
+        self.clear();
+        self.slot.active.store(false, Ordering::Release);
+    }
+}
+
+/// A retired pointer and the function freeing it.
+struct Retired {
+    ptr: *mut (),
+    free: unsafe fn(*mut ()),
+}
+
+// The pointee is only freed once, by any thread.
+unsafe impl Send for Retired {}
+
+impl Retired {
+    fn new<T>(ptr: *mut T) -> Self {
+        unsafe fn free<T>(ptr: *mut ()) {
+            drop(Box::from_raw(ptr.cast::<T>()));
+        }
+        Self {
+            ptr: ptr.cast(),
+            free: free::<T>,
+        }
+    }
+
+    /// Frees the pointee.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be unreachable, and no thread may protect it.
+    unsafe fn free(self) {
+        (self.free)(self.ptr)
+    }
+}
+
+/// Pointers retired by exited threads that were still protected then.
+static ORPHANS: Mutex<Vec<Retired>> = Mutex::new(Vec::new());
+
+/// Removes the pointers of `retired` that no [`Shield`] protects, and returns them.
+fn take_unprotected(retired: &mut Vec<Retired>) -> Vec<Retired> {
+    // Orders unlinking the retired nodes before reading the announcements, see `Shield::set`.
+    fence(Ordering::SeqCst);
+    let hazards = SLOTS.hazards();
+    let (unprotected, protected) = mem::take(retired)
+        .into_iter()
+        .partition(|retired: &Retired| !hazards.contains(&(retired.ptr as usize)));
+    *retired = protected;
+    unprotected
+}
+
+/// Frees `unprotected`, taken by [`take_unprotected`].
+fn free_all(unprotected: Vec<Retired>) {
+    for retired in unprotected {
+        // SAFETY: The pointer is unreachable, and no thread protected it while reachable.
+        unsafe { retired.free() };
+    }
+}
+
+/// The pointers retired by a thread and not freed yet.
+#[derive(Default)]
+struct RetiredSet {
+    retired: Vec<Retired>,
+}
+
+impl RetiredSet {
+    /// Takes the pointers of this thread and of exited threads that aren't protected. They are
+    /// freed once the set isn't borrowed anymore, since dropping them may retire or scan again.
+    fn scan(&mut self) -> Vec<Retired> {
+        let mut unprotected = take_unprotected(&mut self.retired);
+        if let Ok(mut orphans) = ORPHANS.try_lock() {
+            if !orphans.is_empty() {
+                unprotected.extend(take_unprotected(&mut orphans));
+            }
+        }
+        unprotected
+    }
+}
+
+impl Drop for RetiredSet {
+    /// Frees the remaining pointers when the thread exits, and leaves those still protected to
+    /// the other threads' scans.
+    fn drop(&mut self) {
+        let unprotected = take_unprotected(&mut self.retired);
+        if !self.retired.is_empty() {
+            ORPHANS.lock().unwrap().append(&mut self.retired);
+        }
+        free_all(unprotected);
+    }
+}
+
+thread_local! {
+    static RETIRED: RefCell<RetiredSet> = RefCell::default();
+}
+
+/// Retires `ptr`, so that it is freed by a later [`scan`] once no [`Shield`] protects it. Retired
+/// pointers are scanned every so often, and when the thread exits.
+///
+/// # Safety
+///
+/// `ptr` must come from `Box::into_raw`, be unreachable for threads that don't already protect
+/// it, and be retired only once. The pointee must be safe to drop on any thread at any later time.
+pub unsafe fn retire<T>(ptr: *mut T) {
+    let unprotected = RETIRED.try_with(|set| {
+        let mut set = set.borrow_mut();
+        set.retired.push(Retired::new(ptr));
+        if set.retired.len() >= SCAN_THRESHOLD {
+            set.scan()
+        } else {
+            Vec::new()
+        }
+    });
+    match unprotected {
+        Ok(unprotected) => free_all(unprotected),
+        // The thread is exiting, e.g. dropping the pointees of its set retires more.
+        Err(_) => ORPHANS.lock().unwrap().push(Retired::new(ptr)),
+    }
+}
+
+/// Frees the pointers retired by this thread, and those left by exited threads, that no
+/// [`Shield`] protects.
+pub fn scan() {
+    if let Ok(unprotected) = RETIRED.try_with(|set| set.borrow_mut().scan()) {
+        free_all(unprotected);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+    use std::thread;
+
+    use super::{retire, scan, Shield};
+
+    /// Counts its drops, and retires the next pointer when dropped, if any.
+    struct Node {
+        drops: &'static AtomicUsize,
+        retire_next: *mut Node,
+    }
+
+    impl Drop for Node {
+        fn drop(&mut self) {
+            let _ = self.drops.fetch_add(1, Ordering::Relaxed);
+            if !self.retire_next.is_null() {
+                // SAFETY: Owned by this node, and retired once.
+                unsafe { retire(self.retire_next) };
+                scan();
+            }
+        }
+    }
+
+    fn boxed(drops: &'static AtomicUsize, retire_next: *mut Node) -> *mut Node {
+        Box::into_raw(Box::new(Node { drops, retire_next }))
+    }
+
+    #[test]
+    fn dropping_a_retired_node_may_retire_and_scan() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        let mut next = ptr::null_mut();
+        for _ in 0..3 {
+            next = boxed(&DROPS, next);
+        }
+        // SAFETY: Never shared.
+        unsafe { retire(next) };
+        scan();
+        scan();
+        assert_eq!(DROPS.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn protected_node_outlives_the_retiring_thread() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        let node = AtomicPtr::new(boxed(&DROPS, ptr::null_mut()));
+        let shield = Shield::new();
+        let protected = shield.protect(&node);
+
+        let unlinked = node.swap(ptr::null_mut(), Ordering::Relaxed) as usize;
+        // Joined rather than scoped, since a scope may end before the thread's set is dropped.
+        thread::spawn(move || {
+            // SAFETY: Unlinked, and retired by this thread only.
+            unsafe { retire(unlinked as *mut Node) };
+            scan();
+            // Exits with the node still protected.
+        })
+        .join()
+        .unwrap();
+        // SAFETY: Protected while reachable.
+        assert_eq!(unsafe { (*protected).drops }.load(Ordering::Relaxed), 0);
+
+        shield.clear();
+        // A scan skips the orphans while another thread's scan holds them, in which case that scan
+        // frees the node instead.
+        for _ in 0..1000 {
+            scan();
+            if DROPS.load(Ordering::Relaxed) > 0 {
+                break;
+            }
+            thread::yield_now();
+        }
+        assert_eq!(DROPS.load(Ordering::Relaxed), 1);
+    }
+}
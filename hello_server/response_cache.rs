@@ -0,0 +1,166 @@
+//! Caching of whole responses.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::cache::{Cache, CacheBuilder, CacheConfig, CacheConfigError};
+use super::http::{Body, Request, Response};
+use super::middleware::{Middleware, Next};
+
+/// Maximum length in bytes of a body read from a reader, e.g. a static file, to be cached.
+const MAX_READ_BODY_LEN: u64 = 1024 * 1024;
+
+/// Cached responses by method and request target.
+type Responses = Cache<(String, String), Arc<CachedResponse>>;
+
+/// A response stored in a [`ResponseCache`].
+#[derive(Debug)]
+struct CachedResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    etag: String,
+}
+
+impl CachedResponse {
+    fn to_response(&self) -> Response {
+        let mut response = Response::new(self.status).with_body(self.body.clone());
+        response.headers = self.headers.clone();
+        response.with_header("ETag", self.etag.clone())
+    }
+}
+
+/// Middleware caching `GET` and `HEAD` responses by method and request target in a [`Cache`],
+/// and answering conditional requests whose `If-None-Match` matches the `ETag` with 304.
+///
+/// Only 200 responses with an in-memory body, or one read from a reader of up to 1 MiB, are
+/// cached, and not those with `Cache-Control: no-store` or `private`, `Set-Cookie`, `Vary`, or an
+/// `ETag` of their own. Requests with a `Range` are passed through. Since the key ignores the
+/// request headers, add this middleware after [`Compression`](super::Compression) so that it
+/// caches uncompressed responses. Entries expire after the TTL, so changes to the underlying
+/// content show up within that time, or right away if they are [invalidated].
+///
+/// Cheap to clone: the clones share the cached responses, e.g. to invalidate them from a
+/// [`StaticFiles::watch`](super::StaticFiles::watch) callback.
+///
+/// [invalidated]: ResponseCache::invalidate
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    responses: Arc<Responses>,
+}
+
+impl ResponseCache {
+    /// Creates the middleware caching up to `capacity` responses for `ttl` each.
+    pub fn new(capacity: usize, ttl: Duration) -> Result<Self, CacheConfigError> {
+        Ok(Self {
+            responses: Arc::new(Responses::builder().capacity(capacity).ttl(ttl).build()?),
+        })
+    }
+
+    /// Creates the middleware with a cache configured by `config`, e.g. the `response_cache`
+    /// table of the server's config file.
+    pub fn from_config(config: CacheConfig) -> Result<Self, CacheConfigError> {
+        Ok(Self {
+            responses: Arc::new(CacheBuilder::from_config(config).build()?),
+        })
+    }
+
+    /// Removes the cached responses for `path` with any query, e.g. after it is modified.
+    pub fn invalidate(&self, path: &str) {
+        for (key, _) in self.responses.iter_by_recency() {
+            let (_, target) = &key;
+            if target
+                .split_once('?')
+                .map_or(target.as_str(), |(path, _)| path)
+                == path
+            {
+                let _ = self.responses.invalidate(&key);
+            }
+        }
+    }
+}
+
+impl Middleware for ResponseCache {
+    fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        let cached_method = request.method == "GET" || request.method == "HEAD";
+        if !cached_method || request.header("Range").is_some() {
+            return next.run(request);
+        }
+
+        let target = match &request.query {
+            Some(query) => format!("{}?{query}", request.path),
+            None => request.path.clone(),
+        };
+        let key = (request.method.clone(), target);
+        let if_none_match = request.header("If-None-Match").map(String::from);
+
+        let cached = match self.responses.get_with_version(&key) {
+            Some((cached, _)) => cached,
+            None => {
+                let response = next.run(request);
+                let cached = match cacheable(response) {
+                    Ok(cached) => cached,
+                    Err(response) => return response,
+                };
+                let cached = Arc::new(cached);
+                // Someone else may have stored a fresher response in the meantime.
+                let _ = self
+                    .responses
+                    .insert_if_version(key, Responses::ABSENT, cached.clone());
+                cached
+            }
+        };
+
+        if if_none_match.is_some_and(|tags| etag_matches(&tags, &cached.etag)) {
+            return Response::new(304).with_header("ETag", cached.etag.clone());
+        }
+        cached.to_response()
+    }
+}
+
+/// Returns the response as a [`CachedResponse`], or the response back if it can't be cached, or a
+/// 500 if its body can't be read.
+fn cacheable(response: Response) -> Result<CachedResponse, Response> {
+    let no_store = response.header("Cache-Control").is_some_and(|value| {
+        value.split(',').any(|directive| {
+            let directive = directive.trim();
+            directive.eq_ignore_ascii_case("no-store") || directive.eq_ignore_ascii_case("private")
+        })
+    });
+    if response.status != 200
+        || no_store
+        || response.header("Set-Cookie").is_some()
+        || response.header("Vary").is_some()
+        || response.header("ETag").is_some()
+        || !matches!(
+            response.body,
+            Body::Bytes(_) | Body::Reader(_, 0..=MAX_READ_BODY_LEN)
+        )
+    {
+        return Err(response);
+    }
+    let body = response.body.into_bytes().map_err(|err| {
+        println!("[response_cache] failed to read a body: {err}");
+        Response::status_page(500)
+    })?;
+
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    Ok(CachedResponse {
+        status: response.status,
+        headers: response.headers,
+        etag: format!("\"{:016x}\"", hasher.finish()),
+        body,
+    })
+}
+
+/// Whether an `If-None-Match` value matches `etag`, comparing weakly as RFC 9110 requires.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let strip_weak = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .any(|tag| strip_weak(tag) == strip_weak(etag))
+}
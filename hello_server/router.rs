@@ -0,0 +1,283 @@
+//! Request router with methods and path parameters.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::handler::Handler;
+use super::http::{Request, Response};
+use super::middleware::{Middleware, Next};
+
+/// Method of the routes registered with [`Router::any`].
+const ANY_METHOD: &str = "*";
+
+/// Boxed request handler of a route.
+type BoxedHandler = Box<dyn Handler>;
+
+/// One segment of a route pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// Matches the segment exactly.
+    Literal(String),
+    /// `:name`, matches any single segment.
+    Param(String),
+    /// `*name`, matches the rest of the path. Only allowed at the end of a pattern.
+    Rest(String),
+}
+
+struct Route {
+    method: String,
+    pattern: Vec<Segment>,
+    handler: BoxedHandler,
+}
+
+impl Route {
+    /// Returns the path parameters if `path` matches the pattern.
+    fn matches(&self, path: &str) -> Option<HashMap<String, String>> {
+        let mut params = HashMap::new();
+        let mut segments = path.trim_start_matches('/').split('/');
+        for segment in &self.pattern {
+            match segment {
+                Segment::Literal(literal) => {
+                    if segments.next()? != literal {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let value = segments.next().filter(|value| !value.is_empty())?;
+                    let _ = params.insert(name.clone(), value.to_string());
+                }
+                Segment::Rest(name) => {
+                    let rest = segments.collect::<Vec<_>>().join("/");
+                    let _ = params.insert(name.clone(), rest);
+                    return Some(params);
+                }
+            }
+        }
+        segments.next().is_none().then_some(params)
+    }
+}
+
+/// Dispatches requests to the handler registered for their method and path.
+///
+/// Patterns are matched segment by segment. A `:name` segment matches any non-empty segment and a
+/// trailing `*name` segment matches the (possibly empty) rest of the path; the matched values are
+/// stored in [`Request::params`]. Routes are tried in the order they were registered.
+///
+/// A `HEAD` request is dispatched to the `GET` route of its path unless a route for `HEAD` or any
+/// method matches it first. Its method is left as is, so that the handler may skip making the
+/// body, which the server doesn't send anyway. An `OPTIONS` request that no route handles is
+/// answered with 204 and an `Allow` header listing the methods of the routes matching its path,
+/// or of every route for `OPTIONS *`.
+///
+/// Every request, including those answered by the fallback, passes through the middlewares added
+/// with [`Router::wrap`] before it is dispatched.
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: BoxedHandler,
+    middlewares: Vec<Box<dyn Middleware>>,
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.routes
+                    .iter()
+                    .map(|route| (&route.method, &route.pattern)),
+            )
+            .finish()
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Router {
+    /// Creates a router without routes. Every request is answered with 404.
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            fallback: Box::new(|_| Response::status_page(404)),
+            middlewares: Vec::new(),
+        }
+    }
+
+    /// Registers `handler` for requests with `method` whose path matches `pattern`.
+    ///
+    /// A closure handler may need its parameter annotated as `|request: Request|` for the
+    /// request's type to be inferred.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a `*name` segment is not the last one of `pattern`.
+    pub fn route<H>(&mut self, method: &str, pattern: &str, handler: H) -> &mut Self
+    where
+        H: Handler + 'static,
+    {
+        let pattern = pattern
+            .trim_start_matches('/')
+            .split('/')
+            .map(|segment| {
+                if let Some(name) = segment.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else if let Some(name) = segment.strip_prefix('*') {
+                    Segment::Rest(name.to_string())
+                } else {
+                    Segment::Literal(segment.to_string())
+                }
+            })
+            .collect::<Vec<_>>();
+        assert!(
+            pattern
+                .iter()
+                .rev()
+                .skip(1)
+                .all(|segment| !matches!(segment, Segment::Rest(_))),
+            "`*` segment must be the last one"
+        );
+
+        self.routes.push(Route {
+            method: method.to_string(),
+            pattern,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Registers `handler` for `GET` requests matching `pattern`.
+    pub fn get<H>(&mut self, pattern: &str, handler: H) -> &mut Self
+    where
+        H: Handler + 'static,
+    {
+        self.route("GET", pattern, handler)
+    }
+
+    /// Registers `handler` for `POST` requests matching `pattern`.
+    pub fn post<H>(&mut self, pattern: &str, handler: H) -> &mut Self
+    where
+        H: Handler + 'static,
+    {
+        self.route("POST", pattern, handler)
+    }
+
+    /// Registers `handler` for `PUT` requests matching `pattern`.
+    pub fn put<H>(&mut self, pattern: &str, handler: H) -> &mut Self
+    where
+        H: Handler + 'static,
+    {
+        self.route("PUT", pattern, handler)
+    }
+
+    /// Registers `handler` for `DELETE` requests matching `pattern`.
+    pub fn delete<H>(&mut self, pattern: &str, handler: H) -> &mut Self
+    where
+        H: Handler + 'static,
+    {
+        self.route("DELETE", pattern, handler)
+    }
+
+    /// Registers `handler` for requests with any method matching `pattern`.
+    pub fn any<H>(&mut self, pattern: &str, handler: H) -> &mut Self
+    where
+        H: Handler + 'static,
+    {
+        self.route(ANY_METHOD, pattern, handler)
+    }
+
+    /// Sets the handler for requests that match no route. Defaults to a plain 404 response.
+    pub fn fallback<H>(&mut self, handler: H) -> &mut Self
+    where
+        H: Handler + 'static,
+    {
+        self.fallback = Box::new(handler);
+        self
+    }
+
+    /// Adds a middleware. Middlewares run in the order they were added, so the first one sees the
+    /// request first and the response last.
+    pub fn wrap<M>(&mut self, middleware: M) -> &mut Self
+    where
+        M: Middleware + 'static,
+    {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    /// Passes `request` through the middlewares and dispatches it to the first matching route.
+    ///
+    /// If the path matches some routes but none for the request's method, responds with 405 and
+    /// an `Allow` header listing the methods that would have matched.
+    pub fn handle(&self, request: Request) -> Response {
+        Next::new(&self.middlewares, &|request| self.dispatch(request)).run(request)
+    }
+
+    fn dispatch(&self, mut request: Request) -> Response {
+        // `OPTIONS *` asks about the server rather than a resource.
+        if request.method == "OPTIONS" && request.path == "*" {
+            let mut allowed = Vec::new();
+            for route in &self.routes {
+                if route.method != ANY_METHOD && !allowed.contains(&route.method.as_str()) {
+                    allowed.push(route.method.as_str());
+                }
+            }
+            return options(allowed);
+        }
+
+        let mut allowed = Vec::new();
+        // The `GET` route answering a `HEAD` request if no route for it matches.
+        let mut get_route = None;
+        for route in &self.routes {
+            let Some(params) = route.matches(&request.path) else {
+                continue;
+            };
+            if route.method == ANY_METHOD || route.method == request.method {
+                request.params = params;
+                return route.handler.call(request);
+            }
+            if request.method == "HEAD" && route.method == "GET" && get_route.is_none() {
+                get_route = Some((route, params));
+            }
+            if !allowed.contains(&route.method.as_str()) {
+                allowed.push(route.method.as_str());
+            }
+        }
+
+        if let Some((route, params)) = get_route {
+            request.params = params;
+            return route.handler.call(request);
+        }
+        if allowed.is_empty() {
+            return self.fallback.call(request);
+        }
+        if request.method == "OPTIONS" {
+            return options(allowed);
+        }
+        Response::status_page(405).with_header("Allow", allow(allowed))
+    }
+}
+
+impl Handler for Router {
+    fn call(&self, request: Request) -> Response {
+        self.handle(request)
+    }
+}
+
+/// Answers an `OPTIONS` request for a resource with the `allowed` methods.
+fn options(allowed: Vec<&str>) -> Response {
+    Response::new(204).with_header("Allow", allow(allowed))
+}
+
+/// Returns the value of an `Allow` header for the methods of the routes, which include `HEAD` if
+/// they include `GET`, and `OPTIONS`.
+fn allow(mut allowed: Vec<&str>) -> String {
+    if allowed.contains(&"GET") && !allowed.contains(&"HEAD") {
+        allowed.push("HEAD");
+    }
+    if !allowed.contains(&"OPTIONS") {
+        allowed.push("OPTIONS");
+    }
+    allowed.join(", ")
+}
@@ -0,0 +1,920 @@
+//! Serves HTTP connections with a router.
+//!
+//! [`Server`] runs the whole hello server from a [`ServerConfig`], as the `hello_server` binary
+//! does. [`handle_conn`] and [`Connections`] serve connections accepted elsewhere, e.g. with a
+//! router of one's own.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, BufReader};
+use std::net::{IpAddr, Shutdown, SocketAddr};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+#[cfg(unix)]
+use std::sync::Weak;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+use super::access_log::{AccessLog, LogFormat};
+use super::cache::{CacheBuilder, CacheConfigError};
+use super::config::{Backend, ServerConfig, ServerConfigError};
+use super::event_bus::{EventBus, Topic};
+#[cfg(unix)]
+use super::event_loop::EventLoop;
+use super::health::Health;
+use super::hello::Hello;
+use super::http::{Body, Request, Response};
+use super::metrics::Metrics;
+use super::response_cache::ResponseCache;
+use super::router::Router;
+use super::static_files::StaticFiles;
+use super::statistics::{Report, Statistics, Summary};
+use super::stream::Stream;
+use super::sync::{OnceCell, SeqLock, ShardedCounter};
+use super::tcp::{CancellableTcpListener, SocketOptions};
+use super::thread_pool::{PoolError, PoolHandle, ThreadPool};
+use super::trace::{event, span};
+#[cfg(unix)]
+use super::unix::CancellableUnixListener;
+
+/// Maximum number of requests served over one connection before it is closed.
+pub(crate) const MAX_REQUESTS_PER_CONN: usize = 100;
+
+/// Maximum length of a request body in bytes.
+pub(crate) const MAX_BODY_LEN: u64 = 1024 * 1024;
+
+/// Default timeout of reads from and writes to a connection.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default timeout of waiting for the next request on a kept-alive connection.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum length of a request ID taken from the `X-Request-Id` header.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Pause of a listener after failing to accept a connection, e.g. for lack of file descriptors,
+/// so that it doesn't spin while the condition lasts.
+const ACCEPT_ERROR_PAUSE: Duration = Duration::from_millis(10);
+
+/// Topic of the server's shutdown, published once [`ShutdownHandle::shutdown`] stopped accepting
+/// connections, before the open ones are drained.
+pub const SHUTDOWN: Topic<()> = Topic::new("server.shutdown");
+
+/// Topic of the paths of the pages that may have changed with a file under the document root, when
+/// it is watched. See [`StaticFiles::watch`] for the paths.
+pub const PAGE_CHANGED: Topic<String> = Topic::new("server.page_changed");
+
+/// Why a connection isn't served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Refusal {
+    /// The connections are being drained.
+    Draining,
+    /// The server has as many connections as allowed.
+    TooManyConnections,
+    /// The client's IP address has as many connections as allowed.
+    TooManyFromIp,
+}
+
+/// Open connections of a server, tracked so that they can be drained on shutdown, and their
+/// socket settings and limits.
+#[derive(Debug)]
+pub struct Connections {
+    /// The streams and their clients' addresses, if any.
+    streams: Mutex<HashMap<usize, (Stream, Option<IpAddr>)>>,
+    draining: AtomicBool,
+    pub(crate) read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    pub(crate) idle_timeout: Option<Duration>,
+    max_connections: Option<usize>,
+    max_per_ip: Option<usize>,
+    /// Number of requests served, incremented by every worker.
+    requests: ShardedCounter,
+}
+
+impl Default for Connections {
+    fn default() -> Self {
+        Self {
+            streams: Mutex::new(HashMap::new()),
+            draining: AtomicBool::new(false),
+            read_timeout: Some(DEFAULT_TIMEOUT),
+            write_timeout: Some(DEFAULT_TIMEOUT),
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            max_connections: None,
+            max_per_ip: None,
+            requests: ShardedCounter::new(),
+        }
+    }
+}
+
+impl Connections {
+    /// Creates an empty set of connections with 30 second read and write timeouts, a 5 second idle
+    /// timeout, and no limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long a read may block, or `None` to block forever.
+    ///
+    /// A connection that times out waiting for the next request is closed, and one that times out
+    /// in the middle of a request is answered with 408 and closed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timeout is zero.
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        assert_ne!(timeout, Some(Duration::ZERO), "timeout must be positive");
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a write may block, or `None` to block forever. A connection that times out
+    /// writing a response is closed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timeout is zero.
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        assert_ne!(timeout, Some(Duration::ZERO), "timeout must be positive");
+        self.write_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a kept-alive connection may wait for its next request, or `None` to block
+    /// forever, before it is closed. The first request is waited for as long as a read may block.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timeout is zero.
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        assert_ne!(timeout, Some(Duration::ZERO), "timeout must be positive");
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Sets the maximum number of open connections, or `None` for no limit. Further connections
+    /// are answered with 503 and closed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the limit is zero.
+    pub fn max_connections(mut self, max: Option<usize>) -> Self {
+        assert_ne!(max, Some(0), "limit must be positive");
+        self.max_connections = max;
+        self
+    }
+
+    /// Sets the maximum number of open connections from one IP address, or `None` for no limit.
+    /// Further connections from the address are answered with 429 and closed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the limit is zero.
+    pub fn max_connections_per_ip(mut self, max: Option<usize>) -> Self {
+        assert_ne!(max, Some(0), "limit must be positive");
+        self.max_per_ip = max;
+        self
+    }
+
+    /// Starts draining: new connections are refused, idle connections are closed, and connections
+    /// with a request in flight are closed once its response is written.
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::Release);
+        for (stream, _) in self.streams.lock().unwrap().values() {
+            // Makes blocked reads return EOF while still letting responses be written.
+            let _ = stream.shutdown(Shutdown::Read);
+        }
+    }
+
+    /// Returns whether the connections are being drained.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of open connections.
+    pub fn len(&self) -> usize {
+        self.streams.lock().unwrap().len()
+    }
+
+    /// Returns whether there are no open connections.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of requests served so far.
+    pub fn requests(&self) -> u64 {
+        self.requests.sum()
+    }
+
+    /// Registers the connection unless it is refused.
+    fn register(&self, conn_id: usize, stream: &Stream) -> Result<(), Refusal> {
+        let mut streams = self.streams.lock().unwrap();
+        // Checked under the lock so that `drain` doesn't miss the connection.
+        if self.is_draining() {
+            return Err(Refusal::Draining);
+        }
+        if self.max_connections.is_some_and(|max| streams.len() >= max) {
+            return Err(Refusal::TooManyConnections);
+        }
+        let ip = stream.peer_addr().map(|addr| addr.ip());
+        if let (Some(ip), Some(max)) = (ip, self.max_per_ip) {
+            let from_ip = streams.values().filter(|(_, i)| *i == Some(ip)).count();
+            if from_ip >= max {
+                return Err(Refusal::TooManyFromIp);
+            }
+        }
+        // A connection that can't be tracked couldn't be drained either.
+        let Ok(stream) = stream.try_clone() else {
+            return Err(Refusal::Draining);
+        };
+        let _ = streams.insert(conn_id, (stream, ip));
+        Ok(())
+    }
+
+    pub(crate) fn unregister(&self, conn_id: usize) {
+        let _ = self.streams.lock().unwrap().remove(&conn_id);
+    }
+}
+
+/// Answers the connection with 503 and closes it, for when the server is too busy to serve it.
+pub fn reject_overloaded(stream: impl Into<Stream>) {
+    refuse(stream.into(), 503);
+}
+
+/// Answers the connection with `status` without reading a request and closes it.
+fn refuse(mut stream: Stream, status: u16) {
+    // The caller may be the acceptor, so a slow client mustn't hold it up.
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+    let _ = Response::status_page(status)
+        .with_header("Retry-After", "1")
+        .write_to(&mut stream, false);
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+/// Serves the requests on the connection with `router`, calling `report` for each of them.
+///
+/// The connection is kept open for further requests (HTTP/1.1 keep-alive) until the client
+/// closes it or asks to with `Connection: close`, until [`MAX_REQUESTS_PER_CONN`] requests
+/// have been served, or until `connections` are drained.
+pub fn handle_conn(
+    router: &Router,
+    connections: &Connections,
+    conn_id: usize,
+    stream: impl Into<Stream>,
+    report: impl FnMut(Report),
+) {
+    let Some(stream) = admit(connections, conn_id, stream.into()) else {
+        return;
+    };
+    serve_conn(router, connections, conn_id, stream, report);
+    connections.unregister(conn_id);
+}
+
+/// Registers the connection and sets its timeouts, or answers it if it is refused. Returns the
+/// connection if it is to be served.
+pub(crate) fn admit(connections: &Connections, conn_id: usize, stream: Stream) -> Option<Stream> {
+    if let Err(refusal) = connections.register(conn_id, &stream) {
+        let status = match refusal {
+            Refusal::Draining => return None,
+            Refusal::TooManyConnections => 503,
+            Refusal::TooManyFromIp => 429,
+        };
+        println!("[server] refusing connection {conn_id}: {refusal:?}");
+        event!(DEBUG, conn_id, ?refusal, "connection refused");
+        refuse(stream, status);
+        return None;
+    }
+    if let Err(err) = stream
+        .set_read_timeout(connections.read_timeout)
+        .and_then(|()| stream.set_write_timeout(connections.write_timeout))
+    {
+        println!("[server] failed to set timeouts on connection {conn_id}: {err}");
+        connections.unregister(conn_id);
+        return None;
+    }
+    Some(stream)
+}
+
+fn serve_conn(
+    router: &Router,
+    connections: &Connections,
+    conn_id: usize,
+    stream: Stream,
+    mut report: impl FnMut(Report),
+) {
+    let remote_addr = stream.peer_addr();
+    span!(DEBUG, "connection", id = conn_id, peer = ?remote_addr);
+    event!(DEBUG, "connection admitted");
+    let mut reader = match stream.try_clone() {
+        Ok(reader) => BufReader::new(reader),
+        Err(err) => {
+            println!("[server] failed to clone connection {conn_id}: {err}");
+            return;
+        }
+    };
+    let mut writer = stream;
+
+    for served in 1..=MAX_REQUESTS_PER_CONN {
+        // Waits for the next request. Closes the connection if it's closed or idle for too long.
+        let idle = served > 1 && reader.buffer().is_empty();
+        if idle && writer.set_read_timeout(connections.idle_timeout).is_err() {
+            return;
+        }
+        if !matches!(reader.fill_buf(), Ok(buf) if !buf.is_empty()) {
+            return;
+        }
+        if idle && writer.set_read_timeout(connections.read_timeout).is_err() {
+            return;
+        }
+
+        let mut request = match Request::read_head(&mut reader) {
+            Ok(Some(request)) => request,
+            Ok(None) => return,
+            Err(err) => return reject(&mut writer, conn_id, err),
+        };
+        if let Err(err) = request.read_body(&mut reader, MAX_BODY_LEN) {
+            return reject(&mut writer, conn_id, err);
+        }
+        request.remote_addr = remote_addr;
+
+        let last = served == MAX_REQUESTS_PER_CONN;
+        if !respond(
+            router,
+            connections,
+            conn_id,
+            request,
+            last,
+            &mut writer,
+            &mut report,
+        ) {
+            return;
+        }
+    }
+}
+
+/// Returns the ID of `request`: that in its `X-Request-Id` header if it's up to 128 visible ASCII
+/// characters, so that a client or a proxy in front can choose it, and a new unique one otherwise.
+fn request_id(request: &Request) -> String {
+    static PREFIX: OnceCell<u32> = OnceCell::new();
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+
+    match request.header("X-Request-Id") {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.bytes().all(|b| b.is_ascii_graphic()) =>
+        {
+            id.to_string()
+        }
+        // A random prefix tells apart the IDs of different runs of the server.
+        _ => format!(
+            "{:08x}-{:x}",
+            PREFIX.get_or_init(rand::random),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ),
+    }
+}
+
+/// Answers a request that can't be read. The connection is to be closed afterwards.
+pub(crate) fn reject(writer: &mut Stream, conn_id: usize, err: io::Error) {
+    let status = match err.kind() {
+        io::ErrorKind::InvalidData => 400,
+        io::ErrorKind::FileTooLarge => 413,
+        // The read timeout elapsed, reported as `WouldBlock` on some platforms.
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => 408,
+        _ => return,
+    };
+    println!("[server] bad request on connection {conn_id}: {err}");
+    event!(DEBUG, status, error = %err, "bad request");
+    let _ = Response::status_page(status).write_to(writer, false);
+}
+
+/// Handles `request` with `router` and writes the response, calling `report` for it. `last` is
+/// whether no further request is served over the connection.
+///
+/// Returns whether the connection is kept open for the next request.
+pub(crate) fn respond(
+    router: &Router,
+    connections: &Connections,
+    conn_id: usize,
+    mut request: Request,
+    last: bool,
+    writer: &mut Stream,
+    report: &mut impl FnMut(Report),
+) -> bool {
+    connections.requests.increment();
+    let id = request_id(&request);
+    request.id = Some(id.clone());
+    let keep_alive = request.keep_alive() && !last;
+    let path = request.path.clone();
+    let http_1_0 = request.version == "HTTP/1.0";
+    let head = request.method == "HEAD";
+    span!(DEBUG, "request", %id, method = %request.method, %path);
+    event!(DEBUG, "request received");
+    let mut response = match panic::catch_unwind(AssertUnwindSafe(|| router.handle(request))) {
+        Ok(response) => response,
+        // Answers 500 and closes the connection instead of taking the worker thread down.
+        Err(_) => {
+            println!("[server] handler panicked on connection {conn_id} (request {id})");
+            event!(ERROR, "handler panicked");
+            report(Report::new(conn_id, None));
+            let _ = Response::status_page(500)
+                .with_header("X-Request-Id", id)
+                .write_to(writer, false);
+            return false;
+        }
+    };
+    if response.header("X-Request-Id").is_none() {
+        response = response.with_header("X-Request-Id", id);
+    }
+
+    // HTTP/1.0 clients don't understand chunked bodies, so streamed ones are buffered. That of a
+    // response to `HEAD` isn't sent, so it isn't made either.
+    if http_1_0 && !head && response.body.len().is_none() {
+        match std::mem::take(&mut response.body).into_bytes() {
+            Ok(bytes) => response.body = Body::Bytes(bytes),
+            Err(err) => {
+                println!("[server] failed to buffer response on connection {conn_id}: {err}");
+                return false;
+            }
+        }
+    }
+    // Checked after handling so that a drain during the request closes the connection.
+    let keep_alive = keep_alive && !connections.is_draining();
+    report(Report::new(
+        conn_id,
+        (response.status < 400).then_some(path),
+    ));
+
+    event!(DEBUG, status = response.status, keep_alive, "responding");
+    let written = if head {
+        response.write_head_to(writer, keep_alive)
+    } else {
+        response.write_to(writer, keep_alive)
+    };
+    written.is_ok() && keep_alive
+}
+
+/// The hello server configured by a [`ServerConfig`]: the hello pages under `/KEY`, with the
+/// built-in endpoints and static files it enables, served on a thread pool.
+///
+/// ```ignore
+/// let server = Server::bind(ServerConfig::load(env::args().skip(1))?)?;
+/// let handle = server.shutdown_handle();
+/// thread::spawn(move || {
+///     thread::sleep(Duration::from_secs(60));
+///     handle.shutdown().unwrap();
+/// });
+/// let stats = server.run()?;
+/// ```
+pub struct Server {
+    backend: Backend,
+    shutdown_timeout: Option<Duration>,
+    pool: ThreadPool,
+    listeners: Vec<Arc<CancellableTcpListener>>,
+    #[cfg(unix)]
+    unix_listener: Option<Arc<CancellableUnixListener>>,
+    connections: Arc<Connections>,
+    router: Arc<Router>,
+    events: Arc<EventBus>,
+    summary: Arc<SeqLock<Summary>>,
+    shutdown: ShutdownHandle,
+    shutdown_receiver: Receiver<()>,
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("backend", &self.backend)
+            .field("pool", &self.pool)
+            .field("connections", &self.connections)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Server {
+    /// Listens to the addresses of `config`, and to its Unix socket if any, and sets up the
+    /// routes. Connections are only accepted once the server runs.
+    pub fn bind(config: ServerConfig) -> Result<Self, ServerError> {
+        if config.tls_cert.is_some() {
+            return Err(ServerError::Unsupported("TLS"));
+        }
+
+        // The listeners (or the event loop) and the reporter take a thread each, so `pool_size`
+        // threads serve connections. At most `max_queued` connections wait for a thread, and the
+        // listeners turn away the rest.
+        let acceptors = match config.backend {
+            Backend::Threads => config.addrs.len() + usize::from(config.unix_socket.is_some()),
+            Backend::Events => 1,
+        };
+        let pool = ThreadPool::bounded(config.pool_size + acceptors + 1, config.max_queued);
+
+        let socket_options = SocketOptions::new()
+            .nodelay(config.tcp_nodelay)
+            .reuse_addr(config.reuse_addr)
+            .backlog(config.backlog)
+            .send_buffer_size(config.send_buffer_size)
+            .recv_buffer_size(config.recv_buffer_size);
+        let listeners = config
+            .addrs
+            .iter()
+            .map(
+                |addr| match CancellableTcpListener::bind_with(addr, socket_options) {
+                    Ok(listener) => Ok(Arc::new(listener)),
+                    Err(source) => Err(ServerError::Bind {
+                        addr: addr.clone(),
+                        source,
+                    }),
+                },
+            )
+            .collect::<Result<Vec<_>, _>>()?;
+        #[cfg(unix)]
+        let unix_listener = match &config.unix_socket {
+            Some(path) => {
+                let listener =
+                    CancellableUnixListener::bind(path).map_err(|source| ServerError::Bind {
+                        addr: path.display().to_string(),
+                        source,
+                    })?;
+                if let Some(mode) = config.unix_socket_mode {
+                    listener.set_mode(mode)?;
+                }
+                println!("Also listening to {}", path.display());
+                Some(Arc::new(listener))
+            }
+            None => None,
+        };
+
+        // The events of the server, handled on the pool.
+        let events = Arc::new(EventBus::new().pool(pool.handle()));
+
+        // The open connections, drained on shutdown.
+        let connections = Arc::new(
+            Connections::new()
+                .read_timeout(config.read_timeout)
+                .write_timeout(config.write_timeout)
+                .idle_timeout(config.idle_timeout)
+                .max_connections(config.max_connections)
+                .max_connections_per_ip(config.max_connections_per_ip),
+        );
+
+        // Serves the built-in endpoints, the static files, and the hello pages, logging each
+        // request, and caching the responses if configured to.
+        let response_cache = config
+            .response_cache
+            .map(ResponseCache::from_config)
+            .transpose()?;
+        let metrics = config.metrics.then(|| Metrics::new().pool(pool.handle()));
+        let mut cache = CacheBuilder::from_config(config.cache);
+        if let Some(metrics) = &metrics {
+            cache = cache.metrics_sink(Arc::new(metrics.clone()));
+        }
+        let hello = Hello::with_cache_builder(cache)?;
+        let mut router = Router::new();
+        if config.health {
+            let health_connections = connections.clone();
+            Health::new()
+                .check("accepting", move || !health_connections.is_draining())
+                .mount(&mut router);
+        }
+        if let Some(metrics) = metrics {
+            metrics.mount(&mut router, "/metrics");
+        }
+        if let Some(doc_root) = config.doc_root {
+            let mut static_files =
+                StaticFiles::new(doc_root).list_directories(config.list_directories);
+            if config.watch_doc_root {
+                let events = events.clone();
+                let response_cache = response_cache.clone();
+                static_files = static_files.watch(move |path| {
+                    // The paths are relative to where the files are mounted.
+                    if let Some(response_cache) = &response_cache {
+                        response_cache.invalidate(&format!("/static{path}"));
+                    }
+                    let _ = events.publish(PAGE_CHANGED, path.to_string());
+                })?;
+            }
+            static_files.mount(&mut router, "/static");
+        }
+        // The hello pages match any `/KEY`, so they are only tried after the other routes.
+        let _ = router
+            .fallback(hello.into_router())
+            .wrap(AccessLog::stdout(LogFormat::Common));
+        if let Some(response_cache) = response_cache {
+            let _ = router.wrap(response_cache);
+        }
+
+        let (shutdown_sender, shutdown_receiver) = sync_channel(1);
+        let shutdown = ShutdownHandle {
+            listeners: listeners.clone(),
+            // Weak so that the Unix listener is dropped, removing the socket file, once it stops.
+            #[cfg(unix)]
+            unix_listener: unix_listener.as_ref().map(Arc::downgrade),
+            events: events.clone(),
+            published: Arc::new(AtomicBool::new(false)),
+            sender: shutdown_sender,
+        };
+        Ok(Self {
+            backend: config.backend,
+            shutdown_timeout: config.shutdown_timeout,
+            pool,
+            listeners,
+            #[cfg(unix)]
+            unix_listener,
+            connections,
+            router: Arc::new(router),
+            events,
+            summary: Arc::default(),
+            shutdown,
+            shutdown_receiver,
+        })
+    }
+
+    /// Returns the addresses listened to, e.g. to find the ports chosen for the addresses with port
+    /// 0.
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners
+            .iter()
+            .map(|listener| listener.local_addr())
+            .collect()
+    }
+
+    /// Returns a handle stopping the server, e.g. from a signal handler.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Returns the bus of the server's events, e.g. to subscribe to [`SHUTDOWN`] or
+    /// [`PAGE_CHANGED`]. Handlers run on the server's thread pool.
+    pub fn events(&self) -> Arc<EventBus> {
+        self.events.clone()
+    }
+
+    /// Returns the totals of the requests served so far, updated as the requests are reported, e.g.
+    /// for a status page. Reading them never holds up the server.
+    pub fn summary(&self) -> Arc<SeqLock<Summary>> {
+        self.summary.clone()
+    }
+
+    /// Serves connections until [`ShutdownHandle::shutdown`] is called, and then waits for the
+    /// in-flight requests. Returns the statistics of the requests served.
+    ///
+    /// Fails with [`ServerError::Drain`] if the requests don't finish within the shutdown timeout.
+    pub fn run(self) -> Result<Statistics, ServerError> {
+        // In the thread pool, we'll execute:
+        //
+        // - Listeners: they accept incoming connections on each address and, if configured, on a
+        //   Unix socket, and create a new worker for each connection. With the `events` backend, a
+        //   single event loop accepts the connections of all listeners instead, and creates a new
+        //   worker for each request once it has arrived.
+        //
+        // - Workers (once for each incoming connection, or request): a worker handles an incoming
+        //   connection (or request) and sends a corresponding report to the reporter.
+        //
+        // - A reporter: it aggregates the reports from the workers and processes the statistics.
+        //   When it ends, it sends the statistics to the calling thread.
+        let Self {
+            backend,
+            shutdown_timeout,
+            pool,
+            listeners,
+            #[cfg(unix)]
+            unix_listener,
+            connections,
+            router,
+            events: _,
+            summary,
+            shutdown,
+            shutdown_receiver,
+        } = self;
+
+        // The (MPSC) channel of reports between workers and the reporter.
+        let (report_sender, report_receiver) = channel();
+
+        // The (SPSC one-shot) channel of stats between the reporter and the calling thread. It is
+        // buffered so that the reporter finishes without waiting.
+        let (stat_sender, stat_receiver) = sync_channel(1);
+
+        match backend {
+            Backend::Threads => {
+                // Connections are numbered across the listeners.
+                let next_id = Arc::new(AtomicUsize::new(0));
+
+                // Executes the listeners.
+                #[cfg(unix)]
+                if let Some(unix_listener) = unix_listener {
+                    let listener_pool = pool.handle();
+                    let router = router.clone();
+                    let connections = connections.clone();
+                    let next_id = next_id.clone();
+                    let report_sender = report_sender.clone();
+                    pool.execute(move || {
+                        accept(
+                            "unix listener",
+                            unix_listener.incoming(),
+                            &listener_pool,
+                            &router,
+                            &connections,
+                            &next_id,
+                            &report_sender,
+                        );
+                        connections.drain();
+                    });
+                }
+                for listener in listeners {
+                    let listener_pool = pool.handle();
+                    let router = router.clone();
+                    let listener_connections = connections.clone();
+                    let next_id = next_id.clone();
+                    let report_sender = report_sender.clone();
+                    pool.execute(move || {
+                        let name = match listener.local_addr() {
+                            Ok(addr) => format!("listener {addr}"),
+                            Err(_) => "listener".to_string(),
+                        };
+                        accept(
+                            &name,
+                            listener.incoming(),
+                            &listener_pool,
+                            &router,
+                            &listener_connections,
+                            &next_id,
+                            &report_sender,
+                        );
+
+                        // The listener is cancelled. Lets the in-flight requests finish and closes
+                        // the rest.
+                        listener_connections.drain();
+                    });
+                }
+            }
+            // Executes the event loop.
+            #[cfg(unix)]
+            Backend::Events => {
+                let event_loop = EventLoop::new(listeners, unix_listener)?;
+                let loop_pool = pool.handle();
+                let connections = connections.clone();
+                let report_sender = report_sender.clone();
+                pool.execute(move || {
+                    event_loop.run(router, loop_pool, connections, move |report| {
+                        report_sender.send(report).unwrap();
+                    });
+                });
+            }
+            #[cfg(not(unix))]
+            Backend::Events => unreachable!("validated with config"),
+        }
+        // Only the listeners' senders remain, so that the reporter ends with them.
+        drop(report_sender);
+
+        // Executes the reporter.
+        pool.execute(move || {
+            let mut stats = Statistics::new(summary);
+            for report in report_receiver {
+                println!("[report] {report:?}");
+                stats.add_report(report);
+            }
+
+            println!("[sending stat]");
+            stat_sender.send(stats).unwrap();
+            println!("[sent stat]");
+        });
+
+        // Blocks until shutdown is requested, and then waits for the in-flight requests. The
+        // server's own handle keeps the channel open meanwhile.
+        shutdown_receiver.recv().unwrap();
+        drop(shutdown);
+        println!(
+            "[shutdown] served {} request(s), draining {} connection(s)",
+            connections.requests(),
+            connections.len()
+        );
+        match shutdown_timeout {
+            Some(timeout) => {
+                if let Err(err) = pool.join_timeout(timeout) {
+                    // Joining the threads would wait for the requests all the same.
+                    pool.detach();
+                    return Err(ServerError::Drain(err));
+                }
+            }
+            None => pool.join(),
+        }
+
+        // The reporter has sent the statistics.
+        Ok(stat_receiver.recv().unwrap())
+        // When the pool is dropped, its threads are done with the jobs, and are joined.
+    }
+}
+
+/// Accepts the connections from `incoming` until the listener is cancelled, and serves each of
+/// them on `pool` with `router`. The connections are numbered with `next_id`.
+fn accept<S>(
+    name: &str,
+    incoming: impl Iterator<Item = io::Result<S>>,
+    pool: &PoolHandle,
+    router: &Arc<Router>,
+    connections: &Arc<Connections>,
+    next_id: &AtomicUsize,
+    report_sender: &Sender<Report>,
+) where
+    S: Into<Stream>,
+{
+    // For each incoming connection...
+    for stream in incoming {
+        let stream = match stream {
+            Ok(stream) => stream.into(),
+            Err(err) => {
+                println!("[{name}] failed to accept a connection: {err}");
+                event!(WARN, listener = name, error = %err, "accept failed");
+                thread::sleep(ACCEPT_ERROR_PAUSE);
+                continue;
+            }
+        };
+        let id = next_id.fetch_add(1, Ordering::Relaxed);
+        // Kept to answer the connection if the pool has no room for it.
+        let overflow = stream.try_clone();
+
+        // send a job to the thread pool.
+        let report_sender = report_sender.clone();
+        let router = router.clone();
+        let connections = connections.clone();
+        let job = move || {
+            handle_conn(&router, &connections, id, stream, |report| {
+                report_sender.send(report).unwrap();
+            });
+        };
+        if pool.try_execute(job).is_err() {
+            println!("[{name}] pool saturated, rejecting connection {id}");
+            if let Ok(stream) = overflow {
+                reject_overloaded(stream);
+            }
+        }
+    }
+}
+
+/// Stops a [`Server`] from other threads. Clones stop the same server.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    listeners: Vec<Arc<CancellableTcpListener>>,
+    #[cfg(unix)]
+    unix_listener: Option<Weak<CancellableUnixListener>>,
+    events: Arc<EventBus>,
+    /// Whether the shutdown was published.
+    published: Arc<AtomicBool>,
+    sender: SyncSender<()>,
+}
+
+impl fmt::Debug for ShutdownHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShutdownHandle").finish_non_exhaustive()
+    }
+}
+
+impl ShutdownHandle {
+    /// Stops accepting new connections, publishes [`SHUTDOWN`] the first time, and lets
+    /// [`Server::run`] drain the open ones.
+    pub fn shutdown(&self) -> Result<(), ServerError> {
+        let mut result = Ok(());
+        for listener in &self.listeners {
+            result = result.and(listener.cancel());
+        }
+        #[cfg(unix)]
+        if let Some(listener) = self.unix_listener.as_ref().and_then(Weak::upgrade) {
+            result = result.and(listener.cancel());
+        }
+        if !self.published.swap(true, Ordering::Relaxed) {
+            let _ = self.events.publish(SHUTDOWN, ());
+        }
+        let _ = self.sender.try_send(());
+        result.map_err(ServerError::Io)
+    }
+}
+
+/// Error of setting up or running a [`Server`].
+#[derive(Debug, Error)]
+pub enum ServerError {
+    /// The configuration can't be loaded, see [`ServerConfig::load`].
+    #[error(transparent)]
+    Config(#[from] ServerConfigError),
+    /// The configuration asks for a feature that isn't implemented.
+    #[error("{0} is not supported yet")]
+    Unsupported(&'static str),
+    /// The configuration of the cache is invalid.
+    #[error(transparent)]
+    Cache(#[from] CacheConfigError),
+    /// Listening to an address or a Unix socket failed.
+    #[error("failed to listen to {addr}")]
+    Bind {
+        addr: String,
+        #[source]
+        source: io::Error,
+    },
+    /// The in-flight requests didn't finish within the shutdown timeout.
+    #[error("in-flight requests didn't finish within the shutdown timeout")]
+    Drain(#[source] PoolError),
+    /// Other IO failed, e.g. watching the document root.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
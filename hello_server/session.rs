@@ -0,0 +1,299 @@
+//! Server-side sessions.
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::cache::Cache;
+use super::cookie::{Cookie, SameSite};
+use super::http::{is_token, Request, Response};
+use super::middleware::{Middleware, Next};
+
+/// Default name of the session cookie.
+const DEFAULT_COOKIE_NAME: &str = "session";
+
+/// Default time after which an idle session expires.
+const DEFAULT_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// Default maximum number of sessions stored at once.
+const DEFAULT_MAX_SESSIONS: usize = 10_000;
+
+/// Minimum length of the signing key in bytes.
+const MIN_KEY_LEN: usize = 32;
+
+/// Length of session IDs in random bytes.
+const ID_LEN: usize = 16;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Data of the sessions by ID.
+type Store = Cache<String, HashMap<String, String>>;
+
+#[derive(Debug, Default)]
+struct State {
+    data: HashMap<String, String>,
+    changed: bool,
+    destroyed: bool,
+}
+
+/// Data of a client's session, a map from strings to strings, found in [`Request::session`].
+///
+/// Handles to the same session share the data, and the changes are saved once the response is
+/// made.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    state: Arc<Mutex<State>>,
+}
+
+impl PartialEq for Session {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+impl Eq for Session {}
+
+impl Session {
+    fn with_data(data: HashMap<String, String>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                data,
+                ..State::default()
+            })),
+        }
+    }
+
+    /// Returns the value of `key`.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state.lock().unwrap().data.get(key).cloned()
+    }
+
+    /// Sets the value of `key`, returning the previous one.
+    pub fn insert(&self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        state.changed = true;
+        state.data.insert(key.into(), value.into())
+    }
+
+    /// Removes `key`, returning its value.
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        state.changed = true;
+        state.data.remove(key)
+    }
+
+    /// Ends the session, e.g. on logout: its data is dropped and its ID forgotten. Data inserted
+    /// afterwards is saved in a new session with a new ID.
+    pub fn destroy(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.data.clear();
+        state.destroyed = true;
+    }
+}
+
+/// Middleware keeping the data of each client's [`Session`] on the server, in a [`Cache`] whose
+/// entries expire once the client has been idle for the TTL.
+///
+/// A session is identified by a random ID sent in a cookie along with its HMAC-SHA256 signature,
+/// so that a client can't make up IDs. A session is only created, and the cookie only set, once a
+/// handler inserts some data, and it ends when its data is emptied or it is destroyed.
+pub struct Sessions {
+    key: Vec<u8>,
+    cookie_name: String,
+    secure: bool,
+    ttl: Duration,
+    max_sessions: usize,
+    store: Store,
+}
+
+impl fmt::Debug for Sessions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sessions")
+            .field("cookie_name", &self.cookie_name)
+            .field("secure", &self.secure)
+            .field("ttl", &self.ttl)
+            .field("max_sessions", &self.max_sessions)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Sessions {
+    /// Creates the middleware signing the session cookies with `key`, which must be kept secret.
+    /// Sessions expire after 30 idle minutes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is shorter than 32 bytes.
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        let key = key.into();
+        assert!(key.len() >= MIN_KEY_LEN, "key must be at least 32 bytes");
+        Self {
+            key,
+            cookie_name: DEFAULT_COOKIE_NAME.to_string(),
+            secure: false,
+            ttl: DEFAULT_TTL,
+            max_sessions: DEFAULT_MAX_SESSIONS,
+            store: Self::store(DEFAULT_TTL, DEFAULT_MAX_SESSIONS),
+        }
+    }
+
+    /// Sets how long a session is kept after the last request of its client. Defaults to 30
+    /// minutes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the TTL is zero.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        assert_ne!(ttl, Duration::ZERO, "TTL must be positive");
+        self.ttl = ttl;
+        self.store = Self::store(ttl, self.max_sessions);
+        self
+    }
+
+    /// Sets the maximum number of sessions stored at once. When more are created, the least
+    /// recently used one is dropped. Defaults to 10000.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_sessions` is 0.
+    pub fn max_sessions(mut self, max_sessions: usize) -> Self {
+        self.max_sessions = max_sessions;
+        self.store = Self::store(self.ttl, max_sessions);
+        self
+    }
+
+    /// Sets the name of the session cookie. Defaults to `session`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` isn't a token.
+    pub fn cookie_name(mut self, name: impl Into<String>) -> Self {
+        let name = name.into();
+        assert!(is_token(&name), "cookie name must be a token");
+        self.cookie_name = name;
+        self
+    }
+
+    /// Sets whether the session cookie is only sent over HTTPS. Defaults to `false`.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    fn store(ttl: Duration, max_sessions: usize) -> Store {
+        Store::builder()
+            .capacity(max_sessions)
+            .ttl(ttl)
+            .build()
+            .expect("max_sessions must be positive")
+    }
+
+    /// Returns the HMAC of the session ID `id`.
+    fn mac(&self, id: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        mac.update(id.as_bytes());
+        mac
+    }
+
+    /// Returns the session cookie carrying `value`, or removing the cookie if `None`.
+    fn cookie(&self, value: Option<&str>) -> Cookie {
+        let cookie = match value {
+            Some(value) => Cookie::new(self.cookie_name.as_str(), value),
+            None => Cookie::removal(self.cookie_name.as_str()),
+        };
+        cookie
+            .path("/")
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(SameSite::Lax)
+    }
+
+    /// Returns the session ID of the cookie value `value` if its signature is valid.
+    fn verify<'a>(&self, value: &'a str) -> Option<&'a str> {
+        let (id, signature) = value.split_once('.')?;
+        let signature = decode_hex(signature)?;
+        // Compares in constant time, so that the time taken gives nothing away.
+        self.mac(id).verify_slice(&signature).ok()?;
+        Some(id)
+    }
+}
+
+impl Middleware for Sessions {
+    fn handle(&self, mut request: Request, next: Next<'_>) -> Response {
+        let loaded = request
+            .cookie(&self.cookie_name)
+            .and_then(|value| self.verify(value))
+            .and_then(|id| {
+                let (data, version) = self.store.get_with_version(&id.to_string())?;
+                Some((id.to_string(), data, version))
+            });
+        let (mut id, data, version) = match loaded {
+            Some((id, data, version)) => (Some(id), data, version),
+            None => (None, HashMap::new(), Store::ABSENT),
+        };
+        let session = Session::with_data(data);
+        request.session = Some(session.clone());
+        let mut response = next.run(request);
+
+        let state = session.state.lock().unwrap();
+        if state.destroyed || state.data.is_empty() {
+            if let Some(id) = id.take() {
+                let _ = self.store.invalidate(&id);
+                if state.data.is_empty() {
+                    response = response.with_cookie(&self.cookie(None));
+                }
+            }
+        }
+        if state.data.is_empty() {
+            return response;
+        }
+        match id {
+            Some(id) => {
+                // Stored again even if unchanged, so that the session expires only once the client
+                // has been idle for the TTL.
+                if let Err(current) =
+                    self.store
+                        .insert_if_version(id.clone(), version, state.data.clone())
+                {
+                    // Another request of the session stored it concurrently. The last change wins.
+                    if state.changed {
+                        let _ = self
+                            .store
+                            .insert_if_version(id, current, state.data.clone());
+                    }
+                }
+            }
+            None => {
+                let mut bytes = [0; ID_LEN];
+                rand::thread_rng().fill_bytes(&mut bytes);
+                let id = encode_hex(&bytes);
+                let signature = encode_hex(&self.mac(&id).finalize().into_bytes());
+                let _ = self
+                    .store
+                    .insert_if_version(id.clone(), Store::ABSENT, state.data.clone());
+                response = response.with_cookie(&self.cookie(Some(&format!("{id}.{signature}"))));
+            }
+        }
+        response
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.len() == 2)?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
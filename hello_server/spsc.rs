@@ -0,0 +1,177 @@
+//! Wait-free single-producer single-consumer bounded channel.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::cell::UnsafeCell;
+use core::error::Error;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::cache_padded::CachePadded;
+
+/// A ring buffer with one slot more than the capacity, so that a full buffer can be told from an
+/// empty one.
+struct Buffer<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    /// Index of the next slot to pop, written by the consumer only.
+    head: CachePadded<AtomicUsize>,
+    /// Index of the next slot to push, written by the producer only.
+    tail: CachePadded<AtomicUsize>,
+}
+
+// Each slot is accessed by one side at a time, handed over by `head` and `tail`.
+unsafe impl<T: Send> Sync for Buffer<T> {}
+
+impl<T> Buffer<T> {
+    fn next(&self, index: usize) -> usize {
+        if index + 1 == self.slots.len() {
+            0
+        } else {
+            index + 1
+        }
+    }
+}
+
+impl<T> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        let mut head = *self.head.0.get_mut();
+        let tail = *self.tail.0.get_mut();
+        while head != tail {
+            // SAFETY: Slots from the head to the tail hold values pushed and not popped.
+            unsafe { self.slots[head].get_mut().assume_init_drop() };
+            head = self.next(head);
+        }
+    }
+}
+
+/// Creates a channel holding at most `capacity` values, and returns its two ends. Neither blocks:
+/// pushing to a full channel and popping from an empty one fail right away.
+///
+/// # Panics
+///
+/// Panics if `capacity` is 0.
+pub fn channel<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert_ne!(capacity, 0, "capacity must be positive");
+    let buffer = Arc::new(Buffer {
+        slots: (0..=capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        head: CachePadded::default(),
+        tail: CachePadded::default(),
+    });
+    (
+        Producer {
+            buffer: Arc::clone(&buffer),
+            head: 0,
+        },
+        Consumer { buffer, tail: 0 },
+    )
+}
+
+/// The pushing end of a [`channel`].
+pub struct Producer<T> {
+    buffer: Arc<Buffer<T>>,
+    /// Last head seen, so that the consumer's cache line is only read once the buffer looks full.
+    head: usize,
+}
+
+impl<T> fmt::Debug for Producer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Producer").finish_non_exhaustive()
+    }
+}
+
+impl<T> Producer<T> {
+    /// Pushes `value`, or gives it back if the channel is full.
+    pub fn push(&mut self, value: T) -> Result<(), Full<T>> {
+        let buffer = &*self.buffer;
+        let tail = buffer.tail.load(Ordering::Relaxed);
+        let next = buffer.next(tail);
+        if next == self.head {
+            // Acquire so that the consumer is done reading the slot before it is written.
+            self.head = buffer.head.load(Ordering::Acquire);
+            if next == self.head {
+                return Err(Full(value));
+            }
+        }
+        // SAFETY: The slot is outside the consumer's range until the tail moves past it.
+        unsafe { (*buffer.slots[tail].get()).write(value) };
+        // Release so that the consumer sees the value.
+        buffer.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    /// Returns the maximum number of values in the channel.
+    pub fn capacity(&self) -> usize {
+        self.buffer.slots.len() - 1
+    }
+
+    /// Returns whether the consumer was dropped, so that pushed values are never popped.
+    pub fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.buffer) == 1
+    }
+}
+
+/// The popping end of a [`channel`].
+pub struct Consumer<T> {
+    buffer: Arc<Buffer<T>>,
+    /// Last tail seen, so that the producer's cache line is only read once the buffer looks empty.
+    tail: usize,
+}
+
+impl<T> fmt::Debug for Consumer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Consumer").finish_non_exhaustive()
+    }
+}
+
+impl<T> Consumer<T> {
+    /// Pops the oldest value, if any.
+    pub fn pop(&mut self) -> Option<T> {
+        let buffer = &*self.buffer;
+        let head = buffer.head.load(Ordering::Relaxed);
+        if head == self.tail {
+            // Acquire so that the producer's write of the value is seen.
+            self.tail = buffer.tail.load(Ordering::Acquire);
+            if head == self.tail {
+                return None;
+            }
+        }
+        // SAFETY: The slot holds a value pushed, and is outside the producer's range until the
+        // head moves past it.
+        let value = unsafe { (*buffer.slots[head].get()).assume_init_read() };
+        // Release so that the producer overwrites the slot only once it was read.
+        buffer.head.store(buffer.next(head), Ordering::Release);
+        Some(value)
+    }
+
+    /// Returns the maximum number of values in the channel.
+    pub fn capacity(&self) -> usize {
+        self.buffer.slots.len() - 1
+    }
+
+    /// Returns whether the producer was dropped, so that no value is pushed anymore. Values pushed
+    /// before can still be popped.
+    pub fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.buffer) == 1
+    }
+}
+
+/// Error of [`Producer::push`] when the channel is full, with the value not pushed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Full<T>(pub T);
+
+impl<T> fmt::Debug for Full<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Full").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for Full<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "channel is full")
+    }
+}
+
+impl<T> Error for Full<T> {}
@@ -1,82 +1,45 @@
-mod modules;
-
-use modules::{CancellableTcpListener, Handler, Statistics, ThreadPool};
-use std::io;
-use std::sync::mpsc::{channel, sync_channel};
-use std::sync::Arc;
-
-const ADDR: &str = "localhost:7878";
+use cs431_homework::hello_server::{Server, ServerConfig, ServerError};
+use std::env;
+use std::error::Error;
+use std::process;
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("error: {err}");
+        let mut source = err.source();
+        while let Some(err) = source {
+            eprintln!("  caused by: {err}");
+            source = err.source();
+        }
+        // Configuration errors are usage errors.
+        let code = match err {
+            ServerError::Config(_) | ServerError::Unsupported(_) => 2,
+            _ => 1,
+        };
+        process::exit(code);
+    }
+}
+
+fn run() -> Result<(), ServerError> {
+    // Loads the configuration, see `ServerConfig` for the options.
+    let config = ServerConfig::load(env::args().skip(1))?;
 
-fn main() -> io::Result<()> {
     // Use a browser that doesn't cache too eagerly so that request is always sent. For example,
     // Firefox works well.  If you want to test using command line only, use curl. If you want to
-    // run it on the lab server, you may need to change the port number to something else.
-    println!("Run `curl http://{ADDR}/KEY` to query the server with KEY");
-
-    // The thread pool.
-    //
-    // In the thread pool, we'll execute:
-    //
-    // - A listener: it accepts incoming connections, and creates a new worker for each connection.
-    //
-    // - Workers (once for each incoming connection): a worker handles an incoming connection and
-    //   sends a corresponding report to the reporter.
-    //
-    // - A reporter: it aggregates the reports from the workers and processes the statistics. When
-    //   it ends, it sends the statistics to the main thread.
-    let pool = Arc::new(ThreadPool::new(7));
-
-    // The (MPSC) channel of reports between workers and the reporter.
-    let (report_sender, report_receiver) = channel();
-
-    // The (SPSC one-shot) channel of stats between the reporter and the main thread.
-    let (stat_sender, stat_receiver) = sync_channel(0);
-
-    // Listens to the address.
-    let listener = Arc::new(CancellableTcpListener::bind(ADDR)?);
+    // run it on the lab server, you may need to change the address with `--addr`.
+    println!(
+        "Run `curl http://{}/KEY` to query the server with KEY",
+        config.addrs[0]
+    );
 
-    // Installs a Ctrl-C handler.
-    let ctrlc_listener_handle = listener.clone();
-    ctrlc::set_handler(move || {
-        ctrlc_listener_handle.cancel().unwrap();
-    })
-        .expect("Error setting Ctrl-C handler");
+    let server = Server::bind(config)?;
 
-    // Executes the listener.
-    let listener_pool = pool.clone();
-    pool.execute(move || {
-        // Creates the request handler.
-        let handler = Handler::default();
+    // Installs a handler for Ctrl-C (SIGINT) and SIGTERM. Stops accepting new connections.
+    let shutdown = server.shutdown_handle();
+    ctrlc::set_handler(move || shutdown.shutdown().unwrap()).expect("Error setting Ctrl-C handler");
 
-        // For each incoming connection...
-        for (id, stream) in listener.incoming().enumerate() {
-            // send a job to the thread pool.
-            let report_sender = report_sender.clone();
-            let handler = handler.clone();
-            listener_pool.execute(move || {
-                let report = handler.handle_conn(id, stream.unwrap());
-                report_sender.send(report).unwrap();
-            });
-        }
-    });
-
-    // Executes the reporter.
-    pool.execute(move || {
-        let mut stats = Statistics::default();
-        for report in report_receiver {
-            println!("[report] {report:?}");
-            stats.add_report(report);
-        }
-
-        println!("[sending stat]");
-        stat_sender.send(stats).unwrap();
-        println!("[sent stat]");
-    });
-
-    // Blocks until the reporter sends the statistics.
-    let stat = stat_receiver.recv().unwrap();
+    // Serves until shutdown, and then waits for the in-flight requests.
+    let stat = server.run()?;
     println!("[stat] {stat:?}");
-
     Ok(())
-    // When the pool is dropped, all worker threads are joined.
-}
\ No newline at end of file
+}
@@ -0,0 +1,21 @@
+//! The hello server and the concurrency primitives it is built on, as a library.
+//!
+//! - [`thread_pool`]: a thread pool, bounded or not, that joins its threads when dropped.
+//! - [`cache`]: a thread-safe key/value cache loading missing values at most once.
+//! - [`server`]: the HTTP server, whole or connection by connection.
+//!
+//! The other modules are under [`hello_server`], which also re-exports the main types.
+//!
+//! Without the default `std` feature, the crate is `no_std` with `alloc`, and only has the cache,
+//! the lock-free stack and queue, the SPSC ring buffer and the spin lock.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[path = "../mod.rs"]
+pub mod hello_server;
+
+pub use hello_server::cache;
+#[cfg(feature = "std")]
+pub use hello_server::{server, thread_pool};
@@ -0,0 +1,146 @@
+//! Server-sent events.
+
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use super::http::{Body, Response};
+
+/// Media type of event streams.
+pub(crate) const EVENT_STREAM_MEDIA_TYPE: &str = "text/event-stream";
+
+/// Default interval of the keep-alive comments of an event stream.
+const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// An event of an event stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+    data: String,
+}
+
+impl Event {
+    /// Creates an event with `data`, which may span several lines.
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            data: data.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Sets the event type, dispatched to the browser's listeners for it instead of `message`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `event` contains a line break.
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        let event = event.into();
+        assert!(!event.contains(['\r', '\n']), "event type must be one line");
+        self.event = Some(event);
+        self
+    }
+
+    /// Sets the event ID, which the browser sends back in `Last-Event-ID` when it reconnects.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` contains a line break or a NUL.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        let id = id.into();
+        assert!(
+            !id.contains(['\r', '\n', '\0']),
+            "event ID must be one line"
+        );
+        self.id = Some(id);
+        self
+    }
+
+    /// Sets how long the browser waits before reconnecting if the stream is closed.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Writes the event in the `text/event-stream` format.
+    fn write_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        if let Some(event) = &self.event {
+            writeln!(writer, "event: {event}")?;
+        }
+        if let Some(id) = &self.id {
+            writeln!(writer, "id: {id}")?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(writer, "retry: {}", retry.as_millis())?;
+        }
+        // Every line is a field of its own, whichever line breaks it ends with.
+        for line in self.data.replace("\r\n", "\n").split(['\r', '\n']) {
+            writeln!(writer, "data: {line}")?;
+        }
+        writer.write_all(b"\n")
+    }
+}
+
+/// A response streaming server-sent events to a browser's `EventSource`.
+///
+/// The events received from a channel are written and flushed as they arrive, and a comment is
+/// sent whenever no event has been sent for a while, so that proxies keep the connection open and a
+/// closed connection is noticed. The stream ends once every sender is dropped, and the connection
+/// is closed if the client goes away.
+///
+/// The response occupies its connection's thread until the stream ends.
+#[derive(Debug)]
+pub struct Sse {
+    events: Receiver<Event>,
+    keep_alive: Duration,
+}
+
+impl Sse {
+    /// Creates an event stream of the events received from `events`.
+    pub fn new(events: Receiver<Event>) -> Self {
+        Self {
+            events,
+            keep_alive: DEFAULT_KEEP_ALIVE,
+        }
+    }
+
+    /// Creates an event stream and the sender of its events.
+    pub fn channel() -> (Sender<Event>, Self) {
+        let (sender, receiver) = mpsc::channel();
+        (sender, Self::new(receiver))
+    }
+
+    /// Sets how long the stream may go without an event before a keep-alive comment is sent.
+    /// Defaults to 15 seconds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the interval is zero.
+    pub fn keep_alive(mut self, interval: Duration) -> Self {
+        assert_ne!(interval, Duration::ZERO, "interval must be positive");
+        self.keep_alive = interval;
+        self
+    }
+
+    /// Writes the events to `writer` until every sender is dropped.
+    fn stream(self, writer: &mut dyn Write) -> io::Result<()> {
+        loop {
+            match self.events.recv_timeout(self.keep_alive) {
+                Ok(event) => event.write_to(writer)?,
+                Err(RecvTimeoutError::Timeout) => writer.write_all(b": keep-alive\n\n")?,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            writer.flush()?;
+        }
+    }
+}
+
+impl From<Sse> for Response {
+    fn from(sse: Sse) -> Self {
+        Response::new(200)
+            .with_header("Content-Type", EVENT_STREAM_MEDIA_TYPE)
+            .with_header("Cache-Control", "no-cache")
+            .with_body(Body::stream(move |writer| sse.stream(writer)))
+    }
+}
@@ -0,0 +1,389 @@
+//! Static file serving.
+
+use serde_json::json;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::access_log::format_time;
+use super::cache::Cache;
+use super::handler::Handler;
+use super::http::{percent_decode, percent_encode, Body, Request, Response};
+use super::router::Router;
+use super::sync::OnceCell;
+use super::template::Template;
+use super::watch::FileWatcher;
+
+/// What a path under the root resolves to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    /// A directory without `index.html`, answered with a listing.
+    Listing,
+    /// A file, or the `index.html` of a directory.
+    File(PathBuf),
+}
+
+/// Serves files under a root directory.
+///
+/// The requested file is taken from the `path` parameter, so the handler is meant to be mounted on
+/// a `*path` route (see [`StaticFiles::mount`]). Paths containing `..` segments are rejected with
+/// 403, and a request for a directory serves its `index.html`.
+///
+/// A directory without `index.html` is answered with 404, or with a listing of its entries if
+/// enabled with [`StaticFiles::list_directories`].
+///
+/// A single byte range requested with `Range: bytes=...` is served as 206 Partial Content, and an
+/// unsatisfiable one is answered with 416. Requests for several ranges get the whole file.
+///
+/// If the root is watched with [`StaticFiles::watch`], what the requested paths resolve to is
+/// cached until the files change, saving the lookups of directories and `index.html`.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    list_directories: bool,
+    /// What paths resolve to, cached while the root is watched.
+    entries: Option<Arc<Cache<PathBuf, Entry>>>,
+    /// Kept to keep watching the root.
+    watcher: Option<Arc<FileWatcher>>,
+}
+
+impl StaticFiles {
+    /// Creates a handler serving files under `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            list_directories: false,
+            entries: None,
+            watcher: None,
+        }
+    }
+
+    /// Sets whether directories without `index.html` are answered with a listing of their
+    /// entries. Defaults to `false`.
+    pub fn list_directories(mut self, list_directories: bool) -> Self {
+        self.list_directories = list_directories;
+        self
+    }
+
+    /// Watches the files under the root, caching what the requested paths resolve to until they
+    /// change. `on_change` is called on a background thread with the paths, relative to where the
+    /// handler is mounted, of the pages that may have changed with a file: its own and those of its
+    /// directory, e.g. `/css/site.css`, `/css/`, and `/css`, where the root is `/` and the empty
+    /// path. Cached responses for them can then be invalidated, see
+    /// [`ResponseCache::invalidate`](super::ResponseCache::invalidate).
+    pub fn watch<F>(mut self, on_change: F) -> io::Result<Self>
+    where
+        F: Fn(&str) + Send + 'static,
+    {
+        let entries: Arc<Cache<PathBuf, Entry>> = Arc::new(Cache::default());
+        let root = self.root.clone();
+        let cached = entries.clone();
+        let watcher = FileWatcher::new(&self.root, move |relative| {
+            let path = root.join(relative);
+            // The directory's listing or `index.html` may change too, and everything under a
+            // directory that is moved or removed.
+            for (key, _) in cached.iter_by_recency() {
+                if key.starts_with(&path) || Some(key.as_path()) == path.parent() {
+                    let _ = cached.invalidate(&key);
+                }
+            }
+
+            let segments = relative
+                .iter()
+                .map(|segment| percent_encode(&segment.to_string_lossy()))
+                .collect::<Vec<_>>();
+            let Some((name, parent)) = segments.split_last() else {
+                return;
+            };
+            let dir = parent
+                .iter()
+                .map(|segment| format!("/{segment}"))
+                .collect::<String>();
+            on_change(&format!("{dir}/{name}"));
+            on_change(&format!("{dir}/"));
+            on_change(&dir);
+        })?;
+        self.entries = Some(entries);
+        self.watcher = Some(Arc::new(watcher));
+        Ok(self)
+    }
+
+    /// Registers `GET {prefix}/*path` on `router`.
+    pub fn mount(self, router: &mut Router, prefix: &str) {
+        let pattern = format!("{}/*path", prefix.trim_end_matches('/'));
+        let _ = router.get(&pattern, self);
+    }
+
+    /// Answers a request for the file at the `path` parameter.
+    pub fn serve(&self, request: Request) -> Response {
+        let Some(path) = request.param("path").and_then(percent_decode) else {
+            return Response::status_page(400);
+        };
+        let Some(path) = self.resolve(&path) else {
+            return Response::status_page(403);
+        };
+
+        let opened = self.lookup(&path).and_then(|entry| match entry {
+            Entry::Listing => Ok(None),
+            Entry::File(file_path) => Self::open(&file_path)
+                .map(|(file, len)| Some((file, len, file_path)))
+                // The cached entry may be outdated if the change isn't reported yet.
+                .inspect_err(|_| {
+                    if let Some(entries) = &self.entries {
+                        let _ = entries.invalidate(&path);
+                    }
+                }),
+        });
+        let (mut file, len, path) = match opened {
+            Ok(Some(opened)) => opened,
+            Ok(None) => {
+                let is_root = request
+                    .param("path")
+                    .unwrap_or_default()
+                    .trim_matches('/')
+                    .is_empty();
+                return match listing(&request.path, &path, is_root) {
+                    Ok(html) => Response::html(html),
+                    Err(err) => {
+                        println!("[static] failed to list {}: {err}", path.display());
+                        Response::status_page(500)
+                    }
+                };
+            }
+            Err(err) => {
+                return Response::status_page(match err.kind() {
+                    io::ErrorKind::NotFound => 404,
+                    io::ErrorKind::PermissionDenied => 403,
+                    _ => 500,
+                })
+            }
+        };
+        let response = Response::new(200)
+            .with_header("Content-Type", content_type(&path))
+            .with_header("Accept-Ranges", "bytes");
+
+        let (start, end) = match request.header("Range").map(|range| parse_range(range, len)) {
+            None | Some(Range::Ignored) => {
+                return response.with_body(Body::Reader(Box::new(file), len));
+            }
+            Some(Range::Unsatisfiable) => {
+                return Response::status_page(416)
+                    .with_header("Content-Range", format!("bytes */{len}"));
+            }
+            Some(Range::Bytes(start, end)) => (start, end),
+        };
+        if let Err(err) = file.seek(SeekFrom::Start(start)) {
+            println!("[static] failed to seek {}: {err}", path.display());
+            return Response::status_page(500);
+        }
+        let mut response = response
+            .with_header("Content-Range", format!("bytes {start}-{end}/{len}"))
+            .with_body(Body::Reader(Box::new(file), end - start + 1));
+        response.status = 206;
+        response
+    }
+
+    /// Joins the relative `path` to the root. Returns `None` if `path` may escape the root.
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let mut resolved = self.root.clone();
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return None,
+                _ if segment.contains(['\\', '\0']) => return None,
+                _ => resolved.push(segment),
+            }
+        }
+        Some(resolved)
+    }
+
+    /// Returns what `path` resolves to, from the cache if the root is watched.
+    fn lookup(&self, path: &Path) -> io::Result<Entry> {
+        let Some(entries) = &self.entries else {
+            return self.resolve_entry(path);
+        };
+        let key = path.to_path_buf();
+        if let Some((entry, _)) = entries.get_with_version(&key) {
+            return Ok(entry);
+        }
+        let entry = self.resolve_entry(path)?;
+        let _ = entries.insert_if_version(key, Cache::<PathBuf, Entry>::ABSENT, entry.clone());
+        Ok(entry)
+    }
+
+    /// Returns what `path` resolves to: `index.html` if it is a directory, or a listing if that
+    /// doesn't exist and directories are listed.
+    fn resolve_entry(&self, path: &Path) -> io::Result<Entry> {
+        if !fs::metadata(path)?.is_dir() {
+            return Ok(Entry::File(path.to_path_buf()));
+        }
+        let index = path.join("index.html");
+        if self.list_directories && !index.exists() {
+            return Ok(Entry::Listing);
+        }
+        Ok(Entry::File(index))
+    }
+
+    /// Opens the file at `path` and returns its length.
+    fn open(path: &Path) -> io::Result<(File, u64)> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        Ok((file, metadata.len()))
+    }
+}
+
+impl Handler for StaticFiles {
+    fn call(&self, request: Request) -> Response {
+        self.serve(request)
+    }
+}
+
+/// Renders an HTML listing of the directory `dir` requested at `url_path`, directories first. A
+/// link to the parent directory is added unless `is_root`.
+fn listing(url_path: &str, dir: &Path, is_root: bool) -> io::Result<String> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        entries.push((!metadata.is_dir(), name, metadata));
+    }
+    entries.sort_by(|(a_file, a_name, _), (b_file, b_name, _)| {
+        a_file.cmp(b_file).then_with(|| a_name.cmp(b_name))
+    });
+
+    let base = url_path.trim_end_matches('/');
+    let entries: Vec<_> = entries
+        .iter()
+        .map(|(is_file, name, metadata)| {
+            let suffix = if *is_file { "" } else { "/" };
+            let size = if *is_file {
+                metadata.len().to_string()
+            } else {
+                "-".to_string()
+            };
+            let modified = metadata
+                .modified()
+                .map_or_else(|_| "-".to_string(), |time| format_time(time, true));
+            json!({
+                "href": format!("{}{suffix}", percent_encode(name)),
+                "name": format!("{name}{suffix}"),
+                "size": size,
+                "modified": modified,
+            })
+        })
+        .collect();
+
+    static LISTING: OnceCell<Template> = OnceCell::new();
+    let listing = LISTING.get_or_init(|| {
+        Template::parse(include_str!("templates/listing.html")).expect("listing template is valid")
+    });
+    listing
+        .render(&json!({
+            "base": base,
+            "parent": base.rsplit_once('/').map_or("", |(parent, _)| parent),
+            "is_root": is_root,
+            "entries": entries,
+        }))
+        .map_err(io::Error::other)
+}
+
+/// Escapes the characters with a special meaning in HTML text and attribute values.
+pub(crate) fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A `Range` header resolved against the file length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Range {
+    /// The inclusive range of bytes to send.
+    Bytes(u64, u64),
+    /// No byte of the file is in the range.
+    Unsatisfiable,
+    /// Malformed, several ranges, or not in bytes. The whole file is sent.
+    Ignored,
+}
+
+/// Parses a `Range` header value like `bytes=0-99`, `bytes=100-`, or `bytes=-100` (the last 100
+/// bytes) for a file of `len` bytes.
+fn parse_range(value: &str, len: u64) -> Range {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Range::Ignored;
+    };
+    if spec.contains(',') {
+        return Range::Ignored;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return Range::Ignored;
+    };
+    let parse = |s: &str| {
+        (!s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()))
+            .then(|| s.parse::<u64>().ok())
+            .flatten()
+    };
+
+    match (start, end) {
+        ("", suffix) => match parse(suffix) {
+            Some(0) => Range::Unsatisfiable,
+            Some(_) if len == 0 => Range::Unsatisfiable,
+            Some(suffix) => Range::Bytes(len.saturating_sub(suffix), len - 1),
+            None => Range::Ignored,
+        },
+        (start, "") => match parse(start) {
+            Some(start) if start >= len => Range::Unsatisfiable,
+            Some(start) => Range::Bytes(start, len - 1),
+            None => Range::Ignored,
+        },
+        (start, end) => match (parse(start), parse(end)) {
+            (Some(start), Some(end)) if start > end => Range::Ignored,
+            (Some(start), Some(_)) if start >= len => Range::Unsatisfiable,
+            (Some(start), Some(end)) => Range::Bytes(start, end.min(len - 1)),
+            _ => Range::Ignored,
+        },
+    }
+}
+
+/// Returns the MIME type for the extension of `path`.
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("webp") => "image/webp",
+        Some("pdf") => "application/pdf",
+        Some("wasm") => "application/wasm",
+        Some("mp3") => "audio/mpeg",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
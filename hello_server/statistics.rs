@@ -1,12 +1,15 @@
 //! Server statisics
 
 use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::sync::SeqLock;
 
 /// Report for each operation
 #[derive(Debug)]
 pub struct Report {
     _id: usize,
-    key: Option<String>, // None represents invalid request
+    key: Option<String>, // requested path, None represents invalid request
 }
 
 impl Report {
@@ -16,16 +19,83 @@ impl Report {
     }
 }
 
+/// Totals of the reports added so far, see [`Statistics::summary`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Number of requests reported.
+    pub requests: usize,
+    /// Number of invalid requests among them.
+    pub invalid: usize,
+}
+
 /// Operation statisics
 #[derive(Debug, Default)]
 pub struct Statistics {
     hits: HashMap<Option<String>, usize>,
+    /// Updated with each report, so that other threads read the totals without waiting for the
+    /// reporter.
+    summary: Arc<SeqLock<Summary>>,
 }
 
 impl Statistics {
+    /// Creates empty statistics that keep `summary` up to date.
+    pub fn new(summary: Arc<SeqLock<Summary>>) -> Self {
+        Self {
+            hits: HashMap::new(),
+            summary,
+        }
+    }
+
     /// Add a report to the statisics.
     pub fn add_report(&mut self, report: Report) {
+        let invalid = usize::from(report.key.is_none());
         let hits = self.hits.entry(report.key).or_default();
         *hits += 1;
+        self.summary.update(|summary| Summary {
+            requests: summary.requests + 1,
+            invalid: summary.invalid + invalid,
+        });
+    }
+
+    /// Returns the totals of the reports added so far.
+    pub fn summary(&self) -> Summary {
+        self.summary.read()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::super::sync::SeqLock;
+    use super::{Report, Statistics, Summary};
+
+    #[test]
+    fn summary_is_never_torn() {
+        const REPORTS: usize = 100_000;
+        let summary = Arc::new(SeqLock::default());
+        let mut stats = Statistics::new(summary.clone());
+        thread::scope(|scope| {
+            let _ = scope.spawn(|| loop {
+                // Every other report is invalid, so both totals move together.
+                let Summary { requests, invalid } = summary.read();
+                assert_eq!(invalid, requests / 2);
+                if requests == REPORTS {
+                    break;
+                }
+            });
+            for id in 0..REPORTS {
+                let key = (id % 2 == 0).then(|| "/".to_string());
+                stats.add_report(Report::new(id, key));
+            }
+        });
+        assert_eq!(
+            stats.summary(),
+            Summary {
+                requests: REPORTS,
+                invalid: REPORTS / 2,
+            }
+        );
     }
 }
@@ -0,0 +1,423 @@
+//! Software transactional memory.
+//!
+//! [`atomically`] runs a transaction over [`TVar`]s as if no other thread ran meanwhile. It
+//! follows Dice et al., "Transactional Locking II" (DISC 2006): a global clock stamps commits, and
+//! each variable holds the stamp of its last write along with a lock bit. A transaction reads
+//! optimistically, without locking, and fails as soon as it reads a variable written after it
+//! started. It buffers its writes, and at commit locks the variables it writes, checks that the
+//! ones it read are unchanged, and publishes the writes with a new stamp. A failed transaction is
+//! run again from the start.
+//!
+//! ```ignore
+//! let (from, to) = (TVar::new(100), TVar::new(0));
+//! atomically(|tx| {
+//!     let balance = tx.read(&from)?;
+//!     if balance < 30 {
+//!         // Waits until `from` is written by another transaction.
+//!         return tx.retry();
+//!     }
+//!     tx.write(&from, balance - 30);
+//!     tx.modify(&to, |balance| balance + 30)
+//! });
+//! ```
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use super::sync::{ArcCell, Backoff};
+
+/// Stamp of the last commit. Stamps are even, since the lowest bit of a variable's version is
+/// its lock.
+static CLOCK: AtomicU64 = AtomicU64::new(0);
+
+/// Bit of a variable's version set while a transaction writing it commits.
+const LOCKED: u64 = 1;
+
+/// Notified after each commit writing variables, for the transactions waiting in
+/// [`Transaction::retry`].
+static COMMITS: (Mutex<()>, Condvar) = (Mutex::new(()), Condvar::new());
+
+struct Inner<T> {
+    /// Stamp of the last commit writing the variable, with the [`LOCKED`] bit.
+    version: AtomicU64,
+    value: ArcCell<T>,
+}
+
+/// A variable of any type, in the read and write sets of a transaction.
+trait Var: Send + Sync {
+    fn version(&self) -> &AtomicU64;
+
+    /// Replaces the value with `value`, the buffered write of a transaction.
+    fn store(&self, value: Box<dyn Any + Send>);
+}
+
+impl<T: Send + Sync + 'static> Var for Inner<T> {
+    fn version(&self) -> &AtomicU64 {
+        &self.version
+    }
+
+    fn store(&self, value: Box<dyn Any + Send>) {
+        let value = value.downcast::<T>().expect("buffered for this variable");
+        self.value.store(Arc::new(*value));
+    }
+}
+
+/// A variable read and written by transactions. Clones refer to the same variable.
+pub struct TVar<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Clone for TVar<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + fmt::Debug + 'static> fmt::Debug for TVar<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TVar").field(&self.load()).finish()
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> TVar<T> {
+    /// Creates a variable holding `value`.
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                version: AtomicU64::new(0),
+                value: ArcCell::new(Arc::new(value)),
+            }),
+        }
+    }
+
+    /// Returns the value, outside of a transaction. Use [`Transaction::read`] to read several
+    /// variables consistently.
+    pub fn load(&self) -> T {
+        (*self.inner.value.load()).clone()
+    }
+
+    /// Identifies the variable in a transaction, ordering the locking of its write set.
+    fn key(&self) -> usize {
+        Arc::as_ptr(&self.inner) as *const () as usize
+    }
+}
+
+/// Why a transaction stops early, propagated with `?` out of the function given to
+/// [`atomically`], which handles it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StmError {
+    /// Another transaction wrote a variable read by this one. It is run again right away.
+    Conflict,
+    /// The transaction called [`Transaction::retry`]. It is run again once a variable it read is
+    /// written.
+    Retry,
+}
+
+impl fmt::Display for StmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Conflict => write!(f, "transaction conflicted with another"),
+            Self::Retry => write!(f, "transaction retried"),
+        }
+    }
+}
+
+impl Error for StmError {}
+
+/// Result of the operations of a transaction.
+pub type StmResult<T> = Result<T, StmError>;
+
+/// A write buffered until commit.
+struct Write {
+    var: Arc<dyn Var>,
+    value: Box<dyn Any + Send>,
+}
+
+/// The reads and writes of a run of a transaction, given to the function run by [`atomically`].
+pub struct Transaction {
+    /// Stamp of the last commit when the run started. Variables written later can't be read.
+    start: u64,
+    /// The variables read from memory, with their versions then.
+    reads: Vec<(Arc<dyn Var>, u64)>,
+    /// The buffered writes, by [`TVar::key`].
+    writes: BTreeMap<usize, Write>,
+}
+
+impl fmt::Debug for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transaction")
+            .field("start", &self.start)
+            .field("reads", &self.reads.len())
+            .field("writes", &self.writes.len())
+            .finish()
+    }
+}
+
+impl Transaction {
+    fn new() -> Self {
+        Self {
+            // Acquire so that the values written by the commits up to the stamp are seen.
+            start: CLOCK.load(Ordering::Acquire),
+            reads: Vec::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the value of `var`: the one written by this transaction if any, or the one in
+    /// memory if it wasn't written since the transaction started.
+    pub fn read<T: Clone + Send + Sync + 'static>(&mut self, var: &TVar<T>) -> StmResult<T> {
+        if let Some(write) = self.writes.get(&var.key()) {
+            let value = write.value.downcast_ref::<T>();
+            return Ok(value.expect("buffered for this variable").clone());
+        }
+        // Acquire so that the value of the version, or a later one, is loaded.
+        let version = var.inner.version.load(Ordering::Acquire);
+        if version & LOCKED != 0 || version > self.start {
+            return Err(StmError::Conflict);
+        }
+        let value = var.inner.value.load();
+        // The loads are ordered by the acquire load of the cell, so the value is the one of the
+        // version if it is unchanged.
+        if var.inner.version.load(Ordering::Relaxed) != version {
+            return Err(StmError::Conflict);
+        }
+        self.reads.push((var.inner.clone(), version));
+        Ok((*value).clone())
+    }
+
+    /// Buffers the write of `value` to `var`, published when the transaction commits.
+    pub fn write<T: Clone + Send + Sync + 'static>(&mut self, var: &TVar<T>, value: T) {
+        let write = Write {
+            var: var.inner.clone(),
+            value: Box::new(value),
+        };
+        let _ = self.writes.insert(var.key(), write);
+    }
+
+    /// Writes `f` of the value of `var` to it.
+    pub fn modify<T, F>(&mut self, var: &TVar<T>, f: F) -> StmResult<()>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: FnOnce(T) -> T,
+    {
+        let value = self.read(var)?;
+        self.write(var, f(value));
+        Ok(())
+    }
+
+    /// Gives up on this run, e.g. because a value read doesn't allow the transaction to proceed.
+    /// The transaction is run again once another one writes a variable read by this one.
+    pub fn retry<T>(&mut self) -> StmResult<T> {
+        Err(StmError::Retry)
+    }
+
+    /// Checks that the variables read are unchanged, other than by this transaction.
+    fn validate(&self) -> bool {
+        self.reads.iter().all(|(var, version)| {
+            let current = var.version().load(Ordering::Acquire);
+            current == *version
+                || current == version | LOCKED
+                    && self
+                        .writes
+                        .values()
+                        .any(|write| Arc::ptr_eq(&write.var, var))
+        })
+    }
+
+    /// Publishes the writes if the variables read are unchanged.
+    fn commit(self) -> StmResult<()> {
+        // Each read checked that the variable wasn't written since the start.
+        if self.writes.is_empty() {
+            return Ok(());
+        }
+        // Locks the variables written in the order of their keys, so that committing
+        // transactions don't wait for each other in a cycle.
+        let mut locked = Vec::with_capacity(self.writes.len());
+        for write in self.writes.values() {
+            let version = write.var.version().load(Ordering::Relaxed);
+            // Acquire so that the writes of the commit that stamped the version are seen before
+            // they are replaced.
+            if version & LOCKED != 0
+                || write
+                    .var
+                    .version()
+                    .compare_exchange(
+                        version,
+                        version | LOCKED,
+                        Ordering::Acquire,
+                        Ordering::Relaxed,
+                    )
+                    .is_err()
+            {
+                unlock(&locked);
+                return Err(StmError::Conflict);
+            }
+            locked.push((&write.var, version));
+        }
+        // Acquire so that the commits stamped before are seen when validating.
+        let stamp = CLOCK.fetch_add(2, Ordering::AcqRel) + 2;
+        // No other transaction committed since the start, so the reads are still valid.
+        if stamp != self.start + 2 && !self.validate() {
+            unlock(&locked);
+            return Err(StmError::Conflict);
+        }
+        for write in self.writes.into_values() {
+            write.var.store(write.value);
+            // Release so that transactions reading the version see the value.
+            write.var.version().store(stamp, Ordering::Release);
+        }
+        let (lock, condvar) = &COMMITS;
+        // Taken so that a transaction waiting in `retry` isn't between its check and its wait.
+        drop(lock.lock().unwrap());
+        condvar.notify_all();
+        Ok(())
+    }
+
+    /// Blocks until another transaction writes a variable read by this one.
+    fn wait(self) {
+        assert!(
+            !self.reads.is_empty(),
+            "transaction retried without reading any variable"
+        );
+        let (lock, condvar) = &COMMITS;
+        let guard = lock.lock().unwrap();
+        let _guard = condvar
+            .wait_while(guard, |_| {
+                self.reads
+                    .iter()
+                    .all(|(var, version)| var.version().load(Ordering::Relaxed) == *version)
+            })
+            .unwrap();
+    }
+}
+
+/// Releases the locks of a failed commit, restoring the versions.
+fn unlock(locked: &[(&Arc<dyn Var>, u64)]) {
+    for (var, version) in locked {
+        var.version().store(*version, Ordering::Release);
+    }
+}
+
+/// Runs `f` as a transaction, and returns its result once it commits.
+///
+/// `f` may be run several times: each time the transaction conflicts with another, and each time
+/// it calls [`Transaction::retry`]. It must propagate the errors of the transaction with `?`, and
+/// shouldn't have other side effects.
+///
+/// # Panics
+///
+/// Panics if the transaction retries without having read any variable, since nothing could wake
+/// it up.
+pub fn atomically<R, F>(f: F) -> R
+where
+    F: Fn(&mut Transaction) -> StmResult<R>,
+{
+    let mut backoff = Backoff::new();
+    loop {
+        let mut tx = Transaction::new();
+        match f(&mut tx) {
+            Ok(result) => match tx.commit() {
+                Ok(()) => return result,
+                Err(_) => backoff.snooze(),
+            },
+            Err(StmError::Conflict) => backoff.snooze(),
+            Err(StmError::Retry) => tx.wait(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use super::{atomically, TVar};
+
+    const ACCOUNTS: usize = 8;
+    const BALANCE: u64 = 1_000;
+    const TRANSFERS: usize = 10_000;
+
+    fn total(accounts: &[TVar<u64>]) -> u64 {
+        atomically(|tx| {
+            accounts
+                .iter()
+                .try_fold(0, |total, account| Ok(total + tx.read(account)?))
+        })
+    }
+
+    #[test]
+    fn transfers_keep_the_total_balance() {
+        let accounts = (0..ACCOUNTS)
+            .map(|_| TVar::new(BALANCE))
+            .collect::<Vec<_>>();
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            let auditors = (0..2)
+                .map(|_| {
+                    scope.spawn(|| {
+                        while !done.load(Ordering::Relaxed) {
+                            assert_eq!(total(&accounts), ACCOUNTS as u64 * BALANCE);
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            let tellers = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut rng = rand::thread_rng();
+                        for _ in 0..TRANSFERS {
+                            let from = &accounts[rng.gen_range(0..ACCOUNTS)];
+                            let to = &accounts[rng.gen_range(0..ACCOUNTS)];
+                            let amount = rng.gen_range(1..=BALANCE / 10);
+                            atomically(|tx| {
+                                let balance = tx.read(from)?;
+                                if balance < amount {
+                                    return Ok(());
+                                }
+                                tx.write(from, balance - amount);
+                                tx.modify(to, |balance| balance + amount)
+                            });
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for teller in tellers {
+                teller.join().unwrap();
+            }
+            done.store(true, Ordering::Relaxed);
+            for auditor in auditors {
+                auditor.join().unwrap();
+            }
+        });
+        assert_eq!(total(&accounts), ACCOUNTS as u64 * BALANCE);
+    }
+
+    #[test]
+    fn retry_waits_for_a_deposit() {
+        let account = TVar::new(0);
+        thread::scope(|scope| {
+            let withdrawal = scope.spawn(|| {
+                atomically(|tx| {
+                    let balance = tx.read(&account)?;
+                    if balance < 50 {
+                        return tx.retry();
+                    }
+                    tx.write(&account, balance - 50);
+                    Ok(balance)
+                })
+            });
+            for _ in 0..5 {
+                atomically(|tx| tx.modify(&account, |balance| balance + 20));
+            }
+            assert!(withdrawal.join().unwrap() >= 50);
+        });
+        assert_eq!(account.load(), 50);
+    }
+}
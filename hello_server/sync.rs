@@ -0,0 +1,74 @@
+//! Synchronization primitives.
+
+#[cfg(feature = "std")]
+mod arc_cell;
+mod backoff;
+#[cfg(feature = "std")]
+mod barrier;
+#[cfg(feature = "std")]
+mod blocking_queue;
+#[cfg(feature = "std")]
+mod flat_combining;
+#[cfg(feature = "std")]
+mod mcs_lock;
+#[cfg(feature = "std")]
+mod mutex;
+#[cfg(feature = "std")]
+mod once_cell;
+#[cfg(feature = "std")]
+mod parker;
+#[cfg(feature = "std")]
+mod priority_blocking_queue;
+#[cfg(feature = "std")]
+mod raw_mutex;
+#[cfg(feature = "std")]
+mod rwlock;
+#[cfg(feature = "std")]
+mod semaphore;
+#[cfg(feature = "std")]
+mod seq_lock;
+#[cfg(feature = "std")]
+mod sharded_counter;
+pub(crate) mod shim;
+mod spin_lock;
+#[cfg(feature = "std")]
+mod ticket_lock;
+#[cfg(feature = "std")]
+mod wait_group;
+
+#[cfg(feature = "std")]
+pub use arc_cell::ArcCell;
+pub(crate) use backoff::Backoff;
+#[cfg(feature = "std")]
+pub use barrier::Barrier;
+#[cfg(feature = "std")]
+pub use blocking_queue::BlockingQueue;
+#[cfg(feature = "std")]
+pub use flat_combining::FlatCombining;
+#[cfg(feature = "std")]
+pub use mcs_lock::{McsLock, McsLockGuard};
+#[cfg(feature = "std")]
+pub use mutex::{Mutex, MutexGuard};
+#[cfg(feature = "std")]
+pub use once_cell::{Lazy, OnceCell};
+#[cfg(feature = "std")]
+pub use parker::{Parker, Unparker};
+#[cfg(feature = "std")]
+pub use priority_blocking_queue::PriorityBlockingQueue;
+#[cfg(feature = "std")]
+pub use raw_mutex::RawMutex;
+#[cfg(feature = "std")]
+pub use rwlock::{
+    Preference, RwLock, RwLockReadGuard, RwLockUpgradableReadGuard, RwLockWriteGuard,
+};
+#[cfg(feature = "std")]
+pub use semaphore::{Permit, Semaphore};
+#[cfg(feature = "std")]
+pub use seq_lock::SeqLock;
+#[cfg(feature = "std")]
+pub use sharded_counter::ShardedCounter;
+pub use spin_lock::{SpinLock, SpinLockGuard};
+#[cfg(feature = "std")]
+pub use ticket_lock::{TicketLock, TicketLockGuard};
+#[cfg(feature = "std")]
+pub use wait_group::WaitGroup;
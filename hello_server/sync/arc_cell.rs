@@ -0,0 +1,87 @@
+//! Atomically swappable `Arc`.
+
+use std::fmt;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Arc;
+
+use super::super::reclaim::epoch::pin;
+
+/// Holds an `Arc` that threads can load and replace without locking, e.g. a configuration
+/// reloaded while requests read it.
+///
+/// The cell points to a boxed `Arc`. A thread loading it clones the `Arc` while pinned, so that
+/// the box replaced meanwhile is only dropped by the [epoch-based garbage
+/// collector](super::super::reclaim::epoch) once the clone is made.
+pub struct ArcCell<T> {
+    ptr: AtomicPtr<Arc<T>>,
+}
+
+// Like `Arc`, the value is shared and dropped by any thread.
+unsafe impl<T: Send + Sync> Send for ArcCell<T> {}
+unsafe impl<T: Send + Sync> Sync for ArcCell<T> {}
+
+impl<T: Send + Sync + 'static> ArcCell<T> {
+    /// Creates a cell holding `value`.
+    pub fn new(value: Arc<T>) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(value))),
+        }
+    }
+
+    /// Returns the value held.
+    pub fn load(&self) -> Arc<T> {
+        let _guard = pin();
+        // Acquire so that the value is seen as the thread storing it made it.
+        let ptr = self.ptr.load(Ordering::Acquire);
+        // SAFETY: The box isn't dropped while the guard is pinned.
+        unsafe { Arc::clone(&*ptr) }
+    }
+
+    /// Replaces the value held with `value`.
+    pub fn store(&self, value: Arc<T>) {
+        drop(self.swap(value));
+    }
+
+    /// Replaces the value held with `value`, and returns the previous one.
+    pub fn swap(&self, value: Arc<T>) -> Arc<T> {
+        let new = Box::into_raw(Box::new(value));
+        let guard = pin();
+        // Release so that threads loading the value see it as it was made, and acquire so that
+        // the previous one is seen as it was made.
+        let old = self.ptr.swap(new, Ordering::AcqRel);
+        // SAFETY: The box is unreachable once swapped out, and swapped out only once. It is
+        // dropped once no thread pinned at the time may be cloning the `Arc` anymore.
+        unsafe {
+            let previous = Arc::clone(&*old);
+            guard.defer_destroy(old);
+            previous
+        }
+    }
+
+    /// Returns the value held.
+    pub fn into_inner(self) -> Arc<T> {
+        let ptr = self.ptr.load(Ordering::Relaxed);
+        std::mem::forget(self);
+        // SAFETY: No other thread has access to the cell anymore.
+        unsafe { *Box::from_raw(ptr) }
+    }
+}
+
+impl<T: Default + Send + Sync + 'static> Default for ArcCell<T> {
+    fn default() -> Self {
+        Self::new(Arc::default())
+    }
+}
+
+impl<T: fmt::Debug + Send + Sync + 'static> fmt::Debug for ArcCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ArcCell").field(&self.load()).finish()
+    }
+}
+
+impl<T> Drop for ArcCell<T> {
+    fn drop(&mut self) {
+        // SAFETY: No other thread has access to the cell anymore.
+        drop(unsafe { Box::from_raw(*self.ptr.get_mut()) });
+    }
+}
@@ -0,0 +1,43 @@
+//! Exponential backoff for spinning.
+
+use core::hint;
+#[cfg(feature = "std")]
+use std::thread;
+
+/// Steps after which the number of spins stops doubling.
+const SPIN_LIMIT: u32 = 6;
+
+/// Steps after which waiting yields the thread instead of spinning.
+const YIELD_LIMIT: u32 = 10;
+
+/// Waits longer each time a contended operation fails, so that threads retrying stop hammering the
+/// same cache line.
+#[derive(Debug, Default)]
+pub(crate) struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spins for twice as long as the last time, up to a limit, then yields to other threads so
+    /// that the holder of a lock can make progress even on a single core. Without std, there is no
+    /// scheduler to yield to, so it keeps spinning.
+    pub(crate) fn snooze(&mut self) {
+        if self.step <= SPIN_LIMIT {
+            for _ in 0..1 << self.step {
+                hint::spin_loop();
+            }
+        } else {
+            #[cfg(feature = "std")]
+            thread::yield_now();
+            #[cfg(not(feature = "std"))]
+            hint::spin_loop();
+        }
+        if self.step <= YIELD_LIMIT {
+            self.step += 1;
+        }
+    }
+}
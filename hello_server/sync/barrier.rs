@@ -0,0 +1,57 @@
+//! Reusable barrier.
+
+use std::sync::{Condvar, Mutex};
+
+#[derive(Debug, Default)]
+struct State {
+    /// Number of threads waiting in the current generation.
+    arrived: usize,
+    /// Incremented each time the barrier opens.
+    generation: usize,
+}
+
+/// Makes a fixed number of threads wait for each other, e.g. so that workers start together. The
+/// barrier opens each time the last of them arrives, and can be used again afterwards.
+#[derive(Debug)]
+pub struct Barrier {
+    threads: usize,
+    state: Mutex<State>,
+    opened: Condvar,
+}
+
+impl Barrier {
+    /// Creates a barrier for `threads` threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is 0.
+    pub fn new(threads: usize) -> Self {
+        assert_ne!(threads, 0, "threads must be positive");
+        Self {
+            threads,
+            state: Mutex::default(),
+            opened: Condvar::new(),
+        }
+    }
+
+    /// Blocks until all the threads have called `wait`. Returns `true` in exactly one of them, the
+    /// leader, e.g. to do some work once per generation.
+    pub fn wait(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.arrived += 1;
+        if state.arrived == self.threads {
+            state.arrived = 0;
+            state.generation = state.generation.wrapping_add(1);
+            self.opened.notify_all();
+            return true;
+        }
+        // Waits for the generation to change rather than for the count, which the next
+        // generation may have raised again already.
+        let generation = state.generation;
+        let _state = self
+            .opened
+            .wait_while(state, |state| state.generation == generation)
+            .unwrap();
+        false
+    }
+}
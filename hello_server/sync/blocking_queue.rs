@@ -0,0 +1,126 @@
+//! Bounded blocking queue.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// A FIFO queue of bounded capacity, on which producers block while it is full and consumers
+/// block while it is empty, e.g. to bound the jobs waiting for a thread pool.
+pub struct BlockingQueue<T> {
+    capacity: usize,
+    items: Mutex<VecDeque<T>>,
+    /// Notified when an item is taken.
+    not_full: Condvar,
+    /// Notified when an item is put.
+    not_empty: Condvar,
+}
+
+impl<T> fmt::Debug for BlockingQueue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockingQueue")
+            .field("capacity", &self.capacity)
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> BlockingQueue<T> {
+    /// Creates an empty queue holding up to `capacity` items.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        assert_ne!(capacity, 0, "capacity must be positive");
+        Self {
+            capacity,
+            items: Mutex::new(VecDeque::with_capacity(capacity)),
+            not_full: Condvar::new(),
+            not_empty: Condvar::new(),
+        }
+    }
+
+    /// Puts `item` at the back, blocking while the queue is full.
+    pub fn put(&self, item: T) {
+        let items = self.items.lock().unwrap();
+        let mut items = self
+            .not_full
+            .wait_while(items, |items| items.len() == self.capacity)
+            .unwrap();
+        items.push_back(item);
+        self.not_empty.notify_one();
+    }
+
+    /// Puts `item` at the back if the queue isn't full, or gives it back.
+    pub fn try_put(&self, item: T) -> Result<(), T> {
+        let mut items = self.items.lock().unwrap();
+        if items.len() == self.capacity {
+            return Err(item);
+        }
+        items.push_back(item);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    /// Like [`BlockingQueue::put`], but gives up after `timeout` and gives `item` back.
+    pub fn put_timeout(&self, item: T, timeout: Duration) -> Result<(), T> {
+        let items = self.items.lock().unwrap();
+        let (mut items, _) = self
+            .not_full
+            .wait_timeout_while(items, timeout, |items| items.len() == self.capacity)
+            .unwrap();
+        if items.len() == self.capacity {
+            return Err(item);
+        }
+        items.push_back(item);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    /// Takes the item at the front, blocking while the queue is empty.
+    pub fn take(&self) -> T {
+        let items = self.items.lock().unwrap();
+        let mut items = self
+            .not_empty
+            .wait_while(items, |items| items.is_empty())
+            .unwrap();
+        let item = items.pop_front().expect("waited until not empty");
+        self.not_full.notify_one();
+        item
+    }
+
+    /// Takes the item at the front if the queue isn't empty.
+    pub fn try_take(&self) -> Option<T> {
+        let item = self.items.lock().unwrap().pop_front()?;
+        self.not_full.notify_one();
+        Some(item)
+    }
+
+    /// Like [`BlockingQueue::take`], but gives up after `timeout`.
+    pub fn take_timeout(&self, timeout: Duration) -> Option<T> {
+        let items = self.items.lock().unwrap();
+        let (mut items, _) = self
+            .not_empty
+            .wait_timeout_while(items, timeout, |items| items.is_empty())
+            .unwrap();
+        let item = items.pop_front()?;
+        self.not_full.notify_one();
+        Some(item)
+    }
+
+    /// Returns the number of items. Other threads may change it right away.
+    pub fn len(&self) -> usize {
+        self.items.lock().unwrap().len()
+    }
+
+    /// Returns whether the queue is empty. Other threads may change that right away.
+    pub fn is_empty(&self) -> bool {
+        self.items.lock().unwrap().is_empty()
+    }
+
+    /// Returns the maximum number of items.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
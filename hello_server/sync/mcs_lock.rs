@@ -0,0 +1,188 @@
+//! MCS queue lock.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use super::backoff::Backoff;
+
+/// A thread's place in the queue of an [`McsLock`].
+#[derive(Debug)]
+struct Node {
+    /// Cleared by the previous holder when it hands the lock over.
+    waiting: AtomicBool,
+    next: AtomicPtr<Node>,
+}
+
+impl Node {
+    fn boxed(waiting: bool) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            waiting: AtomicBool::new(waiting),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }))
+    }
+}
+
+/// A mutual exclusion lock whose waiters queue up, each spinning on its own node, so that a
+/// release only touches the cache line of the next waiter instead of all of them as with a
+/// [`SpinLock`](super::SpinLock). The lock is handed over in arrival order.
+///
+/// The lock points to the last node of the queue. A thread appends its node by swapping it in, and
+/// links it after the previous last one, whose holder clears the node's flag when it is done. The
+/// node lives in the guard. The lock isn't poisoned by panics.
+pub struct McsLock<T: ?Sized> {
+    tail: AtomicPtr<Node>,
+    data: UnsafeCell<T>,
+}
+
+// Like `std::sync::Mutex`, the data is accessed by one thread at a time.
+unsafe impl<T: ?Sized + Send> Send for McsLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for McsLock<T> {}
+
+impl<T: ?Sized> fmt::Debug for McsLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("McsLock")
+            .field("locked", &self.is_locked())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Default> Default for McsLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> McsLock<T> {
+    /// Creates an unlocked lock holding `value`.
+    pub const fn new(value: T) -> Self {
+        Self {
+            tail: AtomicPtr::new(ptr::null_mut()),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns the value.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> McsLock<T> {
+    /// Takes the lock, spinning until the threads queued before this one are done.
+    pub fn lock(&self) -> McsLockGuard<'_, T> {
+        let node = Node::boxed(true);
+        // Acquire so that the previous node is seen initialized, and release so that the next
+        // thread sees this one initialized.
+        let prev = self.tail.swap(node, Ordering::AcqRel);
+        // SAFETY: The previous holder doesn't free its node until the next one is linked.
+        if let Some(prev) = unsafe { prev.as_ref() } {
+            // Release so that the previous holder sees the node initialized.
+            prev.next.store(node, Ordering::Release);
+            let mut backoff = Backoff::new();
+            // Acquire so that the accesses of the previous holder are seen.
+            // SAFETY: The node is freed by the guard.
+            while unsafe { (*node).waiting.load(Ordering::Acquire) } {
+                backoff.snooze();
+            }
+        }
+        McsLockGuard { lock: self, node }
+    }
+
+    /// Takes the lock if it is free.
+    pub fn try_lock(&self) -> Option<McsLockGuard<'_, T>> {
+        if self.is_locked() {
+            return None;
+        }
+        let node = Node::boxed(false);
+        // Acquire so that the accesses of the previous holder are seen, and release so that the
+        // next thread sees the node initialized.
+        if self
+            .tail
+            .compare_exchange(ptr::null_mut(), node, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            // SAFETY: The node wasn't shared.
+            drop(unsafe { Box::from_raw(node) });
+            return None;
+        }
+        Some(McsLockGuard { lock: self, node })
+    }
+
+    /// Returns whether the lock is held. Other threads may change that right away.
+    pub fn is_locked(&self) -> bool {
+        !self.tail.load(Ordering::Relaxed).is_null()
+    }
+
+    /// Returns the value. No locking is needed, since it is borrowed mutably.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+/// Gives exclusive access to the value of an [`McsLock`] until dropped. Holds the thread's node in
+/// the queue.
+pub struct McsLockGuard<'a, T: ?Sized> {
+    lock: &'a McsLock<T>,
+    /// Freed when the guard is dropped, once the next thread no longer accesses it.
+    node: *mut Node,
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for McsLockGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized> Deref for McsLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The lock is held.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for McsLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The lock is held.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for McsLockGuard<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: The node is only freed below.
+        let node = unsafe { &*self.node };
+        // Acquire so that the next node is seen initialized.
+        let mut next = node.next.load(Ordering::Acquire);
+        // Release so that the next holder sees the accesses made while holding the lock.
+        let released = next.is_null()
+            && self
+                .lock
+                .tail
+                .compare_exchange(
+                    self.node,
+                    ptr::null_mut(),
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                )
+                .is_ok();
+        if !released {
+            // A thread may be queued after this one without having linked its node yet.
+            let mut backoff = Backoff::new();
+            while next.is_null() {
+                backoff.snooze();
+                // Acquire so that the next node is seen initialized.
+                next = node.next.load(Ordering::Acquire);
+            }
+            // Release so that the next holder sees the accesses made while holding the lock.
+            // SAFETY: The next thread doesn't free its node before it holds the lock.
+            unsafe { (*next).waiting.store(false, Ordering::Release) };
+        }
+        // SAFETY: No other thread accesses the node once the lock is handed over.
+        drop(unsafe { Box::from_raw(self.node) });
+    }
+}
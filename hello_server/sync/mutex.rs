@@ -0,0 +1,184 @@
+//! Mutual exclusion lock with poisoning.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
+use std::thread;
+
+use super::raw_mutex::RawMutex;
+
+/// A mutual exclusion lock built on a [`RawMutex`], with the same interface as
+/// `std::sync::Mutex`.
+///
+/// Like the standard one, the lock is poisoned when a thread panics while holding it, since the
+/// data may have been left half modified: locking it returns an error from then on, which still
+/// gives access to the data for threads that can restore it.
+pub struct Mutex<T: ?Sized> {
+    raw: RawMutex,
+    poisoned: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+// Like `std::sync::Mutex`, the data is accessed by one thread at a time.
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Mutex");
+        match self.try_lock() {
+            Ok(guard) => d.field("data", &&*guard),
+            Err(TryLockError::Poisoned(err)) => d.field("data", &&**err.get_ref()),
+            Err(TryLockError::WouldBlock) => d.field("data", &format_args!("<locked>")),
+        };
+        d.field("poisoned", &self.is_poisoned())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> Mutex<T> {
+    /// Creates an unlocked lock holding `value`.
+    pub const fn new(value: T) -> Self {
+        Self {
+            raw: RawMutex::new(),
+            poisoned: AtomicBool::new(false),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns the value, or an error holding it if the lock is poisoned.
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.is_poisoned();
+        let data = self.data.into_inner();
+        if poisoned {
+            Err(PoisonError::new(data))
+        } else {
+            Ok(data)
+        }
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Returns a guard, or an error holding it if the lock is poisoned.
+    fn guard(&self) -> LockResult<MutexGuard<'_, T>> {
+        let guard = MutexGuard {
+            lock: self,
+            panicking: thread::panicking(),
+            _not_send_sync: PhantomData,
+        };
+        if self.is_poisoned() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// Takes the lock, blocking until it is free.
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        self.raw.lock();
+        self.guard()
+    }
+
+    /// Takes the lock if it is free.
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        if !self.raw.try_lock() {
+            return Err(TryLockError::WouldBlock);
+        }
+        Ok(self.guard()?)
+    }
+
+    /// Returns whether a thread panicked while holding the lock.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Marks the lock as not poisoned, e.g. once the data is restored.
+    pub fn clear_poison(&self) {
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    /// Returns the value, or an error holding it if the lock is poisoned. No locking is needed,
+    /// since it is borrowed mutably.
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let poisoned = self.is_poisoned();
+        let data = self.data.get_mut();
+        if poisoned {
+            Err(PoisonError::new(data))
+        } else {
+            Ok(data)
+        }
+    }
+}
+
+/// Gives exclusive access to the value of a [`Mutex`] until dropped.
+///
+/// Like `std::sync::MutexGuard`, it can only be shared between threads if the value can, even
+/// though the mutex itself only needs the value to be `Send`:
+///
+/// ```compile_fail
+/// use std::cell::Cell;
+/// use std::thread;
+///
+/// use cs431_homework::hello_server::sync::Mutex;
+///
+/// let mutex = Mutex::new(Cell::new(0));
+/// let guard = mutex.lock().unwrap();
+/// let guard = &guard;
+/// thread::scope(|s| {
+///     s.spawn(move || guard.set(1));
+///     guard.set(2);
+/// });
+/// ```
+pub struct MutexGuard<'a, T: ?Sized> {
+    lock: &'a Mutex<T>,
+    /// Whether the thread was already panicking when it took the lock, in which case it doesn't
+    /// poison it.
+    panicking: bool,
+    /// Opts out of the auto `Send`, since whether to poison depends on the taking thread, and of
+    /// the auto `Sync`, which would only need `T: Send`.
+    _not_send_sync: PhantomData<*const ()>,
+}
+
+// Sharing the guard shares `&T`, so it is `Sync` only if `T` is.
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The lock is held.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The lock is held.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        if !self.panicking && thread::panicking() {
+            self.lock.poisoned.store(true, Ordering::Relaxed);
+        }
+        // SAFETY: The lock is held by the guard.
+        unsafe { self.lock.raw.unlock() };
+    }
+}
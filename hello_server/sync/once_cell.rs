@@ -0,0 +1,254 @@
+//! Cells initialized once.
+
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::ops::Deref;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, Thread};
+
+/// The cell is empty, and no thread is initializing it.
+const INCOMPLETE: usize = 0;
+/// A thread is initializing the cell, and the other bits point to the threads waiting for it.
+const RUNNING: usize = 1;
+/// The cell holds a value.
+const COMPLETE: usize = 2;
+const STATE_MASK: usize = 3;
+
+/// A thread waiting for the cell to be initialized, on its stack until it is signaled.
+#[repr(align(4))]
+struct Waiter {
+    thread: Thread,
+    signaled: AtomicBool,
+    next: Cell<*const Waiter>,
+}
+
+/// Clears the running state when dropped, whether the initializing thread succeeded, failed, or
+/// panicked, and wakes the waiting threads.
+struct Running<'a> {
+    state: &'a AtomicUsize,
+    complete: bool,
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        let state = if self.complete { COMPLETE } else { INCOMPLETE };
+        // Release so that threads seeing the cell complete see the value, and acquire so that the
+        // waiters are seen as their threads made them.
+        let queue = self.state.swap(state, Ordering::AcqRel);
+        let mut waiter = (queue & !STATE_MASK) as *const Waiter;
+        while !waiter.is_null() {
+            // SAFETY: A waiter stays on its thread's stack until it is signaled.
+            unsafe {
+                let next = (*waiter).next.get();
+                let thread = (*waiter).thread.clone();
+                // Release so that the waiting thread sees the state. The waiter may be gone right
+                // after.
+                (*waiter).signaled.store(true, Ordering::Release);
+                thread.unpark();
+                waiter = next;
+            }
+        }
+    }
+}
+
+/// A cell that is initialized at most once, e.g. for a global that is expensive to create.
+///
+/// A state word tells whether the cell is empty, being initialized, or holds a value. Threads
+/// finding it being initialized push themselves on a list of waiters in the same word and park
+/// until the initializing thread wakes them. If the initialization fails or panics, the cell is
+/// left empty, and one of the waiting threads tries next.
+pub struct OnceCell<T> {
+    state: AtomicUsize,
+    value: UnsafeCell<Option<T>>,
+}
+
+// Like `std::sync::OnceLock`, the value is created by one thread and shared with the others.
+unsafe impl<T: Send> Send for OnceCell<T> {}
+unsafe impl<T: Send + Sync> Sync for OnceCell<T> {}
+
+impl<T: fmt::Debug> fmt::Debug for OnceCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("OnceCell").field(&self.get()).finish()
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> OnceCell<T> {
+    /// Creates an empty cell.
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(INCOMPLETE),
+            value: UnsafeCell::new(None),
+        }
+    }
+
+    /// Returns the value, if the cell is initialized.
+    pub fn get(&self) -> Option<&T> {
+        // Acquire so that the value is seen as the initializing thread wrote it.
+        if self.state.load(Ordering::Acquire) != COMPLETE {
+            return None;
+        }
+        // SAFETY: The value isn't written anymore once the cell is complete.
+        unsafe { (*self.value.get()).as_ref() }
+    }
+
+    /// Initializes the cell with `value`, or gives it back if the cell is already initialized.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.get_or_init(|| value.take().expect("initializes once"));
+        value.map_or(Ok(()), Err)
+    }
+
+    /// Returns the value, initializing the cell with `f` if it is empty. Other threads calling it
+    /// meanwhile wait for `f`.
+    pub fn get_or_init<F: FnOnce() -> T>(&self, f: F) -> &T {
+        match self.get_or_try_init(|| Ok::<T, std::convert::Infallible>(f())) {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Like [`OnceCell::get_or_init`], but leaves the cell empty if `f` fails, so that the next
+    /// thread tries again.
+    pub fn get_or_try_init<F, E>(&self, f: F) -> Result<&T, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        if let Some(value) = self.get() {
+            return Ok(value);
+        }
+        self.initialize(f)?;
+        Ok(self.get().expect("initialized"))
+    }
+
+    #[cold]
+    fn initialize<F, E>(&self, f: F) -> Result<(), E>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        let mut f = Some(f);
+        loop {
+            // Acquire so that the value is seen as the initializing thread wrote it.
+            let state = self.state.load(Ordering::Acquire);
+            match state & STATE_MASK {
+                COMPLETE => return Ok(()),
+                INCOMPLETE => {
+                    // Acquire so that the accesses of a thread that failed before are seen.
+                    if self
+                        .state
+                        .compare_exchange(state, RUNNING, Ordering::Acquire, Ordering::Relaxed)
+                        .is_err()
+                    {
+                        continue;
+                    }
+                    let mut running = Running {
+                        state: &self.state,
+                        complete: false,
+                    };
+                    let value = f.take().expect("only called while empty")()?;
+                    // SAFETY: Only the initializing thread accesses the value while running.
+                    unsafe { *self.value.get() = Some(value) };
+                    running.complete = true;
+                    return Ok(());
+                }
+                _ => self.wait(state),
+            }
+        }
+    }
+
+    /// Waits until the thread initializing the cell is done, from `state` on.
+    fn wait(&self, mut state: usize) {
+        let waiter = Waiter {
+            thread: thread::current(),
+            signaled: AtomicBool::new(false),
+            next: Cell::new(ptr::null()),
+        };
+        let ptr = &waiter as *const Waiter as usize;
+        loop {
+            if state & STATE_MASK != RUNNING {
+                return;
+            }
+            waiter.next.set((state & !STATE_MASK) as *const Waiter);
+            // Release so that the initializing thread sees the waiter as this thread made it.
+            match self.state.compare_exchange(
+                state,
+                ptr | RUNNING,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => state = current,
+            }
+        }
+        // Acquire so that the waiter isn't popped before the initializing thread is done with it.
+        while !waiter.signaled.load(Ordering::Acquire) {
+            thread::park();
+        }
+    }
+
+    /// Returns the value. No synchronization is needed, since the cell is borrowed mutably.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        self.value.get_mut().as_mut()
+    }
+
+    /// Returns the value, if the cell is initialized.
+    pub fn into_inner(self) -> Option<T> {
+        self.value.into_inner()
+    }
+}
+
+/// A value initialized on first access, e.g. a global regex.
+pub struct Lazy<T, F = fn() -> T> {
+    cell: OnceCell<T>,
+    /// Taken by the initializing thread.
+    init: UnsafeCell<Option<F>>,
+}
+
+// Like `std::sync::LazyLock`, the function is called by one thread, and the value shared with the
+// others.
+unsafe impl<T: Send + Sync, F: Send> Sync for Lazy<T, F> {}
+
+impl<T: fmt::Debug, F> fmt::Debug for Lazy<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Lazy").field(&self.cell.get()).finish()
+    }
+}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    /// Creates a value initialized with `init` on first access.
+    pub const fn new(init: F) -> Self {
+        Self {
+            cell: OnceCell::new(),
+            init: UnsafeCell::new(Some(init)),
+        }
+    }
+
+    /// Returns the value, initializing it if it isn't yet.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the initialization panicked before.
+    pub fn force(this: &Self) -> &T {
+        this.cell.get_or_init(|| {
+            // SAFETY: Only the initializing thread accesses the function.
+            match unsafe { (*this.init.get()).take() } {
+                Some(init) => init(),
+                None => panic!("Lazy instance has previously been poisoned"),
+            }
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
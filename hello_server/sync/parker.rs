@@ -0,0 +1,102 @@
+//! Thread parking with a token.
+
+use std::cell::Cell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::shim::{Condvar, Mutex};
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Whether the parker was unparked since it last returned.
+    token: Mutex<bool>,
+    condvar: Condvar,
+}
+
+/// Blocks its thread until an [`Unparker`] wakes it, e.g. an idle worker until a job arrives.
+///
+/// Unlike `std::thread::park`, it isn't tied to a thread, and it doesn't wake spuriously. Unparking
+/// makes a token available, which the next park consumes, so that an unpark that comes before the
+/// park isn't lost. Tokens don't add up: unparking twice lets one park through.
+pub struct Parker {
+    unparker: Unparker,
+    /// Parked by a single thread at a time.
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl fmt::Debug for Parker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Parker").finish_non_exhaustive()
+    }
+}
+
+impl Default for Parker {
+    fn default() -> Self {
+        Self {
+            unparker: Unparker {
+                inner: Arc::default(),
+            },
+            _not_sync: PhantomData,
+        }
+    }
+}
+
+impl Parker {
+    /// Creates a parker without a token.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the unparker waking this parker.
+    pub fn unparker(&self) -> &Unparker {
+        &self.unparker
+    }
+
+    /// Blocks until the token is available, and consumes it.
+    pub fn park(&self) {
+        let inner = &self.unparker.inner;
+        let mut token = inner.token.lock().unwrap();
+        while !*token {
+            token = inner.condvar.wait(token).unwrap();
+        }
+        *token = false;
+    }
+
+    /// Like [`Parker::park`], but gives up after `timeout`. Returns whether the token was
+    /// consumed.
+    pub fn park_timeout(&self, timeout: Duration) -> bool {
+        let inner = &self.unparker.inner;
+        let deadline = Instant::now() + timeout;
+        let mut token = inner.token.lock().unwrap();
+        while !*token {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return false;
+            }
+            token = inner.condvar.wait_timeout(token, timeout).unwrap().0;
+        }
+        std::mem::replace(&mut *token, false)
+    }
+}
+
+/// Wakes a [`Parker`] from other threads. Clones wake the same parker.
+#[derive(Clone)]
+pub struct Unparker {
+    inner: Arc<Inner>,
+}
+
+impl fmt::Debug for Unparker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Unparker").finish_non_exhaustive()
+    }
+}
+
+impl Unparker {
+    /// Makes the token available, waking the parker if it is parked.
+    pub fn unpark(&self) {
+        *self.inner.token.lock().unwrap() = true;
+        self.inner.condvar.notify_one();
+    }
+}
@@ -0,0 +1,80 @@
+//! Priority blocking queue.
+
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// An unbounded queue whose items are taken greatest first, on which consumers block while it is
+/// empty, e.g. for jobs with priorities. Wrap items in `std::cmp::Reverse` to take the least first,
+/// e.g. the job with the earliest deadline.
+pub struct PriorityBlockingQueue<T: Ord> {
+    items: Mutex<BinaryHeap<T>>,
+    /// Notified when an item is put.
+    not_empty: Condvar,
+}
+
+impl<T: Ord> fmt::Debug for PriorityBlockingQueue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PriorityBlockingQueue")
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Ord> Default for PriorityBlockingQueue<T> {
+    fn default() -> Self {
+        Self {
+            items: Mutex::new(BinaryHeap::new()),
+            not_empty: Condvar::new(),
+        }
+    }
+}
+
+impl<T: Ord> PriorityBlockingQueue<T> {
+    /// Creates an empty queue.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Puts `item` in the queue.
+    pub fn put(&self, item: T) {
+        self.items.lock().unwrap().push(item);
+        self.not_empty.notify_one();
+    }
+
+    /// Takes the greatest item, blocking while the queue is empty.
+    pub fn take(&self) -> T {
+        let items = self.items.lock().unwrap();
+        let mut items = self
+            .not_empty
+            .wait_while(items, |items| items.is_empty())
+            .unwrap();
+        items.pop().expect("waited until not empty")
+    }
+
+    /// Takes the greatest item if the queue isn't empty.
+    pub fn try_take(&self) -> Option<T> {
+        self.items.lock().unwrap().pop()
+    }
+
+    /// Like [`PriorityBlockingQueue::take`], but gives up after `timeout`.
+    pub fn take_timeout(&self, timeout: Duration) -> Option<T> {
+        let items = self.items.lock().unwrap();
+        let (mut items, _) = self
+            .not_empty
+            .wait_timeout_while(items, timeout, |items| items.is_empty())
+            .unwrap();
+        items.pop()
+    }
+
+    /// Returns the number of items. Other threads may change it right away.
+    pub fn len(&self) -> usize {
+        self.items.lock().unwrap().len()
+    }
+
+    /// Returns whether the queue is empty. Other threads may change that right away.
+    pub fn is_empty(&self) -> bool {
+        self.items.lock().unwrap().is_empty()
+    }
+}
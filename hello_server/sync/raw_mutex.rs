@@ -0,0 +1,174 @@
+//! Mutual exclusion lock parking its waiters.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::thread::{self, Thread};
+
+use super::backoff::Backoff;
+use super::spin_lock::SpinLock;
+
+/// The lock is held.
+const LOCKED: u8 = 1;
+/// Threads are parked, waiting for the lock.
+const PARKED: u8 = 2;
+
+/// Number of times a thread retries taking a held lock before parking.
+const SPINS: usize = 10;
+
+/// A thread waiting for the lock, on its stack until it is unparked.
+struct Waiter {
+    thread: Thread,
+    unparked: AtomicBool,
+}
+
+/// Points to a [`Waiter`] on another thread's stack.
+struct WaiterPtr(*const Waiter);
+
+// The waiter is only accessed by the unlocking thread until it is unparked.
+unsafe impl Send for WaiterPtr {}
+
+/// A mutual exclusion lock without data, whose waiters sleep once spinning for a while didn't get
+/// them the lock. See [`Mutex`](super::Mutex) for one protecting data.
+///
+/// The state is a byte: a bit for whether the lock is held, and one for whether threads are
+/// parked. Taking and releasing an uncontended lock is a single compare-and-swap. Otherwise, the
+/// parked threads wait in a queue, and releasing the lock wakes the first of them, which competes
+/// for the lock with the threads arriving meanwhile rather than getting it handed over, so that
+/// the lock is never idle while a woken thread is scheduled.
+pub struct RawMutex {
+    state: AtomicU8,
+    /// The parked threads, in arrival order.
+    queue: SpinLock<VecDeque<WaiterPtr>>,
+}
+
+impl fmt::Debug for RawMutex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawMutex")
+            .field("locked", &self.is_locked())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for RawMutex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RawMutex {
+    /// Creates an unlocked lock.
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(0),
+            queue: SpinLock::new(VecDeque::new()),
+        }
+    }
+
+    /// Takes the lock, blocking until it is free.
+    pub fn lock(&self) {
+        if !self.try_lock() {
+            self.lock_slow();
+        }
+    }
+
+    #[cold]
+    fn lock_slow(&self) {
+        let mut backoff = Backoff::new();
+        let mut spins = 0;
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & LOCKED == 0 {
+                // Acquire so that the accesses of the previous holder are seen.
+                if self
+                    .state
+                    .compare_exchange_weak(
+                        state,
+                        state | LOCKED,
+                        Ordering::Acquire,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+                {
+                    return;
+                }
+                continue;
+            }
+            if state & PARKED == 0 && spins < SPINS {
+                spins += 1;
+                backoff.snooze();
+                continue;
+            }
+
+            let waiter = Waiter {
+                thread: thread::current(),
+                unparked: AtomicBool::new(false),
+            };
+            let mut queue = self.queue.lock();
+            // Checks that the lock is still held under the queue's lock, so that the holder sees
+            // the bit, and unparks this thread, once it is in the queue.
+            if self
+                .state
+                .compare_exchange(state, state | PARKED, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+            {
+                continue;
+            }
+            queue.push_back(WaiterPtr(&waiter));
+            drop(queue);
+            // Acquire so that the waiter isn't popped before the unlocking thread is done with it.
+            while !waiter.unparked.load(Ordering::Acquire) {
+                thread::park();
+            }
+        }
+    }
+
+    /// Takes the lock if it is free.
+    pub fn try_lock(&self) -> bool {
+        // Acquire so that the accesses of the previous holder are seen.
+        self.state
+            .compare_exchange(0, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Releases the lock.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held by the caller, e.g. taken by this thread with [`RawMutex::lock`].
+    pub unsafe fn unlock(&self) {
+        // Release so that the next holder sees the accesses made while holding the lock.
+        if self
+            .state
+            .compare_exchange(LOCKED, 0, Ordering::Release, Ordering::Relaxed)
+            .is_err()
+        {
+            self.unlock_slow();
+        }
+    }
+
+    #[cold]
+    fn unlock_slow(&self) {
+        let mut queue = self.queue.lock();
+        let waiter = queue.pop_front();
+        let state = if queue.is_empty() { 0 } else { PARKED };
+        // Release so that the next holder sees the accesses made while holding the lock.
+        self.state.store(state, Ordering::Release);
+        drop(queue);
+        if let Some(WaiterPtr(waiter)) = waiter {
+            // SAFETY: A waiter stays on its thread's stack until it is unparked.
+            unsafe {
+                let thread = (*waiter).thread.clone();
+                // Release so that the woken thread sees the lock released. The waiter may be gone
+                // right after.
+                (*waiter).unparked.store(true, Ordering::Release);
+                thread.unpark();
+            }
+        }
+    }
+
+    /// Returns whether the lock is held. Other threads may change that right away.
+    pub fn is_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & LOCKED != 0
+    }
+}
@@ -0,0 +1,277 @@
+//! Readers-writer lock built from a mutex and condition variables.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+/// Which side of an [`RwLock`] goes first when both readers and writers wait.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Preference {
+    /// Readers take the lock as long as no writer holds it, so that writers may starve under a
+    /// steady stream of readers.
+    Readers,
+    /// Readers wait while a writer waits, so that readers may starve under a steady stream of
+    /// writers.
+    #[default]
+    Writers,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    readers: usize,
+    writer: bool,
+    upgradable: bool,
+    /// Writers waiting, including an upgradable reader waiting to upgrade.
+    waiting_writers: usize,
+}
+
+/// A readers-writer lock: many readers or one writer at a time. Additionally, one upgradable
+/// reader may hold it along with readers, and later become the writer without letting another
+/// writer in between.
+///
+/// Unlike `std::sync::RwLock`, whose fairness depends on the platform, the preference between
+/// readers and writers is chosen, and the lock isn't poisoned by panics.
+pub struct RwLock<T: ?Sized> {
+    preference: Preference,
+    state: Mutex<State>,
+    /// Signaled when readers and upgradable readers may take the lock.
+    readable: Condvar,
+    /// Signaled when writers and upgrading readers may take the lock.
+    writable: Condvar,
+    data: UnsafeCell<T>,
+}
+
+// Like `std::sync::RwLock`, readers share the data across threads.
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T: ?Sized> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RwLock")
+            .field("preference", &self.preference)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> RwLock<T> {
+    /// Creates a writer-preferring lock holding `value`.
+    pub fn new(value: T) -> Self {
+        Self::with_preference(value, Preference::default())
+    }
+
+    /// Creates a lock holding `value`, preferring readers or writers.
+    pub fn with_preference(value: T, preference: Preference) -> Self {
+        Self {
+            preference,
+            state: Mutex::default(),
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns the value.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    fn lock_state(&self) -> MutexGuard<'_, State> {
+        // Never poisoned, since no code of the user runs while it is held.
+        self.state.lock().unwrap()
+    }
+
+    fn can_read(&self, state: &State) -> bool {
+        !state.writer && (self.preference == Preference::Readers || state.waiting_writers == 0)
+    }
+
+    fn can_write(state: &State) -> bool {
+        !state.writer && !state.upgradable && state.readers == 0
+    }
+
+    /// Takes the lock for reading, blocking while a writer holds it, or waits for it if writers
+    /// are preferred.
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        let state = self.lock_state();
+        let mut state = self
+            .readable
+            .wait_while(state, |state| !self.can_read(state))
+            .unwrap();
+        state.readers += 1;
+        RwLockReadGuard { lock: self }
+    }
+
+    /// Takes the lock for reading if [`RwLock::read`] wouldn't block.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let mut state = self.lock_state();
+        if !self.can_read(&state) {
+            return None;
+        }
+        state.readers += 1;
+        Some(RwLockReadGuard { lock: self })
+    }
+
+    /// Takes the lock for writing, blocking while others hold it.
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let mut state = self.lock_state();
+        state.waiting_writers += 1;
+        let mut state = self
+            .writable
+            .wait_while(state, |state| !Self::can_write(state))
+            .unwrap();
+        state.waiting_writers -= 1;
+        state.writer = true;
+        RwLockWriteGuard { lock: self }
+    }
+
+    /// Takes the lock for writing if nobody holds it.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let mut state = self.lock_state();
+        if !Self::can_write(&state) {
+            return None;
+        }
+        state.writer = true;
+        Some(RwLockWriteGuard { lock: self })
+    }
+
+    /// Takes the lock for reading, with the right to [upgrade](RwLockUpgradableReadGuard::upgrade)
+    /// to writing later. Blocks like [`RwLock::read`], and while another upgradable reader holds
+    /// the lock.
+    pub fn upgradable_read(&self) -> RwLockUpgradableReadGuard<'_, T> {
+        let state = self.lock_state();
+        let mut state = self
+            .readable
+            .wait_while(state, |state| state.upgradable || !self.can_read(state))
+            .unwrap();
+        state.upgradable = true;
+        RwLockUpgradableReadGuard { lock: self }
+    }
+
+    /// Returns the value. No locking is needed, since it is borrowed mutably.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+/// Gives shared access to the value of an [`RwLock`] until dropped.
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: No writer holds the lock while readers do.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.lock.lock_state();
+        state.readers -= 1;
+        if state.readers == 0 {
+            self.lock.writable.notify_all();
+        }
+    }
+}
+
+/// Gives exclusive access to the value of an [`RwLock`] until dropped.
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: Nobody else holds the lock while a writer does.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: Nobody else holds the lock while a writer does.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.lock.lock_state();
+        state.writer = false;
+        self.lock.readable.notify_all();
+        self.lock.writable.notify_all();
+    }
+}
+
+/// Gives shared access to the value of an [`RwLock`] until dropped or upgraded.
+pub struct RwLockUpgradableReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockUpgradableReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<'a, T: ?Sized> RwLockUpgradableReadGuard<'a, T> {
+    /// Turns the guard into a write guard, blocking until the other readers are gone. No writer
+    /// takes the lock in between, so that what was read still holds.
+    pub fn upgrade(self) -> RwLockWriteGuard<'a, T> {
+        let lock = self.lock;
+        std::mem::forget(self);
+        let mut state = lock.lock_state();
+        // Counted as a waiting writer, so that new readers wait if writers are preferred.
+        state.waiting_writers += 1;
+        let mut state = lock
+            .writable
+            .wait_while(state, |state| state.readers > 0)
+            .unwrap();
+        state.waiting_writers -= 1;
+        state.upgradable = false;
+        state.writer = true;
+        RwLockWriteGuard { lock }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockUpgradableReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: No writer holds the lock while an upgradable reader does.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockUpgradableReadGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.lock.lock_state();
+        state.upgradable = false;
+        self.lock.readable.notify_all();
+        self.lock.writable.notify_all();
+    }
+}
@@ -0,0 +1,89 @@
+//! Counting semaphore.
+
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// A counting semaphore, handing out a fixed number of permits, e.g. to bound how many handlers
+/// hold many file descriptors at once.
+#[derive(Debug, Default)]
+pub struct Semaphore {
+    permits: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Semaphore {
+    /// Creates a semaphore with `permits` permits available.
+    pub fn new(permits: usize) -> Self {
+        Self {
+            permits: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Takes a permit, blocking until one is available. It is given back when the returned guard
+    /// is dropped.
+    pub fn acquire(&self) -> Permit<'_> {
+        let permits = self.permits.lock().unwrap();
+        let mut permits = self
+            .released
+            .wait_while(permits, |permits| *permits == 0)
+            .unwrap();
+        *permits -= 1;
+        Permit { semaphore: self }
+    }
+
+    /// Takes a permit if one is available.
+    pub fn try_acquire(&self) -> Option<Permit<'_>> {
+        let mut permits = self.permits.lock().unwrap();
+        if *permits == 0 {
+            return None;
+        }
+        *permits -= 1;
+        Some(Permit { semaphore: self })
+    }
+
+    /// Like [`Semaphore::acquire`], but gives up after `timeout`.
+    pub fn acquire_timeout(&self, timeout: Duration) -> Option<Permit<'_>> {
+        let permits = self.permits.lock().unwrap();
+        let (mut permits, _) = self
+            .released
+            .wait_timeout_while(permits, timeout, |permits| *permits == 0)
+            .unwrap();
+        if *permits == 0 {
+            return None;
+        }
+        *permits -= 1;
+        Some(Permit { semaphore: self })
+    }
+
+    /// Adds a permit, e.g. one taken and [forgotten](Permit::forget) before.
+    pub fn release(&self) {
+        *self.permits.lock().unwrap() += 1;
+        self.released.notify_one();
+    }
+
+    /// Returns the number of permits available. Other threads may change it right away.
+    pub fn available_permits(&self) -> usize {
+        *self.permits.lock().unwrap()
+    }
+}
+
+/// A permit of a [`Semaphore`], given back when dropped.
+#[derive(Debug)]
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Permit<'_> {
+    /// Keeps the permit taken, so that the semaphore has one less until [`Semaphore::release`] is
+    /// called.
+    pub fn forget(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
@@ -0,0 +1,143 @@
+//! Sequence lock.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+
+use super::backoff::Backoff;
+
+/// A lock for small `Copy` data read much more often than written, e.g. a snapshot of counters.
+///
+/// A sequence number is odd while a writer writes. Readers don't write anything: they copy the
+/// data optimistically, and try again if the sequence number changed meanwhile or was odd, so
+/// that they never slow down writers nor each other. Writers exclude each other by making the
+/// number odd.
+pub struct SeqLock<T: Copy> {
+    seq: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+// The data is copied between threads.
+unsafe impl<T: Copy + Send> Send for SeqLock<T> {}
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for SeqLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SeqLock").field(&self.read()).finish()
+    }
+}
+
+impl<T: Copy + Default> Default for SeqLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: Copy> SeqLock<T> {
+    /// Creates a lock holding `value`.
+    pub const fn new(value: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns a copy of the value, retrying while a writer writes it.
+    pub fn read(&self) -> T {
+        let mut backoff = Backoff::new();
+        loop {
+            // Acquire so that the data is seen as the last writer left it.
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 0 {
+                // SAFETY: The copy may be torn by a concurrent writer, but it's only returned if
+                // the sequence number shows there was none. Volatile so that it's really read,
+                // since the compiler may assume nothing writes it concurrently.
+                let value = unsafe { ptr::read_volatile(self.data.get()) };
+                // Orders reading the data before checking the sequence number again.
+                fence(Ordering::Acquire);
+                if self.seq.load(Ordering::Relaxed) == seq {
+                    return value;
+                }
+            }
+            backoff.snooze();
+        }
+    }
+
+    /// Replaces the value with `value`.
+    pub fn write(&self, value: T) {
+        self.update(|_| value);
+    }
+
+    /// Replaces the value with `f` of it, e.g. to increment a field. If `f` panics, the value is
+    /// left as it was.
+    pub fn update(&self, f: impl FnOnce(T) -> T) {
+        let mut backoff = Backoff::new();
+        let seq = loop {
+            let seq = self.seq.load(Ordering::Relaxed);
+            // Acquire so that the data is seen as the last writer left it.
+            if seq & 1 == 0
+                && self
+                    .seq
+                    .compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                break seq;
+            }
+            backoff.snooze();
+        };
+        let _unlock = Unlock {
+            seq: &self.seq,
+            next: seq.wrapping_add(2),
+        };
+        // Orders making the sequence number odd before writing the data, so that readers copying
+        // the data being written see it changed.
+        fence(Ordering::Release);
+        // SAFETY: Writers exclude each other, and readers discard what they read meanwhile.
+        unsafe {
+            let data = self.data.get();
+            ptr::write_volatile(data, f(ptr::read_volatile(data)));
+        }
+    }
+
+    /// Returns the value. No locking is needed, since it is borrowed mutably.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Returns the value.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+/// Makes the sequence number even again when dropped, so that the lock is released even if the
+/// update panics, leaving the data as it was.
+struct Unlock<'a> {
+    seq: &'a AtomicUsize,
+    next: usize,
+}
+
+impl Drop for Unlock<'_> {
+    fn drop(&mut self) {
+        // Release so that readers seeing the new sequence number see the data.
+        self.seq.store(self.next, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+
+    use super::SeqLock;
+
+    #[test]
+    fn panicking_update_releases_the_lock() {
+        let lock = SeqLock::new(1);
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| lock.update(|_| panic!("update"))));
+        assert!(panicked.is_err());
+        assert_eq!(lock.read(), 1);
+        lock.update(|value| value + 1);
+        assert_eq!(lock.read(), 2);
+    }
+}
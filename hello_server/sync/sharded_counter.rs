@@ -0,0 +1,69 @@
+//! Counter striped across cache lines.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+
+use super::super::cache_padded::CachePadded;
+
+/// A counter incremented by many threads and read rarely, like Java's `LongAdder`.
+///
+/// A single atomic incremented by every core has its cache line bounce between them. Instead,
+/// each thread adds to one of several cells, each on its own cache line, and reading sums them.
+pub struct ShardedCounter {
+    /// A power of two of them, so that a thread's cell is found with a mask.
+    shards: Box<[CachePadded<AtomicU64>]>,
+}
+
+impl fmt::Debug for ShardedCounter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ShardedCounter").field(&self.sum()).finish()
+    }
+}
+
+impl Default for ShardedCounter {
+    fn default() -> Self {
+        // Twice as many cells as cores, so that threads rarely share one.
+        let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+        let shards = (cores * 2).next_power_of_two();
+        Self {
+            shards: (0..shards).map(|_| CachePadded::default()).collect(),
+        }
+    }
+}
+
+impl ShardedCounter {
+    /// Creates a counter at 0.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `n` to the counter.
+    pub fn add(&self, n: u64) {
+        let shard = &self.shards[thread_index() & (self.shards.len() - 1)];
+        shard.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Adds 1 to the counter.
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    /// Returns the value of the counter. Additions made concurrently may or may not be counted.
+    pub fn sum(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| shard.load(Ordering::Relaxed))
+            .fold(0, u64::wrapping_add)
+    }
+}
+
+/// Returns a number given to the current thread, with threads numbered in the order they first
+/// call it.
+fn thread_index() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static INDEX: usize = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+    INDEX.with(|index| *index)
+}
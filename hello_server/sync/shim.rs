@@ -0,0 +1,117 @@
+//! The primitives the thread pool, the cache and [`Arc`](super::super::arc::Arc) synchronize with:
+//! those of std, or those of loom under `cfg(loom)`, so that loom can explore the interleavings of
+//! their threads.
+//!
+//! Loom's primitives can't be created in statics, and they lack some conveniences of std's, e.g.
+//! `Condvar::wait_while`, so the code using them sticks to what both provide. The code sharing
+//! data keeps `std::sync::Arc`, since loom's can't be coerced into trait objects.
+//!
+//! The loom models are in the `loom_tests` modules of the thread pool, the cache and `Arc`. They are
+//! run with `RUSTFLAGS="--cfg loom" cargo test --release --features check-loom loom_tests`.
+//!
+//! Without the `std` feature, the cache gets spin-based stand-ins for the mutex and the condition
+//! variable, and a clock that doesn't tick.
+
+#[cfg(all(loom, not(feature = "check-loom")))]
+compile_error!("`--cfg loom` needs the `check-loom` feature");
+
+#[cfg(loom)]
+pub(crate) use loom::hint;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{fence, AtomicBool, AtomicU64, AtomicUsize};
+#[cfg(loom)]
+pub(crate) use loom::sync::{Condvar, Mutex};
+#[cfg(loom)]
+pub(crate) use loom::thread;
+
+#[cfg(all(not(loom), feature = "std"))]
+pub(crate) use std::hint;
+#[cfg(all(not(loom), feature = "std"))]
+pub(crate) use std::sync::atomic::{fence, AtomicBool, AtomicU64, AtomicUsize};
+#[cfg(all(not(loom), feature = "std"))]
+pub(crate) use std::sync::{Condvar, Mutex};
+#[cfg(all(not(loom), feature = "std"))]
+pub(crate) use std::thread;
+
+#[cfg(feature = "std")]
+pub(crate) use std::time::Instant;
+
+#[cfg(not(feature = "std"))]
+pub(crate) use core::sync::atomic::AtomicU64;
+#[cfg(not(feature = "std"))]
+pub(crate) use spin::{Condvar, Instant, Mutex};
+
+#[cfg(not(feature = "std"))]
+mod spin {
+    use core::convert::Infallible;
+    use core::ops::Add;
+    use core::time::Duration;
+
+    use super::super::{Backoff, SpinLock, SpinLockGuard};
+
+    /// Number of backoff steps a waiter spins for before checking its condition again.
+    const WAIT_STEPS: usize = 6;
+
+    /// `std::sync::Mutex` on top of a [`SpinLock`]. It is never poisoned.
+    #[derive(Debug, Default)]
+    pub(crate) struct Mutex<T>(SpinLock<T>);
+
+    impl<T> Mutex<T> {
+        pub(crate) const fn new(value: T) -> Self {
+            Self(SpinLock::new(value))
+        }
+
+        pub(crate) fn lock(&self) -> Result<SpinLockGuard<'_, T>, Infallible> {
+            Ok(self.0.lock())
+        }
+    }
+
+    /// `std::sync::Condvar` for the [`Mutex`] above. Waiting releases the lock, spins a while and
+    /// takes it again, i.e. waiters wake up spuriously and check their condition again instead of
+    /// waiting to be notified, so notifying does nothing.
+    #[derive(Debug, Default)]
+    pub(crate) struct Condvar(());
+
+    impl Condvar {
+        pub(crate) const fn new() -> Self {
+            Self(())
+        }
+
+        pub(crate) fn wait<'a, T>(
+            &self,
+            guard: SpinLockGuard<'a, T>,
+        ) -> Result<SpinLockGuard<'a, T>, Infallible> {
+            let lock = SpinLockGuard::unlock(guard);
+            let mut backoff = Backoff::new();
+            for _ in 0..WAIT_STEPS {
+                backoff.snooze();
+            }
+            Ok(lock.lock())
+        }
+
+        pub(crate) fn notify_all(&self) {}
+    }
+
+    /// `std::time::Instant` without a clock: time stands still, so nothing expires and nothing
+    /// takes time. The cache rejects a TTL rather than never expiring the entries.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub(crate) struct Instant;
+
+    impl Instant {
+        pub(crate) fn now() -> Self {
+            Self
+        }
+
+        pub(crate) fn elapsed(&self) -> Duration {
+            Duration::ZERO
+        }
+    }
+
+    impl Add<Duration> for Instant {
+        type Output = Self;
+
+        fn add(self, _: Duration) -> Self {
+            self
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Spin lock.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::backoff::Backoff;
+
+/// A mutual exclusion lock whose waiters spin instead of sleeping, which is cheaper than
+/// `std::sync::Mutex` when critical sections are very short.
+///
+/// Waiters only read the flag until it is cleared, and only then try to set it
+/// (test-and-test-and-set), so that they share its cache line instead of taking it from each
+/// other. They back off exponentially after each failed attempt. The lock isn't poisoned by
+/// panics.
+pub struct SpinLock<T: ?Sized> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+// Like `std::sync::Mutex`, the data is accessed by one thread at a time.
+unsafe impl<T: ?Sized + Send> Send for SpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for SpinLock<T> {}
+
+impl<T: ?Sized> fmt::Debug for SpinLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpinLock")
+            .field("locked", &self.locked.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Default> Default for SpinLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> SpinLock<T> {
+    /// Creates an unlocked lock holding `value`.
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns the value.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinLock<T> {
+    /// Takes the lock, spinning until it is free.
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let mut backoff = Backoff::new();
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            while self.locked.load(Ordering::Relaxed) {
+                backoff.snooze();
+            }
+        }
+    }
+
+    /// Takes the lock if it is free.
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        // Acquire so that the accesses of the previous holder are seen.
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| SpinLockGuard {
+                lock: self,
+                _not_sync: PhantomData,
+            })
+    }
+
+    /// Returns whether the lock is held. Other threads may change that right away.
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Returns the value. No locking is needed, since it is borrowed mutably.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+/// Gives exclusive access to the value of a [`SpinLock`] until dropped.
+pub struct SpinLockGuard<'a, T: ?Sized> {
+    lock: &'a SpinLock<T>,
+    /// Opts out of the auto `Sync`, which would only need `T: Send`.
+    _not_sync: PhantomData<*const ()>,
+}
+
+// Sharing the guard shares `&T`, so like `std::sync::MutexGuard` it is `Sync` only if `T` is.
+// Sending it releases the lock from another thread, which is fine for a spin lock.
+unsafe impl<T: ?Sized + Send> Send for SpinLockGuard<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for SpinLockGuard<'_, T> {}
+
+#[cfg(not(feature = "std"))]
+impl<'a, T: ?Sized> SpinLockGuard<'a, T> {
+    /// Releases the lock, and returns it, e.g. to take it again later.
+    pub(crate) fn unlock(guard: Self) -> &'a SpinLock<T> {
+        let lock = guard.lock;
+        drop(guard);
+        lock
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for SpinLockGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The lock is held.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The lock is held.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        // Release so that the next holder sees the accesses made while holding the lock.
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
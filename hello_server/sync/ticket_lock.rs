@@ -0,0 +1,148 @@
+//! Ticket lock.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::backoff::Backoff;
+
+/// A spinning mutual exclusion lock that is handed over in arrival order, like the queue at a deli
+/// counter: a thread takes the next ticket, and waits until the number served is its own.
+///
+/// Unlike a [`SpinLock`](super::SpinLock), where whichever waiter sees the lock free first takes
+/// it, no thread can be overtaken forever. The price is throughput: the lock waits for the next
+/// waiter in line even when another one is ready to take it right away, and is idle while that
+/// waiter is descheduled, so it suffers most when there are more threads than cores. All waiters
+/// spin on the same counter too, unlike with an [`McsLock`](super::McsLock). The lock isn't
+/// poisoned by panics.
+pub struct TicketLock<T: ?Sized> {
+    /// The ticket the next thread takes.
+    next: AtomicUsize,
+    /// The ticket of the thread holding the lock, or of the next one if it is free.
+    owner: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+// Like `std::sync::Mutex`, the data is accessed by one thread at a time.
+unsafe impl<T: ?Sized + Send> Send for TicketLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for TicketLock<T> {}
+
+impl<T: ?Sized> fmt::Debug for TicketLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TicketLock")
+            .field("locked", &self.is_locked())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Default> Default for TicketLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> TicketLock<T> {
+    /// Creates an unlocked lock holding `value`.
+    pub const fn new(value: T) -> Self {
+        Self {
+            next: AtomicUsize::new(0),
+            owner: AtomicUsize::new(0),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns the value.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> TicketLock<T> {
+    /// Takes the lock, spinning until the threads that came before this one are done.
+    pub fn lock(&self) -> TicketLockGuard<'_, T> {
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut backoff = Backoff::new();
+        // Acquire so that the accesses of the previous holder are seen.
+        while self.owner.load(Ordering::Acquire) != ticket {
+            backoff.snooze();
+        }
+        TicketLockGuard {
+            lock: self,
+            _not_sync: PhantomData,
+        }
+    }
+
+    /// Takes the lock if it is free.
+    pub fn try_lock(&self) -> Option<TicketLockGuard<'_, T>> {
+        let owner = self.owner.load(Ordering::Relaxed);
+        // Acquire so that the accesses of the previous holder are seen.
+        self.next
+            .compare_exchange(
+                owner,
+                owner.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .ok()
+            .map(|_| TicketLockGuard {
+                lock: self,
+                _not_sync: PhantomData,
+            })
+    }
+
+    /// Returns whether the lock is held. Other threads may change that right away.
+    pub fn is_locked(&self) -> bool {
+        self.next.load(Ordering::Relaxed) != self.owner.load(Ordering::Relaxed)
+    }
+
+    /// Returns the value. No locking is needed, since it is borrowed mutably.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+/// Gives exclusive access to the value of a [`TicketLock`] until dropped.
+pub struct TicketLockGuard<'a, T: ?Sized> {
+    lock: &'a TicketLock<T>,
+    /// Opts out of the auto `Sync`, which would only need `T: Send`.
+    _not_sync: PhantomData<*const ()>,
+}
+
+// Sharing the guard shares `&T`, so it is `Sync` only if `T` is. Any thread may release the lock.
+unsafe impl<T: ?Sized + Send> Send for TicketLockGuard<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for TicketLockGuard<'_, T> {}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for TicketLockGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized> Deref for TicketLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The lock is held.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for TicketLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The lock is held.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for TicketLockGuard<'_, T> {
+    fn drop(&mut self) {
+        // Only the holder moves the owner forward.
+        let owner = self.lock.owner.load(Ordering::Relaxed);
+        // Release so that the next holder sees the accesses made while holding the lock.
+        self.lock
+            .owner
+            .store(owner.wrapping_add(1), Ordering::Release);
+    }
+}
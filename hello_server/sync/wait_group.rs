@@ -0,0 +1,71 @@
+//! Go-style wait group.
+
+use std::sync::{Arc, Condvar, Mutex};
+
+#[derive(Debug, Default)]
+struct Inner {
+    count: Mutex<usize>,
+    zero: Condvar,
+}
+
+/// Counts jobs not done yet, so that a thread can wait for an ad-hoc set of jobs, e.g. spread over
+/// several thread pools. Clones share the count.
+///
+/// ```ignore
+/// let wait_group = WaitGroup::new();
+/// for job in jobs {
+///     wait_group.add(1);
+///     let wait_group = wait_group.clone();
+///     pool.execute(move || {
+///         job();
+///         wait_group.done();
+///     });
+/// }
+/// wait_group.wait();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WaitGroup {
+    inner: Arc<Inner>,
+}
+
+impl WaitGroup {
+    /// Creates a wait group with no jobs.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts `jobs` more jobs.
+    pub fn add(&self, jobs: usize) {
+        *self.inner.count.lock().unwrap() += jobs;
+    }
+
+    /// Counts a job as done, waking the waiting threads if it was the last one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if all the jobs counted are done already.
+    pub fn done(&self) {
+        let mut count = self.inner.count.lock().unwrap();
+        *count = count
+            .checked_sub(1)
+            .expect("done called more often than jobs were added");
+        if *count == 0 {
+            self.inner.zero.notify_all();
+        }
+    }
+
+    /// Blocks until all the jobs counted are done.
+    pub fn wait(&self) {
+        let count = self.inner.count.lock().unwrap();
+        let _count = self
+            .inner
+            .zero
+            .wait_while(count, |count| *count > 0)
+            .unwrap();
+    }
+
+    /// Returns the number of jobs not done yet. Other threads may change it right away.
+    pub fn count(&self) -> usize {
+        *self.inner.count.lock().unwrap()
+    }
+}
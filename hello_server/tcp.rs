@@ -1,16 +1,138 @@
 //! TcpListener that can be cancelled.
 
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
 use std::io;
-use std::net::ToSocketAddrs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
 use std::net::{TcpListener, TcpStream};
 use std::sync::atomic::Ordering::Acquire;
 use std::sync::atomic::{AtomicBool, Ordering};
 
+/// Default maximum number of connections waiting to be accepted, as `TcpListener::bind` sets.
+const DEFAULT_BACKLOG: u32 = 128;
+
+/// Options of TCP sockets, set on a listener and on the connections it accepts.
+///
+/// The defaults are those of `TcpListener::bind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketOptions {
+    nodelay: bool,
+    reuse_addr: bool,
+    backlog: u32,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: false,
+            reuse_addr: cfg!(unix),
+            backlog: DEFAULT_BACKLOG,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        }
+    }
+}
+
+impl SocketOptions {
+    /// Creates the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether `TCP_NODELAY` is set on connections, so that small writes are sent right away
+    /// instead of being coalesced by Nagle's algorithm. Defaults to `false`.
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Sets whether `SO_REUSEADDR` is set on the listener, so that the address can be bound again
+    /// right after a restart while old connections are in `TIME_WAIT`. Defaults to `true` on Unix.
+    pub fn reuse_addr(mut self, reuse_addr: bool) -> Self {
+        self.reuse_addr = reuse_addr;
+        self
+    }
+
+    /// Sets the maximum number of connections waiting to be accepted. The OS may cap it, e.g. at
+    /// `net.core.somaxconn` on Linux. Defaults to 128.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `backlog` is 0 or greater than `i32::MAX`.
+    pub fn backlog(mut self, backlog: u32) -> Self {
+        assert_ne!(backlog, 0, "backlog must be positive");
+        assert!(i32::try_from(backlog).is_ok(), "backlog is too large");
+        self.backlog = backlog;
+        self
+    }
+
+    /// Sets the size of the send buffer (`SO_SNDBUF`) of connections in bytes, which the OS may
+    /// round or cap. Defaults to the OS's.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size is 0.
+    pub fn send_buffer_size(mut self, size: Option<usize>) -> Self {
+        assert_ne!(size, Some(0), "buffer size must be positive");
+        self.send_buffer_size = size;
+        self
+    }
+
+    /// Sets the size of the receive buffer (`SO_RCVBUF`) of connections in bytes, which the OS may
+    /// round or cap. Defaults to the OS's.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size is 0.
+    pub fn recv_buffer_size(mut self, size: Option<usize>) -> Self {
+        assert_ne!(size, Some(0), "buffer size must be positive");
+        self.recv_buffer_size = size;
+        self
+    }
+
+    /// Creates a listener bound to `addr` with the options.
+    fn listen(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        // On Windows, `SO_REUSEADDR` lets other sockets steal the address, so it's never set there.
+        if cfg!(not(windows)) {
+            socket.set_reuse_address(self.reuse_addr)?;
+        }
+        // Set on the listener as well, since the window size is negotiated during the handshake,
+        // before the connection is accepted.
+        self.set_buffer_sizes(&SockRef::from(&socket))?;
+        socket.bind(&addr.into())?;
+        socket.listen(self.backlog as i32)?;
+        Ok(socket.into())
+    }
+
+    /// Sets the options of an accepted connection.
+    fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        if self.nodelay {
+            stream.set_nodelay(true)?;
+        }
+        self.set_buffer_sizes(&SockRef::from(stream))
+    }
+
+    fn set_buffer_sizes(&self, socket: &SockRef<'_>) -> io::Result<()> {
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        Ok(())
+    }
+}
+
 /// Like `std::net::tcp::TcpListener`, but `cancel`lable.
 #[derive(Debug)]
 pub struct CancellableTcpListener {
     inner: TcpListener,
 
+    /// Options set on the accepted connections.
+    options: SocketOptions,
+
     /// An atomic boolean flag that indicates if the listener is `cancel`led.
     ///
     /// NOTE: This can be safely read/written by multiple thread at the same time (note that its
@@ -29,11 +151,35 @@ pub struct Incoming<'a> {
 impl CancellableTcpListener {
     /// Wraps `TcpListener::bind`.
     pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<CancellableTcpListener> {
-        let listener = TcpListener::bind(addr)?;
-        Ok(CancellableTcpListener {
-            inner: listener,
-            is_canceled: AtomicBool::new(false),
-        })
+        Self::bind_with(addr, SocketOptions::default())
+    }
+
+    /// Like [`CancellableTcpListener::bind`], but with the socket `options`, which are set on the
+    /// connections it accepts as well.
+    pub fn bind_with<A: ToSocketAddrs>(
+        addr: A,
+        options: SocketOptions,
+    ) -> io::Result<CancellableTcpListener> {
+        // Like `TcpListener::bind`, tries each address until one can be bound.
+        let mut last_err = None;
+        for addr in addr.to_socket_addrs()? {
+            match options.listen(addr) {
+                Ok(listener) => {
+                    return Ok(CancellableTcpListener {
+                        inner: listener,
+                        options,
+                        is_canceled: AtomicBool::new(false),
+                    })
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any addresses",
+            )
+        }))
     }
 
     /// Signals the listener to stop accepting new connections.
@@ -41,7 +187,37 @@ impl CancellableTcpListener {
         // Set the flag first and make a bogus connection to itself to wake up the listener blocked
         // in `accept`. Use `TcpListener::local_addr` and `TcpStream::connect`.
         self.is_canceled.store(true, Ordering::Release);
-        TcpStream::connect(self.inner.local_addr().unwrap()).map(|_| ())
+        let mut addr = self.inner.local_addr().unwrap();
+        // A listener on all interfaces, e.g. `[::]:8080`, is reached through the loopback one.
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr.ip() {
+                IpAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                IpAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        TcpStream::connect(addr).map(|_| ())
+    }
+
+    /// Returns the address the listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    /// Returns whether the listener is `cancel`led.
+    pub(crate) fn is_canceled(&self) -> bool {
+        self.is_canceled.load(Ordering::Acquire)
+    }
+
+    /// Returns the wrapped listener, e.g. to accept connections without blocking.
+    pub(crate) fn inner(&self) -> &TcpListener {
+        &self.inner
+    }
+
+    /// Accepts a connection and sets the socket options on it.
+    pub(crate) fn accept(&self) -> io::Result<TcpStream> {
+        let (stream, _) = self.inner.accept()?;
+        self.options.apply(&stream)?;
+        Ok(stream)
     }
 
     /// Returns an iterator over the connections being received on this listener.  The returned
@@ -58,6 +234,6 @@ impl Iterator for Incoming<'_> {
         if self.listener.is_canceled.load(Ordering::Acquire) {
             return None;
         }
-        Some(self.listener.inner.accept().map(|p| p.0))
+        Some(self.listener.accept())
     }
 }
@@ -0,0 +1,448 @@
+//! HTML templates.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::cache::Cache;
+use super::http::Response;
+use super::static_files::escape_html;
+
+/// A piece of a template source, with the line it starts on.
+#[derive(Debug, Clone, Copy)]
+enum Token<'a> {
+    Text(&'a str),
+    /// `{{ ... }}`
+    Var(&'a str, usize),
+    /// `{% ... %}`
+    Tag(&'a str, usize),
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Var {
+        path: Vec<String>,
+        raw: bool,
+    },
+    If {
+        path: Vec<String>,
+        negate: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        name: String,
+        path: Vec<String>,
+        body: Vec<Node>,
+    },
+}
+
+/// A parsed template, rendered with a context of any [`Serialize`] value, e.g. a struct or a
+/// `serde_json::json!` object.
+///
+/// - `{{ user.name }}` inserts the value at the path, HTML-escaped. Array items are reached by
+///   index, e.g. `{{ items.0 }}`. A missing value or `null` inserts nothing, and arrays and objects
+///   are inserted as JSON.
+/// - `{{ html | raw }}` inserts the value without escaping it.
+/// - `{% if path %} ... {% else %} ... {% endif %}` renders the first part if the value is truthy,
+///   i.e. neither missing, `null`, `false`, `0`, nor empty, and the optional second part
+///   otherwise. `{% if not path %}` does the opposite.
+/// - `{% for item in path %} ... {% endfor %}` renders the body for each item of an array, with
+///   `item` bound to it.
+///
+/// ```ignore
+/// let template = Template::parse("<ul>{% for user in users %}<li>{{ user }}</li>{% endfor %}</ul>")?;
+/// let html = template.render(&json!({ "users": ["<alice>", "bob"] }))?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    /// Parses the template `source`.
+    ///
+    /// Returns a [`TemplateError::Syntax`] error if a tag is unclosed, unknown, or unbalanced.
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let tokens = tokenize(source)?;
+        let mut tokens = tokens.iter();
+        match parse_nodes(&mut tokens)? {
+            (nodes, None) => Ok(Self { nodes }),
+            (_, Some((tag, line))) => Err(syntax(line, format!("unexpected `{tag}`"))),
+        }
+    }
+
+    /// Renders the template with the values of `context`.
+    ///
+    /// Returns a [`TemplateError::NotIterable`] error if a loop is over a value that isn't an
+    /// array.
+    pub fn render<T: Serialize + ?Sized>(&self, context: &T) -> Result<String, TemplateError> {
+        let context = serde_json::to_value(context).map_err(TemplateError::Context)?;
+        let mut out = String::new();
+        render_nodes(&self.nodes, &context, &mut Vec::new(), &mut out)?;
+        Ok(out)
+    }
+}
+
+/// Templates read from the files of a directory and kept parsed in a [`Cache`].
+///
+/// Cheap to clone: the clones share the cache.
+#[derive(Debug, Clone)]
+pub struct Templates {
+    dir: PathBuf,
+    cache: Arc<Cache<String, Arc<Template>>>,
+}
+
+impl Templates {
+    /// Creates the templates read from `dir`. A template is read once and then kept until the
+    /// cache evicts it.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            cache: Arc::new(Cache::default()),
+        }
+    }
+
+    /// Sets how long a template is kept before it is read again, so that edits show up without a
+    /// restart.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the TTL is zero.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        assert_ne!(ttl, Duration::ZERO, "TTL must be positive");
+        self.cache = Arc::new(
+            Cache::builder()
+                .ttl(ttl)
+                .build()
+                .expect("default configuration is valid"),
+        );
+        self
+    }
+
+    /// Returns the template of the file `name`, a path relative to the directory, reading and
+    /// parsing it if it isn't cached.
+    ///
+    /// Returns a [`TemplateError::InvalidName`] error if `name` leaves the directory.
+    pub fn get(&self, name: &str) -> Result<Arc<Template>, TemplateError> {
+        if let Some((template, _)) = self.cache.get_with_version(&name.to_string()) {
+            return Ok(template);
+        }
+        if !Path::new(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(TemplateError::InvalidName(name.to_string()));
+        }
+        let path = self.dir.join(name);
+        let source = fs::read_to_string(&path).map_err(|err| TemplateError::Read(path, err))?;
+        let template = Arc::new(Template::parse(&source)?);
+        // Failures aren't cached, so that a fixed template is read again right away.
+        let _ = self.cache.insert_if_version(
+            name.to_string(),
+            Cache::<String, Arc<Template>>::ABSENT,
+            template.clone(),
+        );
+        Ok(template)
+    }
+
+    /// Renders the template of the file `name` with the values of `context`.
+    pub fn render<T: Serialize + ?Sized>(
+        &self,
+        name: &str,
+        context: &T,
+    ) -> Result<String, TemplateError> {
+        self.get(name)?.render(context)
+    }
+
+    /// Creates a 200 response with the template of the file `name` rendered with `context` as the
+    /// HTML body.
+    ///
+    /// Responds with 500 if the template can't be rendered.
+    pub fn response<T: Serialize + ?Sized>(&self, name: &str, context: &T) -> Response {
+        match self.render(name, context) {
+            Ok(html) => Response::html(html),
+            Err(err) => {
+                println!("[template] failed to render {name}: {err}");
+                Response::status_page(500)
+            }
+        }
+    }
+}
+
+/// Error parsing or rendering a [`Template`].
+#[derive(Debug)]
+pub enum TemplateError {
+    /// The template file can't be read.
+    Read(PathBuf, io::Error),
+    /// The template name is an absolute path or has `..` components.
+    InvalidName(String),
+    /// The template is malformed.
+    Syntax {
+        /// Line of the malformed tag, starting at 1.
+        line: usize,
+        /// What is wrong.
+        message: String,
+    },
+    /// A loop is over a value that isn't an array.
+    NotIterable(String),
+    /// The context can't be serialized, e.g. a map with non-string keys.
+    Context(serde_json::Error),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(path, err) => write!(f, "can't read {}: {err}", path.display()),
+            Self::InvalidName(name) => write!(f, "invalid template name {name:?}"),
+            Self::Syntax { line, message } => write!(f, "line {line}: {message}"),
+            Self::NotIterable(path) => write!(f, "{path} is not an array"),
+            Self::Context(err) => write!(f, "invalid context: {err}"),
+        }
+    }
+}
+
+impl Error for TemplateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Read(_, err) => Some(err),
+            Self::Context(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+fn syntax(line: usize, message: impl Into<String>) -> TemplateError {
+    TemplateError::Syntax {
+        line,
+        message: message.into(),
+    }
+}
+
+/// Splits `source` into text and tags.
+fn tokenize(source: &str) -> Result<Vec<Token<'_>>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    let mut line = 1;
+    loop {
+        let Some(start) = [rest.find("{{"), rest.find("{%")]
+            .into_iter()
+            .flatten()
+            .min()
+        else {
+            if !rest.is_empty() {
+                tokens.push(Token::Text(rest));
+            }
+            return Ok(tokens);
+        };
+        if start > 0 {
+            tokens.push(Token::Text(&rest[..start]));
+        }
+        line += rest[..start].matches('\n').count();
+
+        let is_var = rest[start..].starts_with("{{");
+        let close = if is_var { "}}" } else { "%}" };
+        let inner_start = start + 2;
+        let len = rest[inner_start..]
+            .find(close)
+            .ok_or_else(|| syntax(line, "unclosed tag"))?;
+        let inner = rest[inner_start..inner_start + len].trim();
+        tokens.push(if is_var {
+            Token::Var(inner, line)
+        } else {
+            Token::Tag(inner, line)
+        });
+        line += inner.matches('\n').count();
+        rest = &rest[inner_start + len + 2..];
+    }
+}
+
+/// A tag closing a block, e.g. `endif`, with its line.
+type End<'a> = Option<(&'a str, usize)>;
+
+/// Parses nodes until the end of the tokens or a tag closing a block, which is returned.
+fn parse_nodes<'a>(
+    tokens: &mut std::slice::Iter<'_, Token<'a>>,
+) -> Result<(Vec<Node>, End<'a>), TemplateError> {
+    let mut nodes = Vec::new();
+    while let Some(&token) = tokens.next() {
+        match token {
+            Token::Text(text) => nodes.push(Node::Text(text.to_string())),
+            Token::Var(inner, line) => {
+                let (path, raw) = match inner.split_once('|') {
+                    Some((path, filter)) if filter.trim() == "raw" => (path.trim(), true),
+                    Some(_) => return Err(syntax(line, "unknown filter")),
+                    None => (inner, false),
+                };
+                nodes.push(Node::Var {
+                    path: parse_path(path, line)?,
+                    raw,
+                });
+            }
+            Token::Tag(inner, line) => match inner.split_whitespace().collect::<Vec<_>>()[..] {
+                ["if", path] => nodes.push(parse_if(tokens, path, false, line)?),
+                ["if", "not", path] => nodes.push(parse_if(tokens, path, true, line)?),
+                ["for", name, "in", path] => {
+                    if !is_identifier(name) {
+                        return Err(syntax(line, format!("invalid name `{name}`")));
+                    }
+                    let (body, end) = parse_nodes(tokens)?;
+                    if !matches!(end, Some(("endfor", _))) {
+                        return Err(syntax(line, "`for` without `endfor`"));
+                    }
+                    nodes.push(Node::For {
+                        name: name.to_string(),
+                        path: parse_path(path, line)?,
+                        body,
+                    });
+                }
+                [tag @ ("else" | "endif" | "endfor")] => return Ok((nodes, Some((tag, line)))),
+                _ => return Err(syntax(line, format!("unknown tag `{inner}`"))),
+            },
+        }
+    }
+    Ok((nodes, None))
+}
+
+/// Parses the rest of an `if` block whose condition is `path`.
+fn parse_if(
+    tokens: &mut std::slice::Iter<'_, Token<'_>>,
+    path: &str,
+    negate: bool,
+    line: usize,
+) -> Result<Node, TemplateError> {
+    let path = parse_path(path, line)?;
+    let (then, end) = parse_nodes(tokens)?;
+    let otherwise = match end {
+        Some(("endif", _)) => Vec::new(),
+        Some(("else", _)) => match parse_nodes(tokens)? {
+            (otherwise, Some(("endif", _))) => otherwise,
+            _ => return Err(syntax(line, "`if` without `endif`")),
+        },
+        _ => return Err(syntax(line, "`if` without `endif`")),
+    };
+    Ok(Node::If {
+        path,
+        negate,
+        then,
+        otherwise,
+    })
+}
+
+/// Parses a dotted path like `user.name`.
+fn parse_path(path: &str, line: usize) -> Result<Vec<String>, TemplateError> {
+    path.split('.')
+        .map(|segment| {
+            if is_identifier(segment) || is_index(segment) {
+                Ok(segment.to_string())
+            } else {
+                Err(syntax(line, format!("invalid path `{path}`")))
+            }
+        })
+        .collect()
+}
+
+fn is_index(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty()
+        && !s.starts_with(|c: char| c.is_ascii_digit())
+        && s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+fn render_nodes<'v>(
+    nodes: &'v [Node],
+    context: &'v Value,
+    bindings: &mut Vec<(&'v str, &'v Value)>,
+    out: &mut String,
+) -> Result<(), TemplateError> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Var { path, raw } => {
+                let text = lookup(context, bindings, path).map_or(Cow::Borrowed(""), to_text);
+                if *raw {
+                    out.push_str(&text);
+                } else {
+                    out.push_str(&escape_html(&text));
+                }
+            }
+            Node::If {
+                path,
+                negate,
+                then,
+                otherwise,
+            } => {
+                let truthy = lookup(context, bindings, path).is_some_and(is_truthy);
+                let nodes = if truthy != *negate { then } else { otherwise };
+                render_nodes(nodes, context, bindings, out)?;
+            }
+            Node::For { name, path, body } => {
+                let items = match lookup(context, bindings, path) {
+                    None | Some(Value::Null) => continue,
+                    Some(Value::Array(items)) => items,
+                    Some(_) => return Err(TemplateError::NotIterable(path.join("."))),
+                };
+                for item in items {
+                    bindings.push((name, item));
+                    let rendered = render_nodes(body, context, bindings, out);
+                    let _ = bindings.pop();
+                    rendered?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the value at `path`, starting from the innermost loop variable named like its first
+/// segment, or else from the context.
+fn lookup<'v>(
+    context: &'v Value,
+    bindings: &[(&str, &'v Value)],
+    path: &[String],
+) -> Option<&'v Value> {
+    let (first, rest) = path.split_first()?;
+    let mut value = bindings
+        .iter()
+        .rev()
+        .find(|(name, _)| name == first)
+        .map(|(_, value)| *value)
+        .or_else(|| context.get(first))?;
+    for segment in rest {
+        value = match value {
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => value.get(segment)?,
+        };
+    }
+    Some(value)
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(fields) => !fields.is_empty(),
+    }
+}
+
+fn to_text(value: &Value) -> Cow<'_, str> {
+    match value {
+        Value::Null => Cow::Borrowed(""),
+        Value::String(s) => Cow::Borrowed(s),
+        _ => Cow::Owned(value.to_string()),
+    }
+}
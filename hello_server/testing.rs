@@ -0,0 +1,400 @@
+//! Linearizability checking of concurrent objects.
+//!
+//! A [`Recorder`] stamps the call and the return of each operation run on a concurrent object,
+//! and [`check`] searches the recorded history for a linearization: an order of the operations
+//! that respects their real-time order, i.e. an operation that returned before another was called
+//! comes first, and in which running them one by one on the sequential specification of the
+//! object returns what they returned. The search is Wing and Gong's, "Testing and Verifying
+//! Concurrent Objects" (JPDC 1993), with Lowe's memoization of the configurations already
+//! explored, "Testing for Linearizability" (CCPE 2017).
+//!
+//! [`Stress`] runs random operations from several threads, and checks each history.
+//!
+//! ```ignore
+//! Stress::new().threads(4).operations(30).rounds(200).run(
+//!     || (Queue::new(), QueueSpec::default()),
+//!     |rng| match rng.gen_bool(0.5) {
+//!         true => QueueOp::Enqueue(rng.gen_range(0..100)),
+//!         false => QueueOp::Dequeue,
+//!     },
+//!     |queue, op| match *op {
+//!         QueueOp::Enqueue(value) => {
+//!             queue.enqueue(value);
+//!             None
+//!         }
+//!         QueueOp::Dequeue => queue.dequeue(),
+//!     },
+//! )?;
+//! ```
+
+use rand::rngs::ThreadRng;
+use rand::Rng;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt::{self, Write};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Barrier, Mutex};
+use std::thread;
+use thiserror::Error;
+
+/// Probability that a thread yields before an operation, shuffling the schedule.
+const YIELD: f64 = 0.2;
+
+/// Sequential specification of a concurrent object: the object as if its operations ran one at a
+/// time.
+pub trait Spec: Clone + Eq + Hash {
+    /// An operation on the object, with its arguments.
+    type Op: fmt::Debug;
+    /// What an operation returns.
+    type Ret: fmt::Debug + PartialEq;
+
+    /// Runs `op`, returning what it returns.
+    fn apply(&mut self, op: &Self::Op) -> Self::Ret;
+}
+
+/// An operation of a history, run by `thread` between the stamps `invoked` and `returned`.
+pub struct Operation<S: Spec> {
+    pub thread: usize,
+    pub op: S::Op,
+    pub ret: S::Ret,
+    pub invoked: u64,
+    pub returned: u64,
+}
+
+impl<S: Spec> fmt::Debug for Operation<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "thread {}: {:?} -> {:?} [{}, {}]",
+            self.thread, self.op, self.ret, self.invoked, self.returned
+        )
+    }
+}
+
+/// Records the history of the operations run on a concurrent object by several threads.
+pub struct Recorder<S: Spec> {
+    clock: AtomicU64,
+    operations: Mutex<Vec<Operation<S>>>,
+}
+
+impl<S: Spec> fmt::Debug for Recorder<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder")
+            .field("operations", &self.operations.lock().unwrap().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: Spec> Default for Recorder<S> {
+    fn default() -> Self {
+        Self {
+            clock: AtomicU64::new(0),
+            operations: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<S: Spec> Recorder<S> {
+    /// Creates a recorder with an empty history.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `op` on the object with `run`, recording it as run by `thread`.
+    pub fn record(&self, thread: usize, op: S::Op, run: impl FnOnce(&S::Op) -> S::Ret) {
+        // Acquire so that the accesses of the operation aren't moved before its call stamp.
+        let invoked = self.clock.fetch_add(1, Ordering::Acquire);
+        let ret = run(&op);
+        // Release so that the accesses of the operation aren't moved after its return stamp.
+        let returned = self.clock.fetch_add(1, Ordering::Release);
+        self.operations.lock().unwrap().push(Operation {
+            thread,
+            op,
+            ret,
+            invoked,
+            returned,
+        });
+    }
+
+    /// Returns the history, ordered by call.
+    pub fn into_history(self) -> Vec<Operation<S>> {
+        let mut history = self.operations.into_inner().unwrap();
+        history.sort_by_key(|operation| operation.invoked);
+        history
+    }
+}
+
+/// Error of [`check`] when no linearization of a history exists.
+#[derive(Debug, Error)]
+#[error("history of {operations} operations is not linearizable")]
+pub struct NotLinearizable {
+    pub operations: usize,
+    /// The history, one operation per line.
+    pub history: String,
+}
+
+impl NotLinearizable {
+    fn new<S: Spec>(history: &[Operation<S>]) -> Self {
+        let mut text = String::new();
+        for operation in history {
+            let _ = writeln!(text, "{operation:?}");
+        }
+        Self {
+            operations: history.len(),
+            history: text,
+        }
+    }
+}
+
+/// Set of the operations of a history linearized so far, by index.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Linearized(Vec<u64>);
+
+impl Linearized {
+    fn new(len: usize) -> Self {
+        Self(vec![0; len.div_ceil(64)])
+    }
+
+    fn contains(&self, index: usize) -> bool {
+        self.0[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    fn with(&self, index: usize) -> Self {
+        let mut set = self.clone();
+        set.0[index / 64] |= 1 << (index % 64);
+        set
+    }
+}
+
+/// Searches for a linearization of `history` on the specification starting from `initial`, and
+/// returns it as indices into `history`.
+///
+/// The search extends a linearized prefix with one of the remaining operations called before the
+/// first of them returned, backtracking when none returns what the specification does. Prefixes
+/// linearizing the same operations to the same state are only explored once, which keeps the
+/// search tractable while few operations overlap.
+pub fn check<S: Spec>(initial: S, history: &[Operation<S>]) -> Result<Vec<usize>, NotLinearizable> {
+    let mut explored = HashSet::new();
+    let mut stack = vec![(Linearized::new(history.len()), initial, Vec::new())];
+    while let Some((linearized, state, order)) = stack.pop() {
+        if order.len() == history.len() {
+            return Ok(order);
+        }
+        let remaining = || (0..history.len()).filter(|&i| !linearized.contains(i));
+        let first_return = remaining()
+            .map(|i| history[i].returned)
+            .min()
+            .expect("an operation remains");
+        for i in remaining() {
+            let operation = &history[i];
+            if operation.invoked > first_return {
+                continue;
+            }
+            let mut next = state.clone();
+            if next.apply(&operation.op) != operation.ret {
+                continue;
+            }
+            let linearized = linearized.with(i);
+            if explored.insert((linearized.clone(), next.clone())) {
+                let mut order = order.clone();
+                order.push(i);
+                stack.push((linearized, next, order));
+            }
+        }
+    }
+    Err(NotLinearizable::new(history))
+}
+
+/// Runs random operations on concurrent objects from several threads, and checks that each
+/// history is linearizable.
+#[derive(Debug, Clone, Copy)]
+pub struct Stress {
+    threads: usize,
+    operations: usize,
+    rounds: usize,
+}
+
+impl Default for Stress {
+    fn default() -> Self {
+        Self {
+            threads: 4,
+            operations: 30,
+            rounds: 200,
+        }
+    }
+}
+
+impl Stress {
+    /// Creates a stress test of 200 rounds of 30 operations from each of 4 threads.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs the operations from `threads` threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is 0.
+    pub fn threads(mut self, threads: usize) -> Self {
+        assert_ne!(threads, 0, "threads must be positive");
+        self.threads = threads;
+        self
+    }
+
+    /// Runs `operations` operations from each thread per round.
+    pub fn operations(mut self, operations: usize) -> Self {
+        self.operations = operations;
+        self
+    }
+
+    /// Runs `rounds` rounds, each on a new object.
+    pub fn rounds(mut self, rounds: usize) -> Self {
+        self.rounds = rounds;
+        self
+    }
+
+    /// Runs the rounds. Each one runs operations made by `generate` with `run` on an object made
+    /// by `new` along with its specification, and checks the history. Stops at the first history
+    /// that isn't linearizable.
+    pub fn run<T, S, N, G, R>(&self, new: N, generate: G, run: R) -> Result<(), NotLinearizable>
+    where
+        T: Sync,
+        S: Spec,
+        S::Op: Send,
+        S::Ret: Send,
+        N: Fn() -> (T, S),
+        G: Fn(&mut ThreadRng) -> S::Op + Sync,
+        R: Fn(&T, &S::Op) -> S::Ret + Sync,
+    {
+        for _ in 0..self.rounds {
+            let (object, spec) = new();
+            let recorder = Recorder::new();
+            let barrier = Barrier::new(self.threads);
+            thread::scope(|scope| {
+                for thread in 0..self.threads {
+                    let (object, recorder, barrier) = (&object, &recorder, &barrier);
+                    let (generate, run) = (&generate, &run);
+                    let _ = scope.spawn(move || {
+                        let mut rng = rand::thread_rng();
+                        let _ = barrier.wait();
+                        for _ in 0..self.operations {
+                            let op = generate(&mut rng);
+                            if rng.gen_bool(YIELD) {
+                                thread::yield_now();
+                            }
+                            recorder.record(thread, op, |op| run(object, op));
+                        }
+                    });
+                }
+            });
+            let _ = check(spec, &recorder.into_history())?;
+        }
+        Ok(())
+    }
+}
+
+/// Operation of [`QueueSpec`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum QueueOp<T> {
+    /// Returns `None`.
+    Enqueue(T),
+    Dequeue,
+}
+
+/// Specification of a FIFO queue, e.g. [`lockfree::Queue`](super::lockfree::Queue).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QueueSpec<T>(VecDeque<T>);
+
+impl<T> Default for QueueSpec<T> {
+    fn default() -> Self {
+        Self(VecDeque::new())
+    }
+}
+
+impl<T: Clone + Eq + Hash + fmt::Debug> Spec for QueueSpec<T> {
+    type Op = QueueOp<T>;
+    type Ret = Option<T>;
+
+    fn apply(&mut self, op: &QueueOp<T>) -> Option<T> {
+        match op {
+            QueueOp::Enqueue(value) => {
+                self.0.push_back(value.clone());
+                None
+            }
+            QueueOp::Dequeue => self.0.pop_front(),
+        }
+    }
+}
+
+/// Operation of [`MapSpec`]. Each returns the previous value of the key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapOp<K, V> {
+    Get(K),
+    Insert(K, V),
+    Remove(K),
+}
+
+/// Specification of a map, e.g. [`concurrent::HashMap`](super::concurrent::HashMap).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MapSpec<K, V>(BTreeMap<K, V>);
+
+impl<K, V> Default for MapSpec<K, V> {
+    fn default() -> Self {
+        Self(BTreeMap::new())
+    }
+}
+
+impl<K, V> Spec for MapSpec<K, V>
+where
+    K: Clone + Ord + Hash + fmt::Debug,
+    V: Clone + Eq + Hash + fmt::Debug,
+{
+    type Op = MapOp<K, V>;
+    type Ret = Option<V>;
+
+    fn apply(&mut self, op: &MapOp<K, V>) -> Option<V> {
+        match op {
+            MapOp::Get(key) => self.0.get(key).cloned(),
+            MapOp::Insert(key, value) => self.0.insert(key.clone(), value.clone()),
+            MapOp::Remove(key) => self.0.remove(key),
+        }
+    }
+}
+
+/// Operation of [`CacheSpec`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CacheOp<K, V> {
+    /// Returns the cached value, or caches and returns the given one.
+    GetOrInsert(K, V),
+    /// Returns the removed value.
+    Invalidate(K),
+}
+
+/// Specification of a [`Cache`](super::Cache) that neither evicts nor expires entries, i.e. with
+/// a capacity above the number of keys and without a TTL.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheSpec<K, V>(BTreeMap<K, V>);
+
+impl<K, V> Default for CacheSpec<K, V> {
+    fn default() -> Self {
+        Self(BTreeMap::new())
+    }
+}
+
+impl<K, V> Spec for CacheSpec<K, V>
+where
+    K: Clone + Ord + Hash + fmt::Debug,
+    V: Clone + Eq + Hash + fmt::Debug,
+{
+    type Op = CacheOp<K, V>;
+    type Ret = Option<V>;
+
+    fn apply(&mut self, op: &CacheOp<K, V>) -> Option<V> {
+        match op {
+            CacheOp::GetOrInsert(key, value) => {
+                Some(self.0.entry(key.clone()).or_insert(value.clone()).clone())
+            }
+            CacheOp::Invalidate(key) => self.0.remove(key),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! Checks the cache, the concurrent maps and the lock-free queue against their sequential
+//! specifications, on histories of random operations from several threads.
+
+use cs431_homework::hello_server::concurrent::{HashMap, SkipListMap};
+use cs431_homework::hello_server::lockfree::Queue;
+use cs431_homework::hello_server::testing::{
+    CacheOp, CacheSpec, MapOp, MapSpec, NotLinearizable, QueueOp, QueueSpec, Stress,
+};
+use cs431_homework::hello_server::Cache;
+use rand::rngs::ThreadRng;
+use rand::Rng;
+
+/// Number of keys the operations use, few so that they conflict.
+const KEYS: u8 = 4;
+
+/// Number of values the operations write.
+const VALUES: u32 = 100;
+
+/// Fails with the history that isn't linearizable, if any.
+fn assert_linearizable(result: Result<(), NotLinearizable>) {
+    if let Err(err) = result {
+        panic!("{err}\n{}", err.history);
+    }
+}
+
+#[test]
+fn cache() {
+    assert_linearizable(Stress::new().run(
+        || (Cache::default(), CacheSpec::default()),
+        |rng| {
+            let key = rng.gen_range(0..KEYS);
+            match rng.gen_bool(0.75) {
+                true => CacheOp::GetOrInsert(key, rng.gen_range(0..VALUES)),
+                false => CacheOp::Invalidate(key),
+            }
+        },
+        |cache: &Cache<u8, u32>, op| match *op {
+            CacheOp::GetOrInsert(key, value) => Some(cache.get_or_insert_with(key, |_| value)),
+            CacheOp::Invalidate(key) => cache.invalidate(&key),
+        },
+    ));
+}
+
+fn map_op(rng: &mut impl Rng) -> MapOp<u8, u32> {
+    let key = rng.gen_range(0..KEYS);
+    match rng.gen_range(0..3) {
+        0 => MapOp::Get(key),
+        1 => MapOp::Insert(key, rng.gen_range(0..VALUES)),
+        _ => MapOp::Remove(key),
+    }
+}
+
+/// Mostly replaces the values of a couple of keys, so that gets and removes race the replacements.
+fn replacing_map_op(rng: &mut impl Rng) -> MapOp<u8, u32> {
+    let key = rng.gen_range(0..2);
+    match rng.gen_range(0..8) {
+        0..=1 => MapOp::Get(key),
+        2..=6 => MapOp::Insert(key, rng.gen_range(0..VALUES)),
+        _ => MapOp::Remove(key),
+    }
+}
+
+#[test]
+fn hash_map() {
+    assert_linearizable(Stress::new().run(
+        || (HashMap::new(), MapSpec::default()),
+        map_op,
+        |map: &HashMap<u8, u32>, op| match *op {
+            MapOp::Get(key) => map.get(&key),
+            MapOp::Insert(key, value) => map.insert(key, value),
+            MapOp::Remove(key) => map.remove(&key),
+        },
+    ));
+}
+
+fn check_skip_list_map(generate: fn(&mut ThreadRng) -> MapOp<u8, u32>) {
+    assert_linearizable(Stress::new().rounds(2_000).run(
+        || (SkipListMap::new(), MapSpec::default()),
+        generate,
+        |map: &SkipListMap<u8, u32>, op| match *op {
+            MapOp::Get(key) => map.get(&key),
+            MapOp::Insert(key, value) => map.insert(key, value),
+            MapOp::Remove(key) => map.remove(&key),
+        },
+    ));
+}
+
+#[test]
+fn skip_list_map() {
+    check_skip_list_map(map_op);
+}
+
+#[test]
+fn skip_list_map_replacing_values() {
+    check_skip_list_map(replacing_map_op);
+}
+
+#[test]
+fn queue() {
+    assert_linearizable(Stress::new().run(
+        || (Queue::new(), QueueSpec::default()),
+        |rng| match rng.gen_bool(0.5) {
+            true => QueueOp::Enqueue(rng.gen_range(0..VALUES)),
+            false => QueueOp::Dequeue,
+        },
+        |queue: &Queue<u32>, op| match *op {
+            QueueOp::Enqueue(value) => {
+                queue.enqueue(value);
+                None
+            }
+            QueueOp::Dequeue => queue.dequeue(),
+        },
+    ));
+}
@@ -0,0 +1,136 @@
+//! Runs the whole server on a document root of its own, and requests its pages over TCP.
+
+use cs431_homework::hello_server::{CacheConfig, Server, ServerConfig, ShutdownHandle};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::process;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A server running on its own thread, shut down when dropped.
+struct Running {
+    addr: SocketAddr,
+    doc_root: PathBuf,
+    shutdown: ShutdownHandle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Running {
+    /// Runs a server caching its responses for an hour, on a fresh document root named after
+    /// `name` with the file `a.txt` holding `contents`.
+    fn start(name: &str, contents: &str, watch_doc_root: bool) -> Self {
+        let doc_root = std::env::temp_dir().join(format!("hello_server_{name}_{}", process::id()));
+        let _ = fs::remove_dir_all(&doc_root);
+        fs::create_dir_all(&doc_root).unwrap();
+        fs::write(doc_root.join("a.txt"), contents).unwrap();
+
+        let server = Server::bind(ServerConfig {
+            addrs: vec!["127.0.0.1:0".to_string()],
+            pool_size: 2,
+            doc_root: Some(doc_root.clone()),
+            watch_doc_root,
+            response_cache: Some(CacheConfig {
+                capacity: Some(16),
+                ttl: Some(Duration::from_secs(3600)),
+                ..CacheConfig::default()
+            }),
+            ..ServerConfig::default()
+        })
+        .unwrap();
+        let addr = server.local_addrs().unwrap()[0];
+        let shutdown = server.shutdown_handle();
+        let thread = thread::spawn(move || drop(server.run().unwrap()));
+        Self {
+            addr,
+            doc_root,
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+
+    /// Requests `path` with the extra `headers`, and returns the status, the headers, and the body
+    /// of the response.
+    fn get(&self, path: &str, headers: &[(&str, &str)]) -> (u16, Vec<(String, String)>, String) {
+        let mut stream = TcpStream::connect(self.addr).unwrap();
+        let mut request =
+            format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n");
+        for (name, value) in headers {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).unwrap();
+
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let mut lines = head.split("\r\n");
+        let status = lines.next().unwrap().split(' ').nth(1).unwrap();
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+        (status.parse().unwrap(), headers, body.to_string())
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.shutdown.shutdown().unwrap();
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+        let _ = fs::remove_dir_all(&self.doc_root);
+    }
+}
+
+/// Returns the value of the header `name`, if any.
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header == name)
+        .map(|(_, value)| value.as_str())
+}
+
+#[test]
+fn response_cache_serves_cached_pages() {
+    let server = Running::start("response_cache", "one", false);
+    let (status, headers, body) = server.get("/static/a.txt", &[]);
+    assert_eq!((status, body.as_str()), (200, "one"));
+    let etag = header(&headers, "etag").expect("cached responses have an ETag");
+
+    // Without watching the document root, the cached page is served until it expires.
+    fs::write(server.doc_root.join("a.txt"), "two").unwrap();
+    let (status, headers, body) = server.get("/static/a.txt", &[]);
+    assert_eq!((status, body.as_str()), (200, "one"));
+    assert_eq!(header(&headers, "etag"), Some(etag));
+
+    let (status, _, body) = server.get("/static/a.txt", &[("If-None-Match", etag)]);
+    assert_eq!((status, body.as_str()), (304, ""));
+}
+
+#[test]
+fn changed_pages_are_not_served_from_the_cache() {
+    let server = Running::start("watch", "one", true);
+    let (status, _, body) = server.get("/static/a.txt", &[]);
+    assert_eq!((status, body.as_str()), (200, "one"));
+
+    // The change is reported by a background thread, long before the cached page expires.
+    fs::write(server.doc_root.join("a.txt"), "two").unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let (status, _, body) = server.get("/static/a.txt", &[]);
+        assert_eq!(status, 200);
+        if body == "two" {
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "the cached page was never invalidated"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+    let (_, _, body) = server.get("/static/a.txt", &[]);
+    assert_eq!(body, "two");
+}
@@ -1,12 +1,52 @@
 //! Thread pool that joins all thread when dropped.
+//!
+//! A [`ThreadPool`] runs jobs on a fixed number of threads, taking them from an [MPMC
+//! channel](super::mpmc) and parking while there is none. A bounded pool queues a limited number
+//! of jobs, so that a producer faster than the threads either waits with [`ThreadPool::execute`]
+//! or is turned away with [`ThreadPool::try_execute`].
+//!
+//! Components that submit jobs for as long as they live take a [`PoolHandle`] rather than an
+//! `Arc<ThreadPool>`, so that the pool is dropped, and its threads joined, by its owner.
+//!
+//! ```ignore
+//! let pool = ThreadPool::bounded(4, 64);
+//! for i in 0..100 {
+//!     pool.execute(move || println!("job {i}"));
+//! }
+//! pool.join();
+//! ```
 
-// NOTE: Crossbeam channels are MPMC, which means that you don't need to wrap the receiver in
-// Arc<Mutex<..>>. Just clone the receiver and give it to each worker thread.
-use crossbeam_channel::{unbounded, Receiver, Sender};
-use std::sync::{Arc, Condvar, Mutex};
-use std::thread;
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
 
-struct Job(Box<dyn FnOnce() + Send + 'static>);
+use super::mpmc::{self, Receiver, Sender, TrySendError};
+use super::sync::shim::{thread, Condvar, Mutex};
+use super::sync::{Parker, ShardedCounter, Unparker};
+use super::trace::{event, span};
+
+/// Number of jobs the channel of an unbounded pool holds before the next ones overflow into a list.
+const UNBOUNDED_CHANNEL_CAPACITY: usize = 1024;
+
+struct Job {
+    run: Box<dyn FnOnce() + Send + 'static>,
+    /// The span the job was submitted in, the parent of the span it runs in.
+    #[cfg(feature = "tracing")]
+    parent: tracing::Span,
+}
+
+impl Job {
+    fn new(run: impl FnOnce() + Send + 'static) -> Self {
+        Self {
+            run: Box::new(run),
+            #[cfg(feature = "tracing")]
+            parent: tracing::Span::current(),
+        }
+    }
+}
 
 #[derive(Debug)]
 struct Worker {
@@ -15,12 +55,20 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Receiver<Job>, pool_inner: Arc<ThreadPoolInner>) -> Self {
+    fn new(id: usize, pool_inner: Arc<ThreadPoolInner>) -> Self {
         let thread = thread::spawn(move || {
-            while let Ok(job) = receiver.recv() {
-                pool_inner.start_job();
-                (job.0)();
+            let parker = Parker::new();
+            while let Some(job) = pool_inner.next_job(&parker) {
+                span!(DEBUG, parent: &job.parent, "job", worker = id);
+                event!(TRACE, "job started");
+                // A panicking job takes neither the worker nor the count of jobs down with it.
+                if panic::catch_unwind(AssertUnwindSafe(job.run)).is_err() {
+                    event!(WARN, "job panicked");
+                    pool_inner.panicked.increment();
+                }
+                pool_inner.completed.increment();
                 pool_inner.finish_job();
+                event!(TRACE, "job finished");
             }
         });
 
@@ -32,8 +80,8 @@ impl Worker {
 }
 
 impl Drop for Worker {
-    /// When dropped, the thread's `JoinHandle` must be `join`ed.  If the worker panics, then this
-    /// function should panic too.
+    /// When dropped, the thread's `JoinHandle` must be `join`ed. The worker catches the panics of
+    /// the jobs, so it doesn't panic itself.
     ///
     /// NOTE: The thread is detached if not `join`ed explicitly.
     fn drop(&mut self) {
@@ -43,32 +91,190 @@ impl Drop for Worker {
     }
 }
 
+/// The jobs counted, the jobs that overflowed the channel, and the workers waiting for a job.
+#[derive(Default)]
+struct Queue {
+    /// Number of jobs queued or running.
+    count: usize,
+    /// Jobs of an unbounded pool that found the channel full, oldest first. While there are some,
+    /// the next jobs are queued after them instead of in the channel, so that jobs run in order.
+    overflow: VecDeque<Job>,
+    /// The workers parked until a job is queued, most recently parked last.
+    idle: Vec<Unparker>,
+    /// Whether the pool was dropped, so that no job is queued anymore and the workers exit once the
+    /// jobs are done.
+    closed: bool,
+}
+
+impl fmt::Debug for Queue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Queue")
+            .field("count", &self.count)
+            .field("overflow", &self.overflow.len())
+            .field("idle", &self.idle.len())
+            .field("closed", &self.closed)
+            .finish()
+    }
+}
+
 /// Internal data structure for tracking the current job status. This is shared by worker closures
 /// via `Arc` so that the workers can report to the pool that it started/finished a job.
-#[derive(Debug, Default)]
+#[derive(Debug)]
 struct ThreadPoolInner {
-    job_count: Mutex<usize>,
+    /// Jobs are sent under this lock, so that a worker checking for a job before parking doesn't
+    /// miss one.
+    queue: Mutex<Queue>,
     empty_condvar: Condvar,
+    /// Notified when a job finishes, making room for another, or when the pool is dropped.
+    not_full: Condvar,
+    /// Number of jobs finished, counted apart from `queue` so that reading it doesn't contend with
+    /// the workers.
+    completed: ShardedCounter,
+    /// Number of jobs that panicked, among the completed ones.
+    panicked: ShardedCounter,
+    sender: Sender<Job>,
+    /// Jobs waiting for a worker, which the workers take without locking `queue`.
+    receiver: Receiver<Job>,
+    /// Maximum number of jobs queued or running, if bounded.
+    limit: Option<usize>,
+    /// Number of workers.
+    size: usize,
 }
 
 impl ThreadPoolInner {
-    fn new() -> Self {
+    /// Creates the pool's state, with room for `capacity` jobs waiting for the `size` workers if
+    /// bounded.
+    fn new(size: usize, capacity: Option<usize>) -> Self {
+        // A bounded pool admits no more jobs than the workers run plus `capacity`, so its channel
+        // is never full.
+        let limit = capacity.map(|capacity| size + capacity);
+        let (sender, receiver) = mpmc::channel(limit.unwrap_or(UNBOUNDED_CHANNEL_CAPACITY));
         Self {
-            job_count: Mutex::new(0),
+            queue: Mutex::new(Queue::default()),
             empty_condvar: Condvar::new(),
+            not_full: Condvar::new(),
+            completed: ShardedCounter::new(),
+            panicked: ShardedCounter::new(),
+            sender,
+            receiver,
+            limit,
+            size,
+        }
+    }
+
+    /// Whether a job can be queued: a bounded pool admits no more jobs than its workers run plus
+    /// its capacity.
+    fn has_room(&self, queue: &Queue) -> bool {
+        self.limit.is_none_or(|limit| queue.count < limit)
+    }
+
+    /// Counts `job`, queues it and unparks an idle worker for it, blocking while there is no room
+    /// for it if `block`, or failing otherwise.
+    fn submit(&self, job: Job, block: bool) -> Result<(), PoolError> {
+        let mut queue = self.queue.lock().unwrap();
+        if block {
+            while !queue.closed && !self.has_room(&queue) {
+                queue = self.not_full.wait(queue).unwrap();
+            }
+        }
+        let rejected = if queue.closed {
+            Some(PoolError::Closed)
+        } else if !self.has_room(&queue) {
+            Some(PoolError::Saturated)
+        } else {
+            None
+        };
+        if let Some(rejected) = rejected {
+            event!(DEBUG, "job rejected");
+            return Err(rejected);
+        }
+
+        queue.count += 1;
+        let overflowed = if queue.overflow.is_empty() {
+            match self.sender.try_send(job) {
+                Ok(()) => None,
+                // The pool holds the receiver, so the channel is only ever full.
+                Err(TrySendError::Full(job) | TrySendError::Disconnected(job)) => Some(job),
+            }
+        } else {
+            Some(job)
+        };
+        queue.overflow.extend(overflowed);
+        event!(
+            TRACE,
+            queued = self.receiver.len() + queue.overflow.len(),
+            "job queued"
+        );
+        let idle = queue.idle.pop();
+        drop(queue);
+        if let Some(unparker) = idle {
+            unparker.unpark();
+        }
+        Ok(())
+    }
+
+    /// Takes the next job, parking with `parker` while there is none. Returns `None` once the pool
+    /// is dropped and the jobs are done.
+    ///
+    /// A worker is in the idle list exactly while it is parked: it registers itself under the lock
+    /// of the queue, so that no job is queued between its check and its registration, and the
+    /// thread queuing a job removes it before unparking it.
+    fn next_job(&self, parker: &Parker) -> Option<Job> {
+        // While jobs keep coming, the workers take them without locking the queue.
+        if let Ok(job) = self.receiver.try_recv() {
+            return Some(job);
+        }
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            // Moves the jobs that overflowed into the room left in the channel, oldest first, since
+            // they are newer than the ones in the channel.
+            while let Some(job) = queue.overflow.pop_front() {
+                if let Err(TrySendError::Full(job) | TrySendError::Disconnected(job)) =
+                    self.sender.try_send(job)
+                {
+                    queue.overflow.push_front(job);
+                    break;
+                }
+            }
+            if let Ok(job) = self.receiver.try_recv() {
+                return Some(job);
+            }
+            if queue.closed {
+                return None;
+            }
+            queue.idle.push(parker.unparker().clone());
+            drop(queue);
+            parker.park();
+            queue = self.queue.lock().unwrap();
         }
     }
 
-    /// Increment the job count.
-    fn start_job(&self) {
-        let mut count = self.job_count.lock().unwrap();
-        *count += 1;
+    /// Lets the workers exit once the jobs are done, unparking the idle ones, and turns away the
+    /// jobs submitted from now on.
+    fn close(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.closed = true;
+        for unparker in queue.idle.drain(..) {
+            unparker.unpark();
+        }
+        drop(queue);
+        self.not_full.notify_all();
+    }
+
+    fn queued(&self) -> usize {
+        let queue = self.queue.lock().unwrap();
+        self.receiver.len() + queue.overflow.len()
+    }
+
+    fn jobs(&self) -> usize {
+        self.queue.lock().unwrap().count
     }
 
     /// Decrement the job count.
     fn finish_job(&self) {
-        let mut count = self.job_count.lock().unwrap();
-        *count -= 1;
+        let mut queue = self.queue.lock().unwrap();
+        queue.count -= 1;
+        self.not_full.notify_one();
         self.empty_condvar.notify_all();
     }
 
@@ -77,11 +283,24 @@ impl ThreadPoolInner {
     /// NOTE: We can optimize this function by adding another field to `ThreadPoolInner`, but let's
     /// not care about that in this homework.
     fn wait_empty(&self) {
-        let mut count = self.job_count.lock().unwrap();
-        while *count > 0 {
-            println!("{}", count);
-            count = self.empty_condvar.wait(count).unwrap();
+        let mut queue = self.queue.lock().unwrap();
+        while queue.count > 0 {
+            queue = self.empty_condvar.wait(queue).unwrap();
+        }
+    }
+
+    /// Wait until the job count becomes 0, or until `timeout` elapses. Returns the job count.
+    fn wait_empty_timeout(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.queue.lock().unwrap();
+        while queue.count > 0 {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                break;
+            }
+            queue = self.empty_condvar.wait_timeout(queue, timeout).unwrap().0;
         }
+        queue.count
     }
 }
 
@@ -89,30 +308,42 @@ impl ThreadPoolInner {
 #[derive(Debug)]
 pub struct ThreadPool {
     _workers: Vec<Worker>,
-    job_sender: Option<Sender<Job>>,
     pool_inner: Arc<ThreadPoolInner>,
 }
 
 impl ThreadPool {
-    /// Create a new ThreadPool with `size` threads.
+    /// Create a new ThreadPool with `size` threads, whose queue holds any number of jobs.
     ///
     /// # Panics
     ///
     /// Panics if `size` is 0.
     pub fn new(size: usize) -> Self {
+        Self::with_capacity(size, None)
+    }
+
+    /// Create a new ThreadPool with `size` threads whose queue holds at most `capacity` jobs that
+    /// wait for a thread. When the queue is full, [`ThreadPool::execute`] blocks and
+    /// [`ThreadPool::try_execute`] fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn bounded(size: usize, capacity: usize) -> Self {
+        Self::with_capacity(size, Some(capacity))
+    }
+
+    fn with_capacity(size: usize, capacity: Option<usize>) -> Self {
         assert!(size > 0);
 
-        let (sender, receiver) = unbounded();
         let mut workers = Vec::with_capacity(size);
-        let pool_inner = Arc::new(ThreadPoolInner::new());
+        let pool_inner = Arc::new(ThreadPoolInner::new(size, capacity));
         for id in 0..size {
-            let worker = Worker::new(id, receiver.clone(), Arc::clone(&pool_inner));
+            let worker = Worker::new(id, Arc::clone(&pool_inner));
             workers.push(worker);
         }
 
         Self {
             _workers: workers,
-            job_sender: Some(sender),
             pool_inner,
         }
     }
@@ -122,29 +353,296 @@ impl ThreadPool {
     where
         F: FnOnce() + Send + 'static,
     {
-        if let Some(ref sender) = self.job_sender {
-            sender.send(Job(Box::new(f))).unwrap();
+        if self.pool_inner.submit(Job::new(f), true).is_err() {
+            unreachable!("blocks until there is room, and the pool isn't dropped yet");
+        }
+    }
+
+    /// Execute a new job in the thread pool unless its queue is full (see [`ThreadPool::bounded`]).
+    pub fn try_execute<F>(&self, f: F) -> Result<(), PoolError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.pool_inner.submit(Job::new(f), false)
+    }
+
+    /// Returns a handle submitting jobs to the pool without owning it.
+    pub fn handle(&self) -> PoolHandle {
+        PoolHandle {
+            pool_inner: self.pool_inner.clone(),
         }
     }
 
+    /// Returns the number of jobs waiting for a thread.
+    pub fn queued(&self) -> usize {
+        self.pool_inner.queued()
+    }
+
+    /// Returns the number of threads.
+    pub fn size(&self) -> usize {
+        self.pool_inner.size
+    }
+
+    /// Returns the number of jobs queued or running.
+    pub fn jobs(&self) -> usize {
+        self.pool_inner.jobs()
+    }
+
+    /// Returns the number of jobs executed so far.
+    pub fn completed(&self) -> u64 {
+        self.pool_inner.completed.sum()
+    }
+
+    /// Returns the number of jobs that panicked so far. The panic is caught, and the worker goes
+    /// on with the next job.
+    pub fn panicked(&self) -> u64 {
+        self.pool_inner.panicked.sum()
+    }
+
     /// Block the current thread until all jobs in the pool have been executed.
     ///
     /// NOTE: This method has nothing to do with `JoinHandle::join`.
     pub fn join(&self) {
         self.pool_inner.wait_empty()
     }
+
+    /// Like [`ThreadPool::join`], but gives up after `timeout`, failing with
+    /// [`PoolError::TimedOut`] if some jobs are still queued or running.
+    pub fn join_timeout(&self, timeout: Duration) -> Result<(), PoolError> {
+        match self.pool_inner.wait_empty_timeout(timeout) {
+            0 => Ok(()),
+            jobs => Err(PoolError::TimedOut(jobs)),
+        }
+    }
+
+    /// Drops the pool without joining its threads, e.g. after [`ThreadPool::join_timeout`] gave
+    /// up. The threads still exit once the jobs are done.
+    pub fn detach(mut self) {
+        for worker in &mut self._workers {
+            drop(worker.thread.take());
+        }
+    }
+}
+
+/// Submits jobs to a [`ThreadPool`] without owning it, see [`ThreadPool::handle`].
+///
+/// Unlike an `Arc<ThreadPool>`, a handle doesn't keep the threads running, so the pool is never
+/// dropped by one of its own jobs, which would join its own thread. Once the pool is dropped,
+/// submitting jobs fails with [`PoolError::Closed`].
+#[derive(Debug, Clone)]
+pub struct PoolHandle {
+    pool_inner: Arc<ThreadPoolInner>,
+}
+
+impl PoolHandle {
+    /// Like [`ThreadPool::execute`], but fails if the pool is dropped meanwhile.
+    pub fn execute<F>(&self, f: F) -> Result<(), PoolError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.pool_inner.submit(Job::new(f), true)
+    }
+
+    /// Like [`ThreadPool::try_execute`], but fails if the pool is dropped.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), PoolError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.pool_inner.submit(Job::new(f), false)
+    }
+
+    /// See [`ThreadPool::queued`].
+    pub fn queued(&self) -> usize {
+        self.pool_inner.queued()
+    }
+
+    /// See [`ThreadPool::size`].
+    pub fn size(&self) -> usize {
+        self.pool_inner.size
+    }
+
+    /// See [`ThreadPool::jobs`].
+    pub fn jobs(&self) -> usize {
+        self.pool_inner.jobs()
+    }
+
+    /// See [`ThreadPool::completed`].
+    pub fn completed(&self) -> u64 {
+        self.pool_inner.completed.sum()
+    }
+}
+
+/// Error of submitting jobs to a [`ThreadPool`], or of waiting for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum PoolError {
+    /// The queue of a bounded pool is full, see [`ThreadPool::try_execute`].
+    #[error("thread pool queue is full")]
+    Saturated,
+    /// This many jobs were still queued or running when [`ThreadPool::join_timeout`] gave up.
+    #[error("timed out waiting for {0} job(s)")]
+    TimedOut(usize),
+    /// The pool of a [`PoolHandle`] is dropped.
+    #[error("thread pool is dropped")]
+    Closed,
 }
 
 impl Drop for ThreadPool {
-    /// When dropped, all worker threads' `JoinHandle` must be `join`ed. If the thread panicked,
-    /// then this function should panic too.
+    /// When dropped, all worker threads' `JoinHandle` must be `join`ed, unless the pool is
+    /// [detached](ThreadPool::detach). The workers catch the panics of the jobs, so they don't
+    /// panic themselves.
+    ///
+    /// NOTE: A pool dropped by one of its own jobs detaches that job's thread, which can't join
+    /// itself.
     fn drop(&mut self) {
-        self.job_sender = None;
+        self.pool_inner.close();
 
+        let current = thread::current().id();
         for worker in &mut self._workers {
             if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+                if thread.thread().id() != current {
+                    thread.join().unwrap();
+                }
             }
         }
     }
 }
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::super::sync::Barrier;
+    use super::{PoolError, ThreadPool, UNBOUNDED_CHANNEL_CAPACITY};
+
+    #[test]
+    fn workers_run_jobs_at_once() {
+        const SIZE: usize = 4;
+        let pool = ThreadPool::new(SIZE);
+        // Passed only once a job runs on every worker, along with this thread.
+        let started = Arc::new(Barrier::new(SIZE + 1));
+        let (sender, receiver) = channel();
+        for _ in 0..SIZE {
+            let started = started.clone();
+            let sender = sender.clone();
+            pool.execute(move || {
+                let _ = started.wait();
+                sender.send(thread::current().id()).unwrap();
+            });
+        }
+        let _ = started.wait();
+        pool.join();
+        drop(sender);
+        assert_eq!(receiver.iter().collect::<HashSet<_>>().len(), SIZE);
+        assert_eq!(pool.completed(), SIZE as u64);
+    }
+
+    #[test]
+    fn unbounded_pool_runs_the_jobs_overflowing_the_channel_in_order() {
+        const JOBS: usize = 3 * UNBOUNDED_CHANNEL_CAPACITY;
+        let pool = ThreadPool::new(1);
+        let (started, running) = channel();
+        let (release, blocked) = channel::<()>();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            blocked.recv().unwrap();
+        });
+        running.recv().unwrap();
+        let (sender, receiver) = channel();
+        for i in 0..JOBS {
+            let sender = sender.clone();
+            pool.try_execute(move || sender.send(i).unwrap()).unwrap();
+        }
+        assert_eq!(pool.queued(), JOBS);
+        release.send(()).unwrap();
+        pool.join();
+        drop(sender);
+        assert!(receiver.iter().eq(0..JOBS));
+    }
+
+    #[test]
+    fn handle_fails_once_the_pool_is_dropped() {
+        let pool = ThreadPool::new(2);
+        let handle = pool.handle();
+        let (sender, receiver) = channel();
+        handle.execute(move || sender.send(()).unwrap()).unwrap();
+        receiver.recv().unwrap();
+        drop(pool);
+        assert_eq!(handle.try_execute(|| ()), Err(PoolError::Closed));
+        assert_eq!(handle.execute(|| ()), Err(PoolError::Closed));
+        assert_eq!(handle.jobs(), 0);
+    }
+
+    #[test]
+    fn pool_dropped_by_its_own_job() {
+        let pool = Arc::new(ThreadPool::new(2));
+        let (sender, receiver) = channel();
+        let job_pool = pool.clone();
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(job_pool);
+            sender.send(()).unwrap();
+        });
+        drop(pool);
+        receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the job returns after dropping the pool");
+    }
+
+    #[test]
+    fn detach_doesnt_wait_for_the_jobs() {
+        let pool = ThreadPool::new(1);
+        pool.execute(|| thread::sleep(Duration::from_millis(500)));
+        assert_eq!(
+            pool.join_timeout(Duration::from_millis(10)),
+            Err(PoolError::TimedOut(1))
+        );
+        let detached = Instant::now();
+        pool.detach();
+        assert!(detached.elapsed() < Duration::from_millis(250));
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::ThreadPool;
+
+    #[test]
+    fn join_waits_for_the_jobs() {
+        loom::model(|| {
+            // A small queue keeps the number of atomics loom tracks small.
+            let pool = ThreadPool::bounded(2, 2);
+            let done = Arc::new(AtomicUsize::new(0));
+            for _ in 0..2 {
+                let done = done.clone();
+                pool.execute(move || {
+                    let _ = done.fetch_add(1, Ordering::Relaxed);
+                });
+            }
+            pool.join();
+            assert_eq!(done.load(Ordering::Relaxed), 2);
+        });
+    }
+
+    #[test]
+    fn drop_runs_the_queued_jobs_and_joins_the_workers() {
+        loom::model(|| {
+            let pool = ThreadPool::bounded(1, 2);
+            let done = Arc::new(AtomicUsize::new(0));
+            for _ in 0..2 {
+                let done = done.clone();
+                pool.execute(move || {
+                    let _ = done.fetch_add(1, Ordering::Relaxed);
+                });
+            }
+            drop(pool);
+            assert_eq!(done.load(Ordering::Relaxed), 2);
+        });
+    }
+}
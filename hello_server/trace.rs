@@ -0,0 +1,29 @@
+//! Optional instrumentation with `tracing`, enabled by the `tracing` feature.
+//!
+//! The thread pool enters a span for each job, the cache one for each load, and the server one for
+//! each connection and each request, with events along the way, so that a single subscriber
+//! follows a request from its connection to the jobs and loads it causes. Without the feature,
+//! the macros expand to nothing, and their arguments aren't evaluated.
+
+/// Emits an event at `$level`, e.g. `event!(DEBUG, status, "responded")`.
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::event!(::tracing::Level::$level, $($arg)+);
+    }};
+}
+
+/// Enters a span at `$level` until the end of the enclosing block, e.g.
+/// `span!(DEBUG, "job", id)`. The parent defaults to the current span.
+macro_rules! span {
+    ($level:ident, parent: $parent:expr, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::span!(parent: $parent, ::tracing::Level::$level, $($arg)+).entered();
+    };
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::span!(::tracing::Level::$level, $($arg)+).entered();
+    };
+}
+
+pub(crate) use {event, span};
@@ -0,0 +1,101 @@
+//! UnixListener that can be cancelled.
+
+use std::fs::{self, Permissions};
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Like [`CancellableTcpListener`](super::CancellableTcpListener), but listening to a Unix socket,
+/// e.g. for a reverse proxy on the same machine. The socket file is removed when the listener is
+/// dropped.
+#[derive(Debug)]
+pub struct CancellableUnixListener {
+    inner: UnixListener,
+    path: PathBuf,
+    is_canceled: AtomicBool,
+}
+
+/// Like `std::os::unix::net::Incoming`, but stops `accept`ing connections if the listener is
+/// `cancel`ed.
+#[derive(Debug)]
+pub struct UnixIncoming<'a> {
+    listener: &'a CancellableUnixListener,
+}
+
+impl CancellableUnixListener {
+    /// Wraps `UnixListener::bind`.
+    ///
+    /// A socket file left at `path` by a server that is gone is replaced. Returns an `AddrInUse`
+    /// error if a server is listening to it, and an `AlreadyExists` error if `path` is another
+    /// kind of file.
+    pub fn bind(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(io::ErrorKind::AlreadyExists.into());
+            }
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::ErrorKind::AddrInUse.into());
+            }
+            fs::remove_file(path)?;
+        }
+        Ok(Self {
+            inner: UnixListener::bind(path)?,
+            path: path.to_path_buf(),
+            is_canceled: AtomicBool::new(false),
+        })
+    }
+
+    /// Sets the permissions of the socket file, e.g. `0o660` to only let the owner and its group,
+    /// such as the reverse proxy's, connect.
+    pub fn set_mode(&self, mode: u32) -> io::Result<()> {
+        fs::set_permissions(&self.path, Permissions::from_mode(mode))
+    }
+
+    /// Returns the path of the socket file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Signals the listener to stop accepting new connections.
+    pub fn cancel(&self) -> io::Result<()> {
+        // Wakes up the listener blocked in `accept` like `CancellableTcpListener::cancel`.
+        self.is_canceled.store(true, Ordering::Release);
+        UnixStream::connect(&self.path).map(|_| ())
+    }
+
+    /// Returns whether the listener is `cancel`led.
+    pub(crate) fn is_canceled(&self) -> bool {
+        self.is_canceled.load(Ordering::Acquire)
+    }
+
+    /// Returns the wrapped listener, e.g. to accept connections without blocking.
+    pub(crate) fn inner(&self) -> &UnixListener {
+        &self.inner
+    }
+
+    /// Returns an iterator over the connections being received on this listener.  The returned
+    /// iterator will return `None` if the listener is `cancel`led.
+    pub fn incoming(&self) -> UnixIncoming<'_> {
+        UnixIncoming { listener: self }
+    }
+}
+
+impl Drop for CancellableUnixListener {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Iterator for UnixIncoming<'_> {
+    type Item = io::Result<UnixStream>;
+    /// Returns None if the listener is `cancel()`led.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.listener.is_canceled.load(Ordering::Acquire) {
+            return None;
+        }
+        Some(self.listener.inner.accept().map(|p| p.0))
+    }
+}
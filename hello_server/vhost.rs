@@ -0,0 +1,89 @@
+//! Virtual hosts.
+
+use std::collections::HashMap;
+
+use super::handler::Handler;
+use super::http::{Request, Response};
+use super::router::Router;
+
+/// Dispatches requests to a router by their `Host` header, so that one server can serve several
+/// sites.
+///
+/// Hosts are matched case-insensitively and without the port. A `*.example.com` host matches the
+/// subdomains of `example.com`, unless one is added exactly. Requests for other hosts or without
+/// `Host` go to the default router. To serve a document root for a host, mount
+/// [`StaticFiles`](super::StaticFiles) at `/` on its router.
+#[derive(Debug)]
+pub struct VirtualHosts {
+    hosts: HashMap<String, Router>,
+    wildcards: Vec<(String, Router)>,
+    default: Router,
+}
+
+impl VirtualHosts {
+    /// Creates virtual hosts with `default` for unmatched hosts.
+    pub fn new(default: Router) -> Self {
+        Self {
+            hosts: HashMap::new(),
+            wildcards: Vec::new(),
+            default,
+        }
+    }
+
+    /// Serves requests for `host` with `router`.
+    pub fn host(&mut self, host: &str, router: Router) -> &mut Self {
+        let host = host.to_ascii_lowercase();
+        match host.strip_prefix("*.") {
+            Some(domain) => self.wildcards.push((format!(".{domain}"), router)),
+            None => {
+                let _ = self.hosts.insert(host, router);
+            }
+        }
+        self
+    }
+
+    /// Dispatches `request` to the router of its host.
+    pub fn handle(&self, request: Request) -> Response {
+        let host = request.header("Host").map(host_name);
+        let router = host
+            .as_deref()
+            .and_then(|host| {
+                self.hosts.get(host).or_else(|| {
+                    // Longest suffix first, so that `*.a.example.com` wins over `*.example.com`.
+                    self.wildcards
+                        .iter()
+                        .filter(|(suffix, _)| host.ends_with(suffix.as_str()))
+                        .max_by_key(|(suffix, _)| suffix.len())
+                        .map(|(_, router)| router)
+                })
+            })
+            .unwrap_or(&self.default);
+        router.handle(request)
+    }
+
+    /// Returns a router that dispatches every request with these virtual hosts. Middlewares added
+    /// to it run for all hosts.
+    pub fn into_router(self) -> Router {
+        let mut router = Router::new();
+        let _ = router.fallback(self);
+        router
+    }
+}
+
+impl Handler for VirtualHosts {
+    fn call(&self, request: Request) -> Response {
+        self.handle(request)
+    }
+}
+
+/// Returns the lowercase host name of a `Host` header value, without the port.
+fn host_name(value: &str) -> String {
+    let value = value.trim();
+    let host = if value.starts_with('[') {
+        // IPv6 literal, e.g. `[::1]:8080`.
+        value.split_inclusive(']').next().unwrap_or(value)
+    } else {
+        value.split(':').next().unwrap_or(value)
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
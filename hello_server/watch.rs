@@ -0,0 +1,57 @@
+//! Watching files for changes.
+
+use notify::event::EventKind;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// Watches the files under a directory, with inotify on Linux, kqueue on BSD and macOS, and the
+/// platform's equivalent elsewhere. Stops watching when dropped.
+pub struct FileWatcher {
+    /// Kept to keep watching.
+    _watcher: RecommendedWatcher,
+}
+
+impl fmt::Debug for FileWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileWatcher").finish_non_exhaustive()
+    }
+}
+
+impl FileWatcher {
+    /// Watches the files under `root` recursively, calling `on_change` on a background thread with
+    /// the path relative to `root` of each file or directory created, modified, or removed.
+    pub fn new<F>(root: &Path, on_change: F) -> io::Result<Self>
+    where
+        F: Fn(&Path) + Send + 'static,
+    {
+        // Events carry paths under the root as given, so it's made absolute for them to be
+        // stripped reliably.
+        let root = root.canonicalize()?;
+        let prefix = root.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(err) => {
+                        println!("[watch] failed to watch {}: {err}", prefix.display());
+                        return;
+                    }
+                };
+                if matches!(event.kind, EventKind::Access(_)) {
+                    return;
+                }
+                for path in &event.paths {
+                    if let Ok(relative) = path.strip_prefix(&prefix) {
+                        on_change(relative);
+                    }
+                }
+            })
+            .map_err(io::Error::other)?;
+        watcher
+            .watch(&root, RecursiveMode::Recursive)
+            .map_err(io::Error::other)?;
+        Ok(Self { _watcher: watcher })
+    }
+}